@@ -0,0 +1,12 @@
+ALTER TABLE encounters ADD COLUMN completed BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Running state of a combat encounter, so a live initiative tracker survives reloads.
+-- Combatants (initiative, hp, conditions) are stored as a single document, as they are always read and written together.
+CREATE TABLE encounter_combats (
+    encounter_id INT PRIMARY KEY REFERENCES encounters(id),
+    round INT NOT NULL DEFAULT 1,
+    turn INT NOT NULL DEFAULT 0,
+    combatants JSONB NOT NULL DEFAULT '[]',
+    started_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ended_at TIMESTAMP
+);
@@ -0,0 +1,12 @@
+-- Check attempts made while running a subsystem encounter, from which progress (VP, awareness) is derived.
+-- check_index refers to encounter_skill_checks.order_index, as checks are recreated when an encounter is edited.
+CREATE TABLE encounter_subsystem_attempts (
+    id SERIAL PRIMARY KEY,
+    encounter_id INT NOT NULL REFERENCES encounters(id),
+    check_index INT NOT NULL,
+    character_id INT REFERENCES characters(id),
+    skill VARCHAR(64) NOT NULL,
+    degree_of_success SMALLINT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX encounter_subsystem_attempts_encounter_id_index ON encounter_subsystem_attempts(encounter_id);
@@ -0,0 +1,13 @@
+-- Per-user API tokens, accepted as an 'Authorization: Bearer' header.
+-- Only a SHA-256 hash of the token is stored; the token itself is shown once on creation.
+CREATE TABLE api_tokens (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id),
+    name VARCHAR(64) NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    token_prefix VARCHAR(16) NOT NULL,
+    scope SMALLINT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used_at TIMESTAMP WITH TIME ZONE
+);
+CREATE INDEX api_tokens_user_id_index ON api_tokens(user_id);
@@ -0,0 +1,8 @@
+ALTER TABLE users ADD COLUMN email TEXT UNIQUE;
+
+-- Reset tokens are single use and expire. Like API tokens, only a SHA-256 hash of the token is stored.
+-- Any existing tokens were never usable, so they are dropped rather than migrated.
+DELETE FROM password_reset_tokens;
+ALTER TABLE password_reset_tokens RENAME COLUMN token TO token_hash;
+ALTER TABLE password_reset_tokens ADD COLUMN expires_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP + INTERVAL '1 hour';
+ALTER TABLE password_reset_tokens ADD COLUMN used_at TIMESTAMP WITH TIME ZONE;
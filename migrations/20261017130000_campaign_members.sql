@@ -0,0 +1,12 @@
+-- Users invited to a campaign they do not own, as co-GM (full edit access) or player (read-only).
+-- The owner is not listed here; they remain campaigns.owner.
+-- Players may be linked to their character, limiting the rewards they can see to that character's.
+CREATE TABLE campaign_members (
+    campaign_id INTEGER NOT NULL REFERENCES campaigns(id),
+    user_id INTEGER NOT NULL REFERENCES users(id),
+    role SMALLINT NOT NULL,
+    character_id INTEGER REFERENCES characters(id),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (campaign_id, user_id)
+);
+CREATE INDEX campaign_members_user_id_index ON campaign_members(user_id);
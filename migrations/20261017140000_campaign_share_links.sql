@@ -0,0 +1,3 @@
+-- Share links giving read-only access to a player-facing view of a campaign, without an account.
+-- Only a SHA-256 hash of the token is stored; regenerating the link replaces (and revokes) the previous one.
+ALTER TABLE campaigns ADD COLUMN share_token_hash TEXT UNIQUE;
@@ -0,0 +1,3 @@
+-- Stable id of a library object in the data it was imported from (eg: a Foundry VTT document id),
+-- so that re-importing updates the object in place.
+ALTER TABLE library_objects ADD COLUMN source_id TEXT UNIQUE;
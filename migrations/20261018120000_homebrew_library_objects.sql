@@ -0,0 +1,4 @@
+-- Homebrew library objects have an owner (official content has none), and may be shared to one campaign.
+ALTER TABLE library_objects ADD COLUMN campaign_id INTEGER REFERENCES campaigns(id);
+CREATE INDEX library_objects_owner ON library_objects(owner) WHERE owner IS NOT NULL;
+CREATE INDEX library_objects_campaign_id ON library_objects(campaign_id) WHERE campaign_id IS NOT NULL;
@@ -0,0 +1,3 @@
+-- Structured stat block (defenses, strikes, abilities) of a creature.
+-- Stored as a single document, as it is always read and written alongside the creature.
+ALTER TABLE library_creatures ADD COLUMN stat_block JSONB;
@@ -0,0 +1,5 @@
+ALTER TABLE library_spells
+    ADD COLUMN actions TEXT,
+    ADD COLUMN saving_throw TEXT,
+    ADD COLUMN basic_save BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN heightening JSONB NOT NULL DEFAULT '[]';
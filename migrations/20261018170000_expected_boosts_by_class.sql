@@ -0,0 +1,10 @@
+-- Class specific replacements for expected_treasure_stats_boosts_at_levels.
+-- If a class has any rows for a boost category, they replace the general rows of that category for the class
+-- (eg: a class without weapon strikes can expect no attack potency with a single amount 0 row).
+CREATE TABLE expected_treasure_stats_boosts_by_class(
+    class_id INT NOT NULL REFERENCES library_classes(id) ON DELETE CASCADE,
+    level SMALLINT NOT NULL,
+    stat_boost_category_id SMALLINT NOT NULL REFERENCES stat_boost_category_types(id),
+    amount SMALLINT NOT NULL
+);
+CREATE INDEX expected_treasure_stats_boosts_by_class_class_id ON expected_treasure_stats_boosts_by_class(class_id);
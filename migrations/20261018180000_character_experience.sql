@@ -0,0 +1,22 @@
+-- Whether characters earn the experience of sessions they weren't present for (otherwise, only of those they were)
+ALTER TABLE campaigns ADD COLUMN absent_characters_earn_experience BOOLEAN NOT NULL DEFAULT TRUE;
+
+-- Experience of each character: whatever the campaign started with (its experience not from sessions),
+-- plus that of every session the character earned experience in.
+CREATE OR REPLACE VIEW character_experience AS
+SELECT
+    ch.id AS character_id,
+    o.total_experience,
+    1 + (o.total_experience / 1000) AS level
+FROM characters ch
+INNER JOIN campaigns ca ON ch.campaign = ca.id
+CROSS JOIN LATERAL (
+    SELECT (ca.total_experience - COALESCE(SUM(e.total_experience), 0)
+        + COALESCE(SUM(e.total_experience) FILTER (
+            WHERE ca.absent_characters_earn_experience OR csc.present
+        ), 0))::int AS total_experience
+    FROM campaign_sessions cs
+    INNER JOIN encounters e ON e.session_id = cs.id
+    LEFT JOIN campaign_session_characters csc ON csc.session_id = cs.id AND csc.character_id = ch.id
+    WHERE cs.campaign_id = ca.id
+) o;
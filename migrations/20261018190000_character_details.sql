@@ -0,0 +1,30 @@
+-- What a character is built from, beyond its class
+ALTER TABLE characters ADD COLUMN ancestry TEXT;
+ALTER TABLE characters ADD COLUMN heritage TEXT;
+ALTER TABLE characters ADD COLUMN background TEXT;
+
+-- Set directly (eg: copied from a character builder) rather than tracked from experience
+ALTER TABLE characters ADD COLUMN level SMALLINT;
+
+-- Stored as documents, as they are always read and written with the character
+ALTER TABLE characters ADD COLUMN ability_modifiers JSONB NOT NULL DEFAULT '{}';
+ALTER TABLE characters ADD COLUMN proficiencies JSONB NOT NULL DEFAULT '{}';
+
+-- A level set on the character takes precedence over that of its experience
+CREATE OR REPLACE VIEW character_experience AS
+SELECT
+    ch.id AS character_id,
+    o.total_experience,
+    COALESCE(ch.level::int, 1 + (o.total_experience / 1000)) AS level
+FROM characters ch
+INNER JOIN campaigns ca ON ch.campaign = ca.id
+CROSS JOIN LATERAL (
+    SELECT (ca.total_experience - COALESCE(SUM(e.total_experience), 0)
+        + COALESCE(SUM(e.total_experience) FILTER (
+            WHERE ca.absent_characters_earn_experience OR csc.present
+        ), 0))::int AS total_experience
+    FROM campaign_sessions cs
+    INNER JOIN encounters e ON e.session_id = cs.id
+    LEFT JOIN campaign_session_characters csc ON csc.session_id = cs.id AND csc.character_id = ch.id
+    WHERE cs.campaign_id = ca.id
+) o;
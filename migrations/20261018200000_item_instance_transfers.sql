@@ -0,0 +1,18 @@
+-- Ledger of changes to who holds an item instance, and of the stacks it was split from or merged with.
+CREATE TABLE item_instance_transfers (
+    id SERIAL PRIMARY KEY,
+    item_instance_id INT NOT NULL REFERENCES item_instances(id) ON DELETE CASCADE,
+    -- 'move', 'split' (from another instance), 'merge' (of another instance into this one) or 'use' (of a consumable)
+    transfer_type TEXT NOT NULL,
+    -- Holders before and after. NULL is the party stash.
+    from_character_id INT REFERENCES characters(id) ON DELETE SET NULL,
+    to_character_id INT REFERENCES characters(id) ON DELETE SET NULL,
+    quantity SMALLINT NOT NULL,
+    -- The instance split from, or merged in (which no longer exists)
+    other_item_instance_id INT,
+    user_id INT REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_item_instance_transfers_item_instance_id ON item_instance_transfers(item_instance_id);
+CREATE INDEX idx_item_instances_character_id ON item_instances(character_id);
@@ -0,0 +1,4 @@
+-- Consumables that have been used up. Using part of a stack splits the used quantity off into its own spent instance,
+-- so that what a character received stays counted, separately from what they still hold.
+ALTER TABLE item_instances ADD COLUMN spent BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE item_instances ADD COLUMN spent_session_id INT REFERENCES campaign_sessions(id) ON DELETE SET NULL;
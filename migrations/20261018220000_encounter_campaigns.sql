@@ -0,0 +1,4 @@
+-- The campaign an encounter belongs to. Kept when the encounter is unlinked from its session, so that co-GMs
+-- can still edit it, and set directly for encounters prepared for a campaign before being linked to a session.
+ALTER TABLE encounters ADD COLUMN campaign_id INT REFERENCES campaigns(id) ON DELETE SET NULL;
+UPDATE encounters en SET campaign_id = cs.campaign_id FROM campaign_sessions cs WHERE en.session_id = cs.id;
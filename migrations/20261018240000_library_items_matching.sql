@@ -0,0 +1,58 @@
+-- Ids of the library items matching a listing's filters. Shared by the item search and its facet counts, so the
+-- counts can't drift from the results. A plain SQL function, so the planner inlines it into each query.
+CREATE FUNCTION library_items_matching(
+    name text,
+    rarity int,
+    game_system int,
+    min_level int,
+    max_level int,
+    min_price int,
+    max_price int,
+    any_traits text[],
+    all_traits text[],
+    ids int[],
+    include_remaster bool,
+    include_legacy bool,
+    favor_remaster bool,
+    favor_legacy bool,
+    relic_gift bool,
+    consumable bool,
+    magical bool,
+    cursed bool,
+    -- Homebrew of every user, or only what this user can see
+    all_homebrew bool,
+    user_id int
+) RETURNS TABLE (id int) AS $$
+    SELECT lo.id
+    FROM library_objects lo
+    INNER JOIN library_items li ON lo.id = li.id
+    LEFT JOIN (
+        SELECT library_object_id AS lo_id, ARRAY_AGG(t.tag) FILTER (WHERE t.trait) AS traits
+        FROM library_objects_tags lot
+        INNER JOIN library_tags t ON lot.tag_id = t.id
+        GROUP BY lot.library_object_id
+    ) AS tags ON lo.id = tags.lo_id
+    WHERE
+        ($1 IS NULL OR lo.name ILIKE '%' || $1 || '%')
+        AND ($2 IS NULL OR li.rarity = $2)
+        AND ($3 IS NULL OR lo.game_system = $3)
+        AND ($4 IS NULL OR li.level >= $4)
+        AND ($5 IS NULL OR li.level <= $5)
+        AND ($6 IS NULL OR li.price >= $6)
+        AND ($7 IS NULL OR li.price <= $7)
+        AND ($8 IS NULL OR tags.traits::text[] && $8)
+        AND ($9 IS NULL OR tags.traits::text[] @> $9)
+        AND ($10 IS NULL OR lo.id = ANY($10))
+        AND NOT (NOT $11 AND lo.legacy = FALSE)
+        AND NOT (NOT $12 AND lo.legacy = TRUE)
+        AND NOT ($13 AND lo.remastering_alt_id IS NOT NULL AND lo.legacy = TRUE)
+        AND NOT ($14 AND lo.remastering_alt_id IS NOT NULL AND lo.legacy = FALSE)
+        AND ($15 IS NULL OR ($15 AND li.relic_gift_stage IS NOT NULL) OR (NOT $15 AND li.relic_gift_stage IS NULL))
+        AND ($16 IS NULL OR li.consumable = $16)
+        AND ($17 IS NULL OR li.magical = $17)
+        AND ($18 IS NULL OR li.cursed = $18)
+        AND ($19 OR lo.owner IS NULL OR lo.owner = $20 OR lo.campaign_id IN (
+            SELECT id FROM campaigns WHERE owner = $20
+            UNION SELECT campaign_id FROM campaign_members WHERE campaign_members.user_id = $20
+        ))
+$$ LANGUAGE sql STABLE;
@@ -0,0 +1,2 @@
+-- Awareness points at which complications occur in an infiltration, set per encounter (GM Core default).
+ALTER TABLE encounters ADD COLUMN awareness_thresholds INT[] NOT NULL DEFAULT '{5, 10, 15, 20}';
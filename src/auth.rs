@@ -1,7 +1,8 @@
 use axum::{
-    extract::State,
-    response::IntoResponse,
-    routing::{get, post},
+    extract::{Path, Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
     Json, Router,
 };
 use axum_extra::extract::cookie::{Cookie, CookieJar};
@@ -9,22 +10,31 @@ use rand::Rng;
 use reqwest::StatusCode;
 use serde::Deserialize;
 use sqlx::Pool;
+use std::sync::Arc;
 use time::Duration;
 
 use crate::{
     database,
+    mail::{Mail, Mailer},
     models::{
-        auth::{Session, User},
+        auth::{ApiTokenScope, CreatedApiToken, Session, User},
         ids::InternalId,
     },
     AppState,
 };
 
 pub const SESSION_COOKIE_NAME: &str = "session_id";
+pub const API_TOKEN_PREFIX: &str = "mch_";
+
+tokio::task_local! {
+    // User authenticated by an API token for the current request, set by `authenticate_api_token`
+    static API_TOKEN_USER: User;
+}
 
 #[derive(Deserialize, Debug)]
 pub struct CreateUser {
     pub username: String,
+    pub email: Option<String>,
     pub password: String,
 }
 
@@ -34,18 +44,78 @@ struct LoginRequest {
     password: String,
 }
 
+#[derive(Deserialize, Debug)]
+struct RequestPasswordReset {
+    username: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ConfirmPasswordReset {
+    token: String,
+    new_password: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChangePassword {
+    current_password: String,
+    new_password: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct CreateApiToken {
+    name: String,
+    scope: ApiTokenScope,
+}
+
 pub fn router() -> Router<AppState> {
     Router::new()
         .route("/signup", post(signup))
         .route("/login", post(login))
         .route("/logout", post(logout))
         .route("/me", get(get_current_user))
+        .route("/password/reset", post(request_password_reset))
+        .route("/password/reset/confirm", post(confirm_password_reset))
+        .route("/password/change", post(change_password))
+        .route("/tokens", get(get_api_tokens))
+        .route("/tokens", post(create_api_token))
+        .route("/tokens/{id}", delete(delete_api_token))
+}
+
+// Middleware accepting 'Authorization: Bearer <token>' with a user's API token, as an alternative to the session cookie.
+// Read-scoped tokens may only be used for GET requests.
+pub async fn authenticate_api_token(
+    State(pool): State<sqlx::PgPool>,
+    request: Request,
+    next: Next,
+) -> crate::Result<Response> {
+    let token = request
+        .headers()
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .map(|t| t.trim().to_string());
+    let Some(token) = token else {
+        return Ok(next.run(request).await);
+    };
+
+    let (user, scope) = database::auth::get_user_for_api_token(&pool, &token)
+        .await?
+        .ok_or(crate::ServerError::Unauthorized)?;
+    if !scope.allows(request.method()) {
+        return Err(crate::ServerError::Unauthorized);
+    }
+
+    Ok(API_TOKEN_USER.scope(user, next.run(request)).await)
 }
 
 pub async fn extract_user_from_cookies(
     jar: &CookieJar,
     exec: &sqlx::PgPool,
 ) -> crate::Result<User> {
+    if let Ok(user) = API_TOKEN_USER.try_with(|user| user.clone()) {
+        return Ok(user);
+    }
+
     if let Some(session_id) = jar.get(SESSION_COOKIE_NAME) {
         if let Some(user) = database::auth::get_user_for_session(exec, session_id.value()).await? {
             return Ok(user);
@@ -60,8 +130,7 @@ pub async fn extract_admin_from_headers(
     exec: &sqlx::PgPool,
 ) -> crate::Result<()> {
     // First, check for passed Machete-Admin header, compare to env var
-    // A non-logged in solution.
-    // TODO: Re-add bearer tokens for user-specific API keys
+    // A non-logged in solution. Admins can also use their own API tokens.
     if let Some(auth_header) = headers.get("Machete-Admin") {
         if *auth_header == *dotenvy::var("ADMIN_API_KEY").unwrap() {
             return Ok(());
@@ -95,16 +164,25 @@ async fn signup(
     {
         return Err(crate::ServerError::BadRequest("User already exists".into()));
     }
+    if let Some(email) = user.email.as_deref() {
+        if database::auth::is_email_taken(&pool, email).await? {
+            return Err(crate::ServerError::BadRequest(
+                "Email already in use".into(),
+            ));
+        }
+    }
 
     let mut tx = pool.begin().await?;
     let password_hash = bcrypt::hash(user.password, bcrypt::DEFAULT_COST).unwrap();
-    let id = database::auth::insert_user(&mut tx, &user.username, &password_hash).await?;
+    let id = database::auth::insert_user(
+        &mut tx,
+        &user.username,
+        user.email.as_deref(),
+        &password_hash,
+    )
+    .await?;
 
-    let token: String = rand::thread_rng()
-        .sample_iter(&rand::distributions::Alphanumeric)
-        .take(32)
-        .map(char::from)
-        .collect();
+    let token = generate_token(32);
 
     let mut cookie = Cookie::new(SESSION_COOKIE_NAME, token.clone());
     cookie.set_max_age(Some(Duration::days(7)));
@@ -137,11 +215,7 @@ async fn login(
             return Err(crate::ServerError::Unauthorized);
         }
 
-        let token: String = rand::thread_rng()
-            .sample_iter(&rand::distributions::Alphanumeric)
-            .take(32)
-            .map(char::from)
-            .collect();
+        let token = generate_token(32);
 
         let mut tx = pool.begin().await?;
         database::auth::create_session(&mut tx, user_id, &token).await?;
@@ -175,3 +249,124 @@ async fn logout(
     }
     Ok((jar, StatusCode::NO_CONTENT))
 }
+
+// Emails a single-use reset token to the user, if they have an email.
+// Always succeeds, so it can't be used to find out which users exist.
+async fn request_password_reset(
+    State(pool): State<Pool<sqlx::Postgres>>,
+    State(mailer): State<Arc<dyn Mailer>>,
+    Json(request): Json<RequestPasswordReset>,
+) -> Result<impl IntoResponse, crate::ServerError> {
+    let Some(user) = database::auth::get_user_by_name(&pool, &request.username).await? else {
+        return Ok(StatusCode::NO_CONTENT);
+    };
+    let Some(email) = user.email else {
+        return Ok(StatusCode::NO_CONTENT);
+    };
+
+    let token = generate_token(32);
+    let mut tx = pool.begin().await?;
+    database::auth::insert_password_reset_token(&mut tx, InternalId(user.id as u32), &token)
+        .await?;
+    tx.commit().await?;
+
+    mailer
+        .send(&Mail {
+            to: email,
+            subject: "Machete password reset".to_string(),
+            body: format!(
+                "A password reset was requested for {}. Use this token within the hour to reset your password:\n\n{}",
+                user.username, token
+            ),
+        })
+        .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn confirm_password_reset(
+    State(pool): State<Pool<sqlx::Postgres>>,
+    Json(confirm): Json<ConfirmPasswordReset>,
+) -> Result<impl IntoResponse, crate::ServerError> {
+    let mut tx = pool.begin().await?;
+    let user_id = database::auth::use_password_reset_token(&mut tx, &confirm.token)
+        .await?
+        .ok_or(crate::ServerError::Unauthorized)?;
+
+    let password_hash = bcrypt::hash(confirm.new_password, bcrypt::DEFAULT_COST).unwrap();
+    database::auth::update_password(&mut tx, user_id, &password_hash).await?;
+    // Whoever knew the old password may have created sessions or tokens with it
+    database::auth::delete_user_sessions(&mut tx, user_id).await?;
+    database::auth::delete_user_api_tokens(&mut tx, user_id).await?;
+    tx.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn change_password(
+    State(pool): State<Pool<sqlx::Postgres>>,
+    jar: CookieJar,
+    Json(change): Json<ChangePassword>,
+) -> Result<impl IntoResponse, crate::ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+
+    let current_hash = database::auth::get_password_hash(&pool, user.id).await?;
+    if !bcrypt::verify(change.current_password, &current_hash).unwrap() {
+        return Err(crate::ServerError::Unauthorized);
+    }
+
+    let mut tx = pool.begin().await?;
+    let password_hash = bcrypt::hash(change.new_password, bcrypt::DEFAULT_COST).unwrap();
+    database::auth::update_password(&mut tx, user.id, &password_hash).await?;
+    tx.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_api_tokens(
+    State(pool): State<Pool<sqlx::Postgres>>,
+    jar: CookieJar,
+) -> Result<impl IntoResponse, crate::ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+    let tokens = database::auth::get_api_tokens(&pool, user.id).await?;
+    Ok(Json(tokens))
+}
+
+async fn create_api_token(
+    State(pool): State<Pool<sqlx::Postgres>>,
+    jar: CookieJar,
+    Json(create): Json<CreateApiToken>,
+) -> Result<impl IntoResponse, crate::ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+
+    let token = format!("{}{}", API_TOKEN_PREFIX, generate_token(40));
+    let mut tx = pool.begin().await?;
+    let api_token =
+        database::auth::insert_api_token(&mut tx, user.id, &create.name, &token, create.scope)
+            .await?;
+    tx.commit().await?;
+
+    Ok(Json(CreatedApiToken { token, api_token }))
+}
+
+async fn delete_api_token(
+    State(pool): State<Pool<sqlx::Postgres>>,
+    jar: CookieJar,
+    Path(id): Path<InternalId>,
+) -> Result<impl IntoResponse, crate::ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+
+    let mut tx = pool.begin().await?;
+    database::auth::delete_api_token(&mut tx, user.id, id).await?;
+    tx.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(crate) fn generate_token(length: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
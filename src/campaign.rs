@@ -1,13 +1,19 @@
 use std::collections::HashMap;
 
 use crate::{
-    auth::extract_user_from_cookies,
+    auth::{extract_user_from_cookies, generate_token},
     database::{
-        campaigns::ModifyCampaign,
+        campaigns::{InsertCampaignMember, ModifyCampaign, ModifyCampaignMember},
         import::ImportCampaign,
+        pathbuilder::PathbuilderExport,
+        remaster::RemasterCampaign,
         sessions::{InsertSession, LinkEncounterSession, ModifySession, UpdateCharacterSessions},
+        treasure::GenerateTreasure,
+    },
+    models::{
+        campaign::{CampaignRole, CampaignShareLink},
+        ids::InternalId,
     },
-    models::ids::InternalId,
     AppState,
 };
 use axum::{
@@ -39,10 +45,28 @@ pub fn router() -> Router<AppState> {
         .route("/import", post(import_campaign)) // TODO: Does this need to differ from generic 'insert'?
         .route("/{id}/export", get(export_campaign))
         .route("/{id}/stats", get(get_stats))
+        .route("/{id}/treasure", post(generate_treasure))
+        .route("/{id}/remaster", post(remaster_campaign))
+        .route("/{id}/share", post(create_share_link))
+        .route("/{id}/share", delete(delete_share_link))
+        .route("/{id}/view", get(get_campaign_view))
+        .route("/shared/{token}", get(get_shared_campaign))
+        .route("/{id}/members", get(get_members))
+        .route("/{id}/members", post(insert_member))
+        .route("/{id}/members/{user_id}", patch(edit_member))
+        .route("/{id}/members/{user_id}", delete(delete_member))
         .route("/{id}/characters", get(get_characters))
         .route("/{id}/characters", post(insert_characters))
         .route("/{id}/characters/{id}", put(edit_character))
         .route("/{id}/characters/{id}", delete(delete_character))
+        .route(
+            "/{id}/characters/pathbuilder",
+            post(import_pathbuilder_character),
+        )
+        .route(
+            "/{id}/characters/{id}/pathbuilder",
+            put(update_pathbuilder_character),
+        )
         .route("/{id}/sessions", get(get_sessions))
         .route("/{id}/sessions", post(insert_sessions))
         .route("/{id}/sessions", patch(edit_sessions))
@@ -66,7 +90,7 @@ async fn get_campaigns(
     jar: CookieJar,
 ) -> Result<impl IntoResponse, ServerError> {
     let user = extract_user_from_cookies(&jar, &pool).await?;
-    let campaigns = database::campaigns::get_campaigns_owner(&pool, user.id).await?;
+    let campaigns = database::campaigns::get_campaigns_for_user(&pool, user.id).await?;
     Ok(Json(campaigns))
 }
 
@@ -79,12 +103,10 @@ async fn edit_campaign(
     let user = extract_user_from_cookies(&jar, &pool).await?;
 
     // Check if user has access to the campaign
-    if database::campaigns::get_owned_campaign_id(&pool, id, user.id)
+    let access = database::campaigns::get_campaign_access(&pool, id, user.id)
         .await?
-        .is_none()
-    {
-        return Err(ServerError::NotFound);
-    }
+        .ok_or(ServerError::NotFound)?;
+    access.require(CampaignRole::CoGm)?;
 
     let mut tx = pool.begin().await?;
     database::campaigns::edit_campaign(&mut tx, id, &campaign).await?;
@@ -100,12 +122,10 @@ async fn delete_campaign(
     let user = extract_user_from_cookies(&jar, &pool).await?;
 
     // Check if user has access to the campaign
-    if database::campaigns::get_owned_campaign_id(&pool, id, user.id)
+    let access = database::campaigns::get_campaign_access(&pool, id, user.id)
         .await?
-        .is_none()
-    {
-        return Err(ServerError::NotFound);
-    }
+        .ok_or(ServerError::NotFound)?;
+    access.require(CampaignRole::Owner)?;
 
     let mut tx = pool.begin().await?;
     database::campaigns::delete_campaign(&mut tx, id).await?;
@@ -132,6 +152,156 @@ async fn insert_campaign(
     Ok(Json(campaign))
 }
 
+// Creates a link giving anyone read-only access to the player-facing view, replacing any previous link
+async fn create_share_link(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    Path(id): Path<InternalId>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+
+    // Check if user has access to the campaign
+    let access = database::campaigns::get_campaign_access(&pool, id, user.id)
+        .await?
+        .ok_or(ServerError::NotFound)?;
+    access.require(CampaignRole::CoGm)?;
+
+    let token = generate_token(32);
+    let mut tx = pool.begin().await?;
+    database::campaigns::set_campaign_share_token(&mut tx, id, Some(&token)).await?;
+    tx.commit().await?;
+    Ok(Json(CampaignShareLink { token }))
+}
+
+async fn delete_share_link(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    Path(id): Path<InternalId>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+
+    // Check if user has access to the campaign
+    let access = database::campaigns::get_campaign_access(&pool, id, user.id)
+        .await?
+        .ok_or(ServerError::NotFound)?;
+    access.require(CampaignRole::CoGm)?;
+
+    let mut tx = pool.begin().await?;
+    database::campaigns::set_campaign_share_token(&mut tx, id, None).await?;
+    tx.commit().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_campaign_view(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    Path(id): Path<InternalId>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+
+    // Check if user has access to the campaign
+    let access = database::campaigns::get_campaign_access(&pool, id, user.id)
+        .await?
+        .ok_or(ServerError::NotFound)?;
+    access.require(CampaignRole::Player)?;
+
+    let campaign = database::campaigns::get_shared_campaign(&pool, &access).await?;
+    Ok(Json(campaign))
+}
+
+// Does not require an account: the token is the only credential
+async fn get_shared_campaign(
+    State(pool): State<PgPool>,
+    Path(token): Path<String>,
+) -> Result<impl IntoResponse, ServerError> {
+    let access = database::campaigns::get_campaign_access_for_share_token(&pool, &token)
+        .await?
+        .ok_or(ServerError::NotFound)?;
+
+    let campaign = database::campaigns::get_shared_campaign(&pool, &access).await?;
+    Ok(Json(campaign))
+}
+
+async fn get_members(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    Path(id): Path<InternalId>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+
+    // Check if user has access to the campaign
+    let access = database::campaigns::get_campaign_access(&pool, id, user.id)
+        .await?
+        .ok_or(ServerError::NotFound)?;
+    access.require(CampaignRole::CoGm)?;
+
+    let members = database::campaigns::get_campaign_members(&pool, id).await?;
+    Ok(Json(members))
+}
+
+// Invites a user to the campaign, or replaces their role if they are already a member
+async fn insert_member(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    Path(id): Path<InternalId>,
+    Json(member): Json<InsertCampaignMember>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+
+    // Only the owner manages members
+    let access = database::campaigns::get_campaign_access(&pool, id, user.id)
+        .await?
+        .ok_or(ServerError::NotFound)?;
+    access.require(CampaignRole::Owner)?;
+
+    let mut tx = pool.begin().await?;
+    database::campaigns::insert_campaign_member(&mut tx, &access, &member).await?;
+    let members = database::campaigns::get_campaign_members(&mut *tx, id).await?;
+    tx.commit().await?;
+    Ok(Json(members))
+}
+
+async fn edit_member(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    Path((id, user_id)): Path<(InternalId, InternalId)>,
+    Json(member): Json<ModifyCampaignMember>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+
+    // Only the owner manages members
+    let access = database::campaigns::get_campaign_access(&pool, id, user.id)
+        .await?
+        .ok_or(ServerError::NotFound)?;
+    access.require(CampaignRole::Owner)?;
+
+    let mut tx = pool.begin().await?;
+    database::campaigns::edit_campaign_member(&mut tx, id, user_id, &member).await?;
+    tx.commit().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn delete_member(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    Path((id, user_id)): Path<(InternalId, InternalId)>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+
+    // Only the owner removes members, but members can leave
+    let access = database::campaigns::get_campaign_access(&pool, id, user.id)
+        .await?
+        .ok_or(ServerError::NotFound)?;
+    if user_id != user.id {
+        access.require(CampaignRole::Owner)?;
+    }
+
+    let mut tx = pool.begin().await?;
+    database::campaigns::delete_campaign_member(&mut tx, id, user_id).await?;
+    tx.commit().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
 async fn get_characters(
     Query(filters): Query<CharacterFilters>,
     jar: CookieJar,
@@ -141,14 +311,18 @@ async fn get_characters(
     let user = extract_user_from_cookies(&jar, &pool).await?;
 
     // Check if user has access to the campaign
-    if database::campaigns::get_owned_campaign_id(&pool, id, user.id)
+    let access = database::campaigns::get_campaign_access(&pool, id, user.id)
         .await?
-        .is_none()
-    {
-        return Err(ServerError::NotFound);
-    }
+        .ok_or(ServerError::NotFound)?;
+    access.require(CampaignRole::Player)?;
 
-    let characters = database::characters::get_characters(&pool, user.id, id, &filters).await?;
+    let mut characters =
+        database::characters::get_characters(&pool, access.owner, id, &filters).await?;
+
+    // Players only see their own character
+    if access.role == CampaignRole::Player {
+        characters.retain(|c| Some(c.id) == access.character_id);
+    }
     Ok(Json(characters))
 }
 
@@ -161,12 +335,10 @@ async fn insert_characters(
     let user = extract_user_from_cookies(&jar, &pool).await?;
 
     // Check if user has access to the campaign
-    if database::campaigns::get_owned_campaign_id(&pool, id, user.id)
+    let access = database::campaigns::get_campaign_access(&pool, id, user.id)
         .await?
-        .is_none()
-    {
-        return Err(ServerError::NotFound);
-    }
+        .ok_or(ServerError::NotFound)?;
+    access.require(CampaignRole::CoGm)?;
 
     let mut tx = pool.begin().await?;
     database::characters::insert_characters(&mut tx, id, &characters).await?;
@@ -178,13 +350,19 @@ async fn insert_characters(
 async fn edit_character(
     State(pool): State<PgPool>,
     jar: CookieJar,
-    Path((_, character_id)): Path<(InternalId, InternalId)>,
+    Path((id, character_id)): Path<(InternalId, InternalId)>,
     Json(character): Json<ModifyCharacter>,
 ) -> Result<impl IntoResponse, ServerError> {
     let user = extract_user_from_cookies(&jar, &pool).await?;
 
-    // Check if user has access to the chracter
-    if database::characters::get_chracter_id(&pool, character_id, user.id)
+    // Check if user has access to the campaign
+    let access = database::campaigns::get_campaign_access(&pool, id, user.id)
+        .await?
+        .ok_or(ServerError::NotFound)?;
+    access.require(CampaignRole::CoGm)?;
+
+    // Check if the character is part of the campaign
+    if database::characters::get_chracter_id(&pool, character_id, access.owner)
         .await?
         .is_none()
     {
@@ -200,12 +378,18 @@ async fn edit_character(
 async fn delete_character(
     State(pool): State<PgPool>,
     jar: CookieJar,
-    Path((_, character_id)): Path<(InternalId, InternalId)>,
+    Path((id, character_id)): Path<(InternalId, InternalId)>,
 ) -> Result<impl IntoResponse, ServerError> {
     let user = extract_user_from_cookies(&jar, &pool).await?;
 
-    // Check if user has access to the chracter
-    if database::characters::get_chracter_id(&pool, character_id, user.id)
+    // Check if user has access to the campaign
+    let access = database::campaigns::get_campaign_access(&pool, id, user.id)
+        .await?
+        .ok_or(ServerError::NotFound)?;
+    access.require(CampaignRole::CoGm)?;
+
+    // Check if the character is part of the campaign
+    if database::characters::get_chracter_id(&pool, character_id, access.owner)
         .await?
         .is_none()
     {
@@ -217,6 +401,79 @@ async fn delete_character(
     Ok(StatusCode::NO_CONTENT)
 }
 
+// Updates the campaign's character of the same name, or creates one
+async fn import_pathbuilder_character(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    Path(id): Path<InternalId>,
+    Json(export): Json<PathbuilderExport>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+
+    // Check if user has access to the campaign
+    let access = database::campaigns::get_campaign_access(&pool, id, user.id)
+        .await?
+        .ok_or(ServerError::NotFound)?;
+    access.require(CampaignRole::CoGm)?;
+
+    let character_id =
+        database::characters::get_characters(&pool, access.owner, id, &CharacterFilters::default())
+            .await?
+            .into_iter()
+            .find(|c| c.name.eq_ignore_ascii_case(&export.build.name))
+            .map(|c| c.id);
+
+    let mut tx = pool.begin().await?;
+    let report = database::pathbuilder::import_pathbuilder_character(
+        &mut tx,
+        id,
+        access.owner,
+        character_id,
+        &export.build,
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(Json(report))
+}
+
+async fn update_pathbuilder_character(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    Path((id, character_id)): Path<(InternalId, InternalId)>,
+    Json(export): Json<PathbuilderExport>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+
+    // Check if user has access to the campaign. Players may update their own character.
+    let access = database::campaigns::get_campaign_access(&pool, id, user.id)
+        .await?
+        .ok_or(ServerError::NotFound)?;
+    if access.character_id != Some(character_id) {
+        access.require(CampaignRole::CoGm)?;
+    }
+
+    // Check if the character is part of the campaign
+    if !database::characters::get_characters(&pool, access.owner, id, &CharacterFilters::default())
+        .await?
+        .iter()
+        .any(|c| c.id == character_id)
+    {
+        return Err(ServerError::NotFound);
+    }
+
+    let mut tx = pool.begin().await?;
+    let report = database::pathbuilder::import_pathbuilder_character(
+        &mut tx,
+        id,
+        access.owner,
+        Some(character_id),
+        &export.build,
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(Json(report))
+}
+
 async fn get_sessions(
     State(pool): State<PgPool>,
     jar: CookieJar,
@@ -224,7 +481,16 @@ async fn get_sessions(
 ) -> Result<impl IntoResponse, ServerError> {
     let user = extract_user_from_cookies(&jar, &pool).await?;
 
-    let sessions = database::sessions::get_sessions(&pool, user.id, id).await?;
+    // Check if user has access to the campaign
+    let access = database::campaigns::get_campaign_access(&pool, id, user.id)
+        .await?
+        .ok_or(ServerError::NotFound)?;
+    access.require(CampaignRole::Player)?;
+
+    let mut sessions = database::sessions::get_sessions(&pool, access.owner, id).await?;
+    for session in &mut sessions {
+        access.redact_rewards(&mut session.compiled_rewards);
+    }
     Ok(Json(sessions))
 }
 
@@ -237,12 +503,10 @@ async fn insert_sessions(
     let user = extract_user_from_cookies(&jar, &pool).await?;
 
     // Check if user has access to the campaign
-    if database::campaigns::get_owned_campaign_id(&pool, id, user.id)
+    let access = database::campaigns::get_campaign_access(&pool, id, user.id)
         .await?
-        .is_none()
-    {
-        return Err(ServerError::NotFound);
-    }
+        .ok_or(ServerError::NotFound)?;
+    access.require(CampaignRole::CoGm)?;
 
     let mut tx = pool.begin().await?;
     database::sessions::insert_sessions(&mut tx, id, &session).await?;
@@ -255,13 +519,20 @@ async fn insert_sessions(
 async fn edit_sessions(
     State(pool): State<PgPool>,
     jar: CookieJar,
+    Path(id): Path<InternalId>,
     Json(session): Json<HashMap<InternalId, ModifySession>>,
 ) -> Result<impl IntoResponse, ServerError> {
     let user = extract_user_from_cookies(&jar, &pool).await?;
 
-    // Check if user has access to the session
+    // Check if user has access to the campaign
+    let access = database::campaigns::get_campaign_access(&pool, id, user.id)
+        .await?
+        .ok_or(ServerError::NotFound)?;
+    access.require(CampaignRole::CoGm)?;
+
+    // Check if the sessions are part of the campaign
     let session_ids = session.keys().cloned().collect::<Vec<_>>();
-    if database::sessions::get_owned_session_ids(&pool, &session_ids, user.id)
+    if database::sessions::get_owned_session_ids(&pool, &session_ids, access.owner)
         .await?
         .is_empty()
     {
@@ -277,12 +548,18 @@ async fn edit_sessions(
 async fn delete_session(
     State(pool): State<PgPool>,
     jar: CookieJar,
-    Path((_, session_id)): Path<(InternalId, InternalId)>,
+    Path((id, session_id)): Path<(InternalId, InternalId)>,
 ) -> Result<impl IntoResponse, ServerError> {
     let user = extract_user_from_cookies(&jar, &pool).await?;
 
-    // Check if user has access to the session
-    if database::sessions::get_owned_session_ids(&pool, &[session_id], user.id)
+    // Check if user has access to the campaign
+    let access = database::campaigns::get_campaign_access(&pool, id, user.id)
+        .await?
+        .ok_or(ServerError::NotFound)?;
+    access.require(CampaignRole::CoGm)?;
+
+    // Check if the session is part of the campaign
+    if database::sessions::get_owned_session_ids(&pool, &[session_id], access.owner)
         .await?
         .is_empty()
     {
@@ -298,13 +575,26 @@ async fn delete_session(
 async fn link_sessions_encounters(
     State(pool): State<PgPool>,
     jar: CookieJar,
-    Path((_campaign_id, session_id)): Path<(InternalId, InternalId)>,
+    Path((id, session_id)): Path<(InternalId, InternalId)>,
     Json(link): Json<LinkEncounterSession>,
 ) -> Result<impl IntoResponse, ServerError> {
     let user = extract_user_from_cookies(&jar, &pool).await?;
 
-    // Check if user has access to the session
-    if database::sessions::get_owned_session_ids(&pool, &[session_id], user.id)
+    // Check if user has access to the campaign
+    let access = database::campaigns::get_campaign_access(&pool, id, user.id)
+        .await?
+        .ok_or(ServerError::NotFound)?;
+    access.require(CampaignRole::CoGm)?;
+
+    // Check if the session is part of the campaign
+    if database::sessions::get_owned_session_ids(&pool, &[session_id], access.owner)
+        .await?
+        .is_empty()
+    {
+        return Err(ServerError::NotFound);
+    }
+
+    if database::encounters::get_editable_encounter_ids(&pool, &[link.encounter_id], user.id)
         .await?
         .is_empty()
     {
@@ -324,13 +614,19 @@ async fn link_sessions_encounters(
 async fn update_link_session_encounters(
     State(pool): State<PgPool>,
     jar: CookieJar,
-    Path((_campaign_id, session_id)): Path<(InternalId, InternalId)>,
+    Path((id, session_id)): Path<(InternalId, InternalId)>,
     Json(session): Json<UpdateCharacterSessions>,
 ) -> Result<impl IntoResponse, ServerError> {
     let user = extract_user_from_cookies(&jar, &pool).await?;
 
-    // Check if user has access to the session
-    if database::sessions::get_owned_session_ids(&pool, &[session_id], user.id)
+    // Check if user has access to the campaign
+    let access = database::campaigns::get_campaign_access(&pool, id, user.id)
+        .await?
+        .ok_or(ServerError::NotFound)?;
+    access.require(CampaignRole::CoGm)?;
+
+    // Check if the session is part of the campaign
+    if database::sessions::get_owned_session_ids(&pool, &[session_id], access.owner)
         .await?
         .is_empty()
     {
@@ -349,12 +645,18 @@ async fn update_link_session_encounters(
 async fn unlink_session_encounters(
     State(pool): State<PgPool>,
     jar: CookieJar,
-    Path((_campaign_id, session_id, encounter_id)): Path<(InternalId, InternalId, InternalId)>,
+    Path((id, session_id, encounter_id)): Path<(InternalId, InternalId, InternalId)>,
 ) -> Result<impl IntoResponse, ServerError> {
     let user = extract_user_from_cookies(&jar, &pool).await?;
 
-    // Check if user has access to the session
-    if database::sessions::get_owned_session_ids(&pool, &[session_id], user.id)
+    // Check if user has access to the campaign
+    let access = database::campaigns::get_campaign_access(&pool, id, user.id)
+        .await?
+        .ok_or(ServerError::NotFound)?;
+    access.require(CampaignRole::CoGm)?;
+
+    // Check if the session is part of the campaign
+    if database::sessions::get_owned_session_ids(&pool, &[session_id], access.owner)
         .await?
         .is_empty()
     {
@@ -371,9 +673,13 @@ async fn unlink_session_encounters(
 async fn import_campaign(
     State(pool): State<PgPool>,
     jar: CookieJar,
-    Json(campaign): Json<ImportCampaign>,
+    Json(campaign): Json<serde_json::Value>,
 ) -> Result<impl IntoResponse, ServerError> {
     let user = extract_user_from_cookies(&jar, &pool).await?;
+
+    // Exports of older versions are migrated first
+    let campaign = ImportCampaign::from_json(campaign)?;
+
     let mut tx = pool.begin().await?;
 
     let campaign_id = database::import::import_with_functions(campaign, &mut tx, user.id).await?;
@@ -394,34 +700,88 @@ async fn export_campaign(
 ) -> Result<impl IntoResponse, ServerError> {
     let user = extract_user_from_cookies(&jar, &pool).await?;
 
-    // Ensure owned
-    if database::campaigns::get_owned_campaign_id(&pool, id, user.id)
+    // Ensure user can view the whole campaign
+    let access = database::campaigns::get_campaign_access(&pool, id, user.id)
         .await?
-        .is_none()
-    {
-        return Err(ServerError::NotFound);
-    }
+        .ok_or(ServerError::NotFound)?;
+    access.require(CampaignRole::CoGm)?;
 
-    let campaign = database::import::export(id, &pool, user.id).await?;
+    let campaign = database::import::export(id, &pool, access.owner).await?;
 
     Ok(Json(campaign))
 }
 
-async fn get_stats(
+async fn remaster_campaign(
     State(pool): State<PgPool>,
     jar: CookieJar,
     Path(id): Path<InternalId>,
+    Json(remaster): Json<RemasterCampaign>,
 ) -> Result<impl IntoResponse, ServerError> {
     let user = extract_user_from_cookies(&jar, &pool).await?;
 
-    // Ensure owned
-    if database::campaigns::get_owned_campaign_id(&pool, id, user.id)
+    // Ensure user can edit the campaign
+    let access = database::campaigns::get_campaign_access(&pool, id, user.id)
         .await?
-        .is_none()
-    {
-        return Err(ServerError::NotFound);
+        .ok_or(ServerError::NotFound)?;
+    access.require(CampaignRole::CoGm)?;
+
+    let mut tx = pool.begin().await?;
+    let report =
+        database::remaster::remaster_campaign(&mut tx, id, user.id, remaster.dry_run).await?;
+    if !remaster.dry_run {
+        tx.commit().await?;
+    }
+    Ok(Json(report))
+}
+
+async fn generate_treasure(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    Path(id): Path<InternalId>,
+    Json(generate): Json<GenerateTreasure>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+
+    // Ensure user can edit the campaign
+    let access = database::campaigns::get_campaign_access(&pool, id, user.id)
+        .await?
+        .ok_or(ServerError::NotFound)?;
+    access.require(CampaignRole::CoGm)?;
+
+    let mut parcel =
+        database::treasure::generate_treasure_parcel(&pool, access.owner, id, &generate).await?;
+
+    if let Some(encounter_id) = generate.encounter_id {
+        if database::encounters::get_editable_encounter_ids(&pool, &[encounter_id], user.id)
+            .await?
+            .is_empty()
+        {
+            return Err(ServerError::NotFound);
+        }
+
+        let mut tx = pool.begin().await?;
+        database::treasure::attach_treasure_parcel(&mut tx, user.id, id, encounter_id, &parcel)
+            .await?;
+        tx.commit().await?;
+        parcel.encounter_id = Some(encounter_id);
     }
 
-    let stats = database::stats::get_campaign_stats(&pool, user.id, id).await?;
+    Ok(Json(parcel))
+}
+
+async fn get_stats(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    Path(id): Path<InternalId>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+
+    // Ensure user can view the whole campaign
+    let access = database::campaigns::get_campaign_access(&pool, id, user.id)
+        .await?
+        .ok_or(ServerError::NotFound)?;
+    access.require(CampaignRole::CoGm)?;
+
+    let stats = database::stats::get_campaign_stats(&pool, access.owner, id).await?;
     Ok(Json(stats))
 }
@@ -1,4 +1,4 @@
-use crate::models::auth::User;
+use crate::models::auth::{ApiToken, ApiTokenScope, User};
 use crate::models::ids::InternalId;
 
 #[derive(serde::Deserialize)]
@@ -11,6 +11,7 @@ pub struct InsertUser {
 pub struct DatabaseUser {
     pub id: i32,
     pub username: String,
+    pub email: Option<String>,
     pub is_admin: bool,
     pub password_hash: String,
 }
@@ -24,6 +25,7 @@ pub async fn get_user_by_name(
         SELECT 
             u.id,
             u.username,
+            u.email,
             u.password_hash,
             u.is_admin
         FROM users u
@@ -36,24 +38,42 @@ pub async fn get_user_by_name(
     let user = query.fetch_optional(exec).await?.map(|row| DatabaseUser {
         id: row.id,
         username: row.username,
+        email: row.email,
         is_admin: row.is_admin.unwrap_or(false),
         password_hash: row.password_hash,
     });
     Ok(user)
 }
 
+pub async fn is_email_taken(
+    exec: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    email: &str,
+) -> crate::Result<bool> {
+    let row = sqlx::query!(
+        r#"
+        SELECT EXISTS(SELECT 1 FROM users WHERE email = $1) AS "taken!"
+        "#,
+        email,
+    )
+    .fetch_one(exec)
+    .await?;
+    Ok(row.taken)
+}
+
 pub async fn insert_user(
     tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
     username: &str,
+    email: Option<&str>,
     password_hash: &str,
 ) -> crate::Result<InternalId> {
     let id = sqlx::query!(
         r#"
-        INSERT INTO users (username, password_hash)
-        VALUES ($1, $2)
+        INSERT INTO users (username, email, password_hash)
+        VALUES ($1, $2, $3)
         RETURNING id
         "#,
         username,
+        email,
         password_hash
     )
     .fetch_one(&mut **tx)
@@ -62,10 +82,57 @@ pub async fn insert_user(
     Ok(InternalId(id.id as u32))
 }
 
+pub async fn get_password_hash(
+    exec: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    user_id: InternalId,
+) -> crate::Result<String> {
+    let row = sqlx::query!(
+        r#"
+        SELECT password_hash
+        FROM users
+        WHERE id = $1
+        "#,
+        user_id.0 as i32,
+    )
+    .fetch_one(exec)
+    .await?;
+    Ok(row.password_hash)
+}
+
+pub async fn update_password(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    user_id: InternalId,
+    password_hash: &str,
+) -> crate::Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET password_hash = $2
+        WHERE id = $1
+        "#,
+        user_id.0 as i32,
+        password_hash,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
 pub async fn delete_user(
     tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
     user_id: InternalId,
 ) -> crate::Result<()> {
+    sqlx::query!(
+        r#"
+        DELETE FROM campaign_members
+        WHERE user_id = $1
+        "#,
+        user_id.0 as i32,
+    )
+    .execute(&mut **tx)
+    .await?;
+
     sqlx::query!(
         r#"
         DELETE FROM users
@@ -141,3 +208,201 @@ pub async fn delete_session(
 
     Ok(())
 }
+
+pub async fn insert_api_token(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    user_id: InternalId,
+    name: &str,
+    token: &str,
+    scope: ApiTokenScope,
+) -> crate::Result<ApiToken> {
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO api_tokens (user_id, name, token_hash, token_prefix, scope)
+        VALUES ($1, $2, encode(sha256($3::bytea), 'hex'), $4, $5)
+        RETURNING id, created_at
+        "#,
+        user_id.0 as i32,
+        name,
+        token.as_bytes(),
+        &token[..token.len().min(8)],
+        scope.as_i16(),
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+
+    Ok(ApiToken {
+        id: InternalId(row.id as u32),
+        name: name.to_string(),
+        scope,
+        token_prefix: token[..token.len().min(8)].to_string(),
+        created_at: row.created_at,
+        last_used_at: None,
+    })
+}
+
+pub async fn get_api_tokens(
+    exec: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    user_id: InternalId,
+) -> crate::Result<Vec<ApiToken>> {
+    let tokens = sqlx::query!(
+        r#"
+        SELECT id, name, scope, token_prefix, created_at, last_used_at
+        FROM api_tokens
+        WHERE user_id = $1
+        ORDER BY created_at
+        "#,
+        user_id.0 as i32,
+    )
+    .fetch_all(exec)
+    .await?
+    .into_iter()
+    .map(|row| ApiToken {
+        id: InternalId(row.id as u32),
+        name: row.name,
+        scope: ApiTokenScope::from_i16(row.scope),
+        token_prefix: row.token_prefix,
+        created_at: row.created_at,
+        last_used_at: row.last_used_at,
+    })
+    .collect();
+    Ok(tokens)
+}
+
+pub async fn delete_api_token(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    user_id: InternalId,
+    token_id: InternalId,
+) -> crate::Result<()> {
+    let deleted = sqlx::query!(
+        r#"
+        DELETE FROM api_tokens
+        WHERE id = $1 AND user_id = $2
+        "#,
+        token_id.0 as i32,
+        user_id.0 as i32,
+    )
+    .execute(&mut **tx)
+    .await?
+    .rows_affected();
+
+    if deleted == 0 {
+        return Err(crate::ServerError::NotFound);
+    }
+    Ok(())
+}
+
+// Looks up the user of an API token, marking the token as used
+pub async fn get_user_for_api_token(
+    exec: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    token: &str,
+) -> crate::Result<Option<(User, ApiTokenScope)>> {
+    let query = sqlx::query!(
+        r#"
+        WITH t AS (
+            UPDATE api_tokens
+            SET last_used_at = NOW()
+            WHERE token_hash = encode(sha256($1::bytea), 'hex')
+            RETURNING user_id, scope
+        )
+        SELECT
+            u.id,
+            u.username,
+            u.is_admin,
+            t.scope
+        FROM t
+        JOIN users u ON u.id = t.user_id
+    "#,
+        token.as_bytes(),
+    );
+
+    let user = query.fetch_optional(exec).await?.map(|row| {
+        (
+            User {
+                id: InternalId(row.id as u32),
+                username: row.username,
+                is_admin: row.is_admin.unwrap_or(false),
+            },
+            ApiTokenScope::from_i16(row.scope),
+        )
+    });
+    Ok(user)
+}
+
+// Removes all sessions of a user, logging them out everywhere
+pub async fn delete_user_sessions(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    user_id: InternalId,
+) -> crate::Result<()> {
+    sqlx::query!(
+        r#"
+        DELETE FROM sessions
+        WHERE user_id = $1
+        "#,
+        user_id.0 as i32,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+// Revokes all API tokens of a user
+pub async fn delete_user_api_tokens(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    user_id: InternalId,
+) -> crate::Result<()> {
+    sqlx::query!(
+        r#"
+        DELETE FROM api_tokens
+        WHERE user_id = $1
+        "#,
+        user_id.0 as i32,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn insert_password_reset_token(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    user_id: InternalId,
+    token: &str,
+) -> crate::Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO password_reset_tokens (user_id, token_hash)
+        VALUES ($1, encode(sha256($2::bytea), 'hex'))
+        "#,
+        user_id.0 as i32,
+        token.as_bytes(),
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+// Marks a reset token as used, returning its user if it was valid (unused and not expired)
+pub async fn use_password_reset_token(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    token: &str,
+) -> crate::Result<Option<InternalId>> {
+    let user_id = sqlx::query!(
+        r#"
+        UPDATE password_reset_tokens
+        SET used_at = NOW()
+        WHERE token_hash = encode(sha256($1::bytea), 'hex')
+            AND used_at IS NULL
+            AND expires_at > NOW()
+        RETURNING user_id
+        "#,
+        token.as_bytes(),
+    )
+    .fetch_optional(&mut **tx)
+    .await?
+    .map(|row| InternalId(row.user_id as u32));
+
+    Ok(user_id)
+}
@@ -1,9 +1,14 @@
-use crate::models::campaign::CampaignPartial;
+use std::collections::HashMap;
+
+use crate::models::campaign::{
+    CampaignMember, CampaignPartial, CampaignRole, CampaignSessionCharacterRewards, SharedCampaign,
+};
 use crate::models::encounter::EncounterType;
 use crate::models::ids::InternalId;
 use crate::ServerError;
 
-use super::encounters::{self, InsertEncounter};
+use super::characters::CharacterFilters;
+use super::encounters::{self, EncounterFilters, InsertEncounter};
 
 #[derive(serde::Deserialize, Debug)]
 pub struct InsertCampaign {
@@ -32,9 +37,65 @@ pub struct InsertCampaignInitializationCharacter {
 pub struct ModifyCampaign {
     pub name: Option<String>,
     pub description: Option<String>,
+    pub absent_characters_earn_experience: Option<bool>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct InsertCampaignMember {
+    pub username: String,
+    pub role: CampaignRole,
+    pub character_id: Option<InternalId>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ModifyCampaignMember {
+    pub role: Option<CampaignRole>,
+    // Missing leaves the character as is, null unlinks it
+    #[serde(default, with = "::serde_with::rust::double_option")]
+    pub character_id: Option<Option<InternalId>>,
 }
 
 // TODO: May be prudent to make a separate models system for the database.
+// Campaigns owned by the user, as well as those shared with them.
+pub async fn get_campaigns_for_user(
+    exec: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    user_id: InternalId,
+) -> crate::Result<Vec<CampaignPartial>> {
+    let campaigns = sqlx::query!(
+        r#"
+        SELECT 
+            ca.id,
+            ca.name,
+            description,
+            total_experience,
+            level,
+            absent_characters_earn_experience,
+            CASE WHEN ca.owner = $1 THEN $2::smallint ELSE cm.role END AS "role!"
+        FROM campaigns ca
+        LEFT JOIN campaign_members cm ON cm.campaign_id = ca.id AND cm.user_id = $1
+        WHERE 
+            ca.owner = $1
+            OR cm.user_id IS NOT NULL
+    "#,
+        user_id.0 as i32,
+        CampaignRole::Owner.as_i16(),
+    )
+    .fetch_all(exec)
+    .await?
+    .into_iter()
+    .map(|row| CampaignPartial {
+        id: InternalId(row.id as u32),
+        name: row.name,
+        description: row.description,
+        total_experience: row.total_experience as u64,
+        level: row.level as u8,
+        absent_characters_earn_experience: row.absent_characters_earn_experience,
+        role: CampaignRole::from_i16(row.role),
+    })
+    .collect();
+    Ok(campaigns)
+}
+
 pub async fn get_campaigns_owner(
     exec: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
     owner: InternalId,
@@ -46,7 +107,8 @@ pub async fn get_campaigns_owner(
             ca.name,
             description,
             total_experience,
-            level
+            level,
+            absent_characters_earn_experience
         FROM campaigns ca
         WHERE 
             ca.owner = $1
@@ -65,34 +127,314 @@ pub async fn get_campaigns_owner(
                 description: row.description,
                 total_experience: row.total_experience as u64,
                 level: row.level as u8,
+                absent_characters_earn_experience: row.absent_characters_earn_experience,
+                role: CampaignRole::Owner,
             })
         })
         .collect::<Result<Vec<CampaignPartial>, sqlx::Error>>()?;
     Ok(campaigns)
 }
 
-pub async fn get_owned_campaign_id(
+/// A user's access to a campaign, either as its owner or as an invited member.
+#[derive(Debug, Clone, Copy)]
+pub struct CampaignAccess {
+    pub campaign_id: InternalId,
+    // Owner of the campaign, under which its contents are stored
+    pub owner: InternalId,
+    pub role: CampaignRole,
+    // Character played by the user, if they are a player
+    pub character_id: Option<InternalId>,
+}
+
+impl CampaignAccess {
+    pub fn require(&self, role: CampaignRole) -> crate::Result<()> {
+        if self.role < role {
+            return Err(ServerError::Unauthorized);
+        }
+        Ok(())
+    }
+
+    // Players (and share links) only see their own character's rewards
+    pub fn redact_rewards(
+        &self,
+        compiled_rewards: &mut HashMap<InternalId, CampaignSessionCharacterRewards>,
+    ) {
+        if self.role == CampaignRole::Player {
+            compiled_rewards.retain(|character_id, _| Some(*character_id) == self.character_id);
+        }
+    }
+}
+
+pub async fn get_campaign_access(
     exec: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
     campaign_id: InternalId,
-    owner: InternalId,
-) -> crate::Result<Option<InternalId>> {
-    let query = sqlx::query!(
+    user_id: InternalId,
+) -> crate::Result<Option<CampaignAccess>> {
+    let access = sqlx::query!(
         r#"
         SELECT 
-            ca.id AS "id!"
+            ca.id,
+            ca.owner,
+            CASE WHEN ca.owner = $2 THEN $3::smallint ELSE cm.role END AS "role!",
+            cm.character_id AS "character_id?"
         FROM campaigns ca
+        LEFT JOIN campaign_members cm ON cm.campaign_id = ca.id AND cm.user_id = $2
         WHERE 
             ca.id = $1
-            AND ca.owner = $2
+            AND (ca.owner = $2 OR cm.user_id IS NOT NULL)
     "#,
         campaign_id.0 as i32,
-        owner.0 as i32,
+        user_id.0 as i32,
+        CampaignRole::Owner.as_i16(),
     )
     .fetch_optional(exec)
     .await?
-    .map(|row| InternalId(row.id as u32));
+    .map(|row| CampaignAccess {
+        campaign_id: InternalId(row.id as u32),
+        owner: InternalId(row.owner as u32),
+        role: CampaignRole::from_i16(row.role),
+        character_id: row.character_id.map(|id| InternalId(id as u32)),
+    });
 
-    Ok(query)
+    Ok(access)
+}
+
+// Share links give the same access as a player without a character
+pub async fn get_campaign_access_for_share_token(
+    exec: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    token: &str,
+) -> crate::Result<Option<CampaignAccess>> {
+    let access = sqlx::query!(
+        r#"
+        SELECT 
+            ca.id,
+            ca.owner
+        FROM campaigns ca
+        WHERE ca.share_token_hash = encode(sha256($1::bytea), 'hex')
+    "#,
+        token.as_bytes(),
+    )
+    .fetch_optional(exec)
+    .await?
+    .map(|row| CampaignAccess {
+        campaign_id: InternalId(row.id as u32),
+        owner: InternalId(row.owner as u32),
+        role: CampaignRole::Player,
+        character_id: None,
+    });
+
+    Ok(access)
+}
+
+// Replaces the campaign's share link, or revokes it if no token is given
+pub async fn set_campaign_share_token(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    campaign_id: InternalId,
+    token: Option<&str>,
+) -> crate::Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE campaigns
+        SET share_token_hash = encode(sha256($1::bytea), 'hex')
+        WHERE id = $2
+        "#,
+        token.map(|t| t.as_bytes()),
+        campaign_id.0 as i32,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+// Player-facing view of the campaign, built from its sessions and linked encounters
+pub async fn get_shared_campaign(
+    exec: impl sqlx::Executor<'_, Database = sqlx::Postgres> + Copy,
+    access: &CampaignAccess,
+) -> crate::Result<SharedCampaign> {
+    let campaign = get_campaigns_owner(exec, access.owner)
+        .await?
+        .into_iter()
+        .find(|c| c.id == access.campaign_id)
+        .ok_or(ServerError::NotFound)?;
+    let party = super::characters::get_characters(
+        exec,
+        access.owner,
+        access.campaign_id,
+        &CharacterFilters::default(),
+    )
+    .await?;
+    let sessions = super::sessions::get_sessions(exec, access.owner, access.campaign_id).await?;
+    let encounters = encounters::get_encounters(
+        exec,
+        access.owner,
+        &EncounterFilters {
+            campaign_id: Some(access.campaign_id),
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    let mut campaign = SharedCampaign::new(campaign, party, sessions, encounters);
+    for session in &mut campaign.sessions {
+        access.redact_rewards(&mut session.compiled_rewards);
+    }
+    Ok(campaign)
+}
+
+pub async fn get_campaign_members(
+    exec: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    campaign_id: InternalId,
+) -> crate::Result<Vec<CampaignMember>> {
+    let members = sqlx::query!(
+        r#"
+        SELECT 
+            cm.user_id,
+            u.username,
+            cm.role,
+            cm.character_id,
+            cm.created_at
+        FROM campaign_members cm
+        INNER JOIN users u ON cm.user_id = u.id
+        WHERE cm.campaign_id = $1
+        ORDER BY cm.created_at, cm.user_id
+    "#,
+        campaign_id.0 as i32,
+    )
+    .fetch_all(exec)
+    .await?
+    .into_iter()
+    .map(|row| CampaignMember {
+        user_id: InternalId(row.user_id as u32),
+        username: row.username,
+        role: CampaignRole::from_i16(row.role),
+        character_id: row.character_id.map(|id| InternalId(id as u32)),
+        created_at: row.created_at,
+    })
+    .collect();
+
+    Ok(members)
+}
+
+// Characters assigned to members must be part of the campaign
+async fn check_member_character(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    campaign_id: InternalId,
+    character_id: Option<InternalId>,
+) -> crate::Result<()> {
+    let Some(character_id) = character_id else {
+        return Ok(());
+    };
+    let in_campaign = sqlx::query!(
+        r#"
+        SELECT id FROM characters
+        WHERE id = $1 AND campaign = $2
+        "#,
+        character_id.0 as i32,
+        campaign_id.0 as i32,
+    )
+    .fetch_optional(&mut **tx)
+    .await?
+    .is_some();
+    if !in_campaign {
+        return Err(ServerError::BadRequest(
+            "Character must be part of the campaign".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+pub async fn insert_campaign_member(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    access: &CampaignAccess,
+    insert: &InsertCampaignMember,
+) -> crate::Result<()> {
+    if insert.role == CampaignRole::Owner {
+        return Err(ServerError::BadRequest(
+            "A campaign can only have one owner".to_string(),
+        ));
+    }
+    check_member_character(tx, access.campaign_id, insert.character_id).await?;
+
+    let user = super::auth::get_user_by_name(&mut **tx, &insert.username)
+        .await?
+        .ok_or(ServerError::NotFound)?;
+    if user.id == access.owner.0 as i32 {
+        return Err(ServerError::BadRequest(
+            "The owner cannot be invited to their own campaign".to_string(),
+        ));
+    }
+
+    sqlx::query!(
+        r#"
+        INSERT INTO campaign_members (campaign_id, user_id, role, character_id)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (campaign_id, user_id) DO UPDATE
+        SET role = EXCLUDED.role, character_id = EXCLUDED.character_id
+        "#,
+        access.campaign_id.0 as i32,
+        user.id,
+        insert.role.as_i16(),
+        insert.character_id.map(|id| id.0 as i32),
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn edit_campaign_member(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    campaign_id: InternalId,
+    user_id: InternalId,
+    modify: &ModifyCampaignMember,
+) -> crate::Result<()> {
+    if modify.role == Some(CampaignRole::Owner) {
+        return Err(ServerError::BadRequest(
+            "A campaign can only have one owner".to_string(),
+        ));
+    }
+    check_member_character(tx, campaign_id, modify.character_id.flatten()).await?;
+
+    let updated = sqlx::query!(
+        r#"
+        UPDATE campaign_members
+        SET role = COALESCE($1, role),
+            character_id = CASE WHEN $2 THEN $3 ELSE character_id END
+        WHERE campaign_id = $4 AND user_id = $5
+        "#,
+        modify.role.map(|role| role.as_i16()),
+        modify.character_id.is_some(),
+        modify.character_id.flatten().map(|id| id.0 as i32),
+        campaign_id.0 as i32,
+        user_id.0 as i32,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    if updated.rows_affected() == 0 {
+        return Err(ServerError::NotFound);
+    }
+    Ok(())
+}
+
+pub async fn delete_campaign_member(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    campaign_id: InternalId,
+    user_id: InternalId,
+) -> crate::Result<()> {
+    sqlx::query!(
+        r#"
+        DELETE FROM campaign_members
+        WHERE campaign_id = $1 AND user_id = $2
+        "#,
+        campaign_id.0 as i32,
+        user_id.0 as i32,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
 }
 
 pub async fn insert_campaign(
@@ -150,6 +492,7 @@ pub async fn insert_campaign(
             let insert_encounter = InsertEncounter {
                 name: "Initialization encounter".to_string(),
                 session_id: Some(InternalId(session_id as u32)),
+                campaign_id: None,
                 description: Some("An initialization encounter, including all the characters and items at this point in the campaign.".to_string()),
                 extra_experience: experience,
                 treasure_currency: gold,
@@ -226,6 +569,28 @@ pub async fn delete_campaign(
     .execute(&mut **tx)
     .await?;
 
+    // Homebrew shared to the campaign stays with its owner
+    sqlx::query!(
+        r#"
+        UPDATE library_objects
+        SET campaign_id = NULL
+        WHERE campaign_id = $1
+        "#,
+        campaign_id.0 as i32,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM campaign_members
+        WHERE campaign_id = $1
+        "#,
+        campaign_id.0 as i32,
+    )
+    .execute(&mut **tx)
+    .await?;
+
     // Delete characters
     sqlx::query!(
         r#"
@@ -264,12 +629,14 @@ pub async fn edit_campaign(
         r#"
         UPDATE campaigns
         SET name = COALESCE($1, name),
-            description = COALESCE($2, description)
+            description = COALESCE($2, description),
+            absent_characters_earn_experience = COALESCE($4, absent_characters_earn_experience)
         WHERE id = $3
         "#,
         modify.name.as_ref(),
         modify.description.as_ref(),
         campaign_id.0 as i32,
+        modify.absent_characters_earn_experience,
     );
 
     query.execute(&mut **tx).await?;
@@ -1,4 +1,6 @@
-use crate::models::characters::Character;
+use std::collections::HashMap;
+
+use crate::models::characters::{AbilityModifiers, Character, CharacterProficiencies};
 use crate::models::ids::InternalId;
 
 use serde::{Deserialize, Serialize};
@@ -13,6 +15,15 @@ pub struct InsertCharacter {
     pub name: String,
     pub player: Option<String>,
     pub class: InternalId,
+    pub ancestry: Option<String>,
+    pub heritage: Option<String>,
+    pub background: Option<String>,
+    // Otherwise, from the character's experience
+    pub level: Option<u8>,
+    #[serde(default)]
+    pub ability_modifiers: AbilityModifiers,
+    #[serde(default)]
+    pub proficiencies: CharacterProficiencies,
 }
 
 #[derive(serde::Deserialize)]
@@ -20,6 +31,14 @@ pub struct ModifyCharacter {
     pub name: Option<String>,
     pub player: Option<String>,
     pub class: Option<InternalId>,
+    pub ancestry: Option<String>,
+    pub heritage: Option<String>,
+    pub background: Option<String>,
+    // Missing leaves the level as is, null tracks it from the character's experience again
+    #[serde(default, with = "::serde_with::rust::double_option")]
+    pub level: Option<Option<u8>>,
+    pub ability_modifiers: Option<AbilityModifiers>,
+    pub proficiencies: Option<CharacterProficiencies>,
 }
 
 // TODO: May be prudent to make a separate models system for the database.
@@ -39,9 +58,17 @@ pub async fn get_characters(
             ch.id,
             ch.name,
             ch.player,
-            ch.class
+            ch.class,
+            ch.ancestry,
+            ch.heritage,
+            ch.background,
+            ch.ability_modifiers,
+            ch.proficiencies,
+            cx.total_experience,
+            cx.level
         FROM characters ch
         LEFT JOIN campaigns ca ON ch.campaign = ca.id
+        LEFT JOIN character_experience cx ON cx.character_id = ch.id
         WHERE 
             ($1::text IS NULL OR ch.name ILIKE '%' || $1 || '%')
             AND ($2::int IS NULL OR ca.id = $2)
@@ -62,6 +89,14 @@ pub async fn get_characters(
                 name: row.name,
                 player: row.player,
                 class: InternalId(row.class as u32),
+                ancestry: row.ancestry,
+                heritage: row.heritage,
+                background: row.background,
+                experience: row.total_experience.unwrap_or_default() as u64,
+                level: row.level.unwrap_or(1) as u8,
+                ability_modifiers: serde_json::from_value(row.ability_modifiers)
+                    .unwrap_or_default(),
+                proficiencies: serde_json::from_value(row.proficiencies).unwrap_or_default(),
             })
         })
         .collect::<Result<Vec<Character>, sqlx::Error>>()?;
@@ -79,9 +114,17 @@ pub async fn get_chracter_id(
             ch.id,
             ch.name,
             ch.player,
-            ch.class
+            ch.class,
+            ch.ancestry,
+            ch.heritage,
+            ch.background,
+            ch.ability_modifiers,
+            ch.proficiencies,
+            cx.total_experience,
+            cx.level
         FROM characters ch
         LEFT JOIN campaigns ca ON ch.campaign = ca.id
+        LEFT JOIN character_experience cx ON cx.character_id = ch.id
         WHERE 
             ch.id = $1
             AND ca.owner = $2
@@ -95,10 +138,38 @@ pub async fn get_chracter_id(
         name: row.name,
         player: row.player,
         class: InternalId(row.class as u32),
+        ancestry: row.ancestry,
+        heritage: row.heritage,
+        background: row.background,
+        experience: row.total_experience.unwrap_or_default() as u64,
+        level: row.level.unwrap_or(1) as u8,
+        ability_modifiers: serde_json::from_value(row.ability_modifiers).unwrap_or_default(),
+        proficiencies: serde_json::from_value(row.proficiencies).unwrap_or_default(),
     });
     Ok(character)
 }
 
+/// Levels set on the characters of a campaign, rather than tracked from their experience.
+pub async fn get_pinned_levels(
+    exec: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    campaign_id: InternalId,
+) -> crate::Result<HashMap<InternalId, u8>> {
+    let levels = sqlx::query!(
+        r#"
+        SELECT id, level AS "level!"
+        FROM characters
+        WHERE campaign = $1 AND level IS NOT NULL
+        "#,
+        campaign_id.0 as i32,
+    )
+    .fetch_all(exec)
+    .await?
+    .into_iter()
+    .map(|row| (InternalId(row.id as u32), row.level as u8))
+    .collect();
+    Ok(levels)
+}
+
 pub async fn edit_character(
     tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
     character_id: InternalId,
@@ -109,13 +180,34 @@ pub async fn edit_character(
         UPDATE characters
         SET name = COALESCE($1, name),
             player = COALESCE($2, player),
-            class = COALESCE($3, class)
+            class = COALESCE($3, class),
+            ancestry = COALESCE($5, ancestry),
+            heritage = COALESCE($6, heritage),
+            background = COALESCE($7, background),
+            level = CASE WHEN $8 THEN $9 ELSE level END,
+            ability_modifiers = COALESCE($10, ability_modifiers),
+            proficiencies = COALESCE($11, proficiencies)
         WHERE id = $4
         "#,
         character.name,
         character.player,
         character.class.as_ref().map(|c| c.0 as i32),
         character_id.0 as i32,
+        character.ancestry,
+        character.heritage,
+        character.background,
+        character.level.is_some(),
+        character.level.flatten().map(|l| l as i16),
+        character
+            .ability_modifiers
+            .as_ref()
+            .map(serde_json::to_value)
+            .transpose()?,
+        character
+            .proficiencies
+            .as_ref()
+            .map(serde_json::to_value)
+            .transpose()?,
     );
 
     query.execute(&mut **tx).await?;
@@ -147,8 +239,8 @@ pub async fn insert_characters(
 
     let ids = sqlx::query!(
         r#"
-        INSERT INTO characters (name, player, campaign, class)
-        SELECT * FROM UNNEST ($1::varchar[], $2::varchar[], $3::int[], $4::int[])
+        INSERT INTO characters (name, player, campaign, class, ancestry, heritage, background, level, ability_modifiers, proficiencies)
+        SELECT * FROM UNNEST ($1::varchar[], $2::varchar[], $3::int[], $4::int[], $5::text[], $6::text[], $7::text[], $8::smallint[], $9::jsonb[], $10::jsonb[])
         RETURNING id
         "#,
         &names,
@@ -158,6 +250,30 @@ pub async fn insert_characters(
             .iter()
             .map(|c| c.class.0 as i32)
             .collect::<Vec<i32>>(),
+        &characters
+            .iter()
+            .map(|c| c.ancestry.clone())
+            .collect::<Vec<_>>() as _,
+        &characters
+            .iter()
+            .map(|c| c.heritage.clone())
+            .collect::<Vec<_>>() as _,
+        &characters
+            .iter()
+            .map(|c| c.background.clone())
+            .collect::<Vec<_>>() as _,
+        &characters
+            .iter()
+            .map(|c| c.level.map(|l| l as i16))
+            .collect::<Vec<_>>() as _,
+        &characters
+            .iter()
+            .map(|c| serde_json::to_value(c.ability_modifiers))
+            .collect::<Result<Vec<_>, _>>()?,
+        &characters
+            .iter()
+            .map(|c| serde_json::to_value(&c.proficiencies))
+            .collect::<Result<Vec<_>, _>>()?,
     )
     .fetch_all(&mut **tx)
     .await?
@@ -205,6 +321,28 @@ pub async fn delete_character(
     .execute(&mut **tx)
     .await?;
 
+    sqlx::query!(
+        r#"
+        UPDATE encounter_subsystem_attempts
+        SET character_id = NULL
+        WHERE character_id = $1
+        "#,
+        character_id.0 as i32,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE campaign_members
+        SET character_id = NULL
+        WHERE character_id = $1
+        "#,
+        character_id.0 as i32,
+    )
+    .execute(&mut **tx)
+    .await?;
+
     sqlx::query!(
         r#"
         DELETE FROM characters
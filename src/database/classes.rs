@@ -1,13 +1,17 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use crate::models::library::{classes::LibraryClass, GameSystem, Rarity};
+use crate::models::library::{classes::LibraryClass, GameSystem, LibraryObjectType, Rarity};
 
 use crate::models::ids::InternalId;
 use crate::ServerError;
 use serde::{Deserialize, Serialize};
 
 use super::sorts::{Sortable, SortableColumn};
-use super::{check_library_requested_ids, LegacyStatus, DEFAULT_MAX_LIMIT};
+use super::{
+    insert_library_objects, set_library_object_tags, InsertLibraryObject, LibraryUpsert,
+    LibraryUpsertReport,
+};
+use super::{LegacyStatus, DEFAULT_MAX_LIMIT};
 
 #[derive(Default, Debug, Serialize, Deserialize, Clone)]
 pub struct ClassFilters {
@@ -17,6 +21,8 @@ pub struct ClassFilters {
 
     pub limit: Option<u64>,
     pub page: Option<u64>,
+    // Continue after a previous page's `next_cursor`, instead of by page. `start` for the first page.
+    pub cursor: Option<String>,
     pub sort_by: Option<String>,
     pub order_by: Option<String>,
 }
@@ -37,6 +43,9 @@ pub struct ClassSearch {
 
     pub limit: Option<u64>,
     pub page: Option<u64>,
+    // Searches rank by similarity, so are only paged by offset. Set from a listing's cursor.
+    #[serde(skip)]
+    pub cursor: Option<String>,
     pub sort_by: Option<String>,
     pub order_by: Option<String>,
 }
@@ -52,6 +61,7 @@ impl From<ClassFilters> for ClassSearch {
             legacy: filters.legacy,
             limit: filters.limit,
             page: filters.page,
+            cursor: filters.cursor,
             sort_by: filters.sort_by,
             order_by: filters.order_by,
         }
@@ -66,12 +76,29 @@ impl Sortable for LibraryClass {
     fn default_sort() -> Option<&'static str> {
         Some("name")
     }
+
+    fn get_sort_value(&self, field: &str) -> serde_json::Value {
+        match field {
+            "name" => self.name.as_str().into(),
+            "hp" => self.hp.into(),
+            "rarity" => self.rarity.as_i64().into(),
+            _ => serde_json::Value::Null,
+        }
+    }
+
+    fn get_id(&self) -> InternalId {
+        self.id
+    }
 }
 
 // TODO: 'Filterable' is kind of a mess
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct InsertLibraryClass {
     pub requested_id: Option<InternalId>,
+    #[serde(default)]
+    pub source_id: Option<String>,
+    #[serde(skip)]
+    pub owner: Option<InternalId>,
     pub name: String,
     pub game_system: GameSystem,
     pub hp: u32,
@@ -85,6 +112,22 @@ pub struct InsertLibraryClass {
     pub remastering_alt_id: Option<InternalId>,
 }
 
+impl From<&InsertLibraryClass> for InsertLibraryObject {
+    fn from(object: &InsertLibraryClass) -> Self {
+        InsertLibraryObject {
+            requested_id: object.requested_id,
+            source_id: object.source_id.clone(),
+            owner: object.owner,
+            name: object.name.clone(),
+            game_system: object.game_system.clone(),
+            url: object.url.clone(),
+            description: object.description.clone(),
+            legacy: object.legacy,
+            remastering_alt_id: object.remastering_alt_id,
+        }
+    }
+}
+
 // TODO: May be prudent to make a separate models system for the database.
 pub async fn get_classes(
     exec: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
@@ -117,6 +160,11 @@ pub async fn get_classes_search(
         search.sort_by.as_deref(),
         search.order_by.as_deref(),
     )?;
+    // A cursor picks up after the last object of the previous page, in place of the offset
+    let (offset, after) = match &search.cursor {
+        Some(cursor) => (0, sort.parse_cursor(cursor)?),
+        None => (offset, None),
+    };
 
     let query = sqlx::query!(
         r#"
@@ -158,15 +206,39 @@ pub async fn get_classes_search(
                 AND NOT (NOT $6::bool AND lo.legacy = TRUE)
                 AND NOT ($7::bool AND lo.remastering_alt_id IS NOT NULL AND lo.legacy = TRUE)
                 AND NOT ($8::bool AND lo.remastering_alt_id IS NOT NULL AND lo.legacy = FALSE)
+                -- After the cursor's sort keys, then id. Descending keys swap sides, so that a single row comparison
+                -- covers any mix of directions. Keys past the sort's columns are equal on both sides.
+                AND ($13::jsonb IS NULL OR (
+                    COALESCE(CASE ($10::int[])[1] WHEN 1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($9::text[])[1] WHEN -1 THEN $13 -> 0 END, 'null'),
+                    COALESCE(CASE ($10::int[])[2] WHEN 1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($9::text[])[2] WHEN -1 THEN $13 -> 1 END, 'null'),
+                    COALESCE(CASE ($10::int[])[3] WHEN 1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($9::text[])[3] WHEN -1 THEN $13 -> 2 END, 'null'),
+                    lo.id
+                ) > (
+                    COALESCE(CASE ($10::int[])[1] WHEN 1 THEN $13 -> 0 WHEN -1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($9::text[])[1] END, 'null'),
+                    COALESCE(CASE ($10::int[])[2] WHEN 1 THEN $13 -> 1 WHEN -1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($9::text[])[2] END, 'null'),
+                    COALESCE(CASE ($10::int[])[3] WHEN 1 THEN $13 -> 2 WHEN -1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($9::text[])[3] END, 'null'),
+                    ($13 -> cardinality($9::text[]))::int
+                ))
             GROUP BY lo.id, lc.id 
+            -- Up to MAX_SORT_COLUMNS sort keys, then id to break ties
             ORDER BY similarity DESC, favor_exact_start_length,
-                CASE WHEN $9::text = 'name' AND $10::int = 1 THEN lo.name::text END ASC,
-                CASE WHEN $9::text = 'name' AND $10::int = -1 THEN lo.name::text END DESC,
-                CASE WHEN $9::text = 'hp' THEN lc.hp::int * $10::int END ASC,
-                CASE WHEN $9::text = 'rarity' THEN lc.rarity::int * $10::int END ASC
+                CASE WHEN ($10::int[])[1] = 1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($9::text[])[1] END ASC,
+                CASE WHEN ($10::int[])[1] = -1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($9::text[])[1] END DESC,
+                CASE WHEN ($10::int[])[2] = 1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($9::text[])[2] END ASC,
+                CASE WHEN ($10::int[])[2] = -1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($9::text[])[2] END DESC,
+                CASE WHEN ($10::int[])[3] = 1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($9::text[])[3] END ASC,
+                CASE WHEN ($10::int[])[3] = -1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($9::text[])[3] END DESC,
+                lo.id
             LIMIT $11 OFFSET $12
         ) c
-        ORDER BY similarity DESC, favor_exact_start_length 
+        ORDER BY similarity DESC, favor_exact_start_length,
+            CASE WHEN ($10::int[])[1] = 1 THEN to_jsonb(c) -> ($9::text[])[1] END ASC,
+            CASE WHEN ($10::int[])[1] = -1 THEN to_jsonb(c) -> ($9::text[])[1] END DESC,
+            CASE WHEN ($10::int[])[2] = 1 THEN to_jsonb(c) -> ($9::text[])[2] END ASC,
+            CASE WHEN ($10::int[])[2] = -1 THEN to_jsonb(c) -> ($9::text[])[2] END DESC,
+            CASE WHEN ($10::int[])[3] = 1 THEN to_jsonb(c) -> ($9::text[])[3] END ASC,
+            CASE WHEN ($10::int[])[3] = -1 THEN to_jsonb(c) -> ($9::text[])[3] END DESC,
+            c.id
     "#,
         search.name,
         &search.query,
@@ -176,10 +248,11 @@ pub async fn get_classes_search(
         search.legacy.include_legacy(),
         search.legacy.favor_remaster(),
         search.legacy.favor_legacy(),
-        sort.get_column(),
-        sort.get_sort_direction_i32(),
+        &sort.get_columns(),
+        &sort.get_sort_directions_i32(),
         limit as i64,
         offset as i64,
+        after,
     );
 
     // create initial hm with empty vecs for each query
@@ -219,73 +292,58 @@ pub async fn get_classes_search(
 pub async fn insert_classes(
     tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
     classes: &[InsertLibraryClass],
-) -> crate::Result<()> {
-    // TODO: Do we *need* two tables for this?
-
+) -> crate::Result<Vec<InternalId>> {
     if classes.is_empty() {
-        return Ok(());
+        return Ok(vec![]);
     }
-    // First, check to make sure all requested ids are not already in use
-    let requested_ids = classes
+
+    let objects = classes
         .iter()
-        .filter_map(|i| i.requested_id)
-        .map(|id| id.0 as i32)
-        .collect::<Vec<i32>>();
-    check_library_requested_ids(&mut **tx, &requested_ids).await?;
+        .map(InsertLibraryObject::from)
+        .collect::<Vec<_>>();
+    let ids = insert_library_objects(tx, &objects).await?;
+    write_classes(tx, &ids, classes).await?;
+    Ok(ids.into_iter().map(|id| InternalId(id as u32)).collect())
+}
 
-    let ids = sqlx::query!(
-        r#"
-        INSERT INTO library_objects (id, name, game_system, url, description, legacy, remastering_alt_id)
-        SELECT * FROM UNNEST ($1::int[], $2::text[], $3::int[], $4::text[], $5::text[], $6::bool[], $7::int[])
-        RETURNING id  
-    "#,
-        &classes
-            .iter()
-            .map(|c| c.requested_id.map(|id| id.0 as i32))
-            .collect::<Vec<Option<i32>>>() as _,
-        &classes
-            .iter()
-            .map(|c| c.name.clone())
-            .collect::<Vec<String>>(),
-        &classes
-            .iter()
-            .map(|c| c.game_system.as_i64() as i32)
-            .collect::<Vec<i32>>(),
-        &classes
-            .iter()
-            .map(|c| c.url.clone())
-            .collect::<Vec<Option<String>>>() as _,
-        &classes
-            .iter()
-            .map(|c| c.description.clone())
-            .collect::<Vec<String>>(),
-        &classes
-            .iter()
-            .map(|c| c.legacy)
-            .collect::<Vec<bool>>(),
-        &classes
-            .iter()
-            .map(|c| c.remastering_alt_id.map(|id| id.0 as i32))
-            .collect::<Vec<Option<i32>>>() as _,
-    )
-    .fetch_all(&mut **tx)
-    .await?
-    .into_iter()
-    .map(|row| Ok(row.id))
-    .collect::<Result<Vec<i32>, sqlx::Error>>()?;
+/// Inserts new classes, and updates existing ones in place.
+pub async fn upsert_classes(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    classes: &[InsertLibraryClass],
+) -> crate::Result<LibraryUpsertReport> {
+    if classes.is_empty() {
+        return Ok(LibraryUpsertReport::default());
+    }
+
+    let objects = classes
+        .iter()
+        .map(InsertLibraryObject::from)
+        .collect::<Vec<_>>();
+    let upsert = LibraryUpsert::begin(tx, LibraryObjectType::Class, &objects).await?;
+    write_classes(tx, &upsert.ids, classes).await?;
+    upsert.finish(tx).await
+}
 
+// Writes everything but the 'library_objects' row
+async fn write_classes(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ids: &[i32],
+    classes: &[InsertLibraryClass],
+) -> crate::Result<()> {
     // TODO: Unfortunately, sqlx does not support multidimensional arrays
     // No built in way to UNNEST like in other insertion functions in postgres- unnest entirely flattens.
     // https://wiki.postgresql.org/wiki/Unnest_multidimensional_array
     // Unfortunately, sqlx does not support insertion of multidimensional arrays anyhow.
     // TODO: as i32 should be unnecessary- fix in models
-    for (id, class) in classes.iter().enumerate() {
+    for (id, class) in ids.iter().zip(classes.iter()) {
         sqlx::query!(
             r#"
             INSERT INTO library_classes (id, rarity, hp, traditions)
             VALUES ($1, $2, $3, $4)
+            ON CONFLICT (id) DO UPDATE
+            SET rarity = EXCLUDED.rarity, hp = EXCLUDED.hp, traditions = EXCLUDED.traditions
         "#,
-            ids[id] as i32,
+            id,
             class.rarity.as_i64() as i32,
             class.hp as i32,
             &class.traditions,
@@ -294,5 +352,77 @@ pub async fn insert_classes(
         .await?;
     }
 
+    set_library_object_tags(
+        tx,
+        ids,
+        &classes.iter().map(|o| &o.tags).collect::<Vec<_>>(),
+    )
+    .await
+}
+
+/// A class specific expected stat boost. A class's boosts of a category replace the general expectations of it.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ClassExpectedBoost {
+    // Level the boost is expected from
+    pub level: u8,
+    pub boost_category_id: u32,
+    pub potency: i16,
+}
+
+/// Replaces the expected stat boosts of a class. Categories left out fall back to the general expectations.
+pub async fn set_class_expected_boosts(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    class_id: InternalId,
+    boosts: &[ClassExpectedBoost],
+) -> crate::Result<()> {
+    let class_exists = sqlx::query_scalar!(
+        "SELECT EXISTS (SELECT 1 FROM library_classes WHERE id = $1)",
+        class_id.0 as i32,
+    )
+    .fetch_one(&mut **tx)
+    .await?
+    .unwrap_or(false);
+    if !class_exists {
+        return Err(ServerError::NotFound);
+    }
+
+    let category_ids = boosts
+        .iter()
+        .map(|b| b.boost_category_id as i32)
+        .collect::<Vec<i32>>();
+    let known_categories = sqlx::query_scalar!(
+        "SELECT COUNT(DISTINCT id) FROM stat_boost_category_types WHERE id = ANY($1::int[])",
+        &category_ids,
+    )
+    .fetch_one(&mut **tx)
+    .await?
+    .unwrap_or(0);
+    if known_categories as usize != category_ids.iter().collect::<HashSet<_>>().len() {
+        return Err(ServerError::BadRequest(
+            "Unknown stat boost category".to_string(),
+        ));
+    }
+
+    sqlx::query!(
+        "DELETE FROM expected_treasure_stats_boosts_by_class WHERE class_id = $1",
+        class_id.0 as i32,
+    )
+    .execute(&mut **tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO expected_treasure_stats_boosts_by_class (class_id, level, stat_boost_category_id, amount)
+        SELECT $1, * FROM UNNEST ($2::smallint[], $3::smallint[], $4::smallint[])
+        "#,
+        class_id.0 as i32,
+        &boosts.iter().map(|b| b.level as i16).collect::<Vec<i16>>(),
+        &category_ids
+            .iter()
+            .map(|id| *id as i16)
+            .collect::<Vec<i16>>(),
+        &boosts.iter().map(|b| b.potency).collect::<Vec<i16>>(),
+    )
+    .execute(&mut **tx)
+    .await?;
     Ok(())
 }
@@ -0,0 +1,298 @@
+use serde::Deserialize;
+
+use crate::models::combat::{roll_initiative, CombatState, Combatant, CombatantType};
+use crate::models::encounter::{Encounter, EncounterType};
+use crate::models::ids::InternalId;
+use crate::ServerError;
+
+#[derive(Deserialize, Debug)]
+pub struct StartCombat {
+    pub combatants: Vec<InsertCombatant>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct InsertCombatant {
+    pub name: String,
+    #[serde(flatten)]
+    pub combatant_type: CombatantType,
+    // If not given, initiative is rolled using the modifier
+    pub initiative: Option<i32>,
+    #[serde(default)]
+    pub initiative_modifier: i32,
+    pub hp: Option<i32>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ModifyCombat {
+    pub round: Option<u32>,
+    pub turn: Option<u32>,
+    pub combatants: Option<Vec<Combatant>>,
+}
+
+pub async fn get_combat(
+    exec: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    encounter_id: InternalId,
+) -> crate::Result<Option<CombatState>> {
+    sqlx::query!(
+        r#"
+        SELECT round, turn, combatants, ended_at
+        FROM encounter_combats
+        WHERE encounter_id = $1
+        "#,
+        encounter_id.0 as i32,
+    )
+    .fetch_optional(exec)
+    .await?
+    .map(|row| {
+        Ok(CombatState {
+            encounter_id,
+            round: row.round as u32,
+            turn: row.turn as u32,
+            combatants: serde_json::from_value(row.combatants)?,
+            ended: row.ended_at.is_some(),
+        })
+    })
+    .transpose()
+}
+
+// Characters must be from the encounter's campaign, and enemies and hazards from the encounter itself
+async fn validate_combatants(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    encounter: &Encounter,
+    combatants: &[(&str, &CombatantType)],
+) -> crate::Result<()> {
+    let EncounterType::Combat { enemies, hazards } = &encounter.encounter_type else {
+        return Err(ServerError::BadRequest(
+            "Only combat encounters can be run as a combat".to_string(),
+        ));
+    };
+
+    let character_ids = combatants
+        .iter()
+        .filter_map(|(_, combatant_type)| match combatant_type {
+            CombatantType::Character { id } => Some(id.0 as i32),
+            _ => None,
+        })
+        .collect::<Vec<i32>>();
+    let campaign_characters = sqlx::query!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM characters
+        WHERE id = ANY($1::int[]) AND campaign = $2
+        "#,
+        &character_ids,
+        encounter.campaign_id.map(|id| id.0 as i32),
+    )
+    .fetch_one(&mut **tx)
+    .await?
+    .count;
+    if campaign_characters != character_ids.len() as i64 {
+        return Err(ServerError::BadRequest(
+            "Characters must be part of the encounter's campaign".to_string(),
+        ));
+    }
+    for (name, combatant_type) in combatants {
+        let valid = match combatant_type {
+            CombatantType::Enemy { id, .. } => enemies.iter().any(|e| e.id == *id),
+            CombatantType::Hazard { id } => hazards.contains(id),
+            CombatantType::Character { .. } => true,
+        };
+        if !valid {
+            return Err(ServerError::BadRequest(format!(
+                "{} is not part of the encounter",
+                name
+            )));
+        }
+    }
+    Ok(())
+}
+
+// Starts (or restarts) a combat for a combat encounter, rolling initiative where it was not entered
+pub async fn start_combat(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    encounter: &Encounter,
+    start: &StartCombat,
+) -> crate::Result<CombatState> {
+    let EncounterType::Combat { .. } = &encounter.encounter_type else {
+        return Err(ServerError::BadRequest(
+            "Only combat encounters can be run as a combat".to_string(),
+        ));
+    };
+
+    let combatants = start
+        .combatants
+        .iter()
+        .map(|c| (c.name.as_str(), &c.combatant_type))
+        .collect::<Vec<_>>();
+    validate_combatants(tx, encounter, &combatants).await?;
+
+    // Enemies without entered hp start with the hp of their (weak or elite adjusted) stat block
+    let mut with_stat_blocks = [encounter.clone()];
+    super::encounters::set_enemy_stat_blocks(tx, &mut with_stat_blocks).await?;
+    let enemies = with_stat_blocks[0].encounter_type.get_enemies();
+    let stat_block_hp = |combatant_type: &CombatantType| match combatant_type {
+        CombatantType::Enemy {
+            id,
+            level_adjustment,
+        } => enemies
+            .iter()
+            .find(|e| e.id == *id && e.level_adjustment == *level_adjustment)
+            .and_then(|e| e.stat_block.as_ref())
+            .map(|s| s.hp),
+        _ => None,
+    };
+
+    let combatants = {
+        let mut rng = rand::thread_rng();
+        start
+            .combatants
+            .iter()
+            .map(|c| Combatant {
+                name: c.name.clone(),
+                combatant_type: c.combatant_type.clone(),
+                initiative: c
+                    .initiative
+                    .unwrap_or_else(|| roll_initiative(&mut rng, c.initiative_modifier)),
+                hp: c.hp.or_else(|| stat_block_hp(&c.combatant_type)),
+                max_hp: c.hp.or_else(|| stat_block_hp(&c.combatant_type)),
+                conditions: vec![],
+                defeated: false,
+            })
+            .collect()
+    };
+    let state = CombatState::new(encounter.id, combatants);
+
+    sqlx::query!(
+        r#"
+        DELETE FROM encounter_combats
+        WHERE encounter_id = $1
+        "#,
+        encounter.id.0 as i32,
+    )
+    .execute(&mut **tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO encounter_combats (encounter_id, round, turn, combatants)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        encounter.id.0 as i32,
+        state.round as i32,
+        state.turn as i32,
+        serde_json::to_value(&state.combatants)?,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(state)
+}
+
+// Saves the state of a running combat. Combats that have ended can no longer be changed.
+pub async fn save_combat(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    state: &CombatState,
+) -> crate::Result<()> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE encounter_combats
+        SET round = $2, turn = $3, combatants = $4
+        WHERE encounter_id = $1 AND ended_at IS NULL
+        "#,
+        state.encounter_id.0 as i32,
+        state.round as i32,
+        state.turn as i32,
+        serde_json::to_value(&state.combatants)?,
+    )
+    .execute(&mut **tx)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(ServerError::BadRequest(
+            "Combat has already ended".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// Applies edits to a running combat, such as hp, conditions or reordered initiative
+pub async fn edit_combat(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    encounter: &Encounter,
+    state: &mut CombatState,
+    modify: &ModifyCombat,
+) -> crate::Result<()> {
+    if let Some(combatants) = &modify.combatants {
+        let replacements = combatants
+            .iter()
+            .map(|c| (c.name.as_str(), &c.combatant_type))
+            .collect::<Vec<_>>();
+        validate_combatants(tx, encounter, &replacements).await?;
+        state.combatants = combatants.clone();
+    }
+    if let Some(round) = modify.round {
+        state.round = round;
+    }
+    if let Some(turn) = modify.turn {
+        state.turn = turn;
+    }
+    if state.round == 0 || state.turn as usize >= state.combatants.len().max(1) {
+        return Err(ServerError::BadRequest(
+            "Round must be at least 1 and turn must be a combatant".to_string(),
+        ));
+    }
+
+    save_combat(tx, state).await
+}
+
+// Ends the combat and marks the encounter completed.
+// If the encounter is part of a session, defeated enemies and hazards are added to the event log.
+pub async fn end_combat(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    encounter: &Encounter,
+    state: &mut CombatState,
+) -> crate::Result<()> {
+    // Only ends it once, so its events are not logged twice
+    let result = sqlx::query!(
+        r#"
+        UPDATE encounter_combats
+        SET ended_at = CURRENT_TIMESTAMP
+        WHERE encounter_id = $1 AND ended_at IS NULL
+        "#,
+        encounter.id.0 as i32,
+    )
+    .execute(&mut **tx)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(ServerError::BadRequest(
+            "Combat has already ended".to_string(),
+        ));
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE encounters
+        SET completed = TRUE
+        WHERE id = $1
+        "#,
+        encounter.id.0 as i32,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    let events = state.defeated_events();
+    if let (Some(campaign_id), Some(session_id)) = (encounter.campaign_id, encounter.session_id) {
+        if !events.is_empty() {
+            super::events::insert_event_group(
+                tx,
+                campaign_id,
+                session_id,
+                &encounter.name,
+                encounter.description.as_deref(),
+                &events,
+            )
+            .await?;
+        }
+    }
+
+    state.ended = true;
+    Ok(())
+}
@@ -1,17 +1,20 @@
-use super::check_library_requested_ids;
 use super::sorts::Sortable;
 use super::sorts::SortableColumn;
 use super::tags;
+use super::HomebrewVisibility;
 use super::LegacyStatus;
 use super::DEFAULT_MAX_LIMIT;
+use super::{
+    insert_library_objects, set_library_object_tags, InsertLibraryObject, LibraryUpsert,
+    LibraryUpsertReport,
+};
 use crate::models::ids::InternalId;
 use crate::models::library::{
-    creature::{Alignment, LibraryCreature, Size},
-    GameSystem, Rarity,
+    creature::{Alignment, CreatureStatBlock, LibraryCreature, Size},
+    GameSystem, LibraryObjectType, Rarity,
 };
 use crate::models::query::CommaSeparatedVec;
 use crate::ServerError;
-use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use sqlx::PgConnection;
 use std::collections::HashMap;
@@ -34,8 +37,14 @@ pub struct CreatureFiltering {
     #[serde(default)]
     pub legacy: LegacyStatus,
 
+    // Homebrew objects visible to the requesting user. Not part of the query string.
+    #[serde(skip)]
+    pub homebrew: HomebrewVisibility,
+
     pub limit: Option<u64>,
     pub page: Option<u64>,
+    // Continue after a previous page's `next_cursor`, instead of by page. `start` for the first page.
+    pub cursor: Option<String>,
     pub sort_by: Option<String>,
     pub order_by: Option<String>,
 }
@@ -48,9 +57,11 @@ impl CreatureFiltering {
         }
     }
 
+    // Lookups by id from data that already references these objects (eg: encounters), so includes all homebrew
     pub fn from_ids(ids: &[u32]) -> Self {
         CreatureFiltering {
             ids: Some(CommaSeparatedVec(ids.to_vec())),
+            homebrew: HomebrewVisibility::All,
             ..Default::default()
         }
     }
@@ -79,6 +90,13 @@ pub struct CreatureSearch {
     pub legacy: LegacyStatus,
 
     pub page: Option<u64>,
+    // Searches rank by similarity, so are only paged by offset. Set from a listing's cursor.
+    #[serde(skip)]
+    pub cursor: Option<String>,
+    // Homebrew objects visible to the requesting user. Not part of the query string.
+    #[serde(skip)]
+    pub homebrew: HomebrewVisibility,
+
     pub limit: Option<u64>,
     pub sort_by: Option<String>,
     pub order_by: Option<String>,
@@ -101,8 +119,10 @@ impl From<CreatureFiltering> for CreatureSearch {
             traits_all: filter.traits_all,
             traits_any: filter.traits_any,
             legacy: filter.legacy,
+            homebrew: filter.homebrew,
             limit: filter.limit,
             page: filter.page,
+            cursor: filter.cursor,
             sort_by: filter.sort_by,
             order_by: filter.order_by,
         }
@@ -117,12 +137,31 @@ impl Sortable for LibraryCreature {
     fn default_sort() -> Option<&'static str> {
         Some("name")
     }
+
+    fn get_sort_value(&self, field: &str) -> serde_json::Value {
+        match field {
+            "name" => self.name.as_str().into(),
+            "level" => self.level.into(),
+            "rarity" => self.rarity.as_i64().into(),
+            "size" => self.size.as_i64().into(),
+            "alignment" => self.alignment.as_i64().into(),
+            _ => serde_json::Value::Null,
+        }
+    }
+
+    fn get_id(&self) -> InternalId {
+        self.id
+    }
 }
 
 // TODO: 'Filterable' is kind of a mess
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct InsertLibraryCreature {
     pub requested_id: Option<InternalId>,
+    #[serde(default)]
+    pub source_id: Option<String>,
+    #[serde(skip)]
+    pub owner: Option<InternalId>,
     pub name: String,
     pub game_system: GameSystem,
     pub rarity: Rarity,
@@ -135,6 +174,25 @@ pub struct InsertLibraryCreature {
 
     pub url: Option<String>,
     pub description: String,
+
+    #[serde(default)]
+    pub stat_block: Option<CreatureStatBlock>,
+}
+
+impl From<&InsertLibraryCreature> for InsertLibraryObject {
+    fn from(object: &InsertLibraryCreature) -> Self {
+        InsertLibraryObject {
+            requested_id: object.requested_id,
+            source_id: object.source_id.clone(),
+            owner: object.owner,
+            name: object.name.clone(),
+            game_system: object.game_system.clone(),
+            url: object.url.clone(),
+            description: object.description.clone(),
+            legacy: object.legacy,
+            remastering_alt_id: object.remastering_alt_id,
+        }
+    }
 }
 
 // TODO: May be prudent to make a separate models system for the database.
@@ -172,6 +230,11 @@ pub async fn get_creatures_search(
         search.sort_by.as_deref(),
         search.order_by.as_deref(),
     )?;
+    // A cursor picks up after the last object of the previous page, in place of the offset
+    let (offset, after) = match &search.cursor {
+        Some(cursor) => (0, sort.parse_cursor(cursor)?),
+        None => (offset, None),
+    };
 
     let min_similarity = search.min_similarity.unwrap_or(0.0);
 
@@ -211,6 +274,7 @@ pub async fn get_creatures_search(
                 level,
                 alignment,
                 size,
+                stat_block,
                 tags.tags,
                 tags.traits,
                 lo.legacy,
@@ -237,22 +301,48 @@ pub async fn get_creatures_search(
                 AND ($8::text[] IS NULL OR tags.traits::text[] && $8::text[])
                 AND ($9::text[] IS NULL OR tags.traits::text[] @> $9::text[])
                 AND ($10::int[] IS NULL OR lo.id = ANY($10))
+                AND ($20::bool OR lo.owner IS NULL OR lo.owner = $21 OR lo.campaign_id IN (
+                    SELECT id FROM campaigns WHERE owner = $21
+                    UNION SELECT campaign_id FROM campaign_members WHERE user_id = $21
+                ))
                 AND (($13::bool AND lo.name ILIKE '%' || query || '%') OR SIMILARITY(lo.name, query) >= $12)
                 AND NOT (NOT $14::bool AND lo.legacy = FALSE)
                 AND NOT (NOT $15::bool AND lo.legacy = TRUE)
                 AND NOT ($16::bool AND lo.remastering_alt_id IS NOT NULL AND lo.legacy = TRUE)
                 AND NOT ($17::bool AND lo.remastering_alt_id IS NOT NULL AND lo.legacy = FALSE)
+                -- After the cursor's sort keys, then id. Descending keys swap sides, so that a single row comparison
+                -- covers any mix of directions. Keys past the sort's columns are equal on both sides.
+                AND ($24::jsonb IS NULL OR (
+                    COALESCE(CASE ($19::int[])[1] WHEN 1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($18::text[])[1] WHEN -1 THEN $24 -> 0 END, 'null'),
+                    COALESCE(CASE ($19::int[])[2] WHEN 1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($18::text[])[2] WHEN -1 THEN $24 -> 1 END, 'null'),
+                    COALESCE(CASE ($19::int[])[3] WHEN 1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($18::text[])[3] WHEN -1 THEN $24 -> 2 END, 'null'),
+                    lo.id
+                ) > (
+                    COALESCE(CASE ($19::int[])[1] WHEN 1 THEN $24 -> 0 WHEN -1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($18::text[])[1] END, 'null'),
+                    COALESCE(CASE ($19::int[])[2] WHEN 1 THEN $24 -> 1 WHEN -1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($18::text[])[2] END, 'null'),
+                    COALESCE(CASE ($19::int[])[3] WHEN 1 THEN $24 -> 2 WHEN -1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($18::text[])[3] END, 'null'),
+                    ($24 -> cardinality($18::text[]))::int
+                ))
             GROUP BY lo.id, lc.id, tags.tags, tags.traits 
+            -- Up to MAX_SORT_COLUMNS sort keys, then id to break ties
             ORDER BY similarity DESC, favor_exact_start_length,
-                CASE WHEN $18::text = 'name' AND $19::int = 1 THEN lo.name::text END ASC,
-                CASE WHEN $18::text = 'name' AND $19::int = -1 THEN lo.name::text END DESC,
-                CASE WHEN $18::text = 'level' THEN level::integer * $19::int END ASC,
-                CASE WHEN $18::text = 'rarity' THEN rarity::integer * $19::int END ASC,
-                CASE WHEN $18::text = 'size' THEN size::integer * $19::int END ASC,
-                CASE WHEN $18::text = 'alignment' THEN alignment::integer * $19::int END ASC
-            LIMIT $20 OFFSET $21
+                CASE WHEN ($19::int[])[1] = 1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($18::text[])[1] END ASC,
+                CASE WHEN ($19::int[])[1] = -1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($18::text[])[1] END DESC,
+                CASE WHEN ($19::int[])[2] = 1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($18::text[])[2] END ASC,
+                CASE WHEN ($19::int[])[2] = -1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($18::text[])[2] END DESC,
+                CASE WHEN ($19::int[])[3] = 1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($18::text[])[3] END ASC,
+                CASE WHEN ($19::int[])[3] = -1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($18::text[])[3] END DESC,
+                lo.id
+            LIMIT $22 OFFSET $23
         ) c
-        ORDER BY similarity DESC, favor_exact_start_length 
+        ORDER BY similarity DESC, favor_exact_start_length,
+            CASE WHEN ($19::int[])[1] = 1 THEN to_jsonb(c) -> ($18::text[])[1] END ASC,
+            CASE WHEN ($19::int[])[1] = -1 THEN to_jsonb(c) -> ($18::text[])[1] END DESC,
+            CASE WHEN ($19::int[])[2] = 1 THEN to_jsonb(c) -> ($18::text[])[2] END ASC,
+            CASE WHEN ($19::int[])[2] = -1 THEN to_jsonb(c) -> ($18::text[])[2] END DESC,
+            CASE WHEN ($19::int[])[3] = 1 THEN to_jsonb(c) -> ($18::text[])[3] END ASC,
+            CASE WHEN ($19::int[])[3] = -1 THEN to_jsonb(c) -> ($18::text[])[3] END DESC,
+            c.id
     "#,
         search.name,
         search.rarity.as_ref().map(|r| r.as_i64() as i32),
@@ -271,10 +361,13 @@ pub async fn get_creatures_search(
         search.legacy.include_legacy(),
         search.legacy.favor_remaster(),
         search.legacy.favor_legacy(),
-        sort.get_column(),
-        sort.get_sort_direction_i32(),
+        &sort.get_columns(),
+        &sort.get_sort_directions_i32(),
+        search.homebrew.all(),
+        search.homebrew.user_id(),
         limit as i64,
         offset as i64,
+        after,
     );
 
     // create initial hm with empty vecs for each query
@@ -304,6 +397,9 @@ pub async fn get_creatures_search(
                 legacy: row.legacy,
                 remastering_alt_id: row.remastering_alt_id.map(|id| InternalId(id as u32)),
                 traits: row.traits.unwrap_or_default(),
+                stat_block: row
+                    .stat_block
+                    .and_then(|stat_block| serde_json::from_value(stat_block).ok()),
             };
 
             map.entry(query)
@@ -318,93 +414,78 @@ pub async fn get_creatures_search(
 pub async fn insert_creatures(
     tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
     creatures: &[InsertLibraryCreature],
-) -> crate::Result<()> {
-    // TODO: we don't need two tables for this.
+) -> crate::Result<Vec<InternalId>> {
+    if creatures.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let objects = creatures
+        .iter()
+        .map(InsertLibraryObject::from)
+        .collect::<Vec<_>>();
+    let ids = insert_library_objects(tx, &objects).await?;
+    write_creatures(tx, &ids, creatures).await?;
+    Ok(ids.into_iter().map(|id| InternalId(id as u32)).collect())
+}
 
+/// Inserts new creatures, and updates existing ones in place.
+pub async fn upsert_creatures(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    creatures: &[InsertLibraryCreature],
+) -> crate::Result<LibraryUpsertReport> {
     if creatures.is_empty() {
-        return Ok(());
+        return Ok(LibraryUpsertReport::default());
     }
-    // First, check to make sure all requested ids are not already in use
-    let requested_ids = creatures
+
+    let objects = creatures
         .iter()
-        .filter_map(|i| i.requested_id)
-        .map(|id| id.0 as i32)
-        .collect::<Vec<i32>>();
-    check_library_requested_ids(&mut **tx, &requested_ids).await?;
+        .map(InsertLibraryObject::from)
+        .collect::<Vec<_>>();
+    let upsert = LibraryUpsert::begin(tx, LibraryObjectType::Creature, &objects).await?;
+    write_creatures(tx, &upsert.ids, creatures).await?;
+    upsert.finish(tx).await
+}
 
-    let ids = sqlx::query!(
+// Writes everything but the 'library_objects' row
+async fn write_creatures(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ids: &[i32],
+    creatures: &[InsertLibraryCreature],
+) -> crate::Result<()> {
+    sqlx::query!(
         r#"
-        INSERT INTO library_objects (id, name, game_system, url, description, legacy, remastering_alt_id)
-        SELECT * FROM UNNEST ($1::int[], $2::text[], $3::int[], $4::text[], $5::text[], $6::bool[], $7::int[])
-        RETURNING id  
-    "#,
-        &creatures
-            .iter()
-            .map(|c| c.requested_id.map(|id| id.0 as i32))
-            .collect::<Vec<Option<i32>>>() as _,
+        INSERT INTO library_creatures (id, rarity, level, alignment, size, stat_block)
+        SELECT * FROM UNNEST ($1::int[], $2::int[], $3::int[], $4::int[], $5::int[], $6::jsonb[])
+        ON CONFLICT (id) DO UPDATE
+        SET rarity = EXCLUDED.rarity, level = EXCLUDED.level, alignment = EXCLUDED.alignment, size = EXCLUDED.size,
+            stat_block = EXCLUDED.stat_block
+        "#,
+        ids,
         &creatures
             .iter()
-            .map(|c| c.name.clone())
-            .collect::<Vec<String>>(),
-        &creatures
-            .iter()
-            .map(|c| c.game_system.as_i64() as i32)
+            .map(|c| c.rarity.as_i64() as i32)
             .collect::<Vec<i32>>(),
+        &creatures.iter().map(|c| c.level as i32).collect::<Vec<i32>>(),
         &creatures
             .iter()
-            .map(|c| c.url.clone())
-            .collect::<Vec<Option<String>>>() as _,
-        &creatures
-            .iter()
-            .map(|c| c.description.clone())
-            .collect::<Vec<String>>(),
+            .map(|c| c.alignment.as_i64() as i32)
+            .collect::<Vec<i32>>(),
         &creatures
             .iter()
-            .map(|c| c.legacy)
-            .collect::<Vec<bool>>(),
+            .map(|c| c.size.as_i64() as i32)
+            .collect::<Vec<i32>>(),
         &creatures
             .iter()
-            .map(|c| c.remastering_alt_id.map(|id| id.0 as i32))
-            .collect::<Vec<Option<i32>>>() as _,
+            .map(|c| c.stat_block.as_ref().map(serde_json::to_value).transpose())
+            .collect::<Result<Vec<_>, _>>()? as _,
     )
-    .fetch_all(&mut **tx)
-    .await?
-    .into_iter()
-    .map(|row| Ok(row.id))
-    .collect::<Result<Vec<i32>, sqlx::Error>>()?;
-
-    // TODO: annoying no unnest thing
-    for (creature, id) in creatures.iter().zip(ids.iter()) {
-        sqlx::query!(
-            r#"
-            INSERT INTO library_creatures (id, rarity, level, alignment, size)
-            VALUES ($1, $2, $3, $4, $5)
-            "#,
-            id,
-            creature.rarity.as_i64() as i32,
-            creature.level as i32,
-            creature.alignment.as_i64() as i32,
-            creature.size.as_i64() as i32,
-        )
-        .execute(&mut **tx)
-        .await?;
-
-        sqlx::query!(
-            r#"
-            INSERT INTO library_objects_tags (library_object_id, tag_id)
-            SELECT $1, id FROM UNNEST ($2::int[]) AS id
-            "#,
-            id,
-            &creature
-                .tags
-                .iter()
-                .map(|id| id.0 as i32)
-                .sorted()
-                .collect::<Vec<i32>>(),
-        )
-        .execute(&mut **tx)
-        .await?;
-    }
+    .execute(&mut **tx)
+    .await?;
 
-    Ok(())
+    set_library_object_tags(
+        tx,
+        ids,
+        &creatures.iter().map(|o| &o.tags).collect::<Vec<_>>(),
+    )
+    .await
 }
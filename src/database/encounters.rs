@@ -1,18 +1,26 @@
 use std::collections::HashMap;
 
 use crate::models;
+use crate::models::campaign::CampaignRole;
 use crate::models::encounter::Encounter;
 use crate::models::encounter::{
-    EncounterEnemy, EncounterSubsystemCheck, EncounterSubsystemType, EncounterType,
+    EncounterCandidate, EncounterDifficulty, EncounterEnemy, EncounterSubsystemCheck,
+    EncounterSubsystemType, EncounterType, GeneratedEncounter,
 };
 use crate::models::ids::InternalId;
+use crate::models::library::{GameSystem, Rarity};
 use crate::models::query::CommaSeparatedVec;
+use crate::ServerError;
 use serde::{Deserialize, Serialize};
 use sqlx::PgConnection;
 
 use super::creatures::CreatureFiltering;
 use super::hazards::HazardFiltering;
 use super::items::ItemFiltering;
+use super::tags;
+use super::HomebrewVisibility;
+use super::LegacyStatus;
+use super::DEFAULT_MAX_LIMIT;
 
 #[derive(Default, Serialize, Deserialize, Debug)]
 pub struct EncounterFilters {
@@ -37,6 +45,9 @@ pub struct InsertEncounter {
     pub description: Option<String>,
 
     pub session_id: Option<InternalId>,
+    // Campaign of an encounter not (yet) linked to a session. Set from the session otherwise.
+    #[serde(default)]
+    pub campaign_id: Option<InternalId>,
 
     pub party_level: u8,
     pub party_size: u8,
@@ -59,6 +70,7 @@ pub struct ModifyEncounter {
 
     pub subsystem_checks: Option<Vec<EncounterSubsystemCheck>>,
     pub subsystem_type: Option<EncounterSubsystemType>,
+    pub awareness_thresholds: Option<Vec<u32>>,
 
     pub encounter_type_id: Option<u8>,
 
@@ -72,10 +84,31 @@ pub struct ModifyEncounter {
     pub party_size: Option<u8>,
 }
 
+#[derive(serde::Deserialize, Debug)]
+pub struct GenerateEncounter {
+    pub party_level: u8,
+    pub party_size: u8,
+    pub difficulty: EncounterDifficulty,
+
+    pub rarity: Option<Rarity>,
+    pub game_system: Option<GameSystem>,
+    pub traits_any: Option<Vec<String>>,
+    pub traits_all: Option<Vec<String>>,
+    #[serde(default)]
+    pub legacy: LegacyStatus,
+
+    #[serde(default)]
+    pub include_hazards: bool,
+    pub max_enemies: Option<u8>,
+}
+
+const DEFAULT_GENERATION_MAX_ENEMIES: u8 = 8;
+
 // TODO: May be prudent to make a separate models system for the database.
+// Encounters owned by the user, as well as those in campaigns they own or are a co-GM of.
 pub async fn get_encounters(
     exec: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
-    owner: InternalId,
+    user_id: InternalId,
     // TODO: Could this use be problematic?
     // A postgres alternative can be found here:
     // https://github.com/launchbadge/sqlx/issues/291
@@ -96,7 +129,7 @@ pub async fn get_encounters(
             en.name,
             en.description,
             en.session_id,
-            any_value(cs.campaign_id) as campaign_id,
+            en.campaign_id,
             ee.enemies,
             ee.level_adjustments as enemy_level_adjustments,
             eh.hazards,
@@ -109,10 +142,11 @@ pub async fn get_encounters(
             en.total_items_value,
             en.encounter_type_id,
             en.subsystem_type_id,
-            JSONB_AGG(jsonb_build_object('name', esc.name, 'vp', esc.vp, 'roll_options', esc.roll_options)) as subsystem_rolls,
+            en.awareness_thresholds,
+            en.completed,
+            JSONB_AGG(jsonb_build_object('name', esc.name, 'vp', esc.vp, 'roll_options', esc.roll_options) ORDER BY esc.order_index) as subsystem_rolls,
             en.owner
         FROM encounters en
-        LEFT JOIN campaign_sessions cs ON en.session_id = cs.id
         LEFT JOIN LATERAL (
             SELECT 
                 ARRAY_AGG(enemy) FILTER (WHERE ee.enemy IS NOT NULL) as enemies, 
@@ -140,8 +174,12 @@ pub async fn get_encounters(
             ($1::text IS NULL OR en.name LIKE '%' || $1 || '%')
             AND ($2::int[] IS NULL OR en.id = ANY($2::int[]))
             AND ($3::integer IS NULL OR en.encounter_type_id = $4)
-            AND en.owner = $4
-            AND ($5::int IS NULL OR cs.campaign_id = $5)
+            AND (en.owner = $4 OR en.campaign_id IN (
+                SELECT id FROM campaigns WHERE owner = $4
+                UNION
+                SELECT campaign_id FROM campaign_members WHERE user_id = $4 AND role = $6
+            ))
+            AND ($5::int IS NULL OR en.campaign_id = $5)
         GROUP BY en.id, ee.enemies, ee.level_adjustments, eh.hazards, eti.items
     "#,
         condition.name,
@@ -150,8 +188,9 @@ pub async fn get_encounters(
             .encounter_type
             .as_deref()
             .map(|x| EncounterType::id_from_string(x)),
-        owner.0 as i64,
+        user_id.0 as i64,
         condition.campaign_id.map(|id| id.0 as i32),
+        CampaignRole::CoGm.as_i16(),
     );
 
     let events = query
@@ -167,6 +206,7 @@ pub async fn get_encounters(
                 .map(|(id, adj)| EncounterEnemy {
                     id: InternalId(*id as u32),
                     level_adjustment: *adj,
+                    stat_block: None,
                 })
                 .collect();
 
@@ -187,6 +227,10 @@ pub async fn get_encounters(
                 hazards,
                 encounter_subsystem_type,
                 subsystem_rolls,
+                row.awareness_thresholds
+                    .into_iter()
+                    .map(|t| t as u32)
+                    .collect(),
             );
 
             #[derive(serde::Deserialize, Debug)]
@@ -213,6 +257,7 @@ pub async fn get_encounters(
                     .collect(), // TODO: wrong need to adjust model as well
                 treasure_currency: row.treasure_currency.unwrap_or(0.0) as f32,
                 extra_experience: row.extra_experience,
+                completed: row.completed,
                 total_experience: row.total_experience,
                 total_items_value: row.total_items_value as i32,
             })
@@ -221,10 +266,81 @@ pub async fn get_encounters(
     Ok(events)
 }
 
-pub async fn get_owned_encounter_ids(
+// Encounters the user can edit: those they own, or that are in campaigns they own or are a co-GM of.
+// Fills in the stat blocks of the enemies of encounters, with their weak or elite adjustments applied.
+// Homebrew creatures are looked up as the owner of the encounter's campaign sees them (or the encounter's owner,
+// outside of a campaign).
+pub async fn set_enemy_stat_blocks(
+    conn: &mut PgConnection,
+    encounters: &mut [Encounter],
+) -> crate::Result<()> {
+    let owners = sqlx::query!(
+        r#"
+        SELECT e.id, COALESCE(c.owner, e.owner) AS "owner!"
+        FROM encounters e
+        LEFT JOIN campaigns c ON c.id = e.campaign_id
+        WHERE e.id = ANY($1::int[])
+        "#,
+        &encounters
+            .iter()
+            .map(|e| e.id.0 as i32)
+            .collect::<Vec<i32>>(),
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .map(|row| (InternalId(row.id as u32), InternalId(row.owner as u32)))
+    .collect::<HashMap<_, _>>();
+
+    // Enemy ids of every encounter, grouped by whose homebrew they are looked up with
+    let mut enemy_ids: HashMap<InternalId, Vec<u32>> = HashMap::new();
+    for encounter in encounters.iter() {
+        if let Some(owner) = owners.get(&encounter.id) {
+            enemy_ids.entry(*owner).or_default().extend(
+                encounter
+                    .encounter_type
+                    .get_enemies()
+                    .iter()
+                    .map(|e| e.id.0),
+            );
+        }
+    }
+    let mut creatures = HashMap::new();
+    for (owner, ids) in enemy_ids {
+        if ids.is_empty() {
+            continue;
+        }
+        let filter = CreatureFiltering {
+            ids: Some(CommaSeparatedVec(ids)),
+            homebrew: HomebrewVisibility::User(owner),
+            ..Default::default()
+        };
+        for creature in super::creatures::get_creatures(conn, &filter).await? {
+            creatures.insert((owner, creature.id), creature);
+        }
+    }
+
+    for encounter in encounters.iter_mut() {
+        let Some(owner) = owners.get(&encounter.id) else {
+            continue;
+        };
+        if let EncounterType::Combat { enemies, .. } = &mut encounter.encounter_type {
+            for enemy in enemies {
+                enemy.stat_block = creatures.get(&(*owner, enemy.id)).and_then(|c| {
+                    c.clone()
+                        .with_level_adjustment(enemy.level_adjustment)
+                        .stat_block
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+pub async fn get_editable_encounter_ids(
     exec: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
     encounter_id: &[InternalId],
-    owner: InternalId,
+    user_id: InternalId,
 ) -> crate::Result<Vec<InternalId>> {
     let ids = sqlx::query!(
         r#"
@@ -233,13 +349,18 @@ pub async fn get_owned_encounter_ids(
         FROM encounters en
         WHERE 
             en.id = ANY($1::int[])
-            AND en.owner = $2
+            AND (en.owner = $2 OR en.campaign_id IN (
+                SELECT id FROM campaigns WHERE owner = $2
+                UNION
+                SELECT campaign_id FROM campaign_members WHERE user_id = $2 AND role = $3
+            ))
     "#,
         &encounter_id
             .iter()
             .map(|id| id.0 as i32)
             .collect::<Vec<i32>>(),
-        owner.0 as i32,
+        user_id.0 as i32,
+        CampaignRole::CoGm.as_i16(),
     )
     .fetch_all(exec)
     .await?
@@ -271,8 +392,26 @@ pub async fn insert_encounters(
         let hazards = encounter.encounter_type.get_hazards();
         let subsystem_checks = encounter.encounter_type.get_subsystem_checks();
         let encounter_subsystem_type = encounter.encounter_type.get_subsystem_type();
+        let awareness_thresholds = encounter.encounter_type.get_awareness_thresholds();
+
+        if let Some(campaign_id) = encounter.campaign_id {
+            super::campaigns::get_campaign_access(&mut **tx, campaign_id, owner)
+                .await?
+                .ok_or(ServerError::NotFound)?
+                .require(CampaignRole::CoGm)?;
+        }
 
         let enemy_ids = enemies.iter().map(|e| e.id).collect::<Vec<InternalId>>();
+        require_visible_library_objects(
+            tx,
+            owner,
+            None,
+            enemy_ids
+                .iter()
+                .chain(hazards.iter())
+                .chain(encounter.treasure_items.iter()),
+        )
+        .await?;
         let enemy_level_adjustments = enemies
             .iter()
             .map(|e| e.level_adjustment)
@@ -296,8 +435,8 @@ pub async fn insert_encounters(
 
         let encounter_id = sqlx::query!(
             r#"
-            INSERT INTO encounters (name, description, encounter_type_id, subsystem_type_id, treasure_currency, party_size, party_level, extra_experience, total_experience, total_items_value, owner)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            INSERT INTO encounters (name, description, encounter_type_id, subsystem_type_id, treasure_currency, party_size, party_level, extra_experience, total_experience, total_items_value, owner, campaign_id, awareness_thresholds)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
             RETURNING id
             "#,
             &encounter.name,
@@ -311,6 +450,11 @@ pub async fn insert_encounters(
             derived_total_experience as i64,
             derived_total_treasure_value as f64,
             owner.0 as i64,
+            encounter.campaign_id.map(|id| id.0 as i32),
+            &awareness_thresholds
+                .iter()
+                .map(|t| *t as i32)
+                .collect::<Vec<i32>>(),
         )
         .fetch_one(&mut **tx)
         .await?
@@ -442,11 +586,29 @@ pub async fn edit_encounter(
             .collect::<Vec<i32>>()
     });
 
+    require_visible_library_objects(
+        tx,
+        owner,
+        Some(encounter_id),
+        new_encounter
+            .enemies
+            .iter()
+            .flatten()
+            .map(|e| &e.id)
+            .chain(new_encounter.hazards.iter().flatten())
+            .chain(new_encounter.treasure_items.iter().flatten()),
+    )
+    .await?;
+
     // First, unlink the encounter from the session
     // TODO: We can refactor this editing to not need to explicitly unlinking/relinking (by being more explicit)
     let unlinked_session_id =
         super::sessions::unlink_encounter_from_session(&mut *tx, encounter_id).await?;
 
+    let awareness_thresholds = new_encounter
+        .awareness_thresholds
+        .as_ref()
+        .map(|t| t.iter().map(|t| *t as i32).collect::<Vec<i32>>());
     sqlx::query!(
         r#"
         UPDATE encounters
@@ -457,8 +619,9 @@ pub async fn edit_encounter(
         party_level = COALESCE($5, party_level),
         extra_experience = COALESCE($6, extra_experience),
         encounter_type_id = COALESCE($7, encounter_type_id),
-        subsystem_type_id = COALESCE($8, subsystem_type_id)
-        WHERE id = $9
+        subsystem_type_id = COALESCE($8, subsystem_type_id),
+        awareness_thresholds = COALESCE($9, awareness_thresholds)
+        WHERE id = $10
         "#,
         new_encounter.name.as_deref(),
         new_encounter.description.as_deref(),
@@ -471,6 +634,7 @@ pub async fn edit_encounter(
             .subsystem_type
             .as_ref()
             .map(|e| e.as_i32() as i32),
+        awareness_thresholds.as_deref(),
         encounter_id.0 as i64,
     )
     .fetch_optional(&mut **tx)
@@ -703,6 +867,32 @@ pub async fn delete_encounters(
         super::sessions::unlink_encounter_from_session(&mut *tx, *id).await?;
     }
 
+    sqlx::query!(
+        r#"
+        DELETE FROM encounter_subsystem_attempts
+        WHERE encounter_id = ANY($1::int[])
+        "#,
+        &encounter_id
+            .iter()
+            .map(|id| id.0 as i32)
+            .collect::<Vec<i32>>(),
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM encounter_combats
+        WHERE encounter_id = ANY($1::int[])
+        "#,
+        &encounter_id
+            .iter()
+            .map(|id| id.0 as i32)
+            .collect::<Vec<i32>>(),
+    )
+    .execute(&mut **tx)
+    .await?;
+
     // TODO: Check this again- may be easier to use cascade delete
     // TODO: Should this be a delete
     sqlx::query!(
@@ -862,6 +1052,202 @@ pub async fn recalculate_encounter_summary(
     Ok(())
 }
 
+// Generates (but does not save) a combat encounter from randomly chosen creatures and hazards matching the filters
+pub async fn generate_encounter(
+    conn: &mut PgConnection,
+    user_id: InternalId,
+    generate: &GenerateEncounter,
+) -> crate::Result<GeneratedEncounter> {
+    if generate.party_level == 0 || generate.party_size == 0 {
+        return Err(ServerError::BadRequest(
+            "Party level and size must be at least 1".to_string(),
+        ));
+    }
+
+    let candidates = get_generation_candidates(conn, user_id, generate).await?;
+    let max_enemies = generate
+        .max_enemies
+        .unwrap_or(DEFAULT_GENERATION_MAX_ENEMIES);
+
+    models::encounter::generate_combat_encounter(
+        &mut rand::thread_rng(),
+        &candidates,
+        generate.party_level,
+        generate.party_size,
+        generate.difficulty,
+        max_enemies as usize,
+    )
+    .ok_or_else(|| {
+        ServerError::BadRequest(
+            "Could not generate an encounter of this difficulty from matching creatures"
+                .to_string(),
+        )
+    })
+}
+
+// Helper function fetching a random sample of creatures (and optionally hazards) that can contribute
+// experience to an encounter for the party level. Homebrew is limited to what the user can see.
+async fn get_generation_candidates(
+    conn: &mut PgConnection,
+    user_id: InternalId,
+    generate: &GenerateEncounter,
+) -> crate::Result<Vec<EncounterCandidate>> {
+    let matching_tags =
+        tags::get_tag_matches(&mut *conn, &generate.traits_all, &generate.traits_any).await?;
+
+    // Anything more than 4 levels away from the party is worth the same experience, so we only consider
+    // creatures that are within 4 levels after a weak/elite adjustment
+    let min_level = generate.party_level as i32 - 5;
+    let max_level = generate.party_level as i32 + 5;
+
+    let creatures = sqlx::query!(
+        r#"
+        SELECT lo.id, lc.level AS "level!"
+        FROM library_objects lo
+        INNER JOIN library_creatures lc ON lo.id = lc.id
+        LEFT JOIN (
+            SELECT
+                library_object_id AS lo_id,
+                ARRAY_AGG(t.tag) FILTER (WHERE t.trait) AS traits
+            FROM library_objects_tags lot
+            INNER JOIN library_tags t ON lot.tag_id = t.id
+            GROUP BY lot.library_object_id
+        ) AS tags ON lo.id = tags.lo_id
+        WHERE
+            lc.level >= $1 AND lc.level <= $2
+            AND ($3::int IS NULL OR lc.rarity = $3)
+            AND ($4::int IS NULL OR lo.game_system = $4)
+            AND ($5::text[] IS NULL OR tags.traits::text[] && $5::text[])
+            AND ($6::text[] IS NULL OR tags.traits::text[] @> $6::text[])
+            AND NOT (NOT $7::bool AND lo.legacy = FALSE)
+            AND NOT (NOT $8::bool AND lo.legacy = TRUE)
+            AND NOT ($9::bool AND lo.remastering_alt_id IS NOT NULL AND lo.legacy = TRUE)
+            AND NOT ($10::bool AND lo.remastering_alt_id IS NOT NULL AND lo.legacy = FALSE)
+            AND (lo.owner IS NULL OR lo.owner = $12 OR lo.campaign_id IN (
+                SELECT id FROM campaigns WHERE owner = $12
+                UNION SELECT campaign_id FROM campaign_members WHERE user_id = $12
+            ))
+        ORDER BY RANDOM()
+        LIMIT $11
+        "#,
+        min_level,
+        max_level,
+        generate.rarity.as_ref().map(|r| r.as_i64() as i32),
+        generate.game_system.as_ref().map(|gs| gs.as_i64() as i32),
+        matching_tags.any_traits.as_deref() as _,
+        matching_tags.all_traits.as_deref() as _,
+        generate.legacy.include_remaster(),
+        generate.legacy.include_legacy(),
+        generate.legacy.favor_remaster(),
+        generate.legacy.favor_legacy(),
+        DEFAULT_MAX_LIMIT as i64,
+        user_id.0 as i32,
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .map(|row| EncounterCandidate::Creature {
+        id: InternalId(row.id as u32),
+        level: row.level as i16,
+    });
+
+    let mut candidates = creatures.collect::<Vec<_>>();
+    if !generate.include_hazards {
+        return Ok(candidates);
+    }
+
+    // Hazards are not given traits for filtering, as they rarely share them with the creatures
+    let hazards = sqlx::query!(
+        r#"
+        SELECT lo.id, lh.level AS "level!", lh.complex
+        FROM library_objects lo
+        INNER JOIN library_hazards lh ON lo.id = lh.id
+        WHERE
+            lh.level >= $1 AND lh.level <= $2
+            AND ($3::int IS NULL OR lh.rarity = $3)
+            AND ($4::int IS NULL OR lo.game_system = $4)
+            AND NOT (NOT $5::bool AND lo.legacy = FALSE)
+            AND NOT (NOT $6::bool AND lo.legacy = TRUE)
+            AND NOT ($7::bool AND lo.remastering_alt_id IS NOT NULL AND lo.legacy = TRUE)
+            AND NOT ($8::bool AND lo.remastering_alt_id IS NOT NULL AND lo.legacy = FALSE)
+            AND (lo.owner IS NULL OR lo.owner = $10 OR lo.campaign_id IN (
+                SELECT id FROM campaigns WHERE owner = $10
+                UNION SELECT campaign_id FROM campaign_members WHERE user_id = $10
+            ))
+        ORDER BY RANDOM()
+        LIMIT $9
+        "#,
+        min_level + 1,
+        max_level - 1,
+        generate.rarity.as_ref().map(|r| r.as_i64() as i32),
+        generate.game_system.as_ref().map(|gs| gs.as_i64() as i32),
+        generate.legacy.include_remaster(),
+        generate.legacy.include_legacy(),
+        generate.legacy.favor_remaster(),
+        generate.legacy.favor_legacy(),
+        DEFAULT_MAX_LIMIT as i64,
+        user_id.0 as i32,
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .map(|row| EncounterCandidate::Hazard {
+        id: InternalId(row.id as u32),
+        level: row.level as i16,
+        complex: row.complex,
+    });
+
+    candidates.extend(hazards);
+    Ok(candidates)
+}
+
+// Helper function checking that library objects being attached to an encounter are official, or homebrew
+// the user can see (their own, or shared to a campaign they are part of).
+// Objects already in the encounter are allowed, so co-GMs can edit encounters using the owner's homebrew.
+async fn require_visible_library_objects(
+    conn: &mut PgConnection,
+    user_id: InternalId,
+    encounter_id: Option<InternalId>,
+    ids: impl Iterator<Item = &InternalId>,
+) -> crate::Result<()> {
+    let ids = ids.map(|id| id.0 as i32).collect::<Vec<i32>>();
+    if ids.is_empty() {
+        return Ok(());
+    }
+
+    let hidden = sqlx::query!(
+        r#"
+        SELECT lo.id
+        FROM library_objects lo
+        WHERE lo.id = ANY($1::int[])
+            AND lo.owner IS NOT NULL AND lo.owner != $2
+            AND (lo.campaign_id IS NULL OR lo.campaign_id NOT IN (
+                SELECT id FROM campaigns WHERE owner = $2
+                UNION SELECT campaign_id FROM campaign_members WHERE user_id = $2
+            ))
+            AND lo.id NOT IN (
+                SELECT enemy FROM encounter_enemies WHERE encounter = $3
+                UNION SELECT hazard FROM encounter_hazards WHERE encounter = $3
+                UNION SELECT library_item_id FROM item_instances WHERE encounter_id = $3
+            )
+        LIMIT 1
+        "#,
+        &ids,
+        user_id.0 as i32,
+        encounter_id.map(|id| id.0 as i32),
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    if let Some(row) = hidden {
+        return Err(ServerError::BadRequest(format!(
+            "Unknown library object: {}",
+            row.id
+        )));
+    }
+    Ok(())
+}
+
 // Helper function accessing creatures databases to get levels of enemies given their ids and adjustments
 // Used for default experience calculation
 async fn get_levels_enemies(
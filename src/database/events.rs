@@ -0,0 +1,208 @@
+use chrono::NaiveDateTime;
+
+use crate::models::events::EventType;
+use crate::models::ids::InternalId;
+
+// Inserts an event group with its events at the end of a session's log
+pub async fn insert_event_group(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    campaign_id: InternalId,
+    session_id: InternalId,
+    name: &str,
+    description: Option<&str>,
+    events: &[EventType],
+) -> crate::Result<InternalId> {
+    let group_id = sqlx::query!(
+        r#"
+        INSERT INTO event_groups (campaign, session_id, name, description, intra_session_order)
+        VALUES ($1, $2, $3, $4, (
+            SELECT COALESCE(MAX(intra_session_order), 0) + 1
+            FROM event_groups
+            WHERE session_id = $2
+        ))
+        RETURNING id
+        "#,
+        campaign_id.0 as i32,
+        session_id.0 as i32,
+        name,
+        description,
+    )
+    .fetch_one(&mut **tx)
+    .await?
+    .id;
+
+    let event_data = events
+        .iter()
+        .map(serde_json::to_value)
+        .collect::<Result<Vec<_>, _>>()?;
+    sqlx::query!(
+        r#"
+        INSERT INTO events (event_group, campaign, session_id, event_data, intra_session_order)
+        SELECT $1, $2, $3, event_data, intra_session_order
+        FROM UNNEST($4::jsonb[]) WITH ORDINALITY AS e(event_data, intra_session_order)
+        "#,
+        group_id,
+        campaign_id.0 as i32,
+        session_id.0 as i32,
+        &event_data,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(InternalId(group_id as u32))
+}
+
+/// An event group as stored, with its position in the session's log.
+#[derive(Debug, Clone)]
+pub struct CampaignEventGroup {
+    pub id: InternalId,
+    pub session_id: InternalId,
+    pub name: String,
+    pub description: Option<String>,
+    pub timestamp: NaiveDateTime,
+    pub intra_session_order: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct CampaignEvent {
+    pub id: InternalId,
+    pub event_group: Option<InternalId>,
+    pub session_id: InternalId,
+    pub character: Option<InternalId>,
+    pub timestamp: NaiveDateTime,
+    pub intra_session_order: i32,
+    pub event_type: EventType,
+}
+
+pub async fn get_campaign_event_groups(
+    exec: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    campaign_id: InternalId,
+) -> crate::Result<Vec<CampaignEventGroup>> {
+    let groups = sqlx::query!(
+        r#"
+        SELECT id, session_id, name, description, timestamp, intra_session_order
+        FROM event_groups
+        WHERE campaign = $1
+        ORDER BY id
+        "#,
+        campaign_id.0 as i32,
+    )
+    .fetch_all(exec)
+    .await?
+    .into_iter()
+    .map(|row| CampaignEventGroup {
+        id: InternalId(row.id as u32),
+        session_id: InternalId(row.session_id as u32),
+        name: row.name,
+        description: row.description,
+        timestamp: row.timestamp,
+        intra_session_order: row.intra_session_order,
+    })
+    .collect();
+    Ok(groups)
+}
+
+pub async fn get_campaign_events(
+    exec: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    campaign_id: InternalId,
+) -> crate::Result<Vec<CampaignEvent>> {
+    let events = sqlx::query!(
+        r#"
+        SELECT id, event_group, session_id, character, timestamp, intra_session_order, event_data
+        FROM events
+        WHERE campaign = $1
+        ORDER BY id
+        "#,
+        campaign_id.0 as i32,
+    )
+    .fetch_all(exec)
+    .await?
+    .into_iter()
+    .map(|row| {
+        Ok(CampaignEvent {
+            id: InternalId(row.id as u32),
+            event_group: row.event_group.map(|id| InternalId(id as u32)),
+            session_id: InternalId(row.session_id as u32),
+            character: row.character.map(|id| InternalId(id as u32)),
+            timestamp: row.timestamp,
+            intra_session_order: row.intra_session_order,
+            event_type: serde_json::from_value(row.event_data)?,
+        })
+    })
+    .collect::<crate::Result<Vec<_>>>()?;
+    Ok(events)
+}
+
+// Inserts event groups as-is (ignoring their ids), returning the new ids in order
+pub async fn insert_campaign_event_groups(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    campaign_id: InternalId,
+    groups: &[CampaignEventGroup],
+) -> crate::Result<Vec<InternalId>> {
+    let ids = sqlx::query!(
+        r#"
+        INSERT INTO event_groups (campaign, session_id, name, description, timestamp, intra_session_order)
+        SELECT $1, session_id, name, description, timestamp, intra_session_order
+        FROM UNNEST($2::int[], $3::text[], $4::text[], $5::timestamp[], $6::int[])
+            AS g(session_id, name, description, timestamp, intra_session_order)
+        RETURNING id
+        "#,
+        campaign_id.0 as i32,
+        &groups.iter().map(|g| g.session_id.0 as i32).collect::<Vec<i32>>(),
+        &groups.iter().map(|g| g.name.clone()).collect::<Vec<String>>(),
+        &groups
+            .iter()
+            .map(|g| g.description.clone())
+            .collect::<Vec<Option<String>>>() as _,
+        &groups.iter().map(|g| g.timestamp).collect::<Vec<NaiveDateTime>>(),
+        &groups
+            .iter()
+            .map(|g| g.intra_session_order)
+            .collect::<Vec<i32>>(),
+    )
+    .fetch_all(&mut **tx)
+    .await?
+    .into_iter()
+    .map(|row| InternalId(row.id as u32))
+    .collect();
+    Ok(ids)
+}
+
+// Inserts events as-is (ignoring their ids)
+pub async fn insert_campaign_events(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    campaign_id: InternalId,
+    events: &[CampaignEvent],
+) -> crate::Result<()> {
+    let event_data = events
+        .iter()
+        .map(|e| serde_json::to_value(&e.event_type))
+        .collect::<Result<Vec<_>, _>>()?;
+    sqlx::query!(
+        r#"
+        INSERT INTO events (campaign, event_group, session_id, character, timestamp, intra_session_order, event_data)
+        SELECT $1, event_group, session_id, character, timestamp, intra_session_order, event_data
+        FROM UNNEST($2::int[], $3::int[], $4::int[], $5::timestamp[], $6::int[], $7::jsonb[])
+            AS e(event_group, session_id, character, timestamp, intra_session_order, event_data)
+        "#,
+        campaign_id.0 as i32,
+        &events
+            .iter()
+            .map(|e| e.event_group.map(|id| id.0 as i32))
+            .collect::<Vec<Option<i32>>>() as _,
+        &events.iter().map(|e| e.session_id.0 as i32).collect::<Vec<i32>>(),
+        &events
+            .iter()
+            .map(|e| e.character.map(|id| id.0 as i32))
+            .collect::<Vec<Option<i32>>>() as _,
+        &events.iter().map(|e| e.timestamp).collect::<Vec<NaiveDateTime>>(),
+        &events
+            .iter()
+            .map(|e| e.intra_session_order)
+            .collect::<Vec<i32>>(),
+        &event_data,
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,1025 @@
+use crate::{
+    database::{
+        creatures::InsertLibraryCreature,
+        hazards::InsertLibraryHazard,
+        items::{InsertLibraryItem, InsertRune},
+        spells::InsertLibrarySpell,
+        LibraryUpsertReport,
+    },
+    models::{
+        characters::{Skill, Stat},
+        ids::InternalId,
+        library::{
+            creature::{
+                Alignment, CreatureAbility, CreatureSaves, CreatureSkill, CreatureSpeed,
+                CreatureStatBlock, CreatureStrike, DamageAdjustment, Size, StrikeDamage,
+            },
+            item::{Rune, RuneItemType, SkillPotency},
+            spell::{SavingThrow, SpellHeightening},
+            GameSystem, Rarity,
+        },
+    },
+    ServerError,
+};
+use itertools::Itertools;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    str::FromStr,
+};
+
+/*
+Importer for the Foundry VTT pf2e system compendium (https://github.com/foundryvtt/pf2e).
+
+The compendium is a directory of packs ('packs/<pack>/<document>.json'), where each file is a
+single document. Older releases bundle each pack as newline-delimited JSON ('packs/<pack>.db'), which
+is also accepted. Documents are recognized by their 'type':
+- 'npc': creatures
+- 'hazard': hazards
+- 'spell': spells
+- 'weapon', 'armor', 'shield', 'equipment', 'consumable', 'treasure', 'backpack': items
+Everything else (feats, actions, folders, ...) is skipped.
+
+Objects are upserted with their document id as the source id ('foundry:<_id>'), so importing a newer
+release of the compendium updates the library in place.
+*/
+
+const ITEM_TYPES: [&str; 7] = [
+    "weapon",
+    "armor",
+    "shield",
+    "equipment",
+    "consumable",
+    "treasure",
+    "backpack",
+];
+
+#[derive(Default, Debug)]
+pub struct FoundryLibrary {
+    pub creatures: Vec<FoundryLibraryObject<InsertLibraryCreature>>,
+    pub items: Vec<FoundryLibraryObject<InsertLibraryItem>>,
+    pub spells: Vec<FoundryLibraryObject<InsertLibrarySpell>>,
+    pub hazards: Vec<FoundryLibraryObject<InsertLibraryHazard>>,
+    pub skipped: usize,
+}
+
+/// A mapped library object, with its traits (which are inserted as tags once ids are known).
+#[derive(Debug)]
+pub struct FoundryLibraryObject<T> {
+    pub object: T,
+    pub traits: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ImportFoundryLibrary {
+    // Directory on the server containing the compendium packs
+    pub path: String,
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FoundryImportReport {
+    pub creatures: LibraryUpsertReport,
+    pub items: LibraryUpsertReport,
+    pub spells: LibraryUpsertReport,
+    pub hazards: LibraryUpsertReport,
+    // Documents that are not library objects
+    pub skipped: usize,
+}
+
+#[derive(Deserialize, Debug)]
+struct FoundryDocument {
+    #[serde(rename = "_id")]
+    id: Option<String>,
+    name: String,
+    #[serde(rename = "type")]
+    document_type: Option<String>,
+    #[serde(default)]
+    system: Value,
+    // Embedded documents of an actor, such as a creature's strikes and abilities
+    #[serde(default)]
+    items: Vec<FoundryDocument>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+struct FoundryTraits {
+    value: Vec<String>,
+    rarity: Option<String>,
+    size: Option<FoundryValue<String>>,
+    traditions: Vec<String>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+struct FoundryValue<T: Default> {
+    value: T,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+struct FoundryPublication {
+    remaster: bool,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+struct FoundryActorSystem {
+    details: FoundryActorDetails,
+    traits: FoundryTraits,
+    attributes: Option<FoundryActorAttributes>,
+    perception: Option<FoundryModifier>,
+    saves: HashMap<String, FoundryValue<i32>>,
+    skills: HashMap<String, FoundryModifier>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+struct FoundryActorAttributes {
+    ac: FoundryValue<i32>,
+    hp: FoundryHitPoints,
+    // Older versions of the system keep perception here
+    perception: Option<FoundryValue<i32>>,
+    speed: FoundrySpeed,
+    immunities: Vec<FoundryDamageAdjustment>,
+    resistances: Vec<FoundryDamageAdjustment>,
+    weaknesses: Vec<FoundryDamageAdjustment>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+struct FoundryHitPoints {
+    max: i32,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+struct FoundryModifier {
+    #[serde(alias = "base")]
+    #[serde(rename = "mod")]
+    modifier: i32,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+#[serde(rename_all = "camelCase")]
+struct FoundrySpeed {
+    value: i32,
+    other_speeds: Vec<FoundryDamageAdjustment>,
+}
+
+// Shared shape of immunities, resistances, weaknesses and other speeds: a type, with a value for all but immunities
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+struct FoundryDamageAdjustment {
+    #[serde(rename = "type")]
+    kind: String,
+    value: Option<i32>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+#[serde(rename_all = "camelCase")]
+struct FoundryEmbeddedSystem {
+    traits: FoundryTraits,
+    description: FoundryValue<String>,
+    // Strikes
+    bonus: FoundryValue<i32>,
+    damage_rolls: HashMap<String, FoundryDamageRoll>,
+    // Abilities
+    action_type: FoundryValue<String>,
+    actions: FoundryValue<Option<i32>>,
+    // Lore skills
+    #[serde(rename = "mod")]
+    modifier: FoundryValue<i32>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+#[serde(rename_all = "camelCase")]
+struct FoundryDamageRoll {
+    damage: String,
+    damage_type: String,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+#[serde(rename_all = "camelCase")]
+struct FoundryActorDetails {
+    level: FoundryValue<i64>,
+    alignment: Option<FoundryValue<String>>,
+    public_notes: Option<String>,
+    description: Option<String>,
+    is_complex: bool,
+    publication: FoundryPublication,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+struct FoundryItemSystem {
+    level: FoundryValue<i64>,
+    price: FoundryPrice,
+    traits: FoundryTraits,
+    description: FoundryValue<String>,
+    publication: FoundryPublication,
+    category: Option<String>,
+    usage: Option<FoundryValue<String>>,
+    runes: FoundryRunes,
+    rules: Vec<Value>,
+    // Spells only
+    time: Option<FoundryValue<String>>,
+    defense: Option<FoundrySpellDefense>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+struct FoundrySpellDefense {
+    save: Option<FoundrySave>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+struct FoundrySave {
+    statistic: String,
+    basic: bool,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+struct FoundryPrice {
+    value: HashMap<String, f64>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+struct FoundryRunes {
+    potency: i8,
+    striking: i8,
+    resilient: i8,
+    reinforcing: i8,
+    property: Vec<String>,
+}
+
+impl FoundryLibrary {
+    /// Reads every Foundry document in a directory, recursively.
+    pub fn read_directory(path: &Path) -> crate::Result<Self> {
+        let mut library = Self::default();
+        let mut paths = vec![path.to_path_buf()];
+        while let Some(path) = paths.pop() {
+            let metadata = std::fs::metadata(&path).map_err(|e| {
+                ServerError::BadRequest(format!("Could not read {}: {}", path.display(), e))
+            })?;
+            if metadata.is_dir() {
+                let entries = std::fs::read_dir(&path)
+                    .and_then(|entries| entries.collect::<Result<Vec<_>, _>>())
+                    .map_err(|e| {
+                        ServerError::InternalError(format!("Could not read directory: {}", e))
+                    })?;
+                // Sort so that the insertion order is the same between runs
+                paths.extend(entries.into_iter().map(|e| e.path()).sorted().rev());
+                continue;
+            }
+
+            let extension = path.extension().and_then(|e| e.to_str());
+            if !matches!(extension, Some("json") | Some("db")) {
+                continue;
+            }
+            let contents = std::fs::read_to_string(&path)
+                .map_err(|e| ServerError::InternalError(format!("Could not read file: {}", e)))?;
+            if extension == Some("db") {
+                for line in contents.lines().filter(|l| !l.trim().is_empty()) {
+                    library.add_document(serde_json::from_str(line)?);
+                }
+            } else {
+                match serde_json::from_str(&contents)? {
+                    Value::Array(documents) => documents
+                        .into_iter()
+                        .for_each(|document| library.add_document(document)),
+                    document => library.add_document(document),
+                }
+            }
+        }
+        Ok(library)
+    }
+
+    pub fn add_document(&mut self, document: Value) {
+        let Ok(document) = serde_json::from_value::<FoundryDocument>(document) else {
+            self.skipped += 1;
+            return;
+        };
+        let document_type = document.document_type.clone().unwrap_or_default();
+        let mapped = match document_type.as_str() {
+            "npc" => map_creature(document).map(|c| self.creatures.push(c)),
+            "hazard" => map_hazard(document).map(|h| self.hazards.push(h)),
+            "spell" => map_spell(document).map(|s| self.spells.push(s)),
+            t if ITEM_TYPES.contains(&t) => map_item(document).map(|i| self.items.push(i)),
+            _ => None,
+        };
+        if mapped.is_none() {
+            self.skipped += 1;
+        }
+    }
+}
+
+fn map_creature(document: FoundryDocument) -> Option<FoundryLibraryObject<InsertLibraryCreature>> {
+    let system: FoundryActorSystem = serde_json::from_value(document.system).ok()?;
+    let stat_block = map_stat_block(&system, &document.items);
+    let details = system.details;
+    let creature = InsertLibraryCreature {
+        requested_id: None,
+        source_id: document.id.map(|id| format!("foundry:{}", id)),
+        owner: None,
+        name: document.name,
+        game_system: GameSystem::PF2E,
+        rarity: map_rarity(&system.traits.rarity),
+        level: details.level.value as i8,
+        tags: vec![],
+        // Remastered creatures have no alignment
+        alignment: details
+            .alignment
+            .and_then(|a| Alignment::from_str(&a.value).ok())
+            .unwrap_or(Alignment::None),
+        size: system
+            .traits
+            .size
+            .map(|s| map_size(&s.value))
+            .unwrap_or_default(),
+        legacy: !details.publication.remaster,
+        remastering_alt_id: None,
+        url: None,
+        description: strip_html(&details.public_notes.unwrap_or_default()),
+        stat_block,
+    };
+    Some(FoundryLibraryObject {
+        object: creature,
+        traits: map_traits(&system.traits.value),
+    })
+}
+
+fn map_stat_block(
+    system: &FoundryActorSystem,
+    items: &[FoundryDocument],
+) -> Option<CreatureStatBlock> {
+    let attributes = system.attributes.as_ref()?;
+    let save = |name: &str| system.saves.get(name).map(|s| s.value).unwrap_or_default();
+
+    let mut skills = system
+        .skills
+        .iter()
+        .map(|(name, skill)| CreatureSkill {
+            name: title_case(name),
+            modifier: skill.modifier,
+        })
+        .collect::<Vec<_>>();
+    let mut strikes = vec![];
+    let mut abilities = vec![];
+    for item in items {
+        let Ok(embedded) = serde_json::from_value::<FoundryEmbeddedSystem>(item.system.clone())
+        else {
+            continue;
+        };
+        match item.document_type.as_deref() {
+            Some("lore") => skills.push(CreatureSkill {
+                name: item.name.clone(),
+                modifier: embedded.modifier.value,
+            }),
+            Some("melee") => strikes.push(CreatureStrike {
+                name: item.name.clone(),
+                ranged: embedded.traits.value.iter().any(|t| t.starts_with("range")),
+                attack: embedded.bonus.value,
+                traits: map_traits(&embedded.traits.value),
+                damage: embedded
+                    .damage_rolls
+                    .into_values()
+                    // Damage rolls are keyed by random ids- put the largest (usually the weapon's own) first
+                    .sorted_by(|a, b| a.damage.cmp(&b.damage).reverse())
+                    .map(|d| StrikeDamage {
+                        roll: d.damage.replace(' ', ""),
+                        damage_type: d.damage_type,
+                    })
+                    .collect(),
+            }),
+            Some("action") => abilities.push(CreatureAbility {
+                name: item.name.clone(),
+                actions: match (embedded.action_type.value.as_str(), embedded.actions.value) {
+                    ("action", Some(1)) => Some("one".to_string()),
+                    ("action", Some(2)) => Some("two".to_string()),
+                    ("action", Some(3)) => Some("three".to_string()),
+                    ("reaction", _) => Some("reaction".to_string()),
+                    ("free", _) => Some("free".to_string()),
+                    _ => None,
+                },
+                traits: map_traits(&embedded.traits.value),
+                description: strip_html(&embedded.description.value),
+            }),
+            _ => {}
+        }
+    }
+    skills.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let damage_adjustments = |adjustments: &[FoundryDamageAdjustment]| {
+        adjustments
+            .iter()
+            .map(|a| DamageAdjustment {
+                damage_type: a.kind.clone(),
+                value: a.value.unwrap_or_default(),
+            })
+            .collect::<Vec<_>>()
+    };
+    Some(CreatureStatBlock {
+        ac: attributes.ac.value,
+        hp: attributes.hp.max,
+        perception: system
+            .perception
+            .as_ref()
+            .map(|p| p.modifier)
+            .or(attributes.perception.as_ref().map(|p| p.value))
+            .unwrap_or_default(),
+        saves: CreatureSaves {
+            fortitude: save("fortitude"),
+            reflex: save("reflex"),
+            will: save("will"),
+        },
+        skills,
+        speeds: std::iter::once(CreatureSpeed {
+            movement: "land".to_string(),
+            feet: attributes.speed.value,
+        })
+        .chain(attributes.speed.other_speeds.iter().map(|s| CreatureSpeed {
+            movement: s.kind.clone(),
+            feet: s.value.unwrap_or_default(),
+        }))
+        .filter(|s| s.feet > 0)
+        .collect(),
+        immunities: attributes
+            .immunities
+            .iter()
+            .map(|i| i.kind.clone())
+            .collect(),
+        resistances: damage_adjustments(&attributes.resistances),
+        weaknesses: damage_adjustments(&attributes.weaknesses),
+        strikes,
+        abilities,
+    })
+}
+
+fn map_hazard(document: FoundryDocument) -> Option<FoundryLibraryObject<InsertLibraryHazard>> {
+    let system: FoundryActorSystem = serde_json::from_value(document.system).ok()?;
+    let details = system.details;
+    let hazard = InsertLibraryHazard {
+        requested_id: None,
+        source_id: document.id.map(|id| format!("foundry:{}", id)),
+        owner: None,
+        name: document.name,
+        game_system: GameSystem::PF2E,
+        rarity: map_rarity(&system.traits.rarity),
+        level: details.level.value as i8,
+        tags: vec![],
+        legacy: !details.publication.remaster,
+        remastering_alt_id: None,
+        url: None,
+        description: strip_html(&details.description.unwrap_or_default()),
+        complex: details.is_complex,
+        haunt: system.traits.value.iter().any(|t| t == "haunt"),
+    };
+    Some(FoundryLibraryObject {
+        object: hazard,
+        traits: map_traits(&system.traits.value),
+    })
+}
+
+fn map_spell(document: FoundryDocument) -> Option<FoundryLibraryObject<InsertLibrarySpell>> {
+    let system: FoundryItemSystem = serde_json::from_value(document.system).ok()?;
+    let description = strip_html(&system.description.value);
+    let save = system.defense.as_ref().and_then(|d| d.save.as_ref());
+    let spell = InsertLibrarySpell {
+        requested_id: None,
+        source_id: document.id.map(|id| format!("foundry:{}", id)),
+        owner: None,
+        name: document.name,
+        game_system: GameSystem::PF2E,
+        rarity: map_rarity(&system.traits.rarity),
+        rank: system.level.value as u8,
+        tags: vec![],
+        legacy: !system.publication.remaster,
+        remastering_alt_id: None,
+        traditions: system
+            .traits
+            .traditions
+            .iter()
+            .map(|t| title_case(t))
+            .collect(),
+        actions: system.time.map(|t| t.value).filter(|t| !t.is_empty()),
+        saving_throw: save.and_then(|s| SavingThrow::from_str(&s.statistic).ok()),
+        basic_save: save.is_some_and(|s| s.basic),
+        heightening: map_heightening(&description),
+        url: None,
+        description,
+    };
+    Some(FoundryLibraryObject {
+        object: spell,
+        traits: map_traits(&system.traits.value),
+    })
+}
+
+fn map_item(document: FoundryDocument) -> Option<FoundryLibraryObject<InsertLibraryItem>> {
+    let document_type = document.document_type.unwrap_or_default();
+    let system: FoundryItemSystem = serde_json::from_value(document.system).ok()?;
+    let traits = &system.traits.value;
+    let has_trait = |t: &str| traits.iter().any(|x| x == t);
+
+    let mut item_categories = vec![title_case(&document_type)];
+    if let Some(category) = &system.category {
+        item_categories.push(title_case(category));
+    }
+
+    let runic_context = map_rune_context(&document.name, &system);
+    let item_type = match (document_type.as_str(), &runic_context) {
+        (_, Some((fundamental, _))) if *fundamental => RuneItemType::FundamentalRune,
+        (_, Some(_)) => RuneItemType::PropertyRune,
+        ("weapon", _) => RuneItemType::Weapon,
+        ("armor", _) => RuneItemType::Armor,
+        ("shield", _) => RuneItemType::Shield,
+        _ => RuneItemType::None,
+    };
+
+    let item = InsertLibraryItem {
+        requested_id: None,
+        source_id: document.id.map(|id| format!("foundry:{}", id)),
+        owner: None,
+        game_system: GameSystem::PF2E,
+        rarity: map_rarity(&system.traits.rarity),
+        level: system.level.value as i8,
+        price: map_price(&system.price),
+        url: None,
+        description: strip_html(&system.description.value),
+        item_categories,
+        tags: vec![],
+        consumable: document_type == "consumable" || has_trait("consumable"),
+        magical: ["magical", "arcane", "divine", "occult", "primal"]
+            .iter()
+            .any(|t| has_trait(t)),
+        legacy: !system.publication.remaster,
+        cursed: has_trait("cursed"),
+        relic_gift_stage: None,
+        item_type,
+        skill_boosts: map_skill_boosts(&system.rules),
+        runes: map_runes(&system.runes, item_type),
+        apex_stat: has_trait("apex")
+            .then(|| map_apex_stat(&system.rules))
+            .flatten(),
+        remastering_alt_id: None,
+        base_item_id: None,
+        runic_context: runic_context.map(|(_, rune)| rune),
+        name: document.name,
+    };
+    Some(FoundryLibraryObject {
+        object: item,
+        traits: map_traits(traits),
+    })
+}
+
+/// Rune items are etched onto other items. Returns whether the rune is fundamental, and its context.
+fn map_rune_context(name: &str, system: &FoundryItemSystem) -> Option<(bool, InsertRune)> {
+    let usage = system.usage.as_ref()?.value.as_str();
+    let applied_to = match usage {
+        "etched-onto-a-weapon" => RuneItemType::Weapon,
+        "etched-onto-armor" => RuneItemType::Armor,
+        "etched-onto-a-shield" => RuneItemType::Shield,
+        _ => return None,
+    };
+
+    // Names are either 'Base (Modifier)' or 'Base (+N)'
+    let (base, modifier) = match name.split_once(" (") {
+        Some((base, modifier)) => (base, modifier.trim_end_matches(')')),
+        None => (name, ""),
+    };
+    let fundamental = Rune::from_parts(base, 0, applied_to);
+    let (stat_boost_category_id, fundamental) = match fundamental {
+        Rune::WeaponPotency { .. } => (Some(1), true),
+        Rune::ArmorPotency { .. } => (Some(2), true),
+        Rune::Striking { .. } => (Some(3), true),
+        Rune::Resilient { .. } => (Some(5), true),
+        Rune::ShieldPotency { .. } => (None, true),
+        Rune::PropertyRune { .. } => (None, false),
+    };
+    let potency = if fundamental {
+        match modifier {
+            "" => 1,
+            "Greater" => 2,
+            "Major" => 3,
+            m => m.trim_start_matches('+').parse().unwrap_or(1),
+        }
+    } else {
+        // For a property rune, the potency is the rune's level
+        system.level.value as i8
+    };
+
+    Some((
+        fundamental,
+        InsertRune {
+            runic_stat_boost_category_bonus_id: stat_boost_category_id,
+            potency,
+            base_rune: Some(base.to_string()),
+            applied_to,
+        },
+    ))
+}
+
+/// Runes already etched onto a weapon, armor or shield.
+/// The potency of a property rune is its level, which is not known here: it is resolved against the
+/// imported rune items by `resolve_property_runes`.
+fn map_runes(runes: &FoundryRunes, item_type: RuneItemType) -> Vec<Rune> {
+    let fundamental = match item_type {
+        RuneItemType::Weapon => vec![
+            Rune::WeaponPotency {
+                potency: runes.potency,
+            },
+            Rune::Striking {
+                potency: runes.striking,
+            },
+        ],
+        RuneItemType::Armor => vec![
+            Rune::ArmorPotency {
+                potency: runes.potency,
+            },
+            Rune::Resilient {
+                potency: runes.resilient,
+            },
+        ],
+        RuneItemType::Shield => vec![Rune::ShieldPotency {
+            potency: runes.reinforcing,
+        }],
+        _ => return vec![],
+    };
+    fundamental
+        .into_iter()
+        .filter(|rune| rune.get_potency() > 0)
+        .chain(runes.property.iter().map(|slug| Rune::PropertyRune {
+            property: title_case(slug),
+            applied_to: item_type,
+            potency: 0,
+        }))
+        .collect()
+}
+
+/// Replaces property runes named after their Foundry slug ('Greater Flaming') with the base name and
+/// level of the matching rune item ('Flaming (Greater)', level 15).
+fn resolve_property_runes(items: &mut [InsertLibraryItem]) {
+    let property_runes = items
+        .iter()
+        .filter(|i| matches!(i.item_type, RuneItemType::PropertyRune))
+        .filter_map(|i| {
+            let rune = i.runic_context.as_ref()?;
+            let slug = match i.name.split_once(" (") {
+                Some((base, modifier)) => format!("{} {}", modifier.trim_end_matches(')'), base),
+                None => i.name.clone(),
+            };
+            Some((slug.to_lowercase(), (rune.base_rune.clone()?, rune.potency)))
+        })
+        .collect::<HashMap<String, (String, i8)>>();
+
+    for rune in items.iter_mut().flat_map(|i| i.runes.iter_mut()) {
+        if let Rune::PropertyRune {
+            property, potency, ..
+        } = rune
+        {
+            if let Some((base, level)) = property_runes.get(&property.to_lowercase()) {
+                *property = base.clone();
+                *potency = *level;
+            }
+        }
+    }
+}
+
+/// Item bonuses to skills, from 'FlatModifier' rule elements.
+fn map_skill_boosts(rules: &[Value]) -> Vec<SkillPotency> {
+    rules
+        .iter()
+        .filter(|rule| rule["key"] == "FlatModifier" && rule["type"] == "item")
+        .filter_map(|rule| {
+            let skill = map_skill(rule["selector"].as_str()?)?;
+            let bonus = rule["value"].as_i64()? as i8;
+            Some(SkillPotency { skill, bonus })
+        })
+        .collect()
+}
+
+fn map_skill(selector: &str) -> Option<Skill> {
+    if let Some(lore) = selector.strip_suffix("-lore") {
+        return Some(Skill::Lore(Some(title_case(lore))));
+    }
+    Skill::from_str(&title_case(selector)).filter(|s| *s != Skill::Unknown)
+}
+
+/// Apex items set the character's apex attribute through an override of 'system.build.attributes.apex'.
+fn map_apex_stat(rules: &[Value]) -> Option<Stat> {
+    let attribute = rules
+        .iter()
+        .find(|rule| rule["path"] == "system.build.attributes.apex")?["value"]
+        .as_str()?;
+    match attribute {
+        "str" => Some(Stat::Strength),
+        "dex" => Some(Stat::Dexterity),
+        "con" => Some(Stat::Constitution),
+        "int" => Some(Stat::Intelligence),
+        "wis" => Some(Stat::Wisdom),
+        "cha" => Some(Stat::Charisma),
+        _ => None,
+    }
+}
+
+fn map_price(price: &FoundryPrice) -> Option<f64> {
+    if price.value.is_empty() {
+        return None;
+    }
+    let gold = price
+        .value
+        .iter()
+        .map(|(denomination, amount)| match denomination.as_str() {
+            "pp" => amount * 10.0,
+            "gp" => *amount,
+            "sp" => amount / 10.0,
+            "cp" => amount / 100.0,
+            _ => 0.0,
+        })
+        .sum();
+    Some(gold)
+}
+
+fn map_rarity(rarity: &Option<String>) -> Rarity {
+    rarity
+        .as_deref()
+        .and_then(|r| Rarity::from_str(&title_case(r)).ok())
+        .unwrap_or_default()
+}
+
+fn map_size(size: &str) -> Size {
+    match size {
+        "tiny" => Size::Tiny,
+        "sm" => Size::Small,
+        "lg" => Size::Large,
+        "huge" => Size::Huge,
+        "grg" => Size::Gargantuan,
+        _ => Size::Medium,
+    }
+}
+
+fn map_traits(traits: &[String]) -> Vec<String> {
+    traits.iter().map(|t| title_case(t)).collect()
+}
+
+/// 'greater-flaming' or 'greaterFlaming' -> 'Greater Flaming'
+fn title_case(s: &str) -> String {
+    lazy_static! {
+        static ref WORD_BOUNDARY: Regex = Regex::new(r"([a-z])([A-Z])").unwrap();
+    }
+    WORD_BOUNDARY
+        .replace_all(s, "$1 $2")
+        .split(['-', '_', ' '])
+        .filter(|w| !w.is_empty())
+        .map(|w| {
+            let mut chars = w.chars();
+            chars
+                .next()
+                .map(|c| c.to_uppercase().chain(chars).collect::<String>())
+                .unwrap_or_default()
+        })
+        .join(" ")
+}
+
+/// Foundry descriptions are HTML, with enrichers such as '@UUID[...]{Label}' and '[[/r 1d6]]'.
+// Heightened entries are only given in the description, as their own paragraphs
+// (eg: "Heightened (+1) The damage increases by 2d6." or "Heightened (4th) You can target...")
+fn map_heightening(description: &str) -> Vec<SpellHeightening> {
+    lazy_static! {
+        static ref HEIGHTENED: Regex =
+            Regex::new(r"^Heightened \((?:\+(\d+)|(\d+)(?:st|nd|rd|th))\)\s*(.*)$").unwrap();
+    }
+    description
+        .lines()
+        .filter_map(|line| {
+            let captures = HEIGHTENED.captures(line)?;
+            let description = captures[3].to_string();
+            match (captures.get(1), captures.get(2)) {
+                (Some(interval), _) => Some(SpellHeightening::Interval {
+                    interval: interval.as_str().parse().ok()?,
+                    description,
+                }),
+                (_, Some(rank)) => Some(SpellHeightening::Fixed {
+                    rank: rank.as_str().parse().ok()?,
+                    description,
+                }),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn strip_html(s: &str) -> String {
+    lazy_static! {
+        static ref ENRICHER: Regex = Regex::new(r"@\w+\[[^\]]*\](\{([^}]*)\})?").unwrap();
+        static ref ROLL: Regex = Regex::new(r"\[\[/\w+ ([^\]]*)\]\](\{([^}]*)\})?").unwrap();
+        static ref TAG: Regex = Regex::new(r"<[^>]*>").unwrap();
+        static ref PARAGRAPH: Regex = Regex::new(r"</p>|<br ?/?>|<hr ?/?>").unwrap();
+    }
+    let s = ENRICHER.replace_all(s, "$2");
+    let s = ROLL.replace_all(&s, |c: &regex::Captures| {
+        c.get(3)
+            .or(c.get(1))
+            .map(|m| m.as_str())
+            .unwrap_or_default()
+            .to_string()
+    });
+    let s = PARAGRAPH.replace_all(&s, "\n");
+    let s = TAG.replace_all(&s, "");
+    s.lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty())
+        .join("\n")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+/// Attaches trait tags to mapped objects. Objects that appear twice in the import are only kept once.
+fn with_tags<T>(
+    objects: Vec<FoundryLibraryObject<T>>,
+    trait_ids: &HashMap<String, InternalId>,
+    key: impl Fn(&T) -> (Option<String>, String, bool),
+    tags: impl Fn(&mut T) -> &mut Vec<InternalId>,
+) -> Vec<T> {
+    let mut seen = HashSet::new();
+    objects
+        .into_iter()
+        .filter(|o| seen.insert(key(&o.object)))
+        .map(|mut o| {
+            *tags(&mut o.object) = o
+                .traits
+                .iter()
+                .filter_map(|t| trait_ids.get(t))
+                .copied()
+                .collect();
+            o.object
+        })
+        .collect()
+}
+
+/// Upserts everything in the Foundry library.
+pub async fn import_foundry_library(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    library: FoundryLibrary,
+) -> crate::Result<FoundryImportReport> {
+    let all_traits = library
+        .creatures
+        .iter()
+        .flat_map(|c| &c.traits)
+        .chain(library.items.iter().flat_map(|i| &i.traits))
+        .chain(library.spells.iter().flat_map(|s| &s.traits))
+        .chain(library.hazards.iter().flat_map(|h| &h.traits))
+        .cloned()
+        .unique()
+        .collect::<Vec<String>>();
+    let trait_ids = super::tags::get_or_insert_traits(tx, &all_traits).await?;
+
+    let creatures = with_tags(
+        library.creatures,
+        &trait_ids,
+        |c| (c.source_id.clone(), c.name.clone(), c.legacy),
+        |c| &mut c.tags,
+    );
+    let mut items = with_tags(
+        library.items,
+        &trait_ids,
+        |i| (i.source_id.clone(), i.name.clone(), i.legacy),
+        |i| &mut i.tags,
+    );
+    let spells = with_tags(
+        library.spells,
+        &trait_ids,
+        |s| (s.source_id.clone(), s.name.clone(), s.legacy),
+        |s| &mut s.tags,
+    );
+    let hazards = with_tags(
+        library.hazards,
+        &trait_ids,
+        |h| (h.source_id.clone(), h.name.clone(), h.legacy),
+        |h| &mut h.tags,
+    );
+
+    // Runes must be inserted before the items they are etched onto
+    resolve_property_runes(&mut items);
+    items.sort_by_key(|i| i.runic_context.is_none());
+
+    Ok(FoundryImportReport {
+        creatures: super::creatures::upsert_creatures(tx, &creatures).await?,
+        items: super::items::upsert_items(tx, &items).await?,
+        spells: super::spells::upsert_spells(tx, &spells).await?,
+        hazards: super::hazards::upsert_hazards(tx, &hazards).await?,
+        skipped: library.skipped,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_foundry_mapping() {
+        let mut library = FoundryLibrary::default();
+        library.add_document(json!({
+            "name": "Goblin Warrior",
+            "type": "npc",
+            "system": {
+                "details": {
+                    "level": { "value": -1 },
+                    "publicNotes": "<p>Goblins are @UUID[Compendium.pf2e.x]{small} pests.</p>",
+                    "publication": { "remaster": true }
+                },
+                "traits": { "rarity": "common", "size": { "value": "sm" }, "value": ["goblin", "humanoid"] }
+            }
+        }));
+        library.add_document(json!({
+            "name": "Striking (Greater)",
+            "type": "equipment",
+            "system": {
+                "level": { "value": 12 },
+                "price": { "value": { "gp": 1065 } },
+                "traits": { "rarity": "common", "value": ["magical"] },
+                "usage": { "value": "etched-onto-a-weapon" }
+            }
+        }));
+        library.add_document(json!({
+            "name": "Flaming Longsword",
+            "type": "weapon",
+            "system": {
+                "level": { "value": 8 },
+                "price": { "value": { "gp": 500, "sp": 5 } },
+                "traits": { "rarity": "uncommon", "value": ["versatile-p"] },
+                "runes": { "potency": 1, "striking": 1, "property": ["flaming"] }
+            }
+        }));
+        library.add_document(json!({
+            "name": "Belt of Giant Strength",
+            "type": "equipment",
+            "system": {
+                "level": { "value": 17 },
+                "traits": { "value": ["apex", "invested", "magical"] },
+                "rules": [
+                    { "key": "FlatModifier", "selector": "athletics", "type": "item", "value": 3 },
+                    { "key": "ActiveEffectLike", "path": "system.build.attributes.apex", "value": "str" }
+                ]
+            }
+        }));
+        library.add_document(json!({ "name": "Power Attack", "type": "feat", "system": {} }));
+
+        assert_eq!(library.skipped, 1);
+
+        let goblin = &library.creatures[0];
+        assert_eq!(goblin.object.level, -1);
+        assert_eq!(goblin.object.size, Size::Small);
+        assert_eq!(goblin.object.alignment, Alignment::None);
+        assert!(!goblin.object.legacy);
+        assert_eq!(goblin.object.description, "Goblins are small pests.");
+        assert_eq!(goblin.traits, vec!["Goblin", "Humanoid"]);
+
+        let striking = &library.items[0].object;
+        assert_eq!(striking.item_type, RuneItemType::FundamentalRune);
+        let rune = striking.runic_context.as_ref().unwrap();
+        assert_eq!(rune.potency, 2);
+        assert_eq!(rune.base_rune.as_deref(), Some("Striking"));
+        assert_eq!(rune.applied_to, RuneItemType::Weapon);
+        assert!(striking.legacy);
+
+        let longsword = &library.items[1].object;
+        assert_eq!(longsword.item_type, RuneItemType::Weapon);
+        assert_eq!(longsword.price, Some(500.5));
+        assert_eq!(longsword.rarity, Rarity::Uncommon);
+        assert_eq!(
+            longsword.runes,
+            vec![
+                Rune::WeaponPotency { potency: 1 },
+                Rune::Striking { potency: 1 },
+                Rune::PropertyRune {
+                    property: "Flaming".to_string(),
+                    applied_to: RuneItemType::Weapon,
+                    potency: 0
+                }
+            ]
+        );
+
+        let belt = &library.items[2].object;
+        assert_eq!(belt.price, None);
+        assert_eq!(belt.apex_stat, Some(Stat::Strength));
+        assert_eq!(
+            belt.skill_boosts,
+            vec![SkillPotency {
+                skill: Skill::Athletics,
+                bonus: 3
+            }]
+        );
+    }
+}
@@ -3,17 +3,20 @@ use std::collections::HashMap;
 use crate::models::ids::InternalId;
 use crate::models::library::{
     hazard::{HazardType, LibraryHazard},
-    GameSystem, Rarity,
+    GameSystem, LibraryObjectType, Rarity,
 };
 
 use crate::models::query::CommaSeparatedVec;
 use crate::ServerError;
-use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use sqlx::PgConnection;
 
 use super::sorts::{Sortable, SortableColumn};
-use super::{check_library_requested_ids, tags, LegacyStatus, DEFAULT_MAX_LIMIT};
+use super::{
+    insert_library_objects, set_library_object_tags, InsertLibraryObject, LibraryUpsert,
+    LibraryUpsertReport,
+};
+use super::{tags, HomebrewVisibility, LegacyStatus, DEFAULT_MAX_LIMIT};
 
 #[derive(Default, Serialize, Deserialize, Debug, Clone)]
 pub struct HazardFiltering {
@@ -34,8 +37,14 @@ pub struct HazardFiltering {
     #[serde(default)]
     pub legacy: LegacyStatus,
 
+    // Homebrew objects visible to the requesting user. Not part of the query string.
+    #[serde(skip)]
+    pub homebrew: HomebrewVisibility,
+
     pub limit: Option<u64>,
     pub page: Option<u64>,
+    // Continue after a previous page's `next_cursor`, instead of by page. `start` for the first page.
+    pub cursor: Option<String>,
     pub sort_by: Option<String>,
     pub order_by: Option<String>,
 }
@@ -48,9 +57,11 @@ impl HazardFiltering {
         }
     }
 
+    // Lookups by id from data that already references these objects (eg: encounters), so includes all homebrew
     pub fn from_ids(ids: &[u32]) -> Self {
         HazardFiltering {
             ids: Some(CommaSeparatedVec(ids.to_vec())),
+            homebrew: HomebrewVisibility::All,
             ..Default::default()
         }
     }
@@ -78,8 +89,15 @@ pub struct HazardSearch {
     #[serde(default)]
     pub legacy: LegacyStatus,
 
+    // Homebrew objects visible to the requesting user. Not part of the query string.
+    #[serde(skip)]
+    pub homebrew: HomebrewVisibility,
+
     pub limit: Option<u64>,
     pub page: Option<u64>,
+    // Searches rank by similarity, so are only paged by offset. Set from a listing's cursor.
+    #[serde(skip)]
+    pub cursor: Option<String>,
     pub sort_by: Option<String>,
     pub order_by: Option<String>,
 }
@@ -102,8 +120,10 @@ impl From<HazardFiltering> for HazardSearch {
             traits_all: filter.traits_all,
             traits_any: filter.traits_any,
             legacy: filter.legacy,
+            homebrew: filter.homebrew,
             limit: filter.limit,
             page: filter.page,
+            cursor: filter.cursor,
             sort_by: filter.sort_by,
             order_by: filter.order_by,
         }
@@ -118,11 +138,28 @@ impl Sortable for LibraryHazard {
     fn default_sort() -> Option<&'static str> {
         Some("name")
     }
+
+    fn get_sort_value(&self, field: &str) -> serde_json::Value {
+        match field {
+            "name" => self.name.as_str().into(),
+            "level" => self.level.into(),
+            "rarity" => self.rarity.as_i64().into(),
+            _ => serde_json::Value::Null,
+        }
+    }
+
+    fn get_id(&self) -> InternalId {
+        self.id
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct InsertLibraryHazard {
     pub requested_id: Option<InternalId>,
+    #[serde(default)]
+    pub source_id: Option<String>,
+    #[serde(skip)]
+    pub owner: Option<InternalId>,
     pub name: String,
     pub game_system: GameSystem,
     pub rarity: Rarity,
@@ -138,6 +175,22 @@ pub struct InsertLibraryHazard {
     pub haunt: bool,
 }
 
+impl From<&InsertLibraryHazard> for InsertLibraryObject {
+    fn from(object: &InsertLibraryHazard) -> Self {
+        InsertLibraryObject {
+            requested_id: object.requested_id,
+            source_id: object.source_id.clone(),
+            owner: object.owner,
+            name: object.name.clone(),
+            game_system: object.game_system.clone(),
+            url: object.url.clone(),
+            description: object.description.clone(),
+            legacy: object.legacy,
+            remastering_alt_id: object.remastering_alt_id,
+        }
+    }
+}
+
 // TODO: May be prudent to make a separate models system for the database.
 pub async fn get_hazards(
     conn: &mut PgConnection,
@@ -173,6 +226,11 @@ pub async fn get_hazards_search(
         search.sort_by.as_deref(),
         search.order_by.as_deref(),
     )?;
+    // A cursor picks up after the last object of the previous page, in place of the offset
+    let (offset, after) = match &search.cursor {
+        Some(cursor) => (0, sort.parse_cursor(cursor)?),
+        None => (offset, None),
+    };
 
     let ids = search.ids.clone().map(|t| {
         t.into_inner()
@@ -235,6 +293,10 @@ pub async fn get_hazards_search(
                 AND ($6::text[] IS NULL OR tags.traits::text[] && $6::text[])
                 AND ($7::text[] IS NULL OR tags.traits::text[] @> $7::text[])
                 AND ($8::int[] IS NULL OR lo.id = ANY($8))
+                AND ($20::bool OR lo.owner IS NULL OR lo.owner = $21 OR lo.campaign_id IN (
+                    SELECT id FROM campaigns WHERE owner = $21
+                    UNION SELECT campaign_id FROM campaign_members WHERE user_id = $21
+                ))
                 AND (($11::bool AND lo.name ILIKE '%' || query || '%') OR SIMILARITY(lo.name, query) >= $10)
                 AND NOT (NOT $12::bool AND lo.legacy = FALSE)
                 AND NOT (NOT $13::bool AND lo.legacy = TRUE)
@@ -242,15 +304,39 @@ pub async fn get_hazards_search(
                 AND NOT ($15::bool AND lo.remastering_alt_id IS NOT NULL AND lo.legacy = FALSE)
                 AND ($16::bool IS NULL OR lc.haunt = $16)
                 AND ($17::bool IS NULL OR lc.complex = $17)
+                -- After the cursor's sort keys, then id. Descending keys swap sides, so that a single row comparison
+                -- covers any mix of directions. Keys past the sort's columns are equal on both sides.
+                AND ($24::jsonb IS NULL OR (
+                    COALESCE(CASE ($19::int[])[1] WHEN 1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($18::text[])[1] WHEN -1 THEN $24 -> 0 END, 'null'),
+                    COALESCE(CASE ($19::int[])[2] WHEN 1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($18::text[])[2] WHEN -1 THEN $24 -> 1 END, 'null'),
+                    COALESCE(CASE ($19::int[])[3] WHEN 1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($18::text[])[3] WHEN -1 THEN $24 -> 2 END, 'null'),
+                    lo.id
+                ) > (
+                    COALESCE(CASE ($19::int[])[1] WHEN 1 THEN $24 -> 0 WHEN -1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($18::text[])[1] END, 'null'),
+                    COALESCE(CASE ($19::int[])[2] WHEN 1 THEN $24 -> 1 WHEN -1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($18::text[])[2] END, 'null'),
+                    COALESCE(CASE ($19::int[])[3] WHEN 1 THEN $24 -> 2 WHEN -1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($18::text[])[3] END, 'null'),
+                    ($24 -> cardinality($18::text[]))::int
+                ))
             GROUP BY lo.id, lc.id, tags.tags, tags.traits 
+            -- Up to MAX_SORT_COLUMNS sort keys, then id to break ties
             ORDER BY similarity DESC, favor_exact_start_length,
-                CASE WHEN $18::text = 'name' AND $19::int = 1 THEN lo.name::text END ASC,
-                CASE WHEN $18::text = 'name' AND $19::int = -1 THEN lo.name::text END DESC,
-                CASE WHEN $18::text = 'level' THEN level::integer * $19::int END ASC,
-                CASE WHEN $18::text = 'rarity' THEN rarity::integer * $19::int END ASC
-            LIMIT $20 OFFSET $21
+                CASE WHEN ($19::int[])[1] = 1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($18::text[])[1] END ASC,
+                CASE WHEN ($19::int[])[1] = -1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($18::text[])[1] END DESC,
+                CASE WHEN ($19::int[])[2] = 1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($18::text[])[2] END ASC,
+                CASE WHEN ($19::int[])[2] = -1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($18::text[])[2] END DESC,
+                CASE WHEN ($19::int[])[3] = 1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($18::text[])[3] END ASC,
+                CASE WHEN ($19::int[])[3] = -1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($18::text[])[3] END DESC,
+                lo.id
+            LIMIT $22 OFFSET $23
         ) c
-        ORDER BY similarity DESC, favor_exact_start_length 
+        ORDER BY similarity DESC, favor_exact_start_length,
+            CASE WHEN ($19::int[])[1] = 1 THEN to_jsonb(c) -> ($18::text[])[1] END ASC,
+            CASE WHEN ($19::int[])[1] = -1 THEN to_jsonb(c) -> ($18::text[])[1] END DESC,
+            CASE WHEN ($19::int[])[2] = 1 THEN to_jsonb(c) -> ($18::text[])[2] END ASC,
+            CASE WHEN ($19::int[])[2] = -1 THEN to_jsonb(c) -> ($18::text[])[2] END DESC,
+            CASE WHEN ($19::int[])[3] = 1 THEN to_jsonb(c) -> ($18::text[])[3] END ASC,
+            CASE WHEN ($19::int[])[3] = -1 THEN to_jsonb(c) -> ($18::text[])[3] END DESC,
+            c.id
     "#,
         search.name,
         search.rarity.as_ref().map(|r| r.as_i64() as i32),
@@ -269,10 +355,13 @@ pub async fn get_hazards_search(
         search.legacy.favor_legacy(),
         search.haunt,
         search.complex,
-        sort.get_column(),
-        sort.get_sort_direction_i32(),
+        &sort.get_columns(),
+        &sort.get_sort_directions_i32(),
+        search.homebrew.all(),
+        search.homebrew.user_id(),
         limit as i64,
         offset as i64,
+        after,
     );
 
     // create initial hm with empty vecs for each query
@@ -299,6 +388,7 @@ pub async fn get_hazards_search(
                 url: row.url,
                 description: row.description.unwrap_or_default(),
                 legacy: row.legacy,
+                remastering_alt_id: row.remastering_alt_id.map(|id| InternalId(id as u32)),
             };
             map.entry(query)
                 .or_default()
@@ -311,69 +401,53 @@ pub async fn get_hazards_search(
 pub async fn insert_hazards(
     tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
     hazards: &[InsertLibraryHazard],
-) -> crate::Result<()> {
-    // TODO: Do we *need* two tables for this?
-
+) -> crate::Result<Vec<InternalId>> {
     if hazards.is_empty() {
-        return Ok(());
+        return Ok(vec![]);
     }
 
-    // First, check to make sure all requested ids are not already in use
-    let requested_ids = hazards
+    let objects = hazards
         .iter()
-        .filter_map(|i| i.requested_id)
-        .map(|id| id.0 as i32)
-        .collect::<Vec<i32>>();
-    check_library_requested_ids(&mut **tx, &requested_ids).await?;
+        .map(InsertLibraryObject::from)
+        .collect::<Vec<_>>();
+    let ids = insert_library_objects(tx, &objects).await?;
+    write_hazards(tx, &ids, hazards).await?;
+    Ok(ids.into_iter().map(|id| InternalId(id as u32)).collect())
+}
 
-    let ids = sqlx::query!(
-        r#"
-        INSERT INTO library_objects (id, name, game_system, url, description, legacy, remastering_alt_id)
-        SELECT * FROM UNNEST ($1::int[], $2::text[], $3::int[], $4::text[], $5::text[], $6::bool[], $7::int[])
-        RETURNING id  
-    "#,
-        &hazards
-            .iter()
-            .map(|c| c.requested_id.map(|id| id.0 as i32))
-            .collect::<Vec<Option<i32>>>() as _,
-        &hazards
-            .iter()
-            .map(|c| c.name.clone())
-            .collect::<Vec<String>>(),
-        &hazards
-            .iter()
-            .map(|c| c.game_system.as_i64() as i32)
-            .collect::<Vec<i32>>(),
-        &hazards
-            .iter()
-            .map(|c| c.url.clone())
-            .collect::<Vec<Option<String>>>() as _,
-        &hazards
-            .iter()
-            .map(|c| c.description.clone())
-            .collect::<Vec<String>>(),
-        &hazards
-            .iter()
-            .map(|c| c.legacy)
-            .collect::<Vec<bool>>(),
-        &hazards
-            .iter()
-            .map(|c| c.remastering_alt_id.map(|id| id.0 as i32))
-            .collect::<Vec<Option<i32>>>() as _,
-    )
-    .fetch_all(&mut **tx)
-    .await?
-    .into_iter()
-    .map(|row| Ok(row.id))
-    .collect::<Result<Vec<i32>, sqlx::Error>>()?;
+/// Inserts new hazards, and updates existing ones in place.
+pub async fn upsert_hazards(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    hazards: &[InsertLibraryHazard],
+) -> crate::Result<LibraryUpsertReport> {
+    if hazards.is_empty() {
+        return Ok(LibraryUpsertReport::default());
+    }
+
+    let objects = hazards
+        .iter()
+        .map(InsertLibraryObject::from)
+        .collect::<Vec<_>>();
+    let upsert = LibraryUpsert::begin(tx, LibraryObjectType::Hazard, &objects).await?;
+    write_hazards(tx, &upsert.ids, hazards).await?;
+    upsert.finish(tx).await
+}
 
+// Writes everything but the 'library_objects' row
+async fn write_hazards(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ids: &[i32],
+    hazards: &[InsertLibraryHazard],
+) -> crate::Result<()> {
     // TODO: as i32 should be unnecessary- fix in models
     sqlx::query!(
         r#"
         INSERT INTO library_hazards (id, rarity, level, haunt, complex)
         SELECT * FROM UNNEST ($1::int[], $2::int[], $3::int[], $4::bool[], $5::bool[])
+        ON CONFLICT (id) DO UPDATE
+        SET rarity = EXCLUDED.rarity, level = EXCLUDED.level, haunt = EXCLUDED.haunt, complex = EXCLUDED.complex
     "#,
-        &ids.iter().map(|id| *id as i32).collect::<Vec<i32>>(),
+        ids,
         &hazards
             .iter()
             .map(|c| c.rarity.as_i64() as i32)
@@ -385,23 +459,10 @@ pub async fn insert_hazards(
     .execute(&mut **tx)
     .await?;
 
-    // Add tags
-    for tag in hazards.iter() {
-        sqlx::query!(
-            r#"
-            INSERT INTO library_objects_tags (library_object_id, tag_id)
-            SELECT * FROM UNNEST ($1::int[], $2::int[])
-            "#,
-            &ids.iter().map(|id| *id as i32).collect::<Vec<i32>>(),
-            &tag.tags
-                .iter()
-                .map(|id| id.0 as i32)
-                .sorted()
-                .collect::<Vec<i32>>(),
-        )
-        .execute(&mut **tx)
-        .await?;
-    }
-
-    Ok(())
+    set_library_object_tags(
+        tx,
+        ids,
+        &hazards.iter().map(|o| &o.tags).collect::<Vec<_>>(),
+    )
+    .await
 }
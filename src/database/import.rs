@@ -1,132 +1,198 @@
 use crate::{
-    ServerError, database::{
+    database::{
         characters::{CharacterFilters, InsertCharacter},
         encounters::{EncounterFilters, InsertEncounter},
+        events::{CampaignEvent, CampaignEventGroup},
         sessions::InsertSession,
-    }, models::{
-        campaign::CampaignSessionCharacterRewards, encounter::EncounterType, ids::InternalId,
-    }, v2::{database::item_instances::InsertItemInstance, models::item_instances::ItemInstance}
+    },
+    models::{
+        campaign::CampaignSessionCharacterRewards,
+        characters::{AbilityModifiers, CharacterProficiencies},
+        encounter::EncounterType,
+        events::EventType,
+        ids::InternalId,
+    },
+    v2::database::item_instances::InsertItemInstance,
+    ServerError,
 };
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
-use super::{campaigns::InsertCampaign, sessions::UpdateCharacterSessions};
+use super::{
+    campaigns::{InsertCampaign, ModifyCampaign},
+    sessions::UpdateCharacterSessions,
+};
 
 /*
+Campaign export format, version 1.
+
+Every exported object has an 'id', local to the file and unique per type, numbered from 1 in the order
+objects appear. References between objects ('session_id', 'character_id', ...) use these local ids.
+References into the library ('class', 'library_item_id', enemies, hazards) use library ids.
+Derived values (experience, levels, treasure values, unassigned rewards) are not exported, and are
+recalculated on import.
+
+Objects are exported in a fixed order, so that exporting an imported campaign gives an identical file.
 
 {
+    "version": 1,
     "name": "Campaign 1",
+    "description": null,
+    // Whether characters absent from a session still earn its experience
+    "absent_characters_earn_experience": true,
     "characters": [
         {
-            "name": "Alden",
-            "player": "John",
-            "level": 1,
-            "class": 1
-        } ...
+            "id": 1, "name": "Alden", "player": "John", "class": 12,
+            "ancestry": "Elf", "heritage": null, "background": "Scholar",
+            // Only if set on the character, rather than tracked from its experience
+            "level": null,
+            "ability_modifiers": { "strength": 0, "dexterity": 2, "constitution": 1, "intelligence": 4, "wisdom": 1, "charisma": 0 },
+            "proficiencies": {
+                "perception": "trained", "fortitude": "trained", "reflex": "trained", "will": "expert",
+                "skills": [{ "skill": "Lore (Wizards)", "proficiency": "trained" }]
+            }
+        }
     ],
     "sessions": [
         {
+            "id": 1,
+            "session_order": 1000,
             "name": "Session 1",
             "description": "The party is ambushed by goblins",
-            "date": "2021-01-01",
-            "compiled_rewards": {
-                "Alden": {
-                    "gold": 0.5,
-                    "items": [1, 2]
-                }
-            },
-
+            "play_date": "2021-01-01T00:00:00Z",
+            // character id -> rewards
+            "rewards": {
+                "1": { "gold": 0.5, "present": true }
+            }
         }
     ],
     "encounters": [
         {
+            "id": 1,
+            // Null for encounters of the campaign that are not linked to a session
+            "session_id": 1,
             "name": "Goblin Ambush",
             "description": null,
-            "session_ix": 0,
-
             "party_level": 1,
             "party_size": 4,
-
-            "enemies": [
-                {
-                    "id": 1,
-                    "level_adjustment": 0
-                }
-            ],
+            "encounter_type": "combat",
+            "enemies": [{ "id": 1, "level_adjustment": 0 }],
             "hazards": [1, 2],
-            "treasure_items": [1, 2],
             "treasure_currency": 0.5,
-            "extra_experience": 50,
+            "extra_experience": 50
+        }
+    ],
+    // Item instances: encounter treasure, session rewards and character inventories
+    "items": [
+        {
+            "id": 1,
+            "library_item_id": 40,
+            "parent_item_id": null,
+            "encounter_id": 1,
+            "character_id": 1,
+            "session_id": 1,
+            "is_reward": false,
+            "quantity": 1,
+            "nickname": null,
+            "notes": null,
+            // Used up consumables, and the session they were used in
+            "spent": false,
+            "spent_session_id": null
+        }
+    ],
+    "event_groups": [
+        {
+            "id": 1,
+            // Null for encounters of the campaign that are not linked to a session
+            "session_id": 1,
+            "name": "Goblin Ambush",
+            "description": null,
+            "timestamp": "2021-01-01T00:00:00",
+            "intra_session_order": 1
+        }
+    ],
+    "events": [
+        {
+            "event_group_id": 1,
+            "session_id": 1,
+            "character_id": null,
+            "timestamp": "2021-01-01T00:00:00",
+            "intra_session_order": 1,
+            "event_type": "ExperienceGain",
+            "data": { "experience": 80 }
         }
     ]
 }
 
-
-
+Files without a 'version' are version 0 (see 'v0'), and are migrated on import.
 */
 
-#[derive(Serialize, Debug, Deserialize)]
+/// Current version of the campaign export format.
+pub const EXPORT_VERSION: u32 = 1;
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
 pub struct ImportCampaign {
-    pub id_hash: u32,
+    pub version: u32,
     pub name: String,
-    pub level: u8,
     pub description: Option<String>,
+    // Missing from files exported before it could be set, when absent characters always earned experience
+    #[serde(default = "default_absent_characters_earn_experience")]
+    pub absent_characters_earn_experience: bool,
     pub characters: Vec<ImportCharacter>,
     pub sessions: Vec<ImportSession>,
     pub encounters: Vec<ImportEncounter>,
-    pub items: Vec<ImportItemInstance>
+    pub items: Vec<ImportItemInstance>,
+    pub event_groups: Vec<ImportEventGroup>,
+    pub events: Vec<ImportEvent>,
 }
 
-#[derive(Serialize, Debug, Deserialize)]
+#[derive(Serialize, Debug, Deserialize, Clone)]
 pub struct ImportCharacter {
-    pub id_hash: u32,
+    pub id: u32,
     pub name: String,
     pub player: Option<String>,
     pub class: InternalId,
+    #[serde(default)]
+    pub ancestry: Option<String>,
+    #[serde(default)]
+    pub heritage: Option<String>,
+    #[serde(default)]
+    pub background: Option<String>,
+    #[serde(default)]
+    pub level: Option<u8>,
+    #[serde(default)]
+    pub ability_modifiers: AbilityModifiers,
+    #[serde(default)]
+    pub proficiencies: CharacterProficiencies,
 }
 
-#[derive(Serialize, Debug, Deserialize)]
-pub struct ImportItemInstance { 
-    pub id_hash: u32,
-     // This is a library item, and not a local referenced id, so we explicitly use InternalId
-    pub library_item_id: InternalId,
-    pub parent_item_id: Option<u32>,
-    pub campaign_id: Option<u32>,
-    pub encounter_id: Option<u32>,
-    pub character_id: Option<u32>,
-    pub session_id: Option<u32>,
-    pub is_reward: bool,
-    pub quantity: u16,
-    pub nickname: Option<String>,
-    pub notes: Option<String>,
-}
-
-#[derive(Serialize, Debug, Deserialize)]
+#[derive(Serialize, Debug, Deserialize, Clone)]
 pub struct ImportSession {
-    pub id_hash: u32,
-    pub name: Option<String>,
+    pub id: u32,
+    pub session_order: u32,
+    pub name: String,
     pub description: Option<String>,
-    pub date: Option<DateTime<Utc>>,
-    pub compiled_rewards: HashMap<u32, ImportSessionCharacterRewards>,
+    pub play_date: Option<DateTime<Utc>>,
+    // Character id -> rewards. Ordered, so exports are stable.
+    pub rewards: BTreeMap<u32, ImportSessionCharacterRewards>,
 }
 
-#[derive(Serialize, Debug, Deserialize)]
+#[derive(Serialize, Debug, Deserialize, Clone)]
 pub struct ImportSessionCharacterRewards {
-    pub gold: f32,
-    // Default to true (if the reward struct is provided) for backwards compatibility
-    pub present: Option<bool>,
+    pub gold: f64,
+    pub present: bool,
 }
 
-#[derive(Serialize, Debug, Deserialize)]
+#[derive(Serialize, Debug, Deserialize, Clone)]
 pub struct ImportEncounter {
-    pub id_hash: u32,
+    pub id: u32,
+    pub session_id: Option<u32>,
 
     pub name: String,
     pub description: Option<String>,
-    pub session_ix: usize,
 
     pub party_level: u32,
     pub party_size: u32,
@@ -134,15 +200,80 @@ pub struct ImportEncounter {
     #[serde(flatten)]
     pub encounter_type: EncounterType,
 
-    pub treasure_items: Vec<InternalId>,
+    // Treasure items are exported as item instances referencing the encounter
     pub treasure_currency: f32,
     pub extra_experience: i32,
 }
 
-#[derive(Serialize, Debug, Deserialize)]
-pub struct ImportEncounterEnemy {
-    pub id: InternalId,
-    pub level_adjustment: i16,
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct ImportItemInstance {
+    pub id: u32,
+    // This is a library item, and not a local referenced id, so we explicitly use InternalId
+    pub library_item_id: InternalId,
+    pub parent_item_id: Option<u32>,
+    pub encounter_id: Option<u32>,
+    pub character_id: Option<u32>,
+    pub session_id: Option<u32>,
+    pub is_reward: bool,
+    pub quantity: u16,
+    pub nickname: Option<String>,
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub spent: bool,
+    pub spent_session_id: Option<u32>,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct ImportEventGroup {
+    pub id: u32,
+    pub session_id: u32,
+    pub name: String,
+    pub description: Option<String>,
+    pub timestamp: NaiveDateTime,
+    pub intra_session_order: i32,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct ImportEvent {
+    pub event_group_id: Option<u32>,
+    pub session_id: u32,
+    pub character_id: Option<u32>,
+    pub timestamp: NaiveDateTime,
+    pub intra_session_order: i32,
+    #[serde(flatten)]
+    pub event_type: EventType,
+}
+
+fn default_absent_characters_earn_experience() -> bool {
+    true
+}
+
+impl ImportCampaign {
+    /// Parses a campaign export of any version, migrating it to the current version.
+    pub fn from_json(value: serde_json::Value) -> crate::Result<ImportCampaign> {
+        let version = value.get("version").and_then(|v| v.as_u64()).unwrap_or(0);
+        let parse_error = |e: serde_json::Error| {
+            ServerError::BadRequest(format!("Invalid campaign export: {}", e))
+        };
+        match version {
+            0 => serde_json::from_value::<v0::ImportCampaign>(value)
+                .map_err(parse_error)?
+                .migrate(),
+            1 => serde_json::from_value(value).map_err(parse_error),
+            _ => Err(ServerError::BadRequest(format!(
+                "Unsupported campaign export version {} (latest is {})",
+                version, EXPORT_VERSION
+            ))),
+        }
+    }
+}
+
+// Resolves a local id in the import to the id it was inserted with
+fn resolve(ids: &HashMap<u32, InternalId>, id: u32, kind: &str) -> Result<InternalId, ServerError> {
+    ids.get(&id).copied().ok_or(ServerError::BadRequest(format!(
+        "{} {} not found in '{}s'",
+        kind, id, kind
+    )))
 }
 
 pub async fn import_with_functions(
@@ -150,10 +281,6 @@ pub async fn import_with_functions(
     tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
     owner: InternalId,
 ) -> Result<InternalId, ServerError> {
-
-    // The 'id of the import json' to 'internal id' mapping, added to as they get generated.
-    let mut id_hash_to_internal_id = HashMap::new();
-
     // Insert campaign
     let campaign_id = super::campaigns::insert_campaign(
         &mut *tx,
@@ -166,10 +293,16 @@ pub async fn import_with_functions(
         owner,
     )
     .await?;
-    id_hash_to_internal_id.insert(
-        campaign.id_hash,
+    super::campaigns::edit_campaign(
+        tx,
         campaign_id,
-    );
+        &ModifyCampaign {
+            name: None,
+            description: None,
+            absent_characters_earn_experience: Some(campaign.absent_characters_earn_experience),
+        },
+    )
+    .await?;
 
     // Insert characters
     let ids = super::characters::insert_characters(
@@ -182,166 +315,204 @@ pub async fn import_with_functions(
                 name: c.name.clone(),
                 player: c.player.clone(),
                 class: c.class,
+                ancestry: c.ancestry.clone(),
+                heritage: c.heritage.clone(),
+                background: c.background.clone(),
+                level: c.level,
+                ability_modifiers: c.ability_modifiers,
+                proficiencies: c.proficiencies.clone(),
             })
             .collect_vec(),
     )
     .await?;
-    id_hash_to_internal_id.extend(
-        campaign
-            .characters
-            .iter()
-            .zip(ids.iter())
-            .map(|(c, id)| (c.id_hash, *id)),
-    );
+    let character_ids: HashMap<u32, InternalId> =
+        campaign.characters.iter().map(|c| c.id).zip(ids).collect();
 
     // Insert sessions
     let insert_sessions = campaign
         .sessions
         .iter()
-        .enumerate()
-        .map(|(ix, s)| {
-            Ok(InsertSession {
-                name: s.name.clone(),
-                description: s.description.clone(),
-                session_order: (ix * 1000) as u32,
-                play_date: s.date,
-                characters: Some(
-                    s.compiled_rewards
-                        .keys()
-                        .map(|character_name| {
-                            id_hash_to_internal_id
-                                .get(character_name)
-                                .ok_or(ServerError::BadRequest(format!(
-                                    "Character {} not found in 'characters'",
-                                    character_name
-                                )))
-                                .copied()
-                        })
-                        .collect::<Result<Vec<_>, _>>()?,
-                ),
-            })
+        .map(|s| InsertSession {
+            name: Some(s.name.clone()),
+            description: s.description.clone(),
+            session_order: s.session_order,
+            play_date: s.play_date,
+            characters: None,
         })
-        .collect::<Result<Vec<_>, ServerError>>()?;
-    let session_ids_in_order =
-        super::sessions::insert_sessions(&mut *tx, campaign_id, &insert_sessions).await?;
-    id_hash_to_internal_id.extend(
-        campaign
-            .sessions
-            .iter()
-            .zip(session_ids_in_order.iter())
-            .map(|(s, id)| (s.id_hash, *id)),
-    );
+        .collect_vec();
+    let ids = super::sessions::insert_sessions(&mut *tx, campaign_id, &insert_sessions).await?;
+    let session_ids: HashMap<u32, InternalId> =
+        campaign.sessions.iter().map(|s| s.id).zip(ids).collect();
 
-    // Insert encounters
+    // Insert encounters, linked to their sessions if they have one
     let insert_encounters = campaign
         .encounters
         .iter()
-        .map(|e| InsertEncounter {
-            name: e.name.clone(),
-            description: e.description.clone(),
-
-            session_id: session_ids_in_order.get(e.session_ix).cloned(),
-            party_level: e.party_level as u8,
-            party_size: e.party_size as u8,
-            encounter_type: e.encounter_type.clone(),
-            treasure_items: e.treasure_items.clone(),
-            treasure_currency: e.treasure_currency,
-            extra_experience: e.extra_experience,
+        .map(|e| {
+            Ok(InsertEncounter {
+                name: e.name.clone(),
+                description: e.description.clone(),
+                session_id: e
+                    .session_id
+                    .map(|id| resolve(&session_ids, id, "session"))
+                    .transpose()?,
+                campaign_id: Some(campaign_id),
+                party_level: e.party_level as u8,
+                party_size: e.party_size as u8,
+                encounter_type: e.encounter_type.clone(),
+                treasure_items: vec![],
+                treasure_currency: e.treasure_currency,
+                extra_experience: e.extra_experience,
+            })
         })
-        .collect_vec();
-    let encounter_ids = super::encounters::insert_encounters(&mut *tx, owner, &insert_encounters).await?;
-    id_hash_to_internal_id.extend(
-        campaign
-            .encounters
-            .iter()
-            .zip(encounter_ids.iter())
-            .map(|(e, id)| (e.id_hash, *id)),
-    );
-    println!("id_hash_to_internal_id: {:?}", id_hash_to_internal_id);
+        .collect::<Result<Vec<_>, ServerError>>()?;
+    let encounter_ids_in_order =
+        super::encounters::insert_encounters(&mut *tx, owner, &insert_encounters).await?;
+    let encounter_ids: HashMap<u32, InternalId> = campaign
+        .encounters
+        .iter()
+        .map(|e| e.id)
+        .zip(encounter_ids_in_order.iter().copied())
+        .collect();
 
-    // Assign character contributions
-    for (session, session_id) in campaign.sessions.iter().zip(session_ids_in_order) {
+    // Assign character rewards. This resets item assignments of the session, so it precedes item insertion.
+    for session in &campaign.sessions {
         let compiled_rewards = session
-            .compiled_rewards
+            .rewards
             .iter()
-            .map(|(character_id_hash, rewards)| {
-                let character_id =
-                    id_hash_to_internal_id
-                        .get(character_id_hash)
-                        .ok_or(ServerError::BadRequest(format!(
-                            "Character {} not found in 'characters'",
-                            character_id_hash
-                        )))?;
+            .map(|(character_id, rewards)| {
                 Ok((
-                    *character_id,
+                    resolve(&character_ids, *character_id, "character")?,
                     CampaignSessionCharacterRewards {
-                        gold: rewards.gold as f64,
-                        present: rewards.present.unwrap_or(true),
-                        items: vec![], // TODO: This was removed to match new v2
+                        gold: rewards.gold,
+                        present: rewards.present,
+                        items: vec![],
                     },
                 ))
             })
             .collect::<Result<_, ServerError>>()?;
         let updates = UpdateCharacterSessions { compiled_rewards };
         super::sessions::edit_encounter_session_character_assignments(
-            &mut *tx, session_id, &updates,
+            &mut *tx,
+            session_ids[&session.id],
+            &updates,
         )
         .await?;
     }
 
-    // Insert item instances
-    let item_instances = campaign.items.into_iter().map(|item| {
-        Ok(InsertItemInstance {
-            library_item_id: item.library_item_id,
-            parent_item_id: item.parent_item_id.map(|id| {
-                id_hash_to_internal_id
-                    .get(&id)
-                    .copied()
-                    .ok_or(ServerError::BadRequest(format!(
-                        "Parent item {} not found in 'item_instances'",
-                        id
-                    )))
-            }).transpose()?,
-            campaign_id: item.campaign_id.map(|id| id_hash_to_internal_id.get(&id).copied()
-                .ok_or(ServerError::BadRequest(format!(
-                    "Campaign item {} not found in 'campaigns'",
-                    id
-                )))).transpose()?,
-            encounter_id: item.encounter_id.map(|id| id_hash_to_internal_id.get(&id).copied()
-                .ok_or(ServerError::BadRequest(format!(
-                    "Encounter item {} not found in 'encounters'",
-                    id
-                )))).transpose()?,
-            character_id: item.character_id.map(|id| id_hash_to_internal_id.get(&id).copied()
-                .ok_or(ServerError::BadRequest(format!(
-                    "Character item {} not found in 'characters'",
-                    id
-                )))).transpose()?,
-            session_id: item.session_id.map(|id| id_hash_to_internal_id.get(&id).copied()
-                .ok_or(ServerError::BadRequest(format!(
-                    "Session item {} not found in 'sessions'",
-                    id
-                )))).transpose()?,
-            is_reward: item.is_reward,
-            quantity: item.quantity,
-            nickname: item.nickname,
-            notes: item.notes,
+    // Insert item instances, then link them to their parents (which are not necessarily inserted first)
+    let item_instances = campaign
+        .items
+        .iter()
+        .map(|item| {
+            Ok(InsertItemInstance {
+                library_item_id: item.library_item_id,
+                parent_item_id: None,
+                campaign_id: Some(campaign_id),
+                encounter_id: item
+                    .encounter_id
+                    .map(|id| resolve(&encounter_ids, id, "encounter"))
+                    .transpose()?,
+                character_id: item
+                    .character_id
+                    .map(|id| resolve(&character_ids, id, "character"))
+                    .transpose()?,
+                session_id: item
+                    .session_id
+                    .map(|id| resolve(&session_ids, id, "session"))
+                    .transpose()?,
+                is_reward: item.is_reward,
+                quantity: item.quantity,
+                nickname: item.nickname.clone(),
+                notes: item.notes.clone(),
+            })
         })
-    }).collect::<Result<Vec<_>, ServerError>>()?;
-    crate::v2::database::item_instances::insert_item_instances(&mut *tx, item_instances)
+        .collect::<Result<Vec<_>, ServerError>>()?;
+    let ids = crate::v2::database::item_instances::insert_item_instances(&mut *tx, item_instances)
         .await?;
+    let item_ids: HashMap<u32, InternalId> = campaign.items.iter().map(|i| i.id).zip(ids).collect();
+    let parents = campaign
+        .items
+        .iter()
+        .filter_map(|item| Some((item.id, item.parent_item_id?)))
+        .map(|(id, parent_id)| Ok((item_ids[&id], resolve(&item_ids, parent_id, "item")?)))
+        .collect::<Result<Vec<_>, ServerError>>()?;
+    crate::v2::database::item_instances::set_item_instance_parents(&mut *tx, &parents).await?;
+    let spent = campaign
+        .items
+        .iter()
+        .filter(|item| item.spent)
+        .map(|item| {
+            let session_id = item
+                .spent_session_id
+                .map(|id| resolve(&session_ids, id, "session"))
+                .transpose()?;
+            Ok((item_ids[&item.id], session_id))
+        })
+        .collect::<Result<Vec<_>, ServerError>>()?;
+    crate::v2::database::item_instances::set_item_instances_spent(&mut *tx, &spent).await?;
 
+    // Insert the event log
+    let groups = campaign
+        .event_groups
+        .iter()
+        .map(|g| {
+            Ok(CampaignEventGroup {
+                id: InternalId(0),
+                session_id: resolve(&session_ids, g.session_id, "session")?,
+                name: g.name.clone(),
+                description: g.description.clone(),
+                timestamp: g.timestamp,
+                intra_session_order: g.intra_session_order,
+            })
+        })
+        .collect::<Result<Vec<_>, ServerError>>()?;
+    let ids = super::events::insert_campaign_event_groups(&mut *tx, campaign_id, &groups).await?;
+    let event_group_ids: HashMap<u32, InternalId> = campaign
+        .event_groups
+        .iter()
+        .map(|g| g.id)
+        .zip(ids)
+        .collect();
+    let events = campaign
+        .events
+        .iter()
+        .map(|e| {
+            Ok(CampaignEvent {
+                id: InternalId(0),
+                event_group: e
+                    .event_group_id
+                    .map(|id| resolve(&event_group_ids, id, "event_group"))
+                    .transpose()?,
+                session_id: resolve(&session_ids, e.session_id, "session")?,
+                character: e
+                    .character_id
+                    .map(|id| resolve(&character_ids, id, "character"))
+                    .transpose()?,
+                timestamp: e.timestamp,
+                intra_session_order: e.intra_session_order,
+                event_type: e.event_type.clone(),
+            })
+        })
+        .collect::<Result<Vec<_>, ServerError>>()?;
+    super::events::insert_campaign_events(&mut *tx, campaign_id, &events).await?;
 
     // Recalculate encounter summary derived data.
     // TODO: Antipattern, but not sure what the best way about it is. Postgres function, MV?
-    super::encounters::recalculate_encounter_summary(&mut *tx, owner, &encounter_ids)
+    super::encounters::recalculate_encounter_summary(&mut *tx, owner, &encounter_ids_in_order)
         .await?;
 
-    println!("Done importing campaign: {}", campaign.name);
-
     Ok(campaign_id)
 }
 
+// Assigns local ids (from 1, in order) to exported objects
+fn local_ids(ids: impl IntoIterator<Item = InternalId>) -> HashMap<InternalId, u32> {
+    ids.into_iter()
+        .enumerate()
+        .map(|(ix, id)| (id, ix as u32 + 1))
+        .collect()
+}
+
 pub async fn export(
     campaign_id: InternalId,
     pool: &PgPool,
@@ -349,113 +520,423 @@ pub async fn export(
 ) -> Result<ImportCampaign, ServerError> {
     let campaigns = super::campaigns::get_campaigns_owner(pool, owner).await?;
     let campaign = campaigns
-        .iter()
+        .into_iter()
         .find(|c| c.id == campaign_id)
         .ok_or(ServerError::BadRequest("Campaign not found".to_string()))?;
 
-    let characters =
+    let mut characters =
         super::characters::get_characters(pool, owner, campaign_id, &CharacterFilters::default())
             .await?;
+    characters.sort_by_key(|c| c.id);
+    let pinned_levels = super::characters::get_pinned_levels(pool, campaign_id).await?;
+    let character_ids = local_ids(characters.iter().map(|c| c.id));
+
+    // Sessions are already in play order
     let sessions = super::sessions::get_sessions(pool, owner, campaign_id).await?;
-    let encounters =
-        super::encounters::get_encounters(pool, owner, &EncounterFilters::default()).await?;
+    let session_ids = local_ids(sessions.iter().map(|s| s.id));
 
-    let encounters = encounters
+    // Encounters of the campaign, whether or not they are linked to a session. Unlinked ones come last.
+    let mut encounters = super::encounters::get_encounters(
+        pool,
+        owner,
+        &EncounterFilters {
+            campaign_id: Some(campaign_id),
+            ..Default::default()
+        },
+    )
+    .await?
+    .into_iter()
+    .map(|e| (e.session_id.and_then(|id| session_ids.get(&id).copied()), e))
+    .collect_vec();
+    encounters.sort_by_key(|(session_id, e)| (session_id.is_none(), *session_id, e.id));
+    let encounter_ids = local_ids(encounters.iter().map(|(_, e)| e.id));
+
+    let items = crate::v2::database::item_instances::get_item_instances(pool, campaign_id).await?;
+    let spent_items =
+        crate::v2::database::item_instances::get_spent_item_instances(pool, campaign_id).await?;
+    let item_ids = local_ids(items.iter().map(|i| InternalId::from_i32(i.id)));
+
+    let event_groups = super::events::get_campaign_event_groups(pool, campaign_id).await?;
+    let event_group_ids = local_ids(event_groups.iter().map(|g| g.id));
+    let events = super::events::get_campaign_events(pool, campaign_id).await?;
+
+    // References to objects outside of the export are dropped
+    let local = |ids: &HashMap<InternalId, u32>, id: Option<i32>| -> Option<u32> {
+        ids.get(&InternalId::from_i32(id?)).copied()
+    };
+
+    let characters = characters
         .into_iter()
-        .filter_map(|e| {
-            // Only include encounters that are part of a session
-            let session_ix = sessions.iter().position(|s| Some(s.id) == e.session_id)?;
-            Some(ImportEncounter {
-                id_hash: internal_id_to_hash(e.id),
-                name: e.name,
-                description: e.description,
-                session_ix,
-                party_level: e.party_level,
-                party_size: e.party_size,
-                encounter_type: e.encounter_type,
-                treasure_items: e.treasure_items,
-                treasure_currency: e.treasure_currency,
-                extra_experience: e.extra_experience,
-            })
+        .map(|c| ImportCharacter {
+            id: character_ids[&c.id],
+            name: c.name,
+            player: c.player,
+            class: c.class,
+            ancestry: c.ancestry,
+            heritage: c.heritage,
+            background: c.background,
+            // Only a level set on the character, not one tracked from its experience
+            level: pinned_levels.get(&c.id).copied(),
+            ability_modifiers: c.ability_modifiers,
+            proficiencies: c.proficiencies,
         })
-        .collect_vec();
+        .collect();
 
     let sessions = sessions
         .into_iter()
         .map(|s| ImportSession {
-            id_hash: internal_id_to_hash(s.id),
-            name: Some(s.name),
+            id: session_ids[&s.id],
+            session_order: s.session_order,
+            name: s.name,
             description: s.description,
-            date: Some(s.play_date),
-            compiled_rewards: s
+            play_date: Some(s.play_date),
+            rewards: s
                 .compiled_rewards
                 .into_iter()
-                .map(|(character_id, rewards)| {
-                    let character = characters.iter().find(|c| c.id == character_id).unwrap();
-                    (
-                        internal_id_to_hash(character.id),
+                .filter_map(|(character_id, rewards)| {
+                    Some((
+                        *character_ids.get(&character_id)?,
                         ImportSessionCharacterRewards {
-                            gold: rewards.gold as f32,
-                            present: Some(rewards.present),
-                            // items: rewards.items.to_vec(), TODO: This was removed to match new v2
+                            gold: rewards.gold,
+                            present: rewards.present,
                         },
-                    )
+                    ))
                 })
                 .collect(),
         })
-        .collect_vec();
+        .collect();
 
-    let characters = characters
+    let encounters = encounters
         .into_iter()
-        .map(|c| ImportCharacter {
-            id_hash: internal_id_to_hash(c.id),
-            name: c.name,
-            player: c.player,
-            class: c.class,
+        .map(|(session_id, e)| ImportEncounter {
+            id: encounter_ids[&e.id],
+            session_id,
+            name: e.name,
+            description: e.description,
+            party_level: e.party_level,
+            party_size: e.party_size,
+            encounter_type: e.encounter_type,
+            treasure_currency: e.treasure_currency,
+            extra_experience: e.extra_experience,
         })
         .collect();
 
-        // TODO: This is v2 in a mishmash of v1/v2.
-    let items = crate::v2::database::item_instances::get_item_instances(
-        pool).await?.into_iter().map(|x| ImportItemInstance {
-            id_hash: i32_to_hash(x.id),
+    let items = items
+        .into_iter()
+        .map(|x| ImportItemInstance {
+            id: item_ids[&InternalId::from_i32(x.id)],
             library_item_id: InternalId::from_i32(x.library_item_id),
-            // TODO: Parent ids are intentionally disabled, as they would require the items to be made to get the ids.
-            // It's somewhat self-referential. Minor refactor to fix..
-            parent_item_id: None,
-            campaign_id: x.campaign_id.map(i32_to_hash),
-            encounter_id: x.encounter_id.map(i32_to_hash),
-            character_id: x.character_id.map(i32_to_hash),
-            session_id: x.session_id.map(i32_to_hash),
+            parent_item_id: local(&item_ids, x.parent_item_id),
+            encounter_id: local(&encounter_ids, x.encounter_id),
+            character_id: local(&character_ids, x.character_id),
+            session_id: local(&session_ids, x.session_id),
             is_reward: x.is_reward,
             quantity: x.quantity,
+            spent: spent_items.contains_key(&x.id),
+            spent_session_id: local(&session_ids, spent_items.get(&x.id).copied().flatten()),
             nickname: x.nickname,
             notes: x.notes,
         })
-        .collect_vec();
+        .collect();
+
+    let events = events
+        .into_iter()
+        .filter_map(|e| {
+            Some(ImportEvent {
+                event_group_id: e
+                    .event_group
+                    .and_then(|id| event_group_ids.get(&id).copied()),
+                session_id: *session_ids.get(&e.session_id)?,
+                character_id: e.character.and_then(|id| character_ids.get(&id).copied()),
+                timestamp: e.timestamp,
+                intra_session_order: e.intra_session_order,
+                event_type: e.event_type,
+            })
+        })
+        .collect();
 
-    let campaign = ImportCampaign {
-        id_hash: internal_id_to_hash(campaign.id),
-        name: campaign.name.clone(),
-        level: campaign.level,
-        description: campaign.description.clone(),
+    let event_groups = event_groups
+        .into_iter()
+        .filter_map(|g| {
+            Some(ImportEventGroup {
+                id: event_group_ids[&g.id],
+                session_id: *session_ids.get(&g.session_id)?,
+                name: g.name,
+                description: g.description,
+                timestamp: g.timestamp,
+                intra_session_order: g.intra_session_order,
+            })
+        })
+        .collect();
+
+    Ok(ImportCampaign {
+        version: EXPORT_VERSION,
+        name: campaign.name,
+        description: campaign.description,
+        absent_characters_earn_experience: campaign.absent_characters_earn_experience,
         characters,
         sessions,
         encounters,
-        items
-    };
-
-    Ok(campaign)
+        items,
+        event_groups,
+        events,
+    })
 }
 
-// TODO: This is unnecessarily complicated
-fn internal_id_to_hash(id: InternalId) -> u32 {
-    // Convert InternalId to a hash
-    let bytes = id.0.to_le_bytes();
-    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
-}
+/// Version 0 of the export format: unversioned, with objects identified by an 'id_hash'
+/// (their id in the exporting database) and encounters linked to sessions by index.
+pub mod v0 {
+    use super::*;
+
+    #[derive(Serialize, Debug, Deserialize)]
+    pub struct ImportCampaign {
+        pub id_hash: u32,
+        pub name: String,
+        pub level: u8,
+        pub description: Option<String>,
+        pub characters: Vec<ImportCharacter>,
+        pub sessions: Vec<ImportSession>,
+        pub encounters: Vec<ImportEncounter>,
+        #[serde(default)]
+        pub items: Vec<ImportItemInstance>,
+    }
 
-fn i32_to_hash(id: i32) -> u32 {
-    let internal_id = InternalId::from_i32(id);
-    internal_id_to_hash(internal_id)
-}
\ No newline at end of file
+    #[derive(Serialize, Debug, Deserialize)]
+    pub struct ImportCharacter {
+        pub id_hash: u32,
+        pub name: String,
+        pub player: Option<String>,
+        pub class: InternalId,
+    }
+
+    #[derive(Serialize, Debug, Deserialize)]
+    pub struct ImportItemInstance {
+        pub id_hash: u32,
+        pub library_item_id: InternalId,
+        pub parent_item_id: Option<u32>,
+        pub campaign_id: Option<u32>,
+        pub encounter_id: Option<u32>,
+        pub character_id: Option<u32>,
+        pub session_id: Option<u32>,
+        pub is_reward: bool,
+        pub quantity: u16,
+        pub nickname: Option<String>,
+        pub notes: Option<String>,
+    }
+
+    #[derive(Serialize, Debug, Deserialize)]
+    pub struct ImportSession {
+        pub id_hash: u32,
+        pub name: Option<String>,
+        pub description: Option<String>,
+        pub date: Option<DateTime<Utc>>,
+        pub compiled_rewards: HashMap<u32, ImportSessionCharacterRewards>,
+    }
+
+    #[derive(Serialize, Debug, Deserialize)]
+    pub struct ImportSessionCharacterRewards {
+        pub gold: f32,
+        // Default to true (if the reward struct is provided) for backwards compatibility
+        pub present: Option<bool>,
+    }
+
+    #[derive(Serialize, Debug, Deserialize)]
+    pub struct ImportEncounter {
+        pub id_hash: u32,
+
+        pub name: String,
+        pub description: Option<String>,
+        pub session_ix: usize,
+
+        pub party_level: u32,
+        pub party_size: u32,
+
+        #[serde(flatten)]
+        pub encounter_type: EncounterType,
+
+        pub treasure_items: Vec<InternalId>,
+        pub treasure_currency: f32,
+        pub extra_experience: i32,
+    }
+
+    impl ImportCampaign {
+        /// Migrates to version 1: id hashes and session indices become local ids,
+        /// and encounter treasure items become item instances.
+        pub fn migrate(self) -> crate::Result<super::ImportCampaign> {
+            let character_ids: HashMap<u32, u32> = self
+                .characters
+                .iter()
+                .enumerate()
+                .map(|(ix, c)| (c.id_hash, ix as u32 + 1))
+                .collect();
+            let session_ids: HashMap<u32, u32> = self
+                .sessions
+                .iter()
+                .enumerate()
+                .map(|(ix, s)| (s.id_hash, ix as u32 + 1))
+                .collect();
+            let encounter_ids: HashMap<u32, u32> = self
+                .encounters
+                .iter()
+                .enumerate()
+                .map(|(ix, e)| (e.id_hash, ix as u32 + 1))
+                .collect();
+            let resolve = |ids: &HashMap<u32, u32>, id: u32, kind: &str| {
+                ids.get(&id).copied().ok_or(ServerError::BadRequest(format!(
+                    "{} {} not found in '{}s'",
+                    kind, id, kind
+                )))
+            };
+
+            let characters = self
+                .characters
+                .into_iter()
+                .map(|c| super::ImportCharacter {
+                    id: character_ids[&c.id_hash],
+                    name: c.name,
+                    player: c.player,
+                    class: c.class,
+                    ancestry: None,
+                    heritage: None,
+                    background: None,
+                    level: None,
+                    ability_modifiers: Default::default(),
+                    proficiencies: Default::default(),
+                })
+                .collect();
+
+            let sessions = self
+                .sessions
+                .into_iter()
+                .enumerate()
+                .map(|(ix, s)| {
+                    Ok(super::ImportSession {
+                        id: session_ids[&s.id_hash],
+                        session_order: (ix * 1000) as u32,
+                        name: s.name.unwrap_or_else(|| "Untitled session".to_string()),
+                        description: s.description,
+                        play_date: s.date,
+                        rewards: s
+                            .compiled_rewards
+                            .into_iter()
+                            .map(|(character_id, rewards)| {
+                                Ok((
+                                    resolve(&character_ids, character_id, "character")?,
+                                    super::ImportSessionCharacterRewards {
+                                        gold: rewards.gold as f64,
+                                        present: rewards.present.unwrap_or(true),
+                                    },
+                                ))
+                            })
+                            .collect::<crate::Result<_>>()?,
+                    })
+                })
+                .collect::<crate::Result<Vec<_>>>()?;
+
+            // Encounter treasure items were inserted as item instances of the encounter
+            let mut items = vec![];
+            let mut encounters = vec![];
+            for e in self.encounters {
+                let id = encounter_ids[&e.id_hash];
+                let session_id =
+                    sessions
+                        .get(e.session_ix)
+                        .map(|s| s.id)
+                        .ok_or(ServerError::BadRequest(format!(
+                            "Session {} not found in 'sessions'",
+                            e.session_ix
+                        )))?;
+                items.extend(e.treasure_items.iter().map(|library_item_id| {
+                    super::ImportItemInstance {
+                        id: 0,
+                        library_item_id: *library_item_id,
+                        parent_item_id: None,
+                        encounter_id: Some(id),
+                        character_id: None,
+                        session_id: Some(session_id),
+                        is_reward: false,
+                        quantity: 1,
+                        nickname: None,
+                        notes: None,
+                        spent: false,
+                        spent_session_id: None,
+                    }
+                }));
+                encounters.push(super::ImportEncounter {
+                    id,
+                    session_id: Some(session_id),
+                    name: e.name,
+                    description: e.description,
+                    party_level: e.party_level,
+                    party_size: e.party_size,
+                    encounter_type: e.encounter_type,
+                    treasure_currency: e.treasure_currency,
+                    extra_experience: e.extra_experience,
+                });
+            }
+
+            // Older exports included items of every campaign; only keep those of this one
+            let v0_items = self
+                .items
+                .into_iter()
+                .filter(|i| {
+                    i.campaign_id == Some(self.id_hash)
+                        || i.encounter_id
+                            .is_some_and(|id| encounter_ids.contains_key(&id))
+                        || i.character_id
+                            .is_some_and(|id| character_ids.contains_key(&id))
+                        || i.session_id.is_some_and(|id| session_ids.contains_key(&id))
+                })
+                .collect_vec();
+            let item_ids: HashMap<u32, u32> = v0_items
+                .iter()
+                .enumerate()
+                .map(|(ix, i)| (i.id_hash, (items.len() + ix) as u32 + 1))
+                .collect();
+            for item in v0_items {
+                items.push(super::ImportItemInstance {
+                    id: 0,
+                    library_item_id: item.library_item_id,
+                    parent_item_id: item
+                        .parent_item_id
+                        .map(|id| resolve(&item_ids, id, "item"))
+                        .transpose()?,
+                    encounter_id: item
+                        .encounter_id
+                        .map(|id| resolve(&encounter_ids, id, "encounter"))
+                        .transpose()?,
+                    character_id: item
+                        .character_id
+                        .map(|id| resolve(&character_ids, id, "character"))
+                        .transpose()?,
+                    session_id: item
+                        .session_id
+                        .map(|id| resolve(&session_ids, id, "session"))
+                        .transpose()?,
+                    is_reward: item.is_reward,
+                    quantity: item.quantity,
+                    nickname: item.nickname,
+                    notes: item.notes,
+                    spent: false,
+                    spent_session_id: None,
+                });
+            }
+            for (ix, item) in items.iter_mut().enumerate() {
+                item.id = ix as u32 + 1;
+            }
+
+            Ok(super::ImportCampaign {
+                version: 1,
+                name: self.name,
+                description: self.description,
+                absent_characters_earn_experience: true,
+                characters,
+                sessions,
+                encounters,
+                items,
+                event_groups: vec![],
+                events: vec![],
+            })
+        }
+    }
+}
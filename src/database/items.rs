@@ -1,18 +1,21 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use crate::models::characters::Stat;
 use crate::models::ids::InternalId;
 use crate::models::library::item::{Rune, RuneItemType, SkillPotency};
-use crate::models::library::{item::LibraryItem, GameSystem, Rarity};
+use crate::models::library::{item::LibraryItem, GameSystem, LibraryObjectType, Rarity};
 
 use crate::models::query::CommaSeparatedVec;
 use crate::ServerError;
-use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use sqlx::PgConnection;
 
 use super::sorts::{Sortable, SortableColumn};
-use super::{check_library_requested_ids, tags, LegacyStatus, DEFAULT_MAX_LIMIT};
+use super::{
+    insert_library_objects, set_library_object_tags, InsertLibraryObject, LibraryUpsert,
+    LibraryUpsertReport,
+};
+use super::{tags, HomebrewVisibility, LegacyStatus, DEFAULT_MAX_LIMIT};
 
 #[derive(Default, Serialize, Deserialize, Debug, Clone)]
 pub struct ItemFiltering {
@@ -36,8 +39,14 @@ pub struct ItemFiltering {
     pub magical: Option<bool>,
     pub cursed: Option<bool>,
 
+    // Homebrew objects visible to the requesting user. Not part of the query string.
+    #[serde(skip)]
+    pub homebrew: HomebrewVisibility,
+
     pub limit: Option<u64>,
     pub page: Option<u64>,
+    // Continue after a previous page's `next_cursor`, instead of by page. `start` for the first page.
+    pub cursor: Option<String>,
     pub sort_by: Option<String>,
     pub order_by: Option<String>,
 }
@@ -50,9 +59,11 @@ impl ItemFiltering {
         }
     }
 
+    // Lookups by id from data that already references these objects (eg: encounters), so includes all homebrew
     pub fn from_ids(ids: &[u32]) -> Self {
         Self {
             ids: Some(CommaSeparatedVec(ids.to_vec())),
+            homebrew: HomebrewVisibility::All,
             ..Default::default()
         }
     }
@@ -88,8 +99,15 @@ pub struct ItemSearch {
     pub magical: Option<bool>,
     pub cursed: Option<bool>,
 
+    // Homebrew objects visible to the requesting user. Not part of the query string.
+    #[serde(skip)]
+    pub homebrew: HomebrewVisibility,
+
     pub limit: Option<u64>,
     pub page: Option<u64>,
+    // Searches rank by similarity, so are only paged by offset. Set from a listing's cursor.
+    #[serde(skip)]
+    pub cursor: Option<String>,
     pub sort_by: Option<String>,
     pub order_by: Option<String>,
 }
@@ -111,8 +129,10 @@ impl From<ItemFiltering> for ItemSearch {
             legacy: filter.legacy,
             traits_all: filter.traits_all,
             traits_any: filter.traits_any,
+            homebrew: filter.homebrew,
             limit: filter.limit,
             page: filter.page,
+            cursor: filter.cursor,
             relic_gift: filter.relic_gift,
             consumable: filter.consumable,
             magical: filter.magical,
@@ -123,6 +143,16 @@ impl From<ItemFiltering> for ItemSearch {
     }
 }
 
+/// Number of items matching a filter, in total and by each value of the fields that can be filtered on.
+#[derive(Default, Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ItemFacets {
+    pub total: u64,
+    pub rarity: HashMap<Rarity, u64>,
+    pub level: BTreeMap<i8, u64>,
+    pub traits: BTreeMap<String, u64>,
+    pub item_categories: BTreeMap<String, u64>,
+}
+
 impl Sortable for LibraryItem {
     fn get_allowed_fields() -> &'static [&'static str] {
         &["name", "level", "price", "rarity"]
@@ -131,11 +161,29 @@ impl Sortable for LibraryItem {
     fn default_sort() -> Option<&'static str> {
         Some("name")
     }
+
+    fn get_sort_value(&self, field: &str) -> serde_json::Value {
+        match field {
+            "name" => self.name.as_str().into(),
+            "level" => self.level.into(),
+            "price" => self.price.into(),
+            "rarity" => self.rarity.as_i64().into(),
+            _ => serde_json::Value::Null,
+        }
+    }
+
+    fn get_id(&self) -> InternalId {
+        self.id
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct InsertLibraryItem {
     pub requested_id: Option<InternalId>,
+    #[serde(default)]
+    pub source_id: Option<String>,
+    #[serde(skip)]
+    pub owner: Option<InternalId>,
     pub name: String,
     pub game_system: GameSystem,
     pub rarity: Rarity,
@@ -162,6 +210,22 @@ pub struct InsertLibraryItem {
     pub runic_context: Option<InsertRune>,
 }
 
+impl From<&InsertLibraryItem> for InsertLibraryObject {
+    fn from(object: &InsertLibraryItem) -> Self {
+        InsertLibraryObject {
+            requested_id: object.requested_id,
+            source_id: object.source_id.clone(),
+            owner: object.owner,
+            name: object.name.clone(),
+            game_system: object.game_system.clone(),
+            url: object.url.clone(),
+            description: object.description.clone(),
+            legacy: object.legacy,
+            remastering_alt_id: object.remastering_alt_id,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct InsertRune {
     pub runic_stat_boost_category_bonus_id: Option<i32>, // If a rune, what stat boost category does it belong to?
@@ -203,6 +267,11 @@ pub async fn get_items_search(
         search.sort_by.as_deref(),
         search.order_by.as_deref(),
     )?;
+    // A cursor picks up after the last object of the previous page, in place of the offset
+    let (offset, after) = match &search.cursor {
+        Some(cursor) => (0, sort.parse_cursor(cursor)?),
+        None => (offset, None),
+    };
 
     let min_similarity = search.min_similarity.unwrap_or(0.0);
 
@@ -257,6 +326,11 @@ pub async fn get_items_search(
                 JSON_AGG(JSON_BUILD_OBJECT('name', r.name, 'potency', r.potency)) FILTER (WHERE r.potency IS NOT NULL) AS runes,
                 JSON_AGG(JSON_BUILD_OBJECT('skill', sb.skill, 'bonus', sb.bonus)) FILTER (WHERE sb.bonus IS NOT NULL) AS skill_boosts
             FROM library_objects lo
+            -- The same filters as get_item_facets
+            INNER JOIN library_items_matching(
+                $1::text, $2::int, $3::int, $4::int, $5::int, $6::int, $7::int, $8::text[], $9::text[], $10::int[],
+                $14::bool, $15::bool, $16::bool, $17::bool, $18::bool, $19::bool, $20::bool, $21::bool, $24::bool, $25::int
+            ) m ON lo.id = m.id
             INNER JOIN library_items li ON lo.id = li.id
             LEFT JOIN (
                 SELECT
@@ -271,35 +345,40 @@ pub async fn get_items_search(
             LEFT JOIN runes r ON lir.rune_id = r.id
             LEFT JOIN library_items_skill_boosts sb ON lo.id = sb.item_id
             WHERE
-                ($1::text IS NULL OR lo.name ILIKE '%' || $1 || '%')
-                AND ($2::int IS NULL OR rarity = $2)
-                AND ($3::int IS NULL OR game_system = $3)
-                AND ($4::int IS NULL OR level >= $4)
-                AND ($5::int IS NULL OR level <= $5)
-                AND ($6::int IS NULL OR price >= $6)
-                AND ($7::int IS NULL OR price <= $7)
-                AND ($8::text[] IS NULL OR tags.traits::text[] && $8::text[])
-                AND ($9::text[] IS NULL OR tags.traits::text[] @> $9::text[])
-                AND ($10::int[] IS NULL OR lo.id = ANY($10))
-                AND (($13::bool AND lo.name ILIKE '%' || query || '%') OR SIMILARITY(lo.name, query) >= $12)
-                AND NOT (NOT $14::bool AND lo.legacy = FALSE)
-                AND NOT (NOT $15::bool AND lo.legacy = TRUE)
-                AND NOT ($16::bool AND lo.remastering_alt_id IS NOT NULL AND lo.legacy = TRUE)
-                AND NOT ($17::bool AND lo.remastering_alt_id IS NOT NULL AND lo.legacy = FALSE)
-                AND ($18::bool IS NULL OR ($18::bool AND li.relic_gift_stage IS NOT NULL) OR ($18::bool = FALSE AND li.relic_gift_stage IS NULL))
-                AND ($19::bool IS NULL OR ($19::bool AND li.consumable = TRUE) OR ($19::bool = FALSE AND li.consumable = FALSE))
-                AND ($20::bool IS NULL OR ($20::bool AND li.magical = TRUE) OR ($20::bool = FALSE AND li.magical = FALSE))
-                AND ($21::bool IS NULL OR ($21::bool AND li.cursed = TRUE) OR ($21::bool = FALSE AND li.cursed = FALSE))
+                (($13::bool AND lo.name ILIKE '%' || query || '%') OR SIMILARITY(lo.name, query) >= $12)
+                -- After the cursor's sort keys, then id. Descending keys swap sides, so that a single row comparison
+                -- covers any mix of directions. Keys past the sort's columns are equal on both sides.
+                AND ($28::jsonb IS NULL OR (
+                    COALESCE(CASE ($23::int[])[1] WHEN 1 THEN (to_jsonb(lo) || to_jsonb(li)) -> ($22::text[])[1] WHEN -1 THEN $28 -> 0 END, 'null'),
+                    COALESCE(CASE ($23::int[])[2] WHEN 1 THEN (to_jsonb(lo) || to_jsonb(li)) -> ($22::text[])[2] WHEN -1 THEN $28 -> 1 END, 'null'),
+                    COALESCE(CASE ($23::int[])[3] WHEN 1 THEN (to_jsonb(lo) || to_jsonb(li)) -> ($22::text[])[3] WHEN -1 THEN $28 -> 2 END, 'null'),
+                    lo.id
+                ) > (
+                    COALESCE(CASE ($23::int[])[1] WHEN 1 THEN $28 -> 0 WHEN -1 THEN (to_jsonb(lo) || to_jsonb(li)) -> ($22::text[])[1] END, 'null'),
+                    COALESCE(CASE ($23::int[])[2] WHEN 1 THEN $28 -> 1 WHEN -1 THEN (to_jsonb(lo) || to_jsonb(li)) -> ($22::text[])[2] END, 'null'),
+                    COALESCE(CASE ($23::int[])[3] WHEN 1 THEN $28 -> 2 WHEN -1 THEN (to_jsonb(lo) || to_jsonb(li)) -> ($22::text[])[3] END, 'null'),
+                    ($28 -> cardinality($22::text[]))::int
+                ))
             GROUP BY lo.id, li.id 
+            -- Up to MAX_SORT_COLUMNS sort keys, then id to break ties
             ORDER BY similarity DESC, favor_exact_start_length,
-                CASE WHEN $22::text = 'name' AND $23::int = 1 THEN lo.name::text END ASC,
-                CASE WHEN $22::text = 'name' AND $23::int = -1 THEN lo.name::text END DESC,
-                CASE WHEN $22::text = 'level' THEN level::integer * $23::int END ASC,
-                CASE WHEN $22::text = 'price' THEN price::integer * $23::int END ASC,
-                CASE WHEN $22::text = 'rarity' THEN li.rarity::integer * $23::int END ASC
-            LIMIT $24 OFFSET $25
+                CASE WHEN ($23::int[])[1] = 1 THEN (to_jsonb(lo) || to_jsonb(li)) -> ($22::text[])[1] END ASC,
+                CASE WHEN ($23::int[])[1] = -1 THEN (to_jsonb(lo) || to_jsonb(li)) -> ($22::text[])[1] END DESC,
+                CASE WHEN ($23::int[])[2] = 1 THEN (to_jsonb(lo) || to_jsonb(li)) -> ($22::text[])[2] END ASC,
+                CASE WHEN ($23::int[])[2] = -1 THEN (to_jsonb(lo) || to_jsonb(li)) -> ($22::text[])[2] END DESC,
+                CASE WHEN ($23::int[])[3] = 1 THEN (to_jsonb(lo) || to_jsonb(li)) -> ($22::text[])[3] END ASC,
+                CASE WHEN ($23::int[])[3] = -1 THEN (to_jsonb(lo) || to_jsonb(li)) -> ($22::text[])[3] END DESC,
+                lo.id
+            LIMIT $26 OFFSET $27
         ) c
-        ORDER BY similarity DESC, favor_exact_start_length 
+        ORDER BY similarity DESC, favor_exact_start_length,
+            CASE WHEN ($23::int[])[1] = 1 THEN to_jsonb(c) -> ($22::text[])[1] END ASC,
+            CASE WHEN ($23::int[])[1] = -1 THEN to_jsonb(c) -> ($22::text[])[1] END DESC,
+            CASE WHEN ($23::int[])[2] = 1 THEN to_jsonb(c) -> ($22::text[])[2] END ASC,
+            CASE WHEN ($23::int[])[2] = -1 THEN to_jsonb(c) -> ($22::text[])[2] END DESC,
+            CASE WHEN ($23::int[])[3] = 1 THEN to_jsonb(c) -> ($22::text[])[3] END ASC,
+            CASE WHEN ($23::int[])[3] = -1 THEN to_jsonb(c) -> ($22::text[])[3] END DESC,
+            c.id
     "#,
         search.name,
         search.rarity.as_ref().map(|r| r.as_i64() as i32),
@@ -322,10 +401,13 @@ pub async fn get_items_search(
         search.consumable,
         search.magical,
         search.cursed,
-        sort.get_column(),
-        sort.get_sort_direction_i32(),
+        &sort.get_columns(),
+        &sort.get_sort_directions_i32(),
+        search.homebrew.all(),
+        search.homebrew.user_id(),
         limit as i64,
         offset as i64,
+        after,
     )
     .fetch_all(&mut *conn)
     .await?;
@@ -370,6 +452,7 @@ pub async fn get_items_search(
             traits: row.traits.unwrap_or_default(),
             consumable: row.consumable,
             legacy: row.legacy,
+            remastering_alt_id: row.remastering_alt_id.map(|id| InternalId(id as u32)),
             magical: row.magical,
             cursed: row.cursed,
             relic_gift_stage: row.relic_gift_stage.map(|i| i as i8),
@@ -388,64 +471,139 @@ pub async fn get_items_search(
     Ok(items)
 }
 
+/// Counts the items matching a filter (ignoring paging) by rarity, level, trait and category.
+/// Each count is under all of the filters, including any filter on that same field.
+pub async fn get_item_facets(
+    conn: &mut PgConnection,
+    condition: &ItemFiltering,
+) -> crate::Result<ItemFacets> {
+    let ids = condition.ids.clone().map(|t| {
+        t.into_inner()
+            .into_iter()
+            .map(|id| id as i32)
+            .collect::<Vec<i32>>()
+    });
+    let matching_tags =
+        tags::get_tag_matches(&mut *conn, &condition.traits_all, &condition.traits_any).await?;
+
+    let rows = sqlx::query!(
+        r#"
+        WITH matches AS (
+            SELECT li.rarity, li.level, li.item_categories, tags.traits
+            -- The same filters as get_items_search
+            FROM library_items_matching(
+                $1::text, $2::int, $3::int, $4::int, $5::int, $6::int, $7::int, $8::text[], $9::text[], $10::int[],
+                $11::bool, $12::bool, $13::bool, $14::bool, $15::bool, $16::bool, $17::bool, $18::bool, $19::bool, $20::int
+            ) m
+            INNER JOIN library_items li ON m.id = li.id
+            LEFT JOIN (
+                SELECT library_object_id AS lo_id, ARRAY_AGG(t.tag) FILTER (WHERE t.trait) AS traits
+                FROM library_objects_tags lot
+                INNER JOIN library_tags t ON lot.tag_id = t.id
+                GROUP BY lot.library_object_id
+            ) AS tags ON m.id = tags.lo_id
+        )
+        SELECT 'total' AS "facet!", NULL AS value, COUNT(*) AS "count!" FROM matches
+        UNION ALL
+        SELECT 'rarity', rarity::text, COUNT(*) FROM matches GROUP BY rarity
+        UNION ALL
+        SELECT 'level', level::text, COUNT(*) FROM matches GROUP BY level
+        UNION ALL
+        SELECT 'trait', t, COUNT(*) FROM matches, UNNEST(traits) t GROUP BY t
+        UNION ALL
+        SELECT 'item_category', c, COUNT(*) FROM matches, UNNEST(item_categories) c GROUP BY c
+        "#,
+        condition.name,
+        condition.rarity.as_ref().map(|r| r.as_i64() as i32),
+        condition.game_system.as_ref().map(|gs| gs.as_i64() as i32),
+        condition.min_level.map(|l| l as i32),
+        condition.max_level.map(|l| l as i32),
+        condition.min_price,
+        condition.max_price,
+        matching_tags.any_traits.as_deref() as _,
+        matching_tags.all_traits.as_deref() as _,
+        &ids as _,
+        condition.legacy.include_remaster(),
+        condition.legacy.include_legacy(),
+        condition.legacy.favor_remaster(),
+        condition.legacy.favor_legacy(),
+        condition.relic_gift,
+        condition.consumable,
+        condition.magical,
+        condition.cursed,
+        condition.homebrew.all(),
+        condition.homebrew.user_id(),
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let mut facets = ItemFacets::default();
+    for row in rows {
+        let count = row.count as u64;
+        let value = row.value.unwrap_or_default();
+        match row.facet.as_str() {
+            "total" => facets.total = count,
+            "rarity" => {
+                let rarity = Rarity::from_i64(value.parse().unwrap_or_default());
+                *facets.rarity.entry(rarity).or_default() += count;
+            }
+            "level" => {
+                facets
+                    .level
+                    .insert(value.parse().unwrap_or_default(), count);
+            }
+            "trait" => {
+                facets.traits.insert(value, count);
+            }
+            _ => {
+                facets.item_categories.insert(value, count);
+            }
+        }
+    }
+    Ok(facets)
+}
+
 pub async fn insert_items(
     tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
     items: &[InsertLibraryItem],
-) -> crate::Result<()> {
-    // TODO: Don't *need* two tables for this
+) -> crate::Result<Vec<InternalId>> {
     if items.is_empty() {
-        return Ok(());
+        return Ok(vec![]);
     }
 
-    // First, check to make sure all requested ids are not already in use
-    let requested_ids = items
+    let objects = items
         .iter()
-        .filter_map(|i| i.requested_id)
-        .map(|id| id.0 as i32)
-        .collect::<Vec<i32>>();
-    check_library_requested_ids(&mut **tx, &requested_ids).await?;
+        .map(InsertLibraryObject::from)
+        .collect::<Vec<_>>();
+    let ids = insert_library_objects(tx, &objects).await?;
+    write_items(tx, &ids, items).await?;
+    Ok(ids.into_iter().map(|id| InternalId(id as u32)).collect())
+}
 
-    let ids = sqlx::query!(
-        r#"
-        INSERT INTO library_objects (id, name, game_system, url, description, legacy, remastering_alt_id)
-        SELECT * FROM UNNEST ($1::int[], $2::text[], $3::int[], $4::text[], $5::text[], $6::bool[], $7::int[])
-        RETURNING id 
-    "#,
-        &items
-            .iter()
-            .map(|c| c.requested_id.as_ref().map(|id| id.0 as i32))
-            .collect::<Vec<Option<i32>>>() as _,
-        &items
-            .iter()
-            .map(|c| c.name.clone())
-            .collect::<Vec<String>>(),
-        &items
-            .iter()
-            .map(|c| c.game_system.as_i64() as i32)
-            .collect::<Vec<i32>>(),
-        &items
-            .iter()
-            .map(|c| c.url.clone())
-            .collect::<Vec<Option<String>>>() as _,
-        &items
-            .iter()
-            .map(|c| c.description.clone())
-            .collect::<Vec<String>>(),
-        &items
-            .iter()
-            .map(|c| c.legacy)
-            .collect::<Vec<bool>>(),
-        &items
-            .iter()
-            .map(|c| c.remastering_alt_id.as_ref().map(|id| id.0 as i32))
-            .collect::<Vec<Option<i32>>>() as _,
-    )
-    .fetch_all(&mut **tx)
-    .await?
-    .into_iter()
-    .map(|row| Ok(row.id))
-    .collect::<Result<Vec<i32>, sqlx::Error>>()?;
+/// Inserts new items, and updates existing ones in place.
+pub async fn upsert_items(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    items: &[InsertLibraryItem],
+) -> crate::Result<LibraryUpsertReport> {
+    if items.is_empty() {
+        return Ok(LibraryUpsertReport::default());
+    }
+
+    let objects = items
+        .iter()
+        .map(InsertLibraryObject::from)
+        .collect::<Vec<_>>();
+    let upsert = LibraryUpsert::begin(tx, LibraryObjectType::Item, &objects).await?;
+    write_items(tx, &upsert.ids, items).await?;
+    upsert.finish(tx).await
+}
 
+// Writes everything but the 'library_objects' row
+async fn write_items(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ids: &[i32],
+    items: &[InsertLibraryItem],
+) -> crate::Result<()> {
     // TODO: Unnest problem with arrays again
     for (item, id) in items.iter().zip(ids.iter()) {
         let apex_stat = item.apex_stat.as_ref().map(|s| s.to_string());
@@ -453,6 +611,11 @@ pub async fn insert_items(
             r#"
             INSERT INTO library_items (id, rarity, level, price, item_categories, consumable, magical, cursed, relic_gift_stage, item_type, apex_stat)
             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            ON CONFLICT (id) DO UPDATE
+            SET rarity = EXCLUDED.rarity, level = EXCLUDED.level, price = EXCLUDED.price,
+                item_categories = EXCLUDED.item_categories, consumable = EXCLUDED.consumable,
+                magical = EXCLUDED.magical, cursed = EXCLUDED.cursed, relic_gift_stage = EXCLUDED.relic_gift_stage,
+                item_type = EXCLUDED.item_type, apex_stat = EXCLUDED.apex_stat
         "#,
             id,
             item.rarity.as_i64() as i32,
@@ -484,6 +647,10 @@ pub async fn insert_items(
                 r#"
                 INSERT INTO runes (item_id, name, fundamental, stat_boost_category_id, legacy, potency, applied_to_item_type)
                 VALUES ($1, $2, $3, $4, $5, $6, $7)
+                ON CONFLICT (item_id, name) DO UPDATE
+                SET fundamental = EXCLUDED.fundamental, stat_boost_category_id = EXCLUDED.stat_boost_category_id,
+                    legacy = EXCLUDED.legacy, potency = EXCLUDED.potency,
+                    applied_to_item_type = EXCLUDED.applied_to_item_type
             "#,
                 *id as i32,
                 rune_base_name,
@@ -496,6 +663,15 @@ pub async fn insert_items(
             .execute(&mut **tx)
             .await?;
         } else {
+            sqlx::query!(
+                r#"
+                DELETE FROM library_items_runes WHERE item_id = $1
+            "#,
+                *id as i32,
+            )
+            .execute(&mut **tx)
+            .await?;
+
             // Insert any runes that this may have!
             // We do so by matching names + potency. We also match equivalent legacy status if possible.
             sqlx::query!(
@@ -530,6 +706,15 @@ pub async fn insert_items(
         }
 
         // Insert stat boosts
+        sqlx::query!(
+            r#"
+            DELETE FROM library_items_skill_boosts WHERE item_id = $1
+        "#,
+            *id as i32,
+        )
+        .execute(&mut **tx)
+        .await?;
+
         let skills = item
             .skill_boosts
             .iter()
@@ -550,24 +735,7 @@ pub async fn insert_items(
         )
         .execute(&mut **tx)
         .await?;
-
-        // Insert tags
-        sqlx::query!(
-            r#"
-            INSERT INTO library_objects_tags (library_object_id, tag_id)
-            SELECT $1, tag_id FROM UNNEST ($2::int[]) tag_ids(tag_id)
-            "#,
-            *id as i32,
-            &item
-                .tags
-                .iter()
-                .map(|id| id.0 as i32)
-                .sorted()
-                .collect::<Vec<i32>>(),
-        )
-        .execute(&mut **tx)
-        .await?;
     }
 
-    Ok(())
+    set_library_object_tags(tx, ids, &items.iter().map(|o| &o.tags).collect::<Vec<_>>()).await
 }
@@ -1,22 +1,34 @@
+use std::collections::{HashMap, HashSet};
+
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 
+use crate::models::ids::InternalId;
+use crate::models::library::{GameSystem, LibraryObjectType};
 use crate::ServerError;
 
 pub mod auth;
 pub mod campaigns;
 pub mod characters;
 pub mod classes;
+pub mod combat;
 pub mod creatures;
 pub mod encounters;
+pub mod events;
+pub mod foundry;
 pub mod hazards;
 pub mod import;
 pub mod items;
+pub mod pathbuilder;
+pub mod remaster;
+pub mod search;
 pub mod sessions;
 pub mod sorts;
 pub mod spells;
 pub mod stats;
+pub mod subsystems;
 pub mod tags;
+pub mod treasure;
 
 pub const DEFAULT_MAX_LIMIT: u64 = 100;
 pub const DEFAULT_MAX_GROUP_LIMIT: u64 = 25;
@@ -65,6 +77,56 @@ impl LegacyStatus {
     }
 }
 
+/// Which homebrew library objects a query can see. Official objects (without an owner) are always visible.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum HomebrewVisibility {
+    #[default]
+    None,
+    // Homebrew owned by the user, or shared to a campaign they are part of
+    User(InternalId),
+    All,
+}
+
+impl HomebrewVisibility {
+    pub fn all(&self) -> bool {
+        matches!(self, Self::All)
+    }
+
+    pub fn user_id(&self) -> Option<i32> {
+        match self {
+            Self::User(id) => Some(id.0 as i32),
+            _ => None,
+        }
+    }
+}
+
+/// Shares a homebrew library object with a campaign (or unshares it, with `None`).
+/// Only the owner of the object can change this.
+pub async fn set_library_object_campaign(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    id: InternalId,
+    owner: InternalId,
+    campaign_id: Option<InternalId>,
+) -> crate::Result<()> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE library_objects
+        SET campaign_id = $3
+        WHERE id = $1 AND owner = $2
+        "#,
+        id.0 as i32,
+        owner.0 as i32,
+        campaign_id.map(|id| id.0 as i32),
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(ServerError::NotFound);
+    }
+    Ok(())
+}
+
 pub async fn check_library_requested_ids(
     exec: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
     requested_ids: &[i32],
@@ -87,3 +149,358 @@ pub async fn check_library_requested_ids(
 
     Ok(existing_ids.iter().map(|id| id.id).collect())
 }
+
+/// The fields shared by every library object, stored in `library_objects`.
+pub struct InsertLibraryObject {
+    pub requested_id: Option<InternalId>,
+    // Stable id in the data the object is imported from (eg: 'foundry:<document id>')
+    pub source_id: Option<String>,
+    // The user that owns this homebrew object, or None for official content
+    pub owner: Option<InternalId>,
+    pub name: String,
+    pub game_system: GameSystem,
+    pub url: Option<String>,
+    pub description: String,
+    pub legacy: bool,
+    pub remastering_alt_id: Option<InternalId>,
+}
+
+pub async fn insert_library_objects(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    objects: &[InsertLibraryObject],
+) -> crate::Result<Vec<i32>> {
+    // First, check to make sure all requested ids are not already in use
+    let requested_ids = objects
+        .iter()
+        .filter_map(|i| i.requested_id)
+        .map(|id| id.0 as i32)
+        .collect::<Vec<i32>>();
+    check_library_requested_ids(&mut **tx, &requested_ids).await?;
+
+    let ids = sqlx::query!(
+        r#"
+        INSERT INTO library_objects (id, name, game_system, url, description, legacy, remastering_alt_id, source_id, owner)
+        SELECT
+            -- Objects without a requested id are numbered after every existing and requested id
+            COALESCE(id, GREATEST(
+                (SELECT MAX(id) FROM library_objects),
+                (SELECT MAX(requested) FROM UNNEST($1::int[]) requested),
+                0
+            ) + ordinality),
+            name, game_system, url, description, legacy, remastering_alt_id, source_id, owner
+        FROM UNNEST ($1::int[], $2::text[], $3::int[], $4::text[], $5::text[], $6::bool[], $7::int[], $8::text[], $9::int[])
+            WITH ORDINALITY AS insertion(id, name, game_system, url, description, legacy, remastering_alt_id, source_id, owner, ordinality)
+        ORDER BY ordinality
+        RETURNING id
+    "#,
+        &objects
+            .iter()
+            .map(|c| c.requested_id.map(|id| id.0 as i32))
+            .collect::<Vec<Option<i32>>>() as _,
+        &objects
+            .iter()
+            .map(|c| c.name.clone())
+            .collect::<Vec<String>>(),
+        &objects
+            .iter()
+            .map(|c| c.game_system.as_i64() as i32)
+            .collect::<Vec<i32>>(),
+        &objects
+            .iter()
+            .map(|c| c.url.clone())
+            .collect::<Vec<Option<String>>>() as _,
+        &objects
+            .iter()
+            .map(|c| c.description.clone())
+            .collect::<Vec<String>>(),
+        &objects.iter().map(|c| c.legacy).collect::<Vec<bool>>(),
+        &objects
+            .iter()
+            .map(|c| c.remastering_alt_id.map(|id| id.0 as i32))
+            .collect::<Vec<Option<i32>>>() as _,
+        &objects
+            .iter()
+            .map(|c| c.source_id.clone())
+            .collect::<Vec<Option<String>>>() as _,
+        &objects
+            .iter()
+            .map(|c| c.owner.map(|id| id.0 as i32))
+            .collect::<Vec<Option<i32>>>() as _,
+    )
+    .fetch_all(&mut **tx)
+    .await?
+    .into_iter()
+    .map(|row| row.id)
+    .collect();
+
+    Ok(ids)
+}
+
+/// Replaces the tags of library objects.
+pub async fn set_library_object_tags(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ids: &[i32],
+    tags: &[&Vec<InternalId>],
+) -> crate::Result<()> {
+    sqlx::query!(
+        r#"
+        DELETE FROM library_objects_tags WHERE library_object_id = ANY($1::int[])
+        "#,
+        ids
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    let (object_ids, tag_ids): (Vec<i32>, Vec<i32>) = ids
+        .iter()
+        .zip(tags.iter())
+        .flat_map(|(id, tags)| tags.iter().map(|tag| (*id, tag.0 as i32)))
+        .unzip();
+    sqlx::query!(
+        r#"
+        INSERT INTO library_objects_tags (library_object_id, tag_id)
+        SELECT * FROM UNNEST ($1::int[], $2::int[])
+        "#,
+        &object_ids,
+        &tag_ids,
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// What an upsert did to each library object.
+#[derive(Default, Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LibraryUpsertReport {
+    pub created: Vec<InternalId>,
+    pub updated: Vec<InternalId>,
+    pub unchanged: Vec<InternalId>,
+}
+
+/// An upsert of library objects in progress.
+///
+/// Objects are matched to existing official objects of the same type by `source_id` if given, and otherwise by
+/// name, game system and legacy status. Matched objects keep their ids (so encounters, item instances, etc.
+/// still point at them), and have their `library_objects` row updated. Unmatched objects are created.
+/// Callers then write the type-specific data for `ids`, and call `finish` for the report.
+pub struct LibraryUpsert {
+    pub ids: Vec<i32>,
+    existing: Vec<bool>,
+    fingerprints: HashMap<i32, String>,
+}
+
+impl LibraryUpsert {
+    pub async fn begin(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        object_type: LibraryObjectType,
+        objects: &[InsertLibraryObject],
+    ) -> crate::Result<Self> {
+        // An object given twice would be upserted twice into the same row
+        let mut keys = HashSet::new();
+        for o in objects {
+            let key = match &o.source_id {
+                Some(source_id) => (Some(source_id.as_str()), None),
+                None => (
+                    None,
+                    Some((o.name.as_str(), o.game_system.as_i64(), o.legacy)),
+                ),
+            };
+            if !keys.insert(key) {
+                return Err(ServerError::BadRequest(match &o.source_id {
+                    Some(source_id) => format!("Source id '{}' is given more than once", source_id),
+                    None => format!("'{}' is given more than once", o.name),
+                }));
+            }
+        }
+
+        let matches = sqlx::query!(
+            r#"
+            SELECT (
+                SELECT lo.id FROM library_objects lo
+                WHERE lo.owner IS NULL AND (
+                    (k.source_id IS NOT NULL AND lo.source_id = k.source_id)
+                    -- Objects from another source are never matched by name
+                    OR (
+                        (k.source_id IS NULL OR lo.source_id IS NULL)
+                        AND lo.name = k.name AND lo.game_system = k.game_system AND lo.legacy = k.legacy
+                    )
+                ) AND CASE $5::text
+                    WHEN 'creature' THEN EXISTS (SELECT 1 FROM library_creatures t WHERE t.id = lo.id)
+                    WHEN 'item' THEN EXISTS (SELECT 1 FROM library_items t WHERE t.id = lo.id)
+                    WHEN 'hazard' THEN EXISTS (SELECT 1 FROM library_hazards t WHERE t.id = lo.id)
+                    WHEN 'spell' THEN EXISTS (SELECT 1 FROM library_spells t WHERE t.id = lo.id)
+                    WHEN 'class' THEN EXISTS (SELECT 1 FROM library_classes t WHERE t.id = lo.id)
+                    ELSE FALSE
+                END
+                ORDER BY lo.source_id IS NOT DISTINCT FROM k.source_id DESC, lo.id
+                LIMIT 1
+            ) AS id
+            FROM UNNEST($1::text[], $2::text[], $3::int[], $4::bool[])
+                WITH ORDINALITY AS k(source_id, name, game_system, legacy, ordinality)
+            ORDER BY ordinality
+            "#,
+            &objects
+                .iter()
+                .map(|o| o.source_id.clone())
+                .collect::<Vec<Option<String>>>() as _,
+            &objects
+                .iter()
+                .map(|o| o.name.clone())
+                .collect::<Vec<String>>(),
+            &objects
+                .iter()
+                .map(|o| o.game_system.as_i64() as i32)
+                .collect::<Vec<i32>>(),
+            &objects.iter().map(|o| o.legacy).collect::<Vec<bool>>(),
+            object_type.as_str(),
+        )
+        .fetch_all(&mut **tx)
+        .await?
+        .into_iter()
+        .map(|row| row.id)
+        .collect::<Vec<Option<i32>>>();
+
+        // Different keys can still match the same object (eg: by source id, and by name)
+        let mut matched = HashMap::new();
+        for (o, id) in objects.iter().zip(matches.iter()) {
+            if let Some(other) = id.and_then(|id| matched.insert(id, &o.name)) {
+                return Err(ServerError::BadRequest(format!(
+                    "'{}' and '{}' match the same library object",
+                    other, o.name
+                )));
+            }
+        }
+
+        let existing_ids = matches.iter().flatten().copied().collect::<Vec<i32>>();
+        let fingerprints = get_library_object_fingerprints(&mut **tx, &existing_ids).await?;
+
+        // Create the unmatched objects
+        let (new, existing): (Vec<_>, Vec<_>) = objects
+            .iter()
+            .zip(matches.iter())
+            .partition(|(_, id)| id.is_none());
+        let new = new
+            .into_iter()
+            .map(|(o, _)| InsertLibraryObject {
+                requested_id: o.requested_id,
+                source_id: o.source_id.clone(),
+                owner: o.owner,
+                name: o.name.clone(),
+                game_system: o.game_system.clone(),
+                url: o.url.clone(),
+                description: o.description.clone(),
+                legacy: o.legacy,
+                remastering_alt_id: o.remastering_alt_id,
+            })
+            .collect::<Vec<_>>();
+        let mut new_ids = insert_library_objects(tx, &new).await?.into_iter();
+
+        // Update the matched objects in place
+        let existing = existing
+            .into_iter()
+            .map(|(o, id)| (o, id.unwrap_or_default()))
+            .collect::<Vec<_>>();
+        sqlx::query!(
+            r#"
+            UPDATE library_objects lo
+            SET name = u.name, game_system = u.game_system, legacy = u.legacy, url = u.url,
+                description = u.description,
+                -- Keep remaster pairings made after the object was first inserted
+                remastering_alt_id = COALESCE(u.remastering_alt_id, lo.remastering_alt_id),
+                source_id = COALESCE(u.source_id, lo.source_id)
+            FROM UNNEST($1::int[], $2::text[], $3::int[], $4::bool[], $5::text[], $6::text[], $7::int[], $8::text[])
+                AS u(id, name, game_system, legacy, url, description, remastering_alt_id, source_id)
+            WHERE lo.id = u.id
+            "#,
+            &existing.iter().map(|(_, id)| *id).collect::<Vec<i32>>(),
+            &existing
+                .iter()
+                .map(|(o, _)| o.name.clone())
+                .collect::<Vec<String>>(),
+            &existing
+                .iter()
+                .map(|(o, _)| o.game_system.as_i64() as i32)
+                .collect::<Vec<i32>>(),
+            &existing.iter().map(|(o, _)| o.legacy).collect::<Vec<bool>>(),
+            &existing
+                .iter()
+                .map(|(o, _)| o.url.clone())
+                .collect::<Vec<Option<String>>>() as _,
+            &existing
+                .iter()
+                .map(|(o, _)| o.description.clone())
+                .collect::<Vec<String>>(),
+            &existing
+                .iter()
+                .map(|(o, _)| o.remastering_alt_id.map(|id| id.0 as i32))
+                .collect::<Vec<Option<i32>>>() as _,
+            &existing
+                .iter()
+                .map(|(o, _)| o.source_id.clone())
+                .collect::<Vec<Option<String>>>() as _,
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        let ids = matches
+            .iter()
+            .map(|id| id.or_else(|| new_ids.next()).unwrap_or_default())
+            .collect();
+        Ok(Self {
+            ids,
+            existing: matches.iter().map(|id| id.is_some()).collect(),
+            fingerprints,
+        })
+    }
+
+    pub async fn finish(
+        self,
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> crate::Result<LibraryUpsertReport> {
+        let after = get_library_object_fingerprints(&mut **tx, &self.ids).await?;
+        let mut report = LibraryUpsertReport::default();
+        for (id, existing) in self.ids.iter().zip(self.existing) {
+            let list = if !existing {
+                &mut report.created
+            } else if self.fingerprints.get(id) == after.get(id) {
+                &mut report.unchanged
+            } else {
+                &mut report.updated
+            };
+            list.push(InternalId(*id as u32));
+        }
+        Ok(report)
+    }
+}
+
+/// A hash of everything stored for each library object, to tell whether an upsert changed it.
+async fn get_library_object_fingerprints(
+    exec: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    ids: &[i32],
+) -> crate::Result<HashMap<i32, String>> {
+    let fingerprints = sqlx::query!(
+        r#"
+        SELECT lo.id, md5(concat_ws('|',
+            to_jsonb(lo)::text,
+            (SELECT to_jsonb(t) FROM library_creatures t WHERE t.id = lo.id)::text,
+            (SELECT to_jsonb(t) FROM library_items t WHERE t.id = lo.id)::text,
+            (SELECT to_jsonb(t) FROM library_hazards t WHERE t.id = lo.id)::text,
+            (SELECT to_jsonb(t) FROM library_spells t WHERE t.id = lo.id)::text,
+            (SELECT to_jsonb(t) FROM library_classes t WHERE t.id = lo.id)::text,
+            (SELECT jsonb_agg(tag_id ORDER BY tag_id) FROM library_objects_tags WHERE library_object_id = lo.id)::text,
+            (SELECT jsonb_agg(jsonb_build_array(skill, bonus) ORDER BY skill, bonus) FROM library_items_skill_boosts WHERE item_id = lo.id)::text,
+            (SELECT jsonb_agg(rune_id ORDER BY rune_id) FROM library_items_runes WHERE item_id = lo.id)::text,
+            (SELECT jsonb_agg(to_jsonb(r) - 'id' ORDER BY r.name) FROM runes r WHERE r.item_id = lo.id)::text
+        )) AS "fingerprint!"
+        FROM library_objects lo
+        WHERE lo.id = ANY($1::int[])
+        "#,
+        ids
+    )
+    .fetch_all(exec)
+    .await?
+    .into_iter()
+    .map(|row| (row.id, row.fingerprint))
+    .collect();
+    Ok(fingerprints)
+}
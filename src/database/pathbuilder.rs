@@ -0,0 +1,436 @@
+use crate::{
+    database::characters::{InsertCharacter, ModifyCharacter},
+    models::{
+        characters::{
+            AbilityModifiers, CharacterProficiencies, Proficiency, Skill, SkillProficiency,
+        },
+        ids::InternalId,
+    },
+    v2::database::item_instances::InsertItemInstance,
+    ServerError,
+};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+
+/*
+Importer for Pathbuilder 2e character exports ('Menu' -> 'Export' -> 'Export JSON').
+
+{
+    "success": true,
+    "build": {
+        "name": "Alden",
+        "class": "Fighter",
+        "level": 3,
+        "ancestry": "Human",
+        "heritage": "Versatile Human",
+        "background": "Guard",
+        // Ability scores, not modifiers
+        "abilities": { "str": 18, "dex": 14, "con": 14, "int": 10, "wis": 12, "cha": 8 },
+        // 0 (untrained), 2 (trained), 4 (expert), 6 (master) or 8 (legendary)
+        "proficiencies": { "perception": 4, "fortitude": 4, "reflex": 2, "will": 2, "athletics": 4, ... },
+        "lores": [["Legal", 2]],
+        // [name, quantity], sometimes followed by the container or whether it is invested
+        "equipment": [["Backpack", 1], ["Rations", 2]],
+        "weapons": [{ "name": "Longsword", "qty": 1, ... }],
+        "armor": [{ "name": "Full Plate", "qty": 1, ... }]
+    }
+}
+
+The class and gear are matched by name against the library (official content, or homebrew of the
+campaign's owner or shared to the campaign), preferring remastered versions. Gear is added to the
+character as item instances, except for items the character already holds, so re-importing an updated
+sheet doesn't duplicate them.
+*/
+
+#[derive(Deserialize, Debug)]
+pub struct PathbuilderExport {
+    pub build: PathbuilderBuild,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PathbuilderBuild {
+    pub name: String,
+    pub class: String,
+    pub level: u8,
+    pub ancestry: Option<String>,
+    pub heritage: Option<String>,
+    pub background: Option<String>,
+    #[serde(default)]
+    pub abilities: PathbuilderAbilities,
+    #[serde(default)]
+    pub proficiencies: HashMap<String, Value>,
+    #[serde(default)]
+    pub lores: Vec<(String, u8)>,
+    #[serde(default)]
+    pub equipment: Vec<Vec<Value>>,
+    #[serde(default)]
+    pub weapons: Vec<PathbuilderGear>,
+    #[serde(default)]
+    pub armor: Vec<PathbuilderGear>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct PathbuilderAbilities {
+    pub str: i32,
+    pub dex: i32,
+    pub con: i32,
+    pub int: i32,
+    pub wis: i32,
+    pub cha: i32,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PathbuilderGear {
+    pub name: String,
+    #[serde(default = "default_quantity")]
+    pub qty: u16,
+}
+
+fn default_quantity() -> u16 {
+    1
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PathbuilderImportReport {
+    pub character_id: InternalId,
+    pub created: bool,
+    pub items_added: usize,
+    // Gear with no library item of the same name
+    pub unresolved_items: Vec<String>,
+}
+
+fn proficiency(rank: u64) -> Proficiency {
+    match rank {
+        0..=1 => Proficiency::Untrained,
+        2..=3 => Proficiency::Trained,
+        4..=5 => Proficiency::Expert,
+        6..=7 => Proficiency::Master,
+        _ => Proficiency::Legendary,
+    }
+}
+
+impl PathbuilderBuild {
+    pub fn ability_modifiers(&self) -> AbilityModifiers {
+        let modifier = |score: i32| (score - 10).div_euclid(2) as i8;
+        AbilityModifiers {
+            strength: modifier(self.abilities.str),
+            dexterity: modifier(self.abilities.dex),
+            constitution: modifier(self.abilities.con),
+            intelligence: modifier(self.abilities.int),
+            wisdom: modifier(self.abilities.wis),
+            charisma: modifier(self.abilities.cha),
+        }
+    }
+
+    pub fn character_proficiencies(&self) -> CharacterProficiencies {
+        let rank = |key: &str| {
+            proficiency(
+                self.proficiencies
+                    .get(key)
+                    .and_then(Value::as_u64)
+                    .unwrap_or_default(),
+            )
+        };
+        let skills = Skill::iter()
+            .filter(|skill| !matches!(skill, Skill::Lore(_)))
+            .map(|skill| (rank(&skill.to_string().to_lowercase()), skill))
+            .chain(self.lores.iter().map(|(subject, rank)| {
+                (
+                    proficiency(*rank as u64),
+                    Skill::Lore(Some(subject.clone())),
+                )
+            }))
+            .filter(|(proficiency, _)| *proficiency > Proficiency::Untrained)
+            .map(|(proficiency, skill)| SkillProficiency { skill, proficiency })
+            .collect();
+        CharacterProficiencies {
+            perception: rank("perception"),
+            fortitude: rank("fortitude"),
+            reflex: rank("reflex"),
+            will: rank("will"),
+            skills,
+        }
+    }
+
+    /// Names and quantities of all carried gear, with repeated names combined.
+    /// Quantities (and their totals) must fit in an item instance's stack.
+    pub fn gear(&self) -> crate::Result<Vec<(String, u16)>> {
+        let equipment = self.equipment.iter().filter_map(|entry| {
+            let name = entry.first()?.as_str()?.to_string();
+            let quantity = entry.get(1).and_then(Value::as_i64).unwrap_or(1);
+            Some((name, quantity))
+        });
+        let weapons_and_armor = self
+            .weapons
+            .iter()
+            .chain(self.armor.iter())
+            .map(|gear| (gear.name.clone(), gear.qty as i64));
+
+        let max_quantity = i16::MAX as i64;
+        let out_of_range = |name: &str| {
+            ServerError::BadRequest(format!(
+                "Quantity of {} must be between 1 and {}",
+                name, max_quantity
+            ))
+        };
+        let mut gear: Vec<(String, i64)> = Vec::new();
+        for (name, quantity) in equipment.chain(weapons_and_armor) {
+            if !(1..=max_quantity).contains(&quantity) {
+                return Err(out_of_range(&name));
+            }
+            match gear.iter_mut().find(|(n, _)| n.eq_ignore_ascii_case(&name)) {
+                Some((_, total)) => *total += quantity,
+                None => gear.push((name, quantity)),
+            }
+        }
+        gear.into_iter()
+            .map(|(name, quantity)| {
+                if quantity > max_quantity {
+                    return Err(out_of_range(&name));
+                }
+                Ok((name, quantity as u16))
+            })
+            .collect()
+    }
+}
+
+/// Creates a character in the campaign from a Pathbuilder build, or updates an existing one, then adds its gear.
+pub async fn import_pathbuilder_character(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    campaign_id: InternalId,
+    owner: InternalId,
+    character_id: Option<InternalId>,
+    build: &PathbuilderBuild,
+) -> crate::Result<PathbuilderImportReport> {
+    if !(1..=20).contains(&build.level) {
+        return Err(ServerError::BadRequest(
+            "Level must be between 1 and 20".to_string(),
+        ));
+    }
+    let gear = build.gear()?;
+
+    let class = sqlx::query!(
+        r#"
+        SELECT lo.id
+        FROM library_objects lo
+        INNER JOIN library_classes lc ON lc.id = lo.id
+        WHERE LOWER(lo.name) = LOWER($1)
+            AND (lo.owner IS NULL OR lo.owner = $2 OR lo.campaign_id = $3)
+        ORDER BY lo.legacy, lo.id
+        LIMIT 1
+        "#,
+        build.class,
+        owner.0 as i32,
+        campaign_id.0 as i32,
+    )
+    .fetch_optional(&mut **tx)
+    .await?
+    .map(|row| InternalId(row.id as u32))
+    .ok_or_else(|| ServerError::BadRequest(format!("Unknown class: {}", build.class)))?;
+
+    let ability_modifiers = build.ability_modifiers();
+    let proficiencies = build.character_proficiencies();
+    let (character_id, created) = match character_id {
+        Some(character_id) => {
+            let modify = ModifyCharacter {
+                name: Some(build.name.clone()),
+                player: None,
+                class: Some(class),
+                ancestry: build.ancestry.clone(),
+                heritage: build.heritage.clone(),
+                background: build.background.clone(),
+                level: Some(Some(build.level)),
+                ability_modifiers: Some(ability_modifiers),
+                proficiencies: Some(proficiencies),
+            };
+            super::characters::edit_character(tx, character_id, &modify).await?;
+            (character_id, false)
+        }
+        None => {
+            let insert = InsertCharacter {
+                name: build.name.clone(),
+                player: None,
+                class,
+                ancestry: build.ancestry.clone(),
+                heritage: build.heritage.clone(),
+                background: build.background.clone(),
+                level: Some(build.level),
+                ability_modifiers,
+                proficiencies,
+            };
+            let ids = super::characters::insert_characters(tx, campaign_id, &[insert]).await?;
+            let character_id = ids.into_iter().next().ok_or_else(|| {
+                ServerError::InternalError("Character was not inserted".to_string())
+            })?;
+            (character_id, true)
+        }
+    };
+
+    let names = gear
+        .iter()
+        .map(|(name, _)| name.to_lowercase())
+        .unique()
+        .collect::<Vec<String>>();
+    let library_items: HashMap<String, InternalId> = sqlx::query!(
+        r#"
+        SELECT DISTINCT ON (LOWER(lo.name)) LOWER(lo.name) AS "name!", lo.id
+        FROM library_objects lo
+        INNER JOIN library_items li ON li.id = lo.id
+        WHERE LOWER(lo.name) = ANY($1::text[])
+            AND (lo.owner IS NULL OR lo.owner = $2 OR lo.campaign_id = $3)
+        ORDER BY LOWER(lo.name), lo.legacy, lo.id
+        "#,
+        &names,
+        owner.0 as i32,
+        campaign_id.0 as i32,
+    )
+    .fetch_all(&mut **tx)
+    .await?
+    .into_iter()
+    .map(|row| (row.name, InternalId(row.id as u32)))
+    .collect();
+
+    let held: HashSet<InternalId> = sqlx::query!(
+        r#"
+        SELECT DISTINCT library_item_id
+        FROM item_instances
+        WHERE character_id = $1
+        "#,
+        character_id.0 as i32,
+    )
+    .fetch_all(&mut **tx)
+    .await?
+    .into_iter()
+    .map(|row| InternalId(row.library_item_id as u32))
+    .collect();
+
+    let mut unresolved_items = Vec::new();
+    let mut item_instances = Vec::new();
+    for (name, quantity) in gear {
+        let Some(library_item_id) = library_items.get(&name.to_lowercase()).copied() else {
+            unresolved_items.push(name);
+            continue;
+        };
+        if held.contains(&library_item_id) {
+            continue;
+        }
+        item_instances.push(InsertItemInstance {
+            library_item_id,
+            parent_item_id: None,
+            campaign_id: Some(campaign_id),
+            encounter_id: None,
+            character_id: Some(character_id),
+            session_id: None,
+            // Starting gear, rather than treasure found during the campaign
+            is_reward: false,
+            quantity,
+            nickname: None,
+            notes: None,
+        });
+    }
+    let items_added = item_instances.len();
+    if !item_instances.is_empty() {
+        crate::v2::database::item_instances::insert_item_instances(tx, item_instances).await?;
+    }
+
+    Ok(PathbuilderImportReport {
+        character_id,
+        created,
+        items_added,
+        unresolved_items,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_pathbuilder_mapping() {
+        let export: PathbuilderExport = serde_json::from_value(json!({
+            "success": true,
+            "build": {
+                "name": "Alden",
+                "class": "Fighter",
+                "dualClass": null,
+                "level": 3,
+                "ancestry": "Human",
+                "heritage": "Versatile Human",
+                "background": "Guard",
+                "abilities": {
+                    "str": 18, "dex": 14, "con": 14, "int": 10, "wis": 12, "cha": 9,
+                    "breakdown": { "ancestryFree": ["Str"] }
+                },
+                "proficiencies": {
+                    "classDC": 2, "perception": 4, "fortitude": 4, "reflex": 2, "will": 2,
+                    "athletics": 4, "intimidation": 2, "arcana": 0
+                },
+                "lores": [["Legal", 2]],
+                "equipment": [["Backpack", 1], ["Rations", 2, "Invested"]],
+                "weapons": [{ "name": "Longsword", "qty": 1, "prof": "martial", "die": "d8", "pot": 1 }],
+                "armor": [{ "name": "Full Plate", "qty": 1, "worn": true }, { "name": "backpack", "qty": 1 }]
+            }
+        }))
+        .unwrap();
+        let build = export.build;
+
+        let modifiers = build.ability_modifiers();
+        assert_eq!(modifiers.strength, 4);
+        assert_eq!(modifiers.intelligence, 0);
+        assert_eq!(modifiers.charisma, -1);
+
+        let proficiencies = build.character_proficiencies();
+        assert_eq!(proficiencies.perception, Proficiency::Expert);
+        assert_eq!(proficiencies.reflex, Proficiency::Trained);
+        assert_eq!(proficiencies.skill(&Skill::Athletics), Proficiency::Expert);
+        assert_eq!(proficiencies.skill(&Skill::Arcana), Proficiency::Untrained);
+        assert_eq!(
+            proficiencies.skill(&Skill::Lore(Some("Legal".to_string()))),
+            Proficiency::Trained
+        );
+        assert_eq!(proficiencies.skills.len(), 3);
+
+        assert_eq!(
+            build.gear().unwrap(),
+            [
+                ("Backpack".to_string(), 2),
+                ("Rations".to_string(), 2),
+                ("Longsword".to_string(), 1),
+                ("Full Plate".to_string(), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pathbuilder_gear_quantities() {
+        let build = |equipment: Value| -> PathbuilderBuild {
+            serde_json::from_value(json!({
+                "name": "Alden", "class": "Fighter", "level": 1,
+                "ancestry": null, "heritage": null, "background": null,
+                "equipment": equipment
+            }))
+            .unwrap()
+        };
+
+        assert_eq!(
+            build(json!([["Arrows", 32767]])).gear().unwrap(),
+            [("Arrows".to_string(), 32767)]
+        );
+        for equipment in [
+            json!([["Torch", 0]]),
+            json!([["Torch", -1]]),
+            json!([["Arrows", 32768]]),
+            json!([["Arrows", 65537]]),
+            json!([["Arrows", 20000], ["arrows", 20000]]),
+        ] {
+            assert!(matches!(
+                build(equipment).gear(),
+                Err(ServerError::BadRequest(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,345 @@
+use std::{collections::HashMap, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::{ids::InternalId, library::LibraryObjectType};
+use crate::ServerError;
+
+/// A legacy library object and its remastered counterpart.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RemasterPair {
+    pub legacy_id: InternalId,
+    pub remaster_id: InternalId,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RemasterProposalFilters {
+    pub object_type: LibraryObjectType,
+    pub min_similarity: Option<f32>, // 0.0 to 1.0
+}
+
+/// A suggested pairing of unpaired objects, by name similarity.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RemasterProposal {
+    pub legacy_id: InternalId,
+    pub legacy_name: String,
+    pub remaster_id: InternalId,
+    pub remaster_name: String,
+    pub similarity: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct RemasterCampaign {
+    // Only report what would change
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// A legacy object used in a campaign that is (or would be) swapped for its remaster.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RemasterChange {
+    pub object_type: LibraryObjectType,
+    pub encounter_id: Option<InternalId>,
+    // Set for loot
+    pub item_instance_id: Option<InternalId>,
+    pub legacy_id: InternalId,
+    pub legacy_name: String,
+    pub remaster_id: InternalId,
+    pub remaster_name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct RemasterCampaignReport {
+    pub dry_run: bool,
+    pub changes: Vec<RemasterChange>,
+}
+
+pub async fn get_remaster_proposals(
+    exec: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    filters: &RemasterProposalFilters,
+) -> crate::Result<Vec<RemasterProposal>> {
+    // Each unpaired legacy object is proposed with its most similar unpaired remastered object of the same type
+    let proposals = sqlx::query!(
+        r#"
+        WITH typed AS (
+            SELECT id FROM library_creatures WHERE $1::text = 'creature'
+            UNION ALL SELECT id FROM library_items WHERE $1::text = 'item'
+            UNION ALL SELECT id FROM library_hazards WHERE $1::text = 'hazard'
+            UNION ALL SELECT id FROM library_spells WHERE $1::text = 'spell'
+            UNION ALL SELECT id FROM library_classes WHERE $1::text = 'class'
+        ), candidates AS (
+            SELECT lo.id, lo.name, lo.legacy, lo.game_system
+            FROM library_objects lo
+            INNER JOIN typed ON typed.id = lo.id
+            WHERE lo.remastering_alt_id IS NULL AND lo.owner IS NULL
+        )
+        SELECT DISTINCT ON (l.id)
+            l.id AS legacy_id,
+            l.name AS legacy_name,
+            r.id AS remaster_id,
+            r.name AS remaster_name,
+            SIMILARITY(l.name, r.name) AS "similarity!"
+        FROM candidates l
+        INNER JOIN candidates r ON r.game_system = l.game_system AND NOT r.legacy
+            AND l.name % r.name AND SIMILARITY(l.name, r.name) >= $2
+        WHERE l.legacy
+        ORDER BY l.id, "similarity!" DESC, r.id
+        "#,
+        filters.object_type.as_str(),
+        filters.min_similarity.unwrap_or(0.5),
+    )
+    .fetch_all(exec)
+    .await?
+    .into_iter()
+    .map(|row| RemasterProposal {
+        legacy_id: InternalId(row.legacy_id as u32),
+        legacy_name: row.legacy_name,
+        remaster_id: InternalId(row.remaster_id as u32),
+        remaster_name: row.remaster_name,
+        similarity: row.similarity,
+    })
+    .collect();
+    Ok(proposals)
+}
+
+/// Links each legacy object with its remaster (in both directions), replacing any previous pairing of either.
+pub async fn set_remaster_pairs(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    pairs: &[RemasterPair],
+) -> crate::Result<()> {
+    if pairs.is_empty() {
+        return Ok(());
+    }
+
+    let legacy_ids = pairs
+        .iter()
+        .map(|p| p.legacy_id.0 as i32)
+        .collect::<Vec<i32>>();
+    let remaster_ids = pairs
+        .iter()
+        .map(|p| p.remaster_id.0 as i32)
+        .collect::<Vec<i32>>();
+    let ids = legacy_ids
+        .iter()
+        .chain(remaster_ids.iter())
+        .copied()
+        .collect::<Vec<i32>>();
+
+    let objects = sqlx::query!(
+        r#"
+        SELECT lo.id, lo.legacy,
+            CASE
+                WHEN EXISTS (SELECT 1 FROM library_creatures t WHERE t.id = lo.id) THEN 'creature'
+                WHEN EXISTS (SELECT 1 FROM library_items t WHERE t.id = lo.id) THEN 'item'
+                WHEN EXISTS (SELECT 1 FROM library_hazards t WHERE t.id = lo.id) THEN 'hazard'
+                WHEN EXISTS (SELECT 1 FROM library_spells t WHERE t.id = lo.id) THEN 'spell'
+                WHEN EXISTS (SELECT 1 FROM library_classes t WHERE t.id = lo.id) THEN 'class'
+            END AS object_type
+        FROM library_objects lo
+        WHERE lo.id = ANY($1::int[])
+        "#,
+        &ids,
+    )
+    .fetch_all(&mut **tx)
+    .await?
+    .into_iter()
+    .map(|row| (row.id, (row.legacy, row.object_type)))
+    .collect::<HashMap<_, _>>();
+
+    for (legacy_id, remaster_id) in legacy_ids.iter().zip(remaster_ids.iter()) {
+        let (Some((true, legacy_type)), Some((false, remaster_type))) =
+            (objects.get(legacy_id), objects.get(remaster_id))
+        else {
+            return Err(ServerError::BadRequest(format!(
+                "{} must be an existing legacy object, and {} an existing remastered object",
+                legacy_id, remaster_id
+            )));
+        };
+        if legacy_type != remaster_type {
+            return Err(ServerError::BadRequest(format!(
+                "{} and {} are different types of library object",
+                legacy_id, remaster_id
+            )));
+        }
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE library_objects
+        SET remastering_alt_id = NULL
+        WHERE remastering_alt_id = ANY($1::int[])
+        "#,
+        &ids,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE library_objects lo
+        SET remastering_alt_id = pairs.alt_id
+        FROM UNNEST($1::int[], $2::int[]) AS pairs(id, alt_id)
+        WHERE lo.id = pairs.id
+        "#,
+        &ids,
+        &remaster_ids
+            .iter()
+            .chain(legacy_ids.iter())
+            .copied()
+            .collect::<Vec<i32>>(),
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+/// Swaps the legacy enemies, hazards and loot used in a campaign for their remastered counterparts.
+/// The changes are returned, and only applied if not a dry run.
+pub async fn remaster_campaign(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    campaign_id: InternalId,
+    user_id: InternalId,
+    dry_run: bool,
+) -> crate::Result<RemasterCampaignReport> {
+    let changes = sqlx::query!(
+        r#"
+        WITH campaign_encounters AS (
+            SELECT e.id
+            FROM encounters e
+            WHERE e.campaign_id = $1
+                OR e.session_id IN (SELECT id FROM campaign_sessions WHERE campaign_id = $1)
+        ), used AS (
+            SELECT 'creature' AS object_type, ee.encounter AS encounter_id, NULL::int AS item_instance_id, ee.enemy AS object_id
+            FROM encounter_enemies ee
+            WHERE ee.encounter IN (SELECT id FROM campaign_encounters)
+            UNION ALL
+            SELECT 'hazard', eh.encounter, NULL, eh.hazard
+            FROM encounter_hazards eh
+            WHERE eh.encounter IN (SELECT id FROM campaign_encounters)
+            UNION ALL
+            SELECT 'item', ii.encounter_id, ii.id, ii.library_item_id
+            FROM item_instances ii
+            WHERE ii.campaign_id = $1 OR ii.encounter_id IN (SELECT id FROM campaign_encounters)
+        )
+        SELECT
+            used.object_type AS "object_type!",
+            used.encounter_id,
+            used.item_instance_id,
+            lo.id AS legacy_id,
+            lo.name AS legacy_name,
+            r.id AS remaster_id,
+            r.name AS remaster_name
+        FROM used
+        INNER JOIN library_objects lo ON used.object_id = lo.id
+        INNER JOIN library_objects r ON lo.remastering_alt_id = r.id
+        WHERE lo.legacy AND NOT r.legacy
+        ORDER BY used.encounter_id, used.item_instance_id, lo.id
+        "#,
+        campaign_id.0 as i32,
+    )
+    .fetch_all(&mut **tx)
+    .await?
+    .into_iter()
+    .map(|row| RemasterChange {
+        object_type: LibraryObjectType::from_str(&row.object_type)
+            .unwrap_or(LibraryObjectType::Item),
+        encounter_id: row.encounter_id.map(|id| InternalId(id as u32)),
+        item_instance_id: row.item_instance_id.map(|id| InternalId(id as u32)),
+        legacy_id: InternalId(row.legacy_id as u32),
+        legacy_name: row.legacy_name,
+        remaster_id: InternalId(row.remaster_id as u32),
+        remaster_name: row.remaster_name,
+    })
+    .collect::<Vec<_>>();
+
+    if !dry_run && !changes.is_empty() {
+        apply_remaster_changes(tx, user_id, &changes).await?;
+    }
+
+    Ok(RemasterCampaignReport { dry_run, changes })
+}
+
+async fn apply_remaster_changes(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    user_id: InternalId,
+    changes: &[RemasterChange],
+) -> crate::Result<()> {
+    let encounter_changes = |object_type: LibraryObjectType| {
+        let changes = changes
+            .iter()
+            .filter(|c| c.object_type == object_type)
+            .filter_map(|c| c.encounter_id.map(|e| (e.0 as i32, c)))
+            .collect::<Vec<_>>();
+        (
+            changes.iter().map(|(e, _)| *e).collect::<Vec<i32>>(),
+            changes
+                .iter()
+                .map(|(_, c)| c.legacy_id.0 as i32)
+                .collect::<Vec<i32>>(),
+            changes
+                .iter()
+                .map(|(_, c)| c.remaster_id.0 as i32)
+                .collect::<Vec<i32>>(),
+        )
+    };
+
+    let (encounters, legacy_ids, remaster_ids) = encounter_changes(LibraryObjectType::Creature);
+    sqlx::query!(
+        r#"
+        UPDATE encounter_enemies ee
+        SET enemy = c.remaster_id
+        FROM UNNEST($1::int[], $2::int[], $3::int[]) AS c(encounter_id, legacy_id, remaster_id)
+        WHERE ee.encounter = c.encounter_id AND ee.enemy = c.legacy_id
+        "#,
+        &encounters,
+        &legacy_ids,
+        &remaster_ids,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    let (encounters, legacy_ids, remaster_ids) = encounter_changes(LibraryObjectType::Hazard);
+    sqlx::query!(
+        r#"
+        UPDATE encounter_hazards eh
+        SET hazard = c.remaster_id
+        FROM UNNEST($1::int[], $2::int[], $3::int[]) AS c(encounter_id, legacy_id, remaster_id)
+        WHERE eh.encounter = c.encounter_id AND eh.hazard = c.legacy_id
+        "#,
+        &encounters,
+        &legacy_ids,
+        &remaster_ids,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    let items = changes
+        .iter()
+        .filter_map(|c| {
+            c.item_instance_id
+                .map(|id| (id.0 as i32, c.remaster_id.0 as i32))
+        })
+        .collect::<Vec<_>>();
+    sqlx::query!(
+        r#"
+        UPDATE item_instances ii
+        SET library_item_id = c.remaster_id
+        FROM UNNEST($1::int[], $2::int[]) AS c(id, remaster_id)
+        WHERE ii.id = c.id
+        "#,
+        &items.iter().map(|(id, _)| *id).collect::<Vec<i32>>(),
+        &items.iter().map(|(_, r)| *r).collect::<Vec<i32>>(),
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    // Experience and treasure values follow the new objects
+    let encounter_ids = changes
+        .iter()
+        .filter_map(|c| c.encounter_id)
+        .collect::<std::collections::BTreeSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    super::encounters::recalculate_encounter_summary(tx, user_id, &encounter_ids).await
+}
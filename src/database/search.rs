@@ -0,0 +1,211 @@
+use std::{collections::HashMap, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use super::{tags, HomebrewVisibility, LegacyStatus};
+use crate::models::ids::InternalId;
+use crate::models::library::{GameSystem, LibraryObjectType, Rarity};
+
+// Unless otherwise requested, names must be at least this similar (or contain the query) to match
+const DEFAULT_MIN_SIMILARITY: f32 = 0.3;
+
+/// A search across every type of library object at once, with the filters they share.
+#[derive(Default, Serialize, Deserialize, Debug)]
+pub struct LibrarySearch {
+    pub query: String,
+    pub min_similarity: Option<f32>, // 0.0 to 1.0
+
+    // Types to search- all of them if not given
+    pub object_types: Option<Vec<LibraryObjectType>>,
+    // Level, or rank for spells. Classes have no level, so are excluded by level filters.
+    pub min_level: Option<i8>,
+    pub max_level: Option<i8>,
+    pub rarity: Option<Rarity>,
+    pub game_system: Option<GameSystem>,
+    pub traits_all: Option<Vec<String>>,
+    pub traits_any: Option<Vec<String>>,
+    #[serde(default)]
+    pub legacy: LegacyStatus,
+
+    // Homebrew objects visible to the requesting user. Not part of the query string.
+    #[serde(skip)]
+    pub homebrew: HomebrewVisibility,
+
+    pub limit: Option<u64>,
+    pub page: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LibrarySearchResult {
+    pub object_type: LibraryObjectType,
+    pub id: InternalId,
+    pub name: String,
+    pub game_system: GameSystem,
+    pub rarity: Rarity,
+    pub level: Option<i8>,
+    pub traits: Vec<String>,
+    pub legacy: bool,
+    pub similarity: f32,
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LibrarySearchResponse {
+    // Ranked by similarity to the query, best first
+    pub results: Vec<LibrarySearchResult>,
+    // Number of matches of each type, across all pages
+    pub facets: HashMap<LibraryObjectType, u64>,
+    pub total: u64,
+}
+
+pub async fn search_library(
+    conn: &mut sqlx::PgConnection,
+    search: &LibrarySearch,
+    default_limit: u64,
+) -> crate::Result<LibrarySearchResponse> {
+    let limit = search.limit.unwrap_or(default_limit);
+    let page = search.page.unwrap_or(0);
+    let offset = page * limit;
+
+    let object_types = search.object_types.as_ref().map(|types| {
+        types
+            .iter()
+            .map(|t| t.as_str().to_string())
+            .collect::<Vec<String>>()
+    });
+    let matching_tags =
+        tags::get_tag_matches(&mut *conn, &search.traits_all, &search.traits_any).await?;
+
+    // A single row is always returned for the facets, with the page (if any) left joined onto it
+    let rows = sqlx::query!(
+        r#"
+        WITH objects AS (
+            SELECT
+                lo.id,
+                lo.name,
+                lo.game_system,
+                lo.legacy,
+                lo.remastering_alt_id,
+                lo.owner,
+                lo.campaign_id,
+                CASE
+                    WHEN lc.id IS NOT NULL THEN 'creature'
+                    WHEN li.id IS NOT NULL THEN 'item'
+                    WHEN lh.id IS NOT NULL THEN 'hazard'
+                    WHEN ls.id IS NOT NULL THEN 'spell'
+                    ELSE 'class'
+                END AS object_type,
+                COALESCE(lc.level, li.level, lh.level, ls.rank) AS level,
+                COALESCE(lc.rarity, li.rarity, lh.rarity, ls.rarity, lcl.rarity) AS rarity
+            FROM library_objects lo
+            LEFT JOIN library_creatures lc ON lo.id = lc.id
+            LEFT JOIN library_items li ON lo.id = li.id
+            LEFT JOIN library_hazards lh ON lo.id = lh.id
+            LEFT JOIN library_spells ls ON lo.id = ls.id
+            LEFT JOIN library_classes lcl ON lo.id = lcl.id
+            WHERE COALESCE(lc.id, li.id, lh.id, ls.id, lcl.id) IS NOT NULL
+        ), matches AS (
+            SELECT
+                o.*,
+                tags.traits,
+                CASE
+                    WHEN o.name ILIKE $1 || '%' THEN 1.01
+                    WHEN o.name ILIKE '%' || $1 || '%' THEN 1.0
+                    ELSE SIMILARITY(o.name, $1)
+                END AS similarity
+            FROM objects o
+            LEFT JOIN (
+                SELECT lot.library_object_id AS lo_id, ARRAY_AGG(t.tag) FILTER (WHERE t.trait) AS traits
+                FROM library_objects_tags lot
+                INNER JOIN library_tags t ON lot.tag_id = t.id
+                GROUP BY lot.library_object_id
+            ) AS tags ON o.id = tags.lo_id
+            WHERE
+                (o.name ILIKE '%' || $1 || '%' OR SIMILARITY(o.name, $1) >= $2)
+                AND ($3::text[] IS NULL OR o.object_type = ANY($3))
+                AND ($4::int IS NULL OR o.level >= $4)
+                AND ($5::int IS NULL OR o.level <= $5)
+                AND ($6::int IS NULL OR o.rarity = $6)
+                AND ($7::int IS NULL OR o.game_system = $7)
+                AND ($8::text[] IS NULL OR tags.traits::text[] && $8::text[])
+                AND ($9::text[] IS NULL OR tags.traits::text[] @> $9::text[])
+                AND NOT (NOT $10::bool AND o.legacy = FALSE)
+                AND NOT (NOT $11::bool AND o.legacy = TRUE)
+                AND NOT ($12::bool AND o.remastering_alt_id IS NOT NULL AND o.legacy = TRUE)
+                AND NOT ($13::bool AND o.remastering_alt_id IS NOT NULL AND o.legacy = FALSE)
+                AND ($14::bool OR o.owner IS NULL OR o.owner = $15 OR o.campaign_id IN (
+                    SELECT id FROM campaigns WHERE owner = $15
+                    UNION SELECT campaign_id FROM campaign_members WHERE user_id = $15
+                ))
+        ), facets AS (
+            SELECT JSONB_OBJECT_AGG(object_type, count) AS facets
+            FROM (SELECT object_type, COUNT(*) AS count FROM matches GROUP BY object_type) f
+        ), page AS (
+            SELECT * FROM matches
+            ORDER BY similarity DESC, length(name), name, id
+            LIMIT $16 OFFSET $17
+        )
+        SELECT
+            facets.facets,
+            page.object_type,
+            page.id AS "id?",
+            page.name AS "name?",
+            page.game_system AS "game_system?",
+            page.rarity,
+            page.level,
+            page.traits,
+            page.legacy AS "legacy?",
+            page.similarity
+        FROM facets
+        LEFT JOIN page ON TRUE
+        ORDER BY page.similarity DESC, length(page.name), page.name, page.id
+        "#,
+        search.query,
+        search.min_similarity.unwrap_or(DEFAULT_MIN_SIMILARITY),
+        object_types.as_deref() as _,
+        search.min_level.map(|l| l as i32),
+        search.max_level.map(|l| l as i32),
+        search.rarity.as_ref().map(|r| r.as_i64() as i32),
+        search.game_system.as_ref().map(|gs| gs.as_i64() as i32),
+        matching_tags.any_traits.as_deref() as _,
+        matching_tags.all_traits.as_deref() as _,
+        search.legacy.include_remaster(),
+        search.legacy.include_legacy(),
+        search.legacy.favor_remaster(),
+        search.legacy.favor_legacy(),
+        search.homebrew.all(),
+        search.homebrew.user_id(),
+        limit as i64,
+        offset as i64,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let facets: HashMap<LibraryObjectType, u64> = rows
+        .first()
+        .and_then(|row| row.facets.clone())
+        .map(serde_json::from_value)
+        .transpose()?
+        .unwrap_or_default();
+    let results = rows
+        .into_iter()
+        .filter_map(|row| {
+            Some(LibrarySearchResult {
+                object_type: LibraryObjectType::from_str(&row.object_type?).ok()?,
+                id: InternalId(row.id? as u32),
+                name: row.name?,
+                game_system: GameSystem::from_i64(row.game_system? as i64),
+                rarity: Rarity::from_i64(row.rarity.unwrap_or_default() as i64),
+                level: row.level.map(|l| l as i8),
+                traits: row.traits.unwrap_or_default(),
+                legacy: row.legacy?,
+                similarity: row.similarity.unwrap_or_default(),
+            })
+        })
+        .collect();
+
+    Ok(LibrarySearchResponse {
+        results,
+        total: facets.values().sum(),
+        facets,
+    })
+}
@@ -382,11 +382,12 @@ pub async fn link_encounter_to_session(
     encounter_id: InternalId,
     session_id: InternalId,
 ) -> crate::Result<()> {
-    // Link the encounter to the new session
+    // Link the encounter to the new session (and so, its campaign)
     sqlx::query!(
         r#"
         UPDATE encounters
-        SET session_id = $1
+        SET session_id = $1,
+            campaign_id = (SELECT campaign_id FROM campaign_sessions WHERE id = $1)
         WHERE id = $2
         "#,
         session_id.0 as i32,
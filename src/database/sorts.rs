@@ -1,5 +1,11 @@
+use serde::{Deserialize, Serialize};
+
+use crate::models::ids::InternalId;
 use crate::ServerError;
 
+// Cursor for the first page of a listing. Not valid hex, so never mistaken for an encoded cursor
+pub const FIRST_PAGE_CURSOR: &str = "start";
+
 pub trait Sortable {
     /// Must be implemented- get the allowed fields to sort by (eg: [ "name"])
     fn get_allowed_fields() -> &'static [&'static str];
@@ -8,78 +14,238 @@ pub trait Sortable {
     fn default_sort() -> Option<&'static str> {
         None
     }
+
+    /// Must be implemented- the value of an allowed field, as the database compares it (eg: rarity as its integer)
+    fn get_sort_value(&self, field: &str) -> serde_json::Value;
+
+    /// Must be implemented- the id, to break ties between equal sort values
+    fn get_id(&self) -> InternalId;
 }
 
+// Most keys a listing can be sorted by at once (eg: sort_by=level:desc,name:asc), before ties are broken by id
+pub const MAX_SORT_COLUMNS: usize = 3;
+
 #[derive(Debug, Clone)]
 pub struct SortableColumn<T: Sortable> {
-    column: Option<String>,
-    sort_order: SortOrder,
+    // In order of precedence
+    columns: Vec<(String, SortOrder)>,
     _marker: std::marker::PhantomData<T>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum SortOrder {
     Ascending,
     Descending,
 }
 
-impl<T: Sortable> SortableColumn<T> {
-    pub fn get_column(&self) -> Option<String> {
-        self.column.clone()
-    }
-
-    pub fn get_sort_direction_i32(&self) -> i32 {
-        match self.sort_order {
+impl SortOrder {
+    pub fn as_i32(&self) -> i32 {
+        match self {
             SortOrder::Ascending => 1,
             SortOrder::Descending => -1,
         }
     }
 
-    pub fn try_parse(
-        column: Option<&str>,
-        sort_order: Option<&str>,
-    ) -> Result<SortableColumn<T>, ServerError> {
-        let column = column.or(T::default_sort());
-
+    pub fn try_parse(order: &str) -> Result<SortOrder, ServerError> {
         let allowed_asc = ["asc", "ascending", "a", "1", "true"];
         let allowed_desc = ["desc", "descending", "d", "-1", "false"];
 
-        let sort_order = if let Some(order) = sort_order {
-            if allowed_asc.contains(&order) {
-                SortOrder::Ascending
-            } else if allowed_desc.contains(&order) {
-                SortOrder::Descending
-            } else {
-                return Err(ServerError::BadRequest(format!(
-                    "Invalid sort order: {}. Allowed values: {:?}",
-                    order,
-                    [allowed_asc, allowed_desc].concat()
-                )));
-            }
+        if allowed_asc.contains(&order) {
+            Ok(SortOrder::Ascending)
+        } else if allowed_desc.contains(&order) {
+            Ok(SortOrder::Descending)
         } else {
-            SortOrder::Ascending // Default to ascending if no order is provided
+            Err(ServerError::BadRequest(format!(
+                "Invalid sort order: {}. Allowed values: {:?}",
+                order,
+                [allowed_asc, allowed_desc].concat()
+            )))
+        }
+    }
+}
+
+impl<T: Sortable> SortableColumn<T> {
+    pub fn get_columns(&self) -> Vec<String> {
+        self.columns.iter().map(|(c, _)| c.clone()).collect()
+    }
+
+    pub fn get_sort_directions_i32(&self) -> Vec<i32> {
+        self.columns.iter().map(|(_, o)| o.as_i32()).collect()
+    }
+
+    pub fn cursor_after(&self, object: &T) -> PageCursor {
+        let mut after = self
+            .columns
+            .iter()
+            .map(|(c, _)| object.get_sort_value(c))
+            .collect::<Vec<_>>();
+        after.push(object.get_id().0.into());
+        PageCursor {
+            columns: self.get_columns(),
+            directions: self.get_sort_directions_i32(),
+            after,
+        }
+    }
+
+    /// Parse a cursor from a previous page into the keys to continue after.
+    /// The `start` cursor begins from the first page.
+    pub fn parse_cursor(&self, cursor: &str) -> crate::Result<Option<serde_json::Value>> {
+        if cursor == FIRST_PAGE_CURSOR {
+            return Ok(None);
+        }
+        let cursor = PageCursor::decode(cursor)?;
+        if cursor.columns != self.get_columns()
+            || cursor.directions != self.get_sort_directions_i32()
+        {
+            return Err(ServerError::BadRequest(
+                "Cursor was created with a different sort order".to_string(),
+            ));
+        }
+        Ok(Some(serde_json::Value::Array(cursor.after)))
+    }
+
+    /// Parse a comma separated list of columns to sort by, each optionally followed by its order
+    /// (eg: "level:desc,name"). Columns without an order use `sort_order`, or ascending.
+    pub fn try_parse(
+        columns: Option<&str>,
+        sort_order: Option<&str>,
+    ) -> Result<SortableColumn<T>, ServerError> {
+        let default_order = match sort_order {
+            Some(order) => SortOrder::try_parse(order)?,
+            None => SortOrder::Ascending, // Default to ascending if no order is provided
         };
 
-        if let Some(ref column) = column {
-            if T::get_allowed_fields().contains(column) {
-                Ok(SortableColumn::<T> {
-                    column: Some(column.to_string()),
-                    sort_order,
-                    _marker: std::marker::PhantomData,
-                })
-            } else {
-                Err(ServerError::BadRequest(format!(
+        let mut parsed: Vec<(String, SortOrder)> = Vec::new();
+        for column in columns
+            .or(T::default_sort())
+            .into_iter()
+            .flat_map(|c| c.split(','))
+        {
+            let (column, order) = match column.split_once(':') {
+                Some((column, order)) => (column.trim(), SortOrder::try_parse(order.trim())?),
+                None => (column.trim(), default_order),
+            };
+            if !T::get_allowed_fields().contains(&column) {
+                return Err(ServerError::BadRequest(format!(
                     "Invalid sort column: {}. Allowed columns: {:?}",
                     column,
                     T::get_allowed_fields()
-                )))
+                )));
             }
-        } else {
-            Ok(SortableColumn::<T> {
-                column: None,
-                sort_order,
-                _marker: std::marker::PhantomData,
-            })
+            if parsed.iter().any(|(c, _)| c == column) {
+                return Err(ServerError::BadRequest(format!(
+                    "Duplicate sort column: {}",
+                    column
+                )));
+            }
+            parsed.push((column.to_string(), order));
+        }
+
+        if parsed.len() > MAX_SORT_COLUMNS {
+            return Err(ServerError::BadRequest(format!(
+                "Too many sort columns. Maximum of {}",
+                MAX_SORT_COLUMNS
+            )));
         }
+
+        Ok(SortableColumn::<T> {
+            columns: parsed,
+            _marker: std::marker::PhantomData,
+        })
+    }
+}
+
+/// A position in a sorted listing, handed to clients as an opaque string to fetch the page after it.
+/// Pages continue after the sort keys and id of the last object returned, so objects added or removed
+/// between requests don't shift later pages the way offsets do.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PageCursor {
+    columns: Vec<String>,
+    directions: Vec<i32>,
+    // Sort keys of the last object on the previous page, then its id
+    after: Vec<serde_json::Value>,
+}
+
+impl PageCursor {
+    pub fn encode(&self) -> String {
+        // Serializing a struct of strings and numbers can't fail
+        let json = serde_json::to_vec(self).unwrap_or_default();
+        json.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    pub fn decode(cursor: &str) -> crate::Result<Self> {
+        let invalid = || ServerError::BadRequest(format!("Invalid cursor: {}", cursor));
+        if cursor.len() % 2 != 0 {
+            return Err(invalid());
+        }
+        let bytes = (0..cursor.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(cursor.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(invalid)?;
+        serde_json::from_slice(&bytes).map_err(|_| invalid())
+    }
+}
+
+/// A page of a listing fetched by cursor, with the cursor for the next page if there may be one.
+#[derive(Debug, Serialize)]
+pub struct CursorPage<T> {
+    pub results: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+impl<T: Sortable> CursorPage<T> {
+    // A full page means there may be more after it
+    pub fn new(results: Vec<T>, sort: &SortableColumn<T>, limit: u64) -> Self {
+        let next_cursor = match results.last() {
+            Some(last) if results.len() as u64 >= limit => Some(sort.cursor_after(last).encode()),
+            _ => None,
+        };
+        Self {
+            results,
+            next_cursor,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Sorted;
+
+    impl Sortable for Sorted {
+        fn get_allowed_fields() -> &'static [&'static str] {
+            &["name", "level", "rarity", "size"]
+        }
+
+        fn default_sort() -> Option<&'static str> {
+            Some("name")
+        }
+
+        fn get_sort_value(&self, _: &str) -> serde_json::Value {
+            serde_json::Value::Null
+        }
+
+        fn get_id(&self) -> InternalId {
+            InternalId(0)
+        }
+    }
+
+    #[test]
+    fn test_parse_sort_columns() {
+        let sort = SortableColumn::<Sorted>::try_parse(Some("level:desc,name"), None).unwrap();
+        assert_eq!(sort.get_columns(), ["level", "name"]);
+        assert_eq!(sort.get_sort_directions_i32(), [-1, 1]);
+
+        // Single columns, with a separate order, as before
+        let sort = SortableColumn::<Sorted>::try_parse(None, Some("desc")).unwrap();
+        assert_eq!(sort.get_columns(), ["name"]);
+        assert_eq!(sort.get_sort_directions_i32(), [-1]);
+
+        assert!(SortableColumn::<Sorted>::try_parse(Some("price"), None).is_err());
+        assert!(SortableColumn::<Sorted>::try_parse(Some("level:up"), None).is_err());
+        assert!(SortableColumn::<Sorted>::try_parse(Some("level,level:desc"), None).is_err());
+        assert!(SortableColumn::<Sorted>::try_parse(Some("level,name,rarity,size"), None).is_err());
     }
 }
@@ -1,16 +1,23 @@
 use std::collections::HashMap;
+use std::str::FromStr;
 
 use crate::models::ids::InternalId;
-use crate::models::library::{spell::LibrarySpell, GameSystem, Rarity};
+use crate::models::library::{
+    spell::{LibrarySpell, SavingThrow, SpellHeightening},
+    GameSystem, LibraryObjectType, Rarity,
+};
 use crate::ServerError;
 
-use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 
 use crate::models::query::CommaSeparatedVec;
 
 use super::sorts::{Sortable, SortableColumn};
-use super::{check_library_requested_ids, tags, LegacyStatus, DEFAULT_MAX_LIMIT};
+use super::{
+    insert_library_objects, set_library_object_tags, InsertLibraryObject, LibraryUpsert,
+    LibraryUpsertReport,
+};
+use super::{tags, HomebrewVisibility, LegacyStatus, DEFAULT_MAX_LIMIT};
 
 #[derive(Default, Serialize, Deserialize, Debug, Clone)]
 pub struct SpellFiltering {
@@ -25,10 +32,22 @@ pub struct SpellFiltering {
     pub game_system: Option<GameSystem>,
     pub traits_all: Option<Vec<String>>,
     pub traits_any: Option<Vec<String>>,
+    pub traditions_all: Option<Vec<String>>,
+    pub traditions_any: Option<Vec<String>>,
+    pub actions: Option<String>,
+    pub saving_throw: Option<SavingThrow>,
+    // Spells that can be cast with a slot of this rank- those of this rank or lower
+    pub cast_rank: Option<u8>,
     #[serde(default)]
     pub legacy: LegacyStatus,
+    // Homebrew objects visible to the requesting user. Not part of the query string.
+    #[serde(skip)]
+    pub homebrew: HomebrewVisibility,
+
     pub limit: Option<u64>,
     pub page: Option<u64>,
+    // Continue after a previous page's `next_cursor`, instead of by page. `start` for the first page.
+    pub cursor: Option<String>,
     pub sort_by: Option<String>,
     pub order_by: Option<String>,
 }
@@ -59,10 +78,23 @@ pub struct SpellSearch {
     pub game_system: Option<GameSystem>,
     pub traits_all: Option<Vec<String>>,
     pub traits_any: Option<Vec<String>>,
+    pub traditions_all: Option<Vec<String>>,
+    pub traditions_any: Option<Vec<String>>,
+    pub actions: Option<String>,
+    pub saving_throw: Option<SavingThrow>,
+    // Spells that can be cast with a slot of this rank- those of this rank or lower
+    pub cast_rank: Option<u8>,
     #[serde(default)]
     pub legacy: LegacyStatus,
+    // Homebrew objects visible to the requesting user. Not part of the query string.
+    #[serde(skip)]
+    pub homebrew: HomebrewVisibility,
+
     pub limit: Option<u64>,
     pub page: Option<u64>,
+    // Searches rank by similarity, so are only paged by offset. Set from a listing's cursor.
+    #[serde(skip)]
+    pub cursor: Option<String>,
     pub sort_by: Option<String>,
     pub order_by: Option<String>,
 }
@@ -81,9 +113,16 @@ impl From<SpellFiltering> for SpellSearch {
             game_system: filter.game_system,
             traits_all: filter.traits_all,
             traits_any: filter.traits_any,
+            traditions_all: filter.traditions_all,
+            traditions_any: filter.traditions_any,
+            actions: filter.actions,
+            saving_throw: filter.saving_throw,
+            cast_rank: filter.cast_rank,
             legacy: filter.legacy,
+            homebrew: filter.homebrew,
             limit: filter.limit,
             page: filter.page,
+            cursor: filter.cursor,
             sort_by: filter.sort_by,
             order_by: filter.order_by,
         }
@@ -98,11 +137,28 @@ impl Sortable for LibrarySpell {
     fn default_sort() -> Option<&'static str> {
         Some("name")
     }
+
+    fn get_sort_value(&self, field: &str) -> serde_json::Value {
+        match field {
+            "name" => self.name.as_str().into(),
+            "rank" => self.rank.into(),
+            "rarity" => self.rarity.as_i64().into(),
+            _ => serde_json::Value::Null,
+        }
+    }
+
+    fn get_id(&self) -> InternalId {
+        self.id
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct InsertLibrarySpell {
     pub requested_id: Option<InternalId>,
+    #[serde(default)]
+    pub source_id: Option<String>,
+    #[serde(skip)]
+    pub owner: Option<InternalId>,
     pub name: String,
     pub game_system: GameSystem,
     pub rarity: Rarity,
@@ -112,11 +168,35 @@ pub struct InsertLibrarySpell {
     pub remastering_alt_id: Option<InternalId>,
 
     pub traditions: Vec<String>,
+    #[serde(default)]
+    pub actions: Option<String>,
+    #[serde(default)]
+    pub saving_throw: Option<SavingThrow>,
+    #[serde(default)]
+    pub basic_save: bool,
+    #[serde(default)]
+    pub heightening: Vec<SpellHeightening>,
 
     pub url: Option<String>,
     pub description: String,
 }
 
+impl From<&InsertLibrarySpell> for InsertLibraryObject {
+    fn from(object: &InsertLibrarySpell) -> Self {
+        InsertLibraryObject {
+            requested_id: object.requested_id,
+            source_id: object.source_id.clone(),
+            owner: object.owner,
+            name: object.name.clone(),
+            game_system: object.game_system.clone(),
+            url: object.url.clone(),
+            description: object.description.clone(),
+            legacy: object.legacy,
+            remastering_alt_id: object.remastering_alt_id,
+        }
+    }
+}
+
 // TODO: May be prudent to make a separate models system for the database.
 pub async fn get_spells(
     exec: impl sqlx::Executor<'_, Database = sqlx::Postgres> + Copy,
@@ -151,6 +231,11 @@ pub async fn get_spells_search(
         search.sort_by.as_deref(),
         search.order_by.as_deref(),
     )?;
+    // A cursor picks up after the last object of the previous page, in place of the offset
+    let (offset, after) = match &search.cursor {
+        Some(cursor) => (0, sort.parse_cursor(cursor)?),
+        None => (offset, None),
+    };
 
     let min_similarity = search.min_similarity.unwrap_or(0.0);
 
@@ -162,6 +247,14 @@ pub async fn get_spells_search(
     });
 
     let matching_tags = tags::get_tag_matches(exec, &search.traits_all, &search.traits_any).await?;
+    // Traditions are stored title cased
+    let lowercase = |traditions: &Option<Vec<String>>| {
+        traditions
+            .as_ref()
+            .map(|t| t.iter().map(|t| t.to_lowercase()).collect::<Vec<_>>())
+    };
+    let traditions_any = lowercase(&search.traditions_any);
+    let traditions_all = lowercase(&search.traditions_all);
 
     let query = sqlx::query!(
         r#"
@@ -189,6 +282,10 @@ pub async fn get_spells_search(
                 rarity,
                 rank,
                 traditions,
+                actions,
+                saving_throw,
+                basic_save,
+                heightening,
                 tags.tags,
                 tags.traits,
                 legacy,
@@ -213,21 +310,54 @@ pub async fn get_spells_search(
                 AND ($6::text[] IS NULL OR tags.traits::text[] && $6::text[])
                 AND ($7::text[] IS NULL OR tags.traits::text[] @> $7::text[])
                 AND ($8::int[] IS NULL OR lo.id = ANY($8))
+                AND ($23::text[] IS NULL OR ARRAY(SELECT lower(t) FROM UNNEST(lc.traditions) t) && $23::text[])
+                AND ($24::text[] IS NULL OR ARRAY(SELECT lower(t) FROM UNNEST(lc.traditions) t) @> $24::text[])
+                AND ($25::text IS NULL OR lc.actions = $25)
+                AND ($26::text IS NULL OR lc.saving_throw = $26)
+                AND ($27::int IS NULL OR rank <= $27)
+                AND ($18::bool OR lo.owner IS NULL OR lo.owner = $19 OR lo.campaign_id IN (
+                    SELECT id FROM campaigns WHERE owner = $19
+                    UNION SELECT campaign_id FROM campaign_members WHERE user_id = $19
+                ))
                 AND (($11::bool AND lo.name ILIKE '%' || query || '%') OR SIMILARITY(lo.name, query) >= $10)
                 AND NOT (NOT $12::bool AND lo.legacy = FALSE)
                 AND NOT (NOT $13::bool AND lo.legacy = TRUE)
                 AND NOT ($14::bool AND lo.remastering_alt_id IS NOT NULL AND lo.legacy = TRUE)
                 AND NOT ($15::bool AND lo.remastering_alt_id IS NOT NULL AND lo.legacy = FALSE)
 
+                -- After the cursor's sort keys, then id. Descending keys swap sides, so that a single row comparison
+                -- covers any mix of directions. Keys past the sort's columns are equal on both sides.
+                AND ($22::jsonb IS NULL OR (
+                    COALESCE(CASE ($17::int[])[1] WHEN 1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($16::text[])[1] WHEN -1 THEN $22 -> 0 END, 'null'),
+                    COALESCE(CASE ($17::int[])[2] WHEN 1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($16::text[])[2] WHEN -1 THEN $22 -> 1 END, 'null'),
+                    COALESCE(CASE ($17::int[])[3] WHEN 1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($16::text[])[3] WHEN -1 THEN $22 -> 2 END, 'null'),
+                    lo.id
+                ) > (
+                    COALESCE(CASE ($17::int[])[1] WHEN 1 THEN $22 -> 0 WHEN -1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($16::text[])[1] END, 'null'),
+                    COALESCE(CASE ($17::int[])[2] WHEN 1 THEN $22 -> 1 WHEN -1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($16::text[])[2] END, 'null'),
+                    COALESCE(CASE ($17::int[])[3] WHEN 1 THEN $22 -> 2 WHEN -1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($16::text[])[3] END, 'null'),
+                    ($22 -> cardinality($16::text[]))::int
+                ))
             GROUP BY lo.id, lc.id, tags.tags, tags.traits 
+            -- Up to MAX_SORT_COLUMNS sort keys, then id to break ties
             ORDER BY similarity DESC, favor_exact_start_length,
-                CASE WHEN $16::text = 'name' AND $17::int = 1 THEN lo.name::text END ASC,
-                CASE WHEN $16::text = 'name' AND $17::int = -1 THEN lo.name::text END DESC,
-                CASE WHEN $16::text = 'rank' THEN rank::integer * $17::int END ASC,
-                CASE WHEN $16::text = 'rarity' THEN rarity::integer * $17::int END ASC
-            LIMIT $18 OFFSET $19
+                CASE WHEN ($17::int[])[1] = 1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($16::text[])[1] END ASC,
+                CASE WHEN ($17::int[])[1] = -1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($16::text[])[1] END DESC,
+                CASE WHEN ($17::int[])[2] = 1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($16::text[])[2] END ASC,
+                CASE WHEN ($17::int[])[2] = -1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($16::text[])[2] END DESC,
+                CASE WHEN ($17::int[])[3] = 1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($16::text[])[3] END ASC,
+                CASE WHEN ($17::int[])[3] = -1 THEN (to_jsonb(lo) || to_jsonb(lc)) -> ($16::text[])[3] END DESC,
+                lo.id
+            LIMIT $20 OFFSET $21
         ) c
-        ORDER BY similarity DESC, favor_exact_start_length
+        ORDER BY similarity DESC, favor_exact_start_length,
+            CASE WHEN ($17::int[])[1] = 1 THEN to_jsonb(c) -> ($16::text[])[1] END ASC,
+            CASE WHEN ($17::int[])[1] = -1 THEN to_jsonb(c) -> ($16::text[])[1] END DESC,
+            CASE WHEN ($17::int[])[2] = 1 THEN to_jsonb(c) -> ($16::text[])[2] END ASC,
+            CASE WHEN ($17::int[])[2] = -1 THEN to_jsonb(c) -> ($16::text[])[2] END DESC,
+            CASE WHEN ($17::int[])[3] = 1 THEN to_jsonb(c) -> ($16::text[])[3] END ASC,
+            CASE WHEN ($17::int[])[3] = -1 THEN to_jsonb(c) -> ($16::text[])[3] END DESC,
+            c.id
     "#,
         search.name,
         search.rarity.as_ref().map(|r| r.as_i64() as i32),
@@ -244,10 +374,18 @@ pub async fn get_spells_search(
         search.legacy.include_legacy(),
         search.legacy.favor_remaster(),
         search.legacy.favor_legacy(),
-        sort.get_column(),
-        sort.get_sort_direction_i32(),
+        &sort.get_columns(),
+        &sort.get_sort_directions_i32(),
+        search.homebrew.all(),
+        search.homebrew.user_id(),
         limit as i64,
         offset as i64,
+        after,
+        traditions_any.as_deref(),
+        traditions_all.as_deref(),
+        search.actions.as_ref().map(|a| a.to_lowercase()),
+        search.saving_throw.map(|s| s.as_str()),
+        search.cast_rank.map(|r| r as i32),
     );
 
     // create initial hm with empty vecs for each query
@@ -272,7 +410,14 @@ pub async fn get_spells_search(
                 url: row.url,
                 description: row.description.unwrap_or_default(),
                 traditions: row.traditions,
+                actions: row.actions,
+                saving_throw: row
+                    .saving_throw
+                    .and_then(|s| SavingThrow::from_str(&s).ok()),
+                basic_save: row.basic_save,
+                heightening: serde_json::from_value(row.heightening).unwrap_or_default(),
                 legacy: row.legacy,
+                remastering_alt_id: row.remastering_alt_id.map(|id| InternalId(id as u32)),
                 traits: row.traits.unwrap_or_default(),
             };
             map.entry(query)
@@ -286,100 +431,72 @@ pub async fn get_spells_search(
 pub async fn insert_spells(
     tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
     spells: &[InsertLibrarySpell],
-) -> crate::Result<()> {
-    // TODO: Do we *need* two tables for this?
-
+) -> crate::Result<Vec<InternalId>> {
     if spells.is_empty() {
-        return Ok(());
+        return Ok(vec![]);
     }
 
-    // First, check to make sure all requested ids are not already in use
-    let requested_ids = spells
+    let objects = spells
         .iter()
-        .filter_map(|i| i.requested_id)
-        .map(|id| id.0 as i32)
-        .collect::<Vec<i32>>();
-    check_library_requested_ids(&mut **tx, &requested_ids).await?;
+        .map(InsertLibraryObject::from)
+        .collect::<Vec<_>>();
+    let ids = insert_library_objects(tx, &objects).await?;
+    write_spells(tx, &ids, spells).await?;
+    Ok(ids.into_iter().map(|id| InternalId(id as u32)).collect())
+}
 
-    let ids = sqlx::query!(
-        r#"
-        INSERT INTO library_objects (id, name, game_system, url, description, legacy, remastering_alt_id)
-        SELECT * FROM UNNEST ($1::int[], $2::text[], $3::int[], $4::text[], $5::text[], $6::bool[], $7::int[])
-        RETURNING id  
-    "#,
-        &spells
-            .iter()
-            .map(|c| c.requested_id.map(|id| id.0 as i32))
-            .collect::<Vec<Option<i32>>>() as _,
-        &spells
-            .iter()
-            .map(|c| c.name.clone())
-            .collect::<Vec<String>>(),
-        &spells
-            .iter()
-            .map(|c| c.game_system.as_i64() as i32)
-            .collect::<Vec<i32>>(),
-        &spells
-            .iter()
-            .map(|c| c.url.clone())
-            .collect::<Vec<Option<String>>>() as _,
-        &spells
-            .iter()
-            .map(|c| c.description.clone())
-            .collect::<Vec<String>>(),
-        &spells
-            .iter()
-            .map(|c| c.legacy)
-            .collect::<Vec<bool>>(),
-        &spells
-            .iter()
-            .map(|c| c.remastering_alt_id.map(|id| id.0 as i32))
-            .collect::<Vec<Option<i32>>>() as _
-    )
-    .fetch_all(&mut **tx)
-    .await?
-    .into_iter()
-    .map(|row| Ok(row.id))
-    .collect::<Result<Vec<i32>, sqlx::Error>>()?;
+/// Inserts new spells, and updates existing ones in place.
+pub async fn upsert_spells(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    spells: &[InsertLibrarySpell],
+) -> crate::Result<LibraryUpsertReport> {
+    if spells.is_empty() {
+        return Ok(LibraryUpsertReport::default());
+    }
 
+    let objects = spells
+        .iter()
+        .map(InsertLibraryObject::from)
+        .collect::<Vec<_>>();
+    let upsert = LibraryUpsert::begin(tx, LibraryObjectType::Spell, &objects).await?;
+    write_spells(tx, &upsert.ids, spells).await?;
+    upsert.finish(tx).await
+}
+
+// Writes everything but the 'library_objects' row
+async fn write_spells(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ids: &[i32],
+    spells: &[InsertLibrarySpell],
+) -> crate::Result<()> {
     // TODO: Unfortunately, sqlx does not support multidimensional arrays
     // No built in way to UNNEST like in other insertion functions in postgres- unnest entirely flattens.
     // https://wiki.postgresql.org/wiki/Unnest_multidimensional_array
     // Unfortunately, sqlx does not support insertion of multidimensional arrays anyhow.
     // TODO: as i32 should be unnecessary- fix in models
-    for (id, spell) in spells.iter().enumerate() {
+    for (id, spell) in ids.iter().zip(spells.iter()) {
         sqlx::query!(
             r#"
-            INSERT INTO library_spells (id, rarity, rank, traditions)
-            VALUES ($1, $2, $3, $4)
+            INSERT INTO library_spells (id, rarity, rank, traditions, actions, saving_throw, basic_save, heightening)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            ON CONFLICT (id) DO UPDATE
+            SET rarity = EXCLUDED.rarity, rank = EXCLUDED.rank, traditions = EXCLUDED.traditions,
+                actions = EXCLUDED.actions, saving_throw = EXCLUDED.saving_throw,
+                basic_save = EXCLUDED.basic_save, heightening = EXCLUDED.heightening
         "#,
-            ids[id] as i32,
+            id,
             spell.rarity.as_i64() as i32,
             spell.rank as i32,
             &spell.traditions,
+            // Lowercased, to filter on
+            spell.actions.as_ref().map(|a| a.to_lowercase()),
+            spell.saving_throw.map(|s| s.as_str()),
+            spell.basic_save,
+            serde_json::to_value(&spell.heightening)?,
         )
         .execute(&mut **tx)
         .await?;
-
-        // Insert tags
-        if !spell.tags.is_empty() {
-            sqlx::query!(
-                r#"
-                INSERT INTO library_objects_tags (library_object_id, tag_id)
-                SELECT $1, unnest($2::int[])
-            "#,
-                ids[id] as i32,
-                &spell
-                    .tags
-                    .iter()
-                    .map(|id| id.0 as i32)
-                    .sorted()
-                    .collect::<Vec<i32>>(),
-            )
-            .execute(&mut **tx)
-            .await?;
-        }
     }
 
-    Ok(())
+    set_library_object_tags(tx, ids, &spells.iter().map(|o| &o.tags).collect::<Vec<_>>()).await
 }
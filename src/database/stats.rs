@@ -3,7 +3,10 @@ use std::collections::HashMap;
 use crate::models::encounter::EncounterType;
 use crate::models::ids::InternalId;
 use crate::models::stats::CampaignStats;
-use crate::models::stats::{AssignedBoost, AssignedRewardsSession, CharacterStats, EncounterStats};
+use crate::models::stats::{
+    treasure_deficits, AssignedBoost, AssignedRewardsSession, CharacterStats, EncounterStats,
+    ExpectedBoost, ExpectedClassItem,
+};
 
 use serde::Deserialize;
 
@@ -12,18 +15,26 @@ pub async fn get_campaign_stats(
     owner: InternalId,
     campaign_id: InternalId,
 ) -> crate::Result<CampaignStats> {
+    // An item instance is a stack of 'quantity' copies, so treasure values and item counts are weighted by it
     let characters_query = sqlx::query!(
         r#"
         SELECT
             ch.id,
+            class.name AS class_name,
+            cx.total_experience,
+            cx.level,
             items.total_treasure_item_value,
+            items.spent_consumables_value,
             coalesce(items.items, '[]'::jsonb) as items,
             gold.total_gold,
             owned_boosts.assigned_boosts,
             expected_boosts.expected_boosts,
+            expected_items.expected_items,
             reward_by_session.reward_by_session
         FROM characters ch
         INNER JOIN campaigns c ON ch.campaign = c.id
+        INNER JOIN library_objects class ON ch.class = class.id
+        LEFT JOIN character_experience cx ON cx.character_id = ch.id
         LEFT JOIN LATERAL (
             SELECT
                 SUM(csc.gold_rewards) AS total_gold
@@ -37,10 +48,13 @@ pub async fn get_campaign_stats(
                     json_build_object(
                         'id', ci.id,
                         'library_item_id', ci.library_item_id,
-                        'consumable', li.consumable
+                        'quantity', ci.quantity,
+                        'consumable', li.consumable,
+                        'spent', ci.spent
                     )
                 ) filter (where ci.id is not null) as items,
-                SUM(li.price) as total_treasure_item_value
+                SUM(li.price * ci.quantity) as total_treasure_item_value,
+                SUM(li.price * ci.quantity) FILTER (WHERE ci.spent) as spent_consumables_value
             FROM item_instances ci
             INNER JOIN library_items li ON li.id = ci.library_item_id
             INNER JOIN campaign_sessions cs ON ci.session_id = cs.id
@@ -57,7 +71,7 @@ pub async fn get_campaign_stats(
             FROM  campaign_session_characters csc
             INNER JOIN campaign_sessions cs ON csc.session_id = cs.id
             LEFT JOIN LATERAL (
-                SELECT SUM(li.price) as price_sum, JSONB_AGG(json_build_object(
+                SELECT SUM(li.price * ci.quantity) as price_sum, JSONB_AGG(json_build_object(
                     'id', ci.id,
                     'library_item_id', ci.library_item_id
                 )) as items_group
@@ -87,15 +101,58 @@ pub async fn get_campaign_stats(
         LEFT JOIN LATERAL (
             SELECT json_agg(
                     json_build_object(
+                    'level', etsb.level,
                     'boost_category_id', etsb.stat_boost_category_id,
                     'boost_category_name', sbct.name,
                     'potency', etsb.amount
                     )
                 ) AS expected_boosts
-                FROM expected_treasure_stats_boosts_at_levels etsb
+                FROM (
+                    SELECT level, stat_boost_category_id, amount
+                    FROM expected_treasure_stats_boosts_by_class
+                    WHERE class_id = ch.class
+                    UNION ALL
+                    SELECT level, stat_boost_category_id, amount
+                    FROM expected_treasure_stats_boosts_at_levels general
+                    WHERE NOT EXISTS (
+                        SELECT 1 FROM expected_treasure_stats_boosts_by_class bc
+                        WHERE bc.class_id = ch.class AND bc.stat_boost_category_id = general.stat_boost_category_id
+                    )
+                ) etsb
                 INNER JOIN stat_boost_category_types sbct ON etsb.stat_boost_category_id = sbct.id
             WHERE etsb.level <= c.level
         ) expected_boosts ON true
+        LEFT JOIN LATERAL (
+            SELECT json_agg(
+                    json_build_object(
+                    'level', etc.level,
+                    'importance', etc.importance,
+                    'item_ids', COALESCE(group_items.ids, '{}'),
+                    'item_names', COALESCE(group_items.names, '{}'),
+                    'traits', COALESCE(group_traits.tags, '{}'),
+                    'owned', EXISTS (
+                        SELECT 1 FROM item_instances ii
+                        LEFT JOIN library_objects_tags lot ON lot.library_object_id = ii.library_item_id
+                        WHERE ii.character_id = ch.id
+                            AND (ii.library_item_id = ANY(group_items.ids) OR lot.tag_id = ANY(group_traits.ids))
+                    )
+                    ) ORDER BY etc.level, etc.importance DESC
+                ) AS expected_items
+            FROM expected_treasure_by_class_by_level etc
+            LEFT JOIN LATERAL (
+                SELECT ARRAY_AGG(lo.id) AS ids, ARRAY_AGG(lo.name) AS names
+                FROM expected_treasure_by_class_by_level_items etci
+                INNER JOIN library_objects lo ON lo.id = etci.item_id
+                WHERE etci.item_group_id = etc.item_group_id
+            ) group_items ON true
+            LEFT JOIN LATERAL (
+                SELECT ARRAY_AGG(t.id) AS ids, ARRAY_AGG(t.tag) AS tags
+                FROM expected_treasure_by_class_by_level_traits etct
+                INNER JOIN library_tags t ON t.id = etct.trait_id
+                WHERE etct.item_group_id = etc.item_group_id
+            ) group_traits ON true
+            WHERE etc.class_id = ch.class AND etc.level <= c.level
+        ) expected_items ON true
         WHERE c.owner = $1 AND c.id = $2
         "#,
         owner.0 as i32,
@@ -107,29 +164,52 @@ pub async fn get_campaign_stats(
     .map(|row| {
         let assigned_boosts: Vec<AssignedBoost> =
             serde_json::from_value(row.assigned_boosts.unwrap_or_default()).unwrap_or_default();
-        let expected_boosts: Vec<AssignedBoost> =
+        let expected_boosts: Vec<ExpectedBoost> =
             serde_json::from_value(row.expected_boosts.unwrap_or_default()).unwrap_or_default();
+        let expected_items: Vec<ExpectedClassItem> =
+            serde_json::from_value(row.expected_items.unwrap_or_default()).unwrap_or_default();
+        let deficits = treasure_deficits(
+            &row.class_name,
+            &expected_boosts,
+            &assigned_boosts,
+            &expected_items,
+        );
 
         #[derive(Deserialize, Debug)]
         pub struct Item {
             pub id: i32,
             pub library_item_id: i32,
+            pub quantity: u16,
             pub consumable: bool,
+            pub spent: bool,
+        }
+        impl Item {
+            // Once for each in the stack
+            fn library_item_ids(&self) -> impl Iterator<Item = InternalId> {
+                std::iter::repeat(InternalId(self.library_item_id as u32)).take(self.quantity as usize)
+            }
         }
         let items = serde_json::from_value::<Vec<Item>>(row.items.unwrap()).unwrap();
         let consumable_items: Vec<InternalId> = items
             .iter()
             .filter(|i| i.consumable)
-            .map(|i| InternalId(i.library_item_id as u32))
+            .flat_map(Item::library_item_ids)
+            .collect();
+        let held_consumable_items: Vec<InternalId> = items
+            .iter()
+            .filter(|i| i.consumable && !i.spent)
+            .flat_map(Item::library_item_ids)
             .collect();
         let permanent_items: Vec<InternalId> = items
             .iter()
             .filter(|i| !i.consumable)
-            .map(|i| InternalId(i.library_item_id as u32))
+            .flat_map(Item::library_item_ids)
             .collect();
 
+        let experience = row.total_experience.unwrap_or_default().max(0) as u64;
         let gold: f64 = row.total_gold.unwrap_or(0.0);
         let total_treasure_items_value: f64 = row.total_treasure_item_value.unwrap_or(0.0);
+        let spent_consumables_value: f64 = row.spent_consumables_value.unwrap_or(0.0);
 
         #[derive(Deserialize, Debug)]
         pub struct AssignedRewardSession {
@@ -153,23 +233,34 @@ pub async fn get_campaign_stats(
         (
             InternalId(row.id as u32),
             CharacterStats {
+                total_experience: experience,
+                level: row.level.unwrap_or(1) as u8,
+                experience_this_level: experience % 1000,
+
                 total_combined_treasure: gold + total_treasure_items_value,
                 total_treasure_items_value,
                 total_gold: gold,
+                spent_consumables_value,
+                held_combined_treasure: gold + total_treasure_items_value - spent_consumables_value,
+                // Set once the party's expected treasure is known, below
+                expected_combined_treasure: 0.0,
 
                 available_boosts: assigned_boosts,
                 expected_boosts,
+                expected_items,
+                deficits,
 
                 rewards_per_session: rewards_by_session,
 
                 total_permanent_items: permanent_items,
                 total_consumable_items: consumable_items,
+                held_consumable_items,
             },
         )
     })
     .collect::<HashMap<InternalId, CharacterStats>>();
 
-    let query = sqlx::query!(
+    let mut stats = sqlx::query!(
         r#"
         SELECT
             c.level,
@@ -228,7 +319,7 @@ pub async fn get_campaign_stats(
         ) by_encounter ON true
         LEFT JOIN LATERAL (
             SELECT
-                SUM(li.price) total_treasure_items_value
+                SUM(li.price * ci.quantity) total_treasure_items_value
             FROM item_instances ci
             INNER JOIN encounters e ON ci.encounter_id = e.id
             INNER JOIN campaign_sessions cs ON e.session_id = cs.id
@@ -243,7 +334,7 @@ pub async fn get_campaign_stats(
             SELECT
                 li.level::text AS level,
                 li.consumable,
-                COUNT(*) AS total
+                SUM(ci.quantity) AS total
             FROM item_instances ci
             INNER JOIN encounters e ON ci.encounter_id = e.id
             INNER JOIN campaign_sessions cs ON e.session_id = cs.id
@@ -371,5 +462,11 @@ pub async fn get_campaign_stats(
         }
     }).ok_or_else(|| crate::ServerError::NotFound)?;
 
-    Ok(query)
+    // Expected treasure is only known for the party as a whole, so each character is expected an even share of it
+    let num_characters = stats.character_stats.len().max(1) as f64;
+    for character in stats.character_stats.values_mut() {
+        character.expected_combined_treasure =
+            stats.total_expected_combined_treasure as f64 / num_characters;
+    }
+    Ok(stats)
 }
@@ -0,0 +1,149 @@
+use serde::Deserialize;
+
+use crate::models::characters::{skill_serialize, Skill};
+use crate::models::encounter::{Encounter, EncounterType};
+use crate::models::ids::InternalId;
+use crate::models::subsystem::{
+    calculate_subsystem_progress, DegreeOfSuccess, SubsystemAttempt, SubsystemProgress,
+};
+use crate::ServerError;
+
+#[derive(Deserialize, Debug)]
+pub struct InsertSubsystemAttempt {
+    pub check_index: u32,
+    pub character_id: Option<InternalId>,
+    #[serde(with = "skill_serialize")]
+    pub skill: Skill,
+    pub degree_of_success: DegreeOfSuccess,
+}
+
+pub async fn get_subsystem_attempts(
+    exec: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    encounter_id: InternalId,
+) -> crate::Result<Vec<SubsystemAttempt>> {
+    let attempts = sqlx::query!(
+        r#"
+        SELECT id, check_index, character_id, skill, degree_of_success
+        FROM encounter_subsystem_attempts
+        WHERE encounter_id = $1
+        ORDER BY created_at, id
+        "#,
+        encounter_id.0 as i32,
+    )
+    .fetch_all(exec)
+    .await?
+    .into_iter()
+    .map(|row| {
+        Ok(SubsystemAttempt {
+            id: InternalId(row.id as u32),
+            check_index: row.check_index as u32,
+            character_id: row.character_id.map(|id| InternalId(id as u32)),
+            skill: Skill::from_str(&row.skill).unwrap_or(Skill::Unknown),
+            degree_of_success: DegreeOfSuccess::from_i16(row.degree_of_success)?,
+        })
+    })
+    .collect::<crate::Result<Vec<_>>>()?;
+    Ok(attempts)
+}
+
+// Current progress of a subsystem encounter, derived from all attempts made so far
+pub async fn get_subsystem_progress(
+    exec: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    encounter: &Encounter,
+) -> crate::Result<SubsystemProgress> {
+    let EncounterType::Subsystem {
+        subsystem_type,
+        subsystem_checks,
+        awareness_thresholds,
+    } = &encounter.encounter_type
+    else {
+        return Err(ServerError::BadRequest(
+            "Encounter is not a subsystem encounter".to_string(),
+        ));
+    };
+
+    let attempts = get_subsystem_attempts(exec, encounter.id).await?;
+    Ok(calculate_subsystem_progress(
+        subsystem_type.clone(),
+        subsystem_checks,
+        awareness_thresholds,
+        attempts,
+    ))
+}
+
+pub async fn insert_subsystem_attempt(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    encounter: &Encounter,
+    attempt: &InsertSubsystemAttempt,
+) -> crate::Result<InternalId> {
+    let EncounterType::Subsystem {
+        subsystem_checks, ..
+    } = &encounter.encounter_type
+    else {
+        return Err(ServerError::BadRequest(
+            "Encounter is not a subsystem encounter".to_string(),
+        ));
+    };
+    if attempt.check_index as usize >= subsystem_checks.len() {
+        return Err(ServerError::BadRequest(format!(
+            "Check {} does not exist in this encounter",
+            attempt.check_index
+        )));
+    }
+
+    // Characters must be part of the encounter's campaign
+    if let Some(character_id) = attempt.character_id {
+        let in_campaign = sqlx::query!(
+            r#"
+            SELECT id FROM characters
+            WHERE id = $1 AND campaign = $2
+            "#,
+            character_id.0 as i32,
+            encounter.campaign_id.map(|id| id.0 as i32),
+        )
+        .fetch_optional(&mut **tx)
+        .await?
+        .is_some();
+        if !in_campaign {
+            return Err(ServerError::BadRequest(
+                "Character must be part of the encounter's campaign".to_string(),
+            ));
+        }
+    }
+
+    let id = sqlx::query!(
+        r#"
+        INSERT INTO encounter_subsystem_attempts (encounter_id, check_index, character_id, skill, degree_of_success)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id
+        "#,
+        encounter.id.0 as i32,
+        attempt.check_index as i32,
+        attempt.character_id.map(|id| id.0 as i32),
+        attempt.skill.to_string(),
+        attempt.degree_of_success.as_i16(),
+    )
+    .fetch_one(&mut **tx)
+    .await?
+    .id;
+
+    Ok(InternalId(id as u32))
+}
+
+pub async fn delete_subsystem_attempt(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    encounter_id: InternalId,
+    attempt_id: InternalId,
+) -> crate::Result<()> {
+    sqlx::query!(
+        r#"
+        DELETE FROM encounter_subsystem_attempts
+        WHERE id = $1 AND encounter_id = $2
+        "#,
+        attempt_id.0 as i32,
+        encounter_id.0 as i32,
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
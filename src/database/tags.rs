@@ -70,6 +70,44 @@ pub async fn insert_tags(
     Ok(())
 }
 
+/// Gets the ids of the given traits, inserting any that don't exist yet.
+pub async fn get_or_insert_traits(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    traits: &[String],
+) -> crate::Result<HashMap<String, InternalId>> {
+    if traits.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    // Tags are inserted with explicit ids elsewhere, so new ids continue from the highest rather than the sequence
+    sqlx::query!(
+        r#"
+        INSERT INTO library_tags (id, tag, "trait")
+        SELECT (SELECT COALESCE(MAX(id), 0) FROM library_tags) + ordinality, tag, TRUE
+        FROM UNNEST($1::text[]) WITH ORDINALITY AS missing(tag, ordinality)
+        WHERE NOT EXISTS (SELECT 1 FROM library_tags lt WHERE lt.tag = missing.tag)
+        "#,
+        &traits.iter().unique().cloned().collect::<Vec<String>>(),
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    let ids = sqlx::query!(
+        r#"
+        SELECT DISTINCT ON (tag) id, tag FROM library_tags
+        WHERE tag = ANY($1::text[])
+        ORDER BY tag, "trait" DESC, id
+        "#,
+        traits,
+    )
+    .fetch_all(&mut **tx)
+    .await?
+    .into_iter()
+    .map(|row| (row.tag, InternalId(row.id as u32)))
+    .collect();
+    Ok(ids)
+}
+
 #[derive(Debug)]
 pub struct TagMatches {
     pub all_traits: Option<Vec<String>>,
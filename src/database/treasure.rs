@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use super::LegacyStatus;
+use crate::models::ids::InternalId;
+use crate::models::library::Rarity;
+use crate::models::stats::{missing_items_by_level, TreasureParcel};
+use crate::v2::database::item_instances::InsertItemInstance;
+use crate::ServerError;
+
+#[derive(Deserialize, Debug, Default)]
+pub struct GenerateTreasure {
+    // Level to fill treasure up to the end of. Defaults to the campaign's current level.
+    pub level: Option<u8>,
+    pub rarity: Option<Rarity>,
+    #[serde(default)]
+    pub legacy: LegacyStatus,
+
+    // If set, the parcel is added to the treasure of this encounter
+    pub encounter_id: Option<InternalId>,
+}
+
+// Proposes items and currency that close the gap between what a campaign has been given and
+// the expected treasure at the end of the target level.
+pub async fn generate_treasure_parcel(
+    exec: impl sqlx::Executor<'_, Database = sqlx::Postgres> + Copy,
+    owner: InternalId,
+    campaign_id: InternalId,
+    generate: &GenerateTreasure,
+) -> crate::Result<TreasureParcel> {
+    let stats = super::stats::get_campaign_stats(exec, owner, campaign_id).await?;
+    let level = generate.level.map(|l| l as u32).unwrap_or(stats.level);
+    if !(1..=20).contains(&level) {
+        return Err(ServerError::BadRequest(
+            "Level must be between 1 and 20".to_string(),
+        ));
+    }
+
+    let expected = sqlx::query!(
+        r#"
+        SELECT
+            (
+                SELECT SUM(party_currency + $2::int * currency_per_additional_player)
+                FROM expected_treasures_by_level
+                WHERE level <= $1
+            ) AS expected_currency,
+            (
+                SELECT jsonb_object_agg(key, total) FROM (
+                    SELECT key, SUM(value::int) AS total
+                    FROM expected_treasures_by_level etbl,
+                        LATERAL jsonb_each(etbl.permanent_items_by_level)
+                    WHERE etbl.level <= $1
+                    GROUP BY key
+                ) s
+            ) AS expected_permanent_items_by_level,
+            (
+                SELECT jsonb_object_agg(key, total) FROM (
+                    SELECT key, SUM(value::int) AS total
+                    FROM expected_treasures_by_level etbl,
+                        LATERAL jsonb_each(etbl.consumable_items_by_level)
+                    WHERE etbl.level <= $1
+                    GROUP BY key
+                ) s
+            ) AS expected_consumable_items_by_level
+        "#,
+        level as i32,
+        stats.character_stats.len() as i32 - 4,
+    )
+    .fetch_one(exec)
+    .await?;
+
+    let expected_permanent: HashMap<u32, u32> = serde_json::from_value(
+        expected
+            .expected_permanent_items_by_level
+            .unwrap_or_default(),
+    )
+    .unwrap_or_default();
+    let expected_consumable: HashMap<u32, u32> = serde_json::from_value(
+        expected
+            .expected_consumable_items_by_level
+            .unwrap_or_default(),
+    )
+    .unwrap_or_default();
+
+    // (level, consumable) of every item slot still to be filled
+    let mut slots =
+        missing_items_by_level(&expected_permanent, &stats.total_permanent_items_by_level)
+            .into_iter()
+            .map(|l| (l, false))
+            .chain(
+                missing_items_by_level(
+                    &expected_consumable,
+                    &stats.total_consumable_items_by_level,
+                )
+                .into_iter()
+                .map(|l| (l, true)),
+            )
+            .collect::<Vec<_>>();
+
+    // Items the classes in the party specifically want take priority over random ones.
+    let class_items = sqlx::query!(
+        r#"
+        SELECT
+            li.id,
+            li.level AS "level!",
+            li.consumable,
+            MAX(etc.importance) AS importance
+        FROM expected_treasure_by_class_by_level etc
+        INNER JOIN expected_treasure_by_class_by_level_items etci ON etci.item_group_id = etc.item_group_id
+        INNER JOIN characters ch ON ch.class = etc.class_id AND ch.campaign = $1
+        INNER JOIN library_items li ON li.id = etci.item_id
+        WHERE etc.level <= $2
+            AND NOT EXISTS (
+                SELECT 1 FROM item_instances ii
+                LEFT JOIN encounters e ON ii.encounter_id = e.id
+                LEFT JOIN campaign_sessions cs ON e.session_id = cs.id
+                WHERE ii.library_item_id = li.id
+                    AND (ii.campaign_id = $1 OR cs.campaign_id = $1)
+            )
+        GROUP BY li.id
+        ORDER BY importance DESC
+        "#,
+        campaign_id.0 as i32,
+        level as i32,
+    )
+    .fetch_all(exec)
+    .await?;
+
+    let mut chosen = Vec::new();
+    for item in class_items {
+        let slot = (item.level as u32, item.consumable);
+        if let Some(ix) = slots.iter().position(|s| *s == slot) {
+            slots.swap_remove(ix);
+            chosen.push((InternalId(item.id as u32), item.consumable));
+        }
+    }
+
+    // Fill the remaining slots with random items of the matching level and kind, from official content or
+    // homebrew of the campaign owner or shared to the campaign.
+    let random_items = sqlx::query!(
+        r#"
+        SELECT
+            i.id AS "id!",
+            i.consumable AS "consumable!"
+        FROM UNNEST($1::int[], $2::bool[]) AS s(level, consumable)
+        CROSS JOIN LATERAL (
+            SELECT li.id, li.consumable
+            FROM library_items li
+            INNER JOIN library_objects lo ON lo.id = li.id
+            WHERE li.level = s.level
+                AND li.consumable = s.consumable
+                AND li.price IS NOT NULL
+                AND NOT li.cursed
+                AND ($3::int IS NULL OR li.rarity = $3)
+                AND NOT (NOT $4::bool AND lo.legacy = FALSE)
+                AND NOT (NOT $5::bool AND lo.legacy = TRUE)
+                AND NOT ($6::bool AND lo.remastering_alt_id IS NOT NULL AND lo.legacy = TRUE)
+                AND NOT ($7::bool AND lo.remastering_alt_id IS NOT NULL AND lo.legacy = FALSE)
+                AND (lo.owner IS NULL OR lo.owner = $8 OR lo.campaign_id = $9)
+            ORDER BY RANDOM()
+            LIMIT 1
+        ) i
+        "#,
+        &slots.iter().map(|(l, _)| *l as i32).collect::<Vec<i32>>(),
+        &slots.iter().map(|(_, c)| *c).collect::<Vec<bool>>(),
+        generate.rarity.as_ref().map(|r| r.as_i64() as i32),
+        generate.legacy.include_remaster(),
+        generate.legacy.include_legacy(),
+        generate.legacy.favor_remaster(),
+        generate.legacy.favor_legacy(),
+        owner.0 as i32,
+        campaign_id.0 as i32,
+    )
+    .fetch_all(exec)
+    .await?;
+    chosen.extend(
+        random_items
+            .into_iter()
+            .map(|i| (InternalId(i.id as u32), i.consumable)),
+    );
+
+    let ids = chosen.iter().map(|(id, _)| id.0 as i32).collect::<Vec<_>>();
+    let total_items_value = sqlx::query!(
+        r#"
+        SELECT SUM(li.price) AS total
+        FROM UNNEST($1::int[]) AS ids(id)
+        INNER JOIN library_items li ON li.id = ids.id
+        "#,
+        &ids,
+    )
+    .fetch_one(exec)
+    .await?
+    .total
+    .unwrap_or_default();
+
+    let currency =
+        (expected.expected_currency.unwrap_or_default() - stats.total_gold as f64).max(0.0);
+
+    let (consumable_items, permanent_items): (Vec<_>, Vec<_>) =
+        chosen.into_iter().partition(|(_, consumable)| *consumable);
+    Ok(TreasureParcel {
+        level,
+        currency,
+        permanent_items: permanent_items.into_iter().map(|(id, _)| id).collect(),
+        consumable_items: consumable_items.into_iter().map(|(id, _)| id).collect(),
+        total_items_value,
+        encounter_id: None,
+    })
+}
+
+// Adds the parcel's items (as rewards) and currency to the treasure of an encounter in the campaign
+// The user must be able to edit the encounter. Encounters outside of the campaign are not found.
+pub async fn attach_treasure_parcel(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    user_id: InternalId,
+    campaign_id: InternalId,
+    encounter_id: InternalId,
+    parcel: &TreasureParcel,
+) -> crate::Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE encounters
+        SET treasure_currency = treasure_currency + $1
+        WHERE id = $2 AND (
+            campaign_id = $3
+            OR session_id IN (SELECT id FROM campaign_sessions WHERE campaign_id = $3)
+        )
+        RETURNING id
+        "#,
+        parcel.currency,
+        encounter_id.0 as i32,
+        campaign_id.0 as i32,
+    )
+    .fetch_optional(&mut **tx)
+    .await?
+    .ok_or(ServerError::NotFound)?;
+
+    let item_instances = parcel
+        .permanent_items
+        .iter()
+        .chain(parcel.consumable_items.iter())
+        .map(|id| InsertItemInstance {
+            library_item_id: *id,
+            parent_item_id: None,
+            campaign_id: Some(campaign_id),
+            encounter_id: Some(encounter_id),
+            character_id: None,
+            session_id: None,
+            is_reward: true,
+            quantity: 1,
+            nickname: None,
+            notes: None,
+        })
+        .collect::<Vec<_>>();
+    crate::v2::database::item_instances::insert_item_instances(tx, item_instances).await?;
+
+    super::encounters::recalculate_encounter_summary(tx, user_id, &[encounter_id]).await
+}
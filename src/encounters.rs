@@ -13,7 +13,9 @@ use sqlx::PgPool;
 use crate::{
     database::{
         self,
-        encounters::{EncounterFilters, InsertEncounter, ModifyEncounter},
+        combat::{ModifyCombat, StartCombat},
+        encounters::{EncounterFilters, GenerateEncounter, InsertEncounter, ModifyEncounter},
+        subsystems::InsertSubsystemAttempt,
     },
     ServerError,
 };
@@ -22,10 +24,22 @@ pub fn router() -> Router<AppState> {
     Router::new()
         .route("/", get(get_encounters))
         .route("/", post(insert_encounter))
+        .route("/generate", post(generate_encounter))
         .route("/{id}", get(get_encounter))
         .route("/{id}", patch(edit_encounter))
         .route("/{id}", delete(delete_encounter))
         .route("/{id}/session", delete(delete_session_link))
+        .route("/{id}/combat", get(get_combat))
+        .route("/{id}/combat", post(start_combat))
+        .route("/{id}/combat", patch(edit_combat))
+        .route("/{id}/combat/next", post(next_combat_turn))
+        .route("/{id}/combat/end", post(end_combat))
+        .route("/{id}/subsystem", get(get_subsystem_progress))
+        .route("/{id}/subsystem/attempts", post(insert_subsystem_attempt))
+        .route(
+            "/{id}/subsystem/attempts/{attempt_id}",
+            delete(delete_subsystem_attempt),
+        )
 }
 
 async fn get_encounters(
@@ -35,7 +49,9 @@ async fn get_encounters(
 ) -> Result<impl IntoResponse, ServerError> {
     let user = extract_user_from_cookies(&jar, &pool).await?;
 
-    let encounters = database::encounters::get_encounters(&pool, user.id, &filters).await?;
+    let mut encounters = database::encounters::get_encounters(&pool, user.id, &filters).await?;
+    database::encounters::set_enemy_stat_blocks(&mut *pool.acquire().await?, &mut encounters)
+        .await?;
     Ok(Json(encounters))
 }
 
@@ -47,10 +63,12 @@ async fn get_encounter(
     let user = extract_user_from_cookies(&jar, &pool).await?;
 
     let filters = EncounterFilters::from_ids(&[encounter_id]);
-    let encounters = database::encounters::get_encounters(&pool, user.id, &filters).await?;
+    let mut encounters = database::encounters::get_encounters(&pool, user.id, &filters).await?;
     if encounters.is_empty() {
         return Err(ServerError::NotFound);
     }
+    database::encounters::set_enemy_stat_blocks(&mut *pool.acquire().await?, &mut encounters)
+        .await?;
 
     Ok(Json(encounters[0].clone()))
 }
@@ -64,13 +82,26 @@ async fn insert_encounter(
     let user = extract_user_from_cookies(&jar, &pool).await?;
     let mut tx = pool.begin().await?;
     let ids = database::encounters::insert_encounters(&mut tx, user.id, &encounters).await?;
-    let encounters =
+    let mut encounters =
         database::encounters::get_encounters(&mut *tx, user.id, &EncounterFilters::from_ids(&ids))
             .await?;
+    database::encounters::set_enemy_stat_blocks(&mut tx, &mut encounters).await?;
     tx.commit().await?;
     Ok(Json(encounters))
 }
 
+async fn generate_encounter(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    Json(generate): Json<GenerateEncounter>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+
+    let mut conn = pool.acquire().await?;
+    let encounter = database::encounters::generate_encounter(&mut conn, user.id, &generate).await?;
+    Ok(Json(encounter))
+}
+
 async fn edit_encounter(
     State(pool): State<PgPool>,
     jar: CookieJar,
@@ -80,7 +111,7 @@ async fn edit_encounter(
     let user = extract_user_from_cookies(&jar, &pool).await?;
 
     // Check if user has access to the encounter
-    if database::encounters::get_owned_encounter_ids(&pool, &[encounter_id], user.id)
+    if database::encounters::get_editable_encounter_ids(&pool, &[encounter_id], user.id)
         .await?
         .is_empty()
     {
@@ -101,7 +132,7 @@ async fn delete_encounter(
 ) -> Result<impl IntoResponse, ServerError> {
     let user = extract_user_from_cookies(&jar, &pool).await?;
     // Check if user has access to the encounter
-    if database::encounters::get_owned_encounter_ids(&pool, &[encounter_id], user.id)
+    if database::encounters::get_editable_encounter_ids(&pool, &[encounter_id], user.id)
         .await?
         .is_empty()
     {
@@ -123,7 +154,7 @@ async fn delete_session_link(
     let user = extract_user_from_cookies(&jar, &pool).await?;
 
     // Check if user has access to the encounter
-    if database::encounters::get_owned_encounter_ids(&pool, &[encounter_id], user.id)
+    if database::encounters::get_editable_encounter_ids(&pool, &[encounter_id], user.id)
         .await?
         .is_empty()
     {
@@ -137,3 +168,184 @@ async fn delete_session_link(
 
     Ok(StatusCode::NO_CONTENT)
 }
+
+async fn get_combat(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    Path(encounter_id): Path<InternalId>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+
+    // Check if user has access to the encounter
+    if database::encounters::get_editable_encounter_ids(&pool, &[encounter_id], user.id)
+        .await?
+        .is_empty()
+    {
+        return Err(ServerError::NotFound);
+    }
+
+    let combat = database::combat::get_combat(&pool, encounter_id)
+        .await?
+        .ok_or(ServerError::NotFound)?;
+    Ok(Json(combat))
+}
+
+async fn start_combat(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    Path(encounter_id): Path<InternalId>,
+    Json(start): Json<StartCombat>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+
+    let filters = EncounterFilters::from_ids(&[encounter_id]);
+    let encounter = database::encounters::get_encounters(&pool, user.id, &filters)
+        .await?
+        .into_iter()
+        .next()
+        .ok_or(ServerError::NotFound)?;
+
+    let mut tx = pool.begin().await?;
+    let combat = database::combat::start_combat(&mut tx, &encounter, &start).await?;
+    tx.commit().await?;
+
+    Ok(Json(combat))
+}
+
+async fn edit_combat(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    Path(encounter_id): Path<InternalId>,
+    Json(modify): Json<ModifyCombat>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+
+    let filters = EncounterFilters::from_ids(&[encounter_id]);
+    let encounter = database::encounters::get_encounters(&pool, user.id, &filters)
+        .await?
+        .into_iter()
+        .next()
+        .ok_or(ServerError::NotFound)?;
+
+    let mut tx = pool.begin().await?;
+    let mut combat = database::combat::get_combat(&mut *tx, encounter_id)
+        .await?
+        .ok_or(ServerError::NotFound)?;
+    database::combat::edit_combat(&mut tx, &encounter, &mut combat, &modify).await?;
+    tx.commit().await?;
+
+    Ok(Json(combat))
+}
+
+async fn next_combat_turn(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    Path(encounter_id): Path<InternalId>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+
+    // Check if user has access to the encounter
+    if database::encounters::get_editable_encounter_ids(&pool, &[encounter_id], user.id)
+        .await?
+        .is_empty()
+    {
+        return Err(ServerError::NotFound);
+    }
+
+    let mut tx = pool.begin().await?;
+    let mut combat = database::combat::get_combat(&mut *tx, encounter_id)
+        .await?
+        .ok_or(ServerError::NotFound)?;
+    combat.next_turn();
+    database::combat::save_combat(&mut tx, &combat).await?;
+    tx.commit().await?;
+
+    Ok(Json(combat))
+}
+
+async fn end_combat(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    Path(encounter_id): Path<InternalId>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+
+    let filters = EncounterFilters::from_ids(&[encounter_id]);
+    let encounter = database::encounters::get_encounters(&pool, user.id, &filters)
+        .await?
+        .into_iter()
+        .next()
+        .ok_or(ServerError::NotFound)?;
+
+    let mut tx = pool.begin().await?;
+    let mut combat = database::combat::get_combat(&mut *tx, encounter_id)
+        .await?
+        .ok_or(ServerError::NotFound)?;
+    database::combat::end_combat(&mut tx, &encounter, &mut combat).await?;
+    tx.commit().await?;
+
+    Ok(Json(combat))
+}
+
+async fn get_subsystem_progress(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    Path(encounter_id): Path<InternalId>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+
+    let filters = EncounterFilters::from_ids(&[encounter_id]);
+    let encounter = database::encounters::get_encounters(&pool, user.id, &filters)
+        .await?
+        .into_iter()
+        .next()
+        .ok_or(ServerError::NotFound)?;
+
+    let progress = database::subsystems::get_subsystem_progress(&pool, &encounter).await?;
+    Ok(Json(progress))
+}
+
+async fn insert_subsystem_attempt(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    Path(encounter_id): Path<InternalId>,
+    Json(attempt): Json<InsertSubsystemAttempt>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+
+    let filters = EncounterFilters::from_ids(&[encounter_id]);
+    let encounter = database::encounters::get_encounters(&pool, user.id, &filters)
+        .await?
+        .into_iter()
+        .next()
+        .ok_or(ServerError::NotFound)?;
+
+    let mut tx = pool.begin().await?;
+    database::subsystems::insert_subsystem_attempt(&mut tx, &encounter, &attempt).await?;
+    let progress = database::subsystems::get_subsystem_progress(&mut *tx, &encounter).await?;
+    tx.commit().await?;
+
+    Ok(Json(progress))
+}
+
+async fn delete_subsystem_attempt(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    Path((encounter_id, attempt_id)): Path<(InternalId, InternalId)>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+
+    // Check if user has access to the encounter
+    if database::encounters::get_editable_encounter_ids(&pool, &[encounter_id], user.id)
+        .await?
+        .is_empty()
+    {
+        return Err(ServerError::NotFound);
+    }
+
+    let mut tx = pool.begin().await?;
+    database::subsystems::delete_subsystem_attempt(&mut tx, encounter_id, attempt_id).await?;
+    tx.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
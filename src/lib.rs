@@ -5,8 +5,8 @@ use axum::{
     routing::get,
     Router,
 };
-use models::ids::InternalId;
 use reqwest::Method;
+use std::sync::Arc;
 use tower::ServiceBuilder;
 use tower_http::cors::CorsLayer;
 
@@ -15,6 +15,7 @@ pub mod campaign;
 pub mod database;
 pub mod encounters;
 pub mod library;
+pub mod mail;
 pub mod models;
 pub mod v2;
 
@@ -32,7 +33,10 @@ pub async fn run_server() {
     log::info!("Connected to database");
 
     // build our application with a route
-    let app = app(pool.clone());
+    let app = app_with_state(AppState {
+        pool: pool.clone(),
+        mailer: mail::mailer_from_env(),
+    });
 
     // run our app with hyper, listening globally on port 3000
     let bind_addr = dotenvy::var("BIND_URL").expect("BIND_URL must be set");
@@ -42,20 +46,29 @@ pub async fn run_server() {
     axum::serve(listener, app).await.unwrap();
 }
 
+// Mail is written to the log
 pub fn app(
     pool:sqlx::Pool<sqlx::Postgres>
 ) -> Router {
+    app_with_state(AppState {
+        pool,
+        mailer: Arc::new(mail::LogMailer),
+    })
+}
 
-    let app_state = AppState { pool: pool.clone() };
-
+pub fn app_with_state(app_state: AppState) -> Router {
     Router::new()
         // `GET /` goes to `root`
         .route("/", get(root))
-        .nest("/v2", v2::routes::router())
+        .nest("/v2", v2::router())
         .nest("/auth", auth::router())
         .nest("/library", library::router())
         .nest("/campaign", campaign::router())
         .nest("/encounters", encounters::router())
+        .layer(axum::middleware::from_fn_with_state(
+            app_state.clone(),
+            auth::authenticate_api_token,
+        ))
         .with_state(app_state)
         .layer(
             ServiceBuilder::new().layer(
@@ -87,6 +100,7 @@ pub fn app(
 #[derive(Clone)]
 pub struct AppState {
     pub pool: sqlx::PgPool,
+    pub mailer: Arc<dyn mail::Mailer>,
 }
 
 impl FromRef<AppState> for sqlx::PgPool {
@@ -95,6 +109,12 @@ impl FromRef<AppState> for sqlx::PgPool {
     }
 }
 
+impl FromRef<AppState> for Arc<dyn mail::Mailer> {
+    fn from_ref(state: &AppState) -> Arc<dyn mail::Mailer> {
+        state.mailer.clone()
+    }
+}
+
 // Check for required environment variables
 // Panics if any are missing
 fn check_env() {
@@ -109,11 +129,6 @@ async fn root() -> &'static str {
     "Hello, World!"
 }
 
-// TODO: users are not added yet. all users use this same id.
-pub fn dummy_test_user() -> InternalId {
-    InternalId(1)
-}
-
 pub type Result<T> = std::result::Result<T, ServerError>;
 
 #[derive(thiserror::Error, Debug)]
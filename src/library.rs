@@ -1,19 +1,27 @@
 use std::collections::HashMap;
 
 use crate::{
-    auth::extract_admin_from_headers,
+    auth::{extract_admin_from_headers, extract_user_from_cookies},
     database::{
-        classes::{ClassSearch, InsertLibraryClass},
+        classes::{ClassExpectedBoost, ClassSearch, InsertLibraryClass},
         creatures::{CreatureFiltering, CreatureSearch, InsertLibraryCreature},
+        foundry::{FoundryLibrary, ImportFoundryLibrary},
         hazards::{HazardFiltering, HazardSearch, InsertLibraryHazard},
         items::{InsertLibraryItem, ItemFiltering, ItemSearch},
+        remaster::{RemasterPair, RemasterProposalFilters},
+        search::LibrarySearch,
+        sorts::{CursorPage, Sortable, SortableColumn},
         spells::{InsertLibrarySpell, SpellFiltering, SpellSearch},
         tags::InsertTag,
-        DEFAULT_MAX_GROUP_LIMIT, DEFAULT_MAX_LIMIT,
+        HomebrewVisibility, DEFAULT_MAX_GROUP_LIMIT, DEFAULT_MAX_LIMIT,
     },
-    models::library::{
-        classes::LibraryClass, creature::LibraryCreature, hazard::LibraryHazard, item::LibraryItem,
-        spell::LibrarySpell,
+    models::{
+        campaign::CampaignRole,
+        ids::InternalId,
+        library::{
+            classes::LibraryClass, creature::LibraryCreature, hazard::LibraryHazard,
+            item::LibraryItem, spell::LibrarySpell,
+        },
     },
     AppState,
 };
@@ -21,11 +29,12 @@ use axum::{
     extract::{Path, State},
     http::{HeaderMap, StatusCode},
     response::IntoResponse,
-    routing::{get, post},
+    routing::{get, patch, post, put},
     Json, Router,
 };
 use axum_extra::extract::CookieJar;
 use axum_extra::extract::Query;
+use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 
 use crate::{
@@ -33,6 +42,63 @@ use crate::{
     ServerError,
 };
 
+#[derive(Deserialize, Default, Debug)]
+pub struct InsertOptions {
+    // Update existing objects (matched by source id, or by name, game system and legacy status) instead of
+    // inserting duplicates
+    #[serde(default)]
+    pub upsert: bool,
+}
+
+#[derive(Deserialize, Default, Debug)]
+pub struct FacetOptions {
+    // Also count the matches by the values of each filterable field, for filter sidebars
+    #[serde(default)]
+    pub facets: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct FacetedResponse<T, F> {
+    pub results: Vec<T>,
+    pub facets: F,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+// Listings requested by cursor come back with the cursor for the page after them
+fn cursor_page<T: Sortable>(
+    results: Vec<T>,
+    sort_by: &Option<String>,
+    order_by: &Option<String>,
+    limit: Option<u64>,
+) -> crate::Result<CursorPage<T>> {
+    let sort = SortableColumn::<T>::try_parse(sort_by.as_deref(), order_by.as_deref())?;
+    Ok(CursorPage::new(
+        results,
+        &sort,
+        limit.unwrap_or(DEFAULT_MAX_LIMIT),
+    ))
+}
+
+#[derive(Deserialize, Default, Debug)]
+pub struct CreatureOptions {
+    // Weak (-1) or elite (+1) adjustment, applied to the level and stat block
+    #[serde(default)]
+    pub level_adjustment: i16,
+}
+
+#[derive(Deserialize, Default, Debug)]
+pub struct SpellOptions {
+    // Rank the spell is cast at, to include the heightened entries that apply
+    pub cast_rank: Option<u8>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ShareHomebrew {
+    // Campaign the homebrew is shared with, or None to make it private again
+    pub campaign_id: Option<InternalId>,
+}
+
 pub fn router() -> Router<AppState> {
     Router::new()
         .route("/creatures", get(get_creatures))
@@ -54,14 +120,37 @@ pub fn router() -> Router<AppState> {
         .route("/classes", get(get_classes))
         .route("/classes/search", get(get_classes_search))
         .route("/classes", post(insert_classes))
+        .route(
+            "/classes/{id}/expected_boosts",
+            put(set_class_expected_boosts),
+        )
+        .route("/search", get(search_library))
         .route("/tags", post(insert_tags))
         .route("/tags", get(get_tags))
+        .route("/import/foundry", post(import_foundry))
+        .route("/remaster", post(insert_remaster_pairs))
+        .route("/remaster/proposals", get(get_remaster_proposals))
+        .route("/homebrew/creatures", post(insert_homebrew_creatures))
+        .route("/homebrew/items", post(insert_homebrew_items))
+        .route("/homebrew/spells", post(insert_homebrew_spells))
+        .route("/homebrew/hazards", post(insert_homebrew_hazards))
+        .route("/homebrew/{id}", patch(share_homebrew))
+}
+
+// Logged in users also see their own homebrew, and homebrew shared to their campaigns
+async fn homebrew_visibility(jar: &CookieJar, pool: &PgPool) -> HomebrewVisibility {
+    extract_user_from_cookies(jar, pool)
+        .await
+        .map(|user| HomebrewVisibility::User(user.id))
+        .unwrap_or_default()
 }
 
 async fn get_creatures(
-    Query(payload): Query<CreatureFiltering>,
+    Query(mut payload): Query<CreatureFiltering>,
     State(pool): State<PgPool>,
+    jar: CookieJar,
 ) -> Result<impl IntoResponse, ServerError> {
+    payload.homebrew = homebrew_visibility(&jar, &pool).await;
     if payload.limit.unwrap_or(0) > DEFAULT_MAX_LIMIT {
         return Err(ServerError::BadRequest(format!(
             "Limit exceeds maximum of {}",
@@ -71,13 +160,24 @@ async fn get_creatures(
 
     let mut conn = pool.acquire().await?;
     let creatures = database::creatures::get_creatures(&mut conn, &payload).await?;
-    Ok(Json(creatures))
+    if payload.cursor.is_some() {
+        let page = cursor_page(
+            creatures,
+            &payload.sort_by,
+            &payload.order_by,
+            payload.limit,
+        )?;
+        return Ok(Json(page).into_response());
+    }
+    Ok(Json(creatures).into_response())
 }
 
 async fn get_creatures_search(
-    Query(payload): Query<CreatureSearch>,
+    Query(mut payload): Query<CreatureSearch>,
     State(pool): State<PgPool>,
+    jar: CookieJar,
 ) -> Result<impl IntoResponse, ServerError> {
+    payload.homebrew = homebrew_visibility(&jar, &pool).await;
     if payload.limit.unwrap_or(0) > DEFAULT_MAX_GROUP_LIMIT {
         return Err(ServerError::BadRequest(format!(
             "Limit exceeds maximum of {}",
@@ -97,34 +197,53 @@ async fn get_creatures_search(
 
 async fn get_creature_id(
     State(pool): State<PgPool>,
+    jar: CookieJar,
     Path(id): Path<u32>,
+    Query(options): Query<CreatureOptions>,
 ) -> Result<impl IntoResponse, ServerError> {
+    if !(-1..=1).contains(&options.level_adjustment) {
+        return Err(ServerError::BadRequest(
+            "Level adjustment must be -1 (weak), 0 or 1 (elite)".to_string(),
+        ));
+    }
     let mut conn = pool.acquire().await?;
-    let payload = CreatureFiltering::from_id(id);
+    let mut payload = CreatureFiltering::from_id(id);
+    payload.homebrew = homebrew_visibility(&jar, &pool).await;
     let creature = database::creatures::get_creatures(&mut conn, &payload)
         .await?
         .pop()
         .ok_or(ServerError::NotFound)?;
-    Ok(Json(creature))
+    Ok(Json(
+        creature.with_level_adjustment(options.level_adjustment),
+    ))
 }
 
 async fn insert_creatures(
     State(pool): State<PgPool>,
     jar: CookieJar,
     headers: HeaderMap,
+    Query(options): Query<InsertOptions>,
     Json(payload): Json<Vec<InsertLibraryCreature>>,
 ) -> Result<impl IntoResponse, ServerError> {
     extract_admin_from_headers(&jar, &headers, &pool).await?;
     let mut tx = pool.begin().await?;
+    if options.upsert {
+        let report = database::creatures::upsert_creatures(&mut tx, &payload).await?;
+        tx.commit().await?;
+        return Ok(Json(report).into_response());
+    }
     database::creatures::insert_creatures(&mut tx, &payload).await?;
     tx.commit().await?;
-    Ok(StatusCode::NO_CONTENT)
+    Ok(StatusCode::NO_CONTENT.into_response())
 }
 
 async fn get_items(
-    Query(payload): Query<ItemFiltering>,
+    Query(mut payload): Query<ItemFiltering>,
+    Query(options): Query<FacetOptions>,
     State(pool): State<PgPool>,
+    jar: CookieJar,
 ) -> Result<impl IntoResponse, ServerError> {
+    payload.homebrew = homebrew_visibility(&jar, &pool).await;
     if payload.limit.unwrap_or(0) > DEFAULT_MAX_LIMIT {
         return Err(ServerError::BadRequest(format!(
             "Limit exceeds maximum of {}",
@@ -133,13 +252,35 @@ async fn get_items(
     }
     let mut conn = pool.acquire().await?;
     let items = database::items::get_items(&mut conn, &payload).await?;
-    Ok(Json(items))
+    if options.facets {
+        let facets = database::items::get_item_facets(&mut conn, &payload).await?;
+        let (results, next_cursor) = match payload.cursor {
+            Some(_) => {
+                let page = cursor_page(items, &payload.sort_by, &payload.order_by, payload.limit)?;
+                (page.results, page.next_cursor)
+            }
+            None => (items, None),
+        };
+        return Ok(Json(FacetedResponse {
+            results,
+            facets,
+            next_cursor,
+        })
+        .into_response());
+    }
+    if payload.cursor.is_some() {
+        let page = cursor_page(items, &payload.sort_by, &payload.order_by, payload.limit)?;
+        return Ok(Json(page).into_response());
+    }
+    Ok(Json(items).into_response())
 }
 
 async fn get_items_search(
-    Query(payload): Query<ItemSearch>,
+    Query(mut payload): Query<ItemSearch>,
     State(pool): State<PgPool>,
+    jar: CookieJar,
 ) -> Result<impl IntoResponse, ServerError> {
+    payload.homebrew = homebrew_visibility(&jar, &pool).await;
     if payload.limit.unwrap_or(0) > DEFAULT_MAX_GROUP_LIMIT {
         return Err(ServerError::BadRequest(format!(
             "Limit exceeds maximum of {}",
@@ -158,9 +299,11 @@ async fn get_items_search(
 
 async fn get_item_id(
     State(pool): State<PgPool>,
+    jar: CookieJar,
     Path(id): Path<u32>,
 ) -> Result<impl IntoResponse, ServerError> {
-    let payload = ItemFiltering::from_id(id);
+    let mut payload = ItemFiltering::from_id(id);
+    payload.homebrew = homebrew_visibility(&jar, &pool).await;
     let mut conn = pool.acquire().await?;
     let item = database::items::get_items(&mut conn, &payload)
         .await?
@@ -173,19 +316,27 @@ async fn insert_items(
     State(pool): State<PgPool>,
     jar: CookieJar,
     headers: HeaderMap,
+    Query(options): Query<InsertOptions>,
     Json(payload): Json<Vec<InsertLibraryItem>>,
 ) -> Result<impl IntoResponse, ServerError> {
     extract_admin_from_headers(&jar, &headers, &pool).await?;
     let mut tx = pool.begin().await?;
+    if options.upsert {
+        let report = database::items::upsert_items(&mut tx, &payload).await?;
+        tx.commit().await?;
+        return Ok(Json(report).into_response());
+    }
     database::items::insert_items(&mut tx, &payload).await?;
     tx.commit().await?;
-    Ok(StatusCode::NO_CONTENT)
+    Ok(StatusCode::NO_CONTENT.into_response())
 }
 
 async fn get_spells(
-    Query(payload): Query<SpellFiltering>,
+    Query(mut payload): Query<SpellFiltering>,
     State(pool): State<PgPool>,
+    jar: CookieJar,
 ) -> Result<impl IntoResponse, ServerError> {
+    payload.homebrew = homebrew_visibility(&jar, &pool).await;
     if payload.limit.unwrap_or(0) > DEFAULT_MAX_LIMIT {
         return Err(ServerError::BadRequest(format!(
             "Limit exceeds maximum of {}",
@@ -193,13 +344,19 @@ async fn get_spells(
         )));
     };
     let spells = database::spells::get_spells(&pool, &payload).await?;
-    Ok(Json(spells))
+    if payload.cursor.is_some() {
+        let page = cursor_page(spells, &payload.sort_by, &payload.order_by, payload.limit)?;
+        return Ok(Json(page).into_response());
+    }
+    Ok(Json(spells).into_response())
 }
 
 async fn get_spells_search(
-    Query(payload): Query<SpellSearch>,
+    Query(mut payload): Query<SpellSearch>,
     State(pool): State<PgPool>,
+    jar: CookieJar,
 ) -> Result<impl IntoResponse, ServerError> {
+    payload.homebrew = homebrew_visibility(&jar, &pool).await;
     if payload.limit.unwrap_or(0) > DEFAULT_MAX_GROUP_LIMIT {
         return Err(ServerError::BadRequest(format!(
             "Limit exceeds maximum of {}",
@@ -217,33 +374,51 @@ async fn get_spells_search(
 
 async fn get_spell_id(
     State(pool): State<PgPool>,
+    jar: CookieJar,
     Path(id): Path<u32>,
+    Query(options): Query<SpellOptions>,
 ) -> Result<impl IntoResponse, ServerError> {
-    let payload = SpellFiltering::from_id(id);
+    let mut payload = SpellFiltering::from_id(id);
+    payload.homebrew = homebrew_visibility(&jar, &pool).await;
     let spell = database::spells::get_spells(&pool, &payload)
         .await?
         .pop()
         .ok_or(ServerError::NotFound)?;
-    Ok(Json(spell))
+    match options.cast_rank {
+        Some(rank) if rank < spell.rank || rank > 10 => Err(ServerError::BadRequest(format!(
+            "Cast rank must be between {} and 10",
+            spell.rank
+        ))),
+        Some(rank) => Ok(Json(spell.cast_at(rank)).into_response()),
+        None => Ok(Json(spell).into_response()),
+    }
 }
 
 async fn insert_spells(
     State(pool): State<PgPool>,
     jar: CookieJar,
     headers: HeaderMap,
+    Query(options): Query<InsertOptions>,
     Json(payload): Json<Vec<InsertLibrarySpell>>,
 ) -> Result<impl IntoResponse, ServerError> {
     extract_admin_from_headers(&jar, &headers, &pool).await?;
     let mut tx = pool.begin().await?;
+    if options.upsert {
+        let report = database::spells::upsert_spells(&mut tx, &payload).await?;
+        tx.commit().await?;
+        return Ok(Json(report).into_response());
+    }
     database::spells::insert_spells(&mut tx, &payload).await?;
     tx.commit().await?;
-    Ok(StatusCode::NO_CONTENT)
+    Ok(StatusCode::NO_CONTENT.into_response())
 }
 
 async fn get_hazards(
-    Query(payload): Query<HazardFiltering>,
+    Query(mut payload): Query<HazardFiltering>,
     State(pool): State<PgPool>,
+    jar: CookieJar,
 ) -> Result<impl IntoResponse, ServerError> {
+    payload.homebrew = homebrew_visibility(&jar, &pool).await;
     if payload.limit.unwrap_or(0) > DEFAULT_MAX_LIMIT {
         return Err(ServerError::BadRequest(format!(
             "Limit exceeds maximum of {}",
@@ -252,13 +427,19 @@ async fn get_hazards(
     }
     let mut conn = pool.acquire().await?;
     let hazards = database::hazards::get_hazards(&mut conn, &payload).await?;
-    Ok(Json(hazards))
+    if payload.cursor.is_some() {
+        let page = cursor_page(hazards, &payload.sort_by, &payload.order_by, payload.limit)?;
+        return Ok(Json(page).into_response());
+    }
+    Ok(Json(hazards).into_response())
 }
 
 async fn get_hazards_search(
-    Query(payload): Query<HazardSearch>,
+    Query(mut payload): Query<HazardSearch>,
     State(pool): State<PgPool>,
+    jar: CookieJar,
 ) -> Result<impl IntoResponse, ServerError> {
+    payload.homebrew = homebrew_visibility(&jar, &pool).await;
     if payload.limit.unwrap_or(0) > DEFAULT_MAX_GROUP_LIMIT {
         return Err(ServerError::BadRequest(format!(
             "Limit exceeds maximum of {}",
@@ -277,10 +458,12 @@ async fn get_hazards_search(
 
 async fn get_hazard_id(
     State(pool): State<PgPool>,
+    jar: CookieJar,
     Path(id): Path<u32>,
 ) -> Result<impl IntoResponse, ServerError> {
     let mut conn = pool.acquire().await?;
-    let payload = HazardFiltering::from_id(id);
+    let mut payload = HazardFiltering::from_id(id);
+    payload.homebrew = homebrew_visibility(&jar, &pool).await;
     let hazard = database::hazards::get_hazards(&mut conn, &payload)
         .await?
         .pop()
@@ -292,13 +475,19 @@ async fn insert_hazards(
     State(pool): State<PgPool>,
     jar: CookieJar,
     headers: HeaderMap,
+    Query(options): Query<InsertOptions>,
     Json(payload): Json<Vec<InsertLibraryHazard>>,
 ) -> Result<impl IntoResponse, ServerError> {
     extract_admin_from_headers(&jar, &headers, &pool).await?;
     let mut tx = pool.begin().await?;
+    if options.upsert {
+        let report = database::hazards::upsert_hazards(&mut tx, &payload).await?;
+        tx.commit().await?;
+        return Ok(Json(report).into_response());
+    }
     database::hazards::insert_hazards(&mut tx, &payload).await?;
     tx.commit().await?;
-    Ok(StatusCode::NO_CONTENT)
+    Ok(StatusCode::NO_CONTENT.into_response())
 }
 
 async fn get_classes(
@@ -306,7 +495,11 @@ async fn get_classes(
     State(pool): State<PgPool>,
 ) -> Result<impl IntoResponse, ServerError> {
     let classes = database::classes::get_classes(&pool, &payload).await?;
-    Ok(Json(classes))
+    if payload.cursor.is_some() {
+        let page = cursor_page(classes, &payload.sort_by, &payload.order_by, payload.limit)?;
+        return Ok(Json(page).into_response());
+    }
+    Ok(Json(classes).into_response())
 }
 
 async fn get_classes_search(
@@ -334,12 +527,32 @@ async fn insert_classes(
     State(pool): State<PgPool>,
     jar: CookieJar,
     headers: HeaderMap,
+    Query(options): Query<InsertOptions>,
     Json(payload): Json<Vec<InsertLibraryClass>>,
 ) -> Result<impl IntoResponse, ServerError> {
     extract_admin_from_headers(&jar, &headers, &pool).await?;
     let mut tx = pool.begin().await?;
+    if options.upsert {
+        let report = database::classes::upsert_classes(&mut tx, &payload).await?;
+        tx.commit().await?;
+        return Ok(Json(report).into_response());
+    }
     database::classes::insert_classes(&mut tx, &payload).await?;
     tx.commit().await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+async fn set_class_expected_boosts(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    headers: HeaderMap,
+    Path(id): Path<InternalId>,
+    Json(boosts): Json<Vec<ClassExpectedBoost>>,
+) -> Result<impl IntoResponse, ServerError> {
+    extract_admin_from_headers(&jar, &headers, &pool).await?;
+    let mut tx = pool.begin().await?;
+    database::classes::set_class_expected_boosts(&mut tx, id, &boosts).await?;
+    tx.commit().await?;
     Ok(StatusCode::NO_CONTENT)
 }
 
@@ -356,7 +569,159 @@ async fn insert_tags(
     Ok(StatusCode::NO_CONTENT)
 }
 
+async fn import_foundry(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    headers: HeaderMap,
+    Json(payload): Json<ImportFoundryLibrary>,
+) -> Result<impl IntoResponse, ServerError> {
+    extract_admin_from_headers(&jar, &headers, &pool).await?;
+    let library = tokio::task::spawn_blocking(move || {
+        FoundryLibrary::read_directory(std::path::Path::new(&payload.path))
+    })
+    .await
+    .map_err(|e| ServerError::InternalError(e.to_string()))??;
+
+    let mut tx = pool.begin().await?;
+    let report = database::foundry::import_foundry_library(&mut tx, library).await?;
+    tx.commit().await?;
+    Ok(Json(report))
+}
+
+async fn search_library(
+    Query(mut payload): Query<LibrarySearch>,
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+) -> Result<impl IntoResponse, ServerError> {
+    payload.homebrew = homebrew_visibility(&jar, &pool).await;
+    if payload.limit.unwrap_or(0) > DEFAULT_MAX_LIMIT {
+        return Err(ServerError::BadRequest(format!(
+            "Limit exceeds maximum of {}",
+            DEFAULT_MAX_LIMIT
+        )));
+    }
+
+    let mut conn = pool.acquire().await?;
+    let response =
+        database::search::search_library(&mut conn, &payload, DEFAULT_MAX_GROUP_LIMIT).await?;
+    Ok(Json(response))
+}
+
+async fn get_remaster_proposals(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    headers: HeaderMap,
+    Query(filters): Query<RemasterProposalFilters>,
+) -> Result<impl IntoResponse, ServerError> {
+    extract_admin_from_headers(&jar, &headers, &pool).await?;
+    let proposals = database::remaster::get_remaster_proposals(&pool, &filters).await?;
+    Ok(Json(proposals))
+}
+
+async fn insert_remaster_pairs(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    headers: HeaderMap,
+    Json(pairs): Json<Vec<RemasterPair>>,
+) -> Result<impl IntoResponse, ServerError> {
+    extract_admin_from_headers(&jar, &headers, &pool).await?;
+    let mut tx = pool.begin().await?;
+    database::remaster::set_remaster_pairs(&mut tx, &pairs).await?;
+    tx.commit().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
 async fn get_tags(State(pool): State<PgPool>) -> Result<impl IntoResponse, ServerError> {
     let tags = database::tags::get_tags(&pool).await?;
     Ok(Json(tags))
 }
+
+async fn insert_homebrew_creatures(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    Json(mut payload): Json<Vec<InsertLibraryCreature>>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+    for object in payload.iter_mut() {
+        object.requested_id = None;
+        object.source_id = None;
+        object.owner = Some(user.id);
+    }
+    let mut tx = pool.begin().await?;
+    let ids = database::creatures::insert_creatures(&mut tx, &payload).await?;
+    tx.commit().await?;
+    Ok(Json(ids))
+}
+
+async fn insert_homebrew_items(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    Json(mut payload): Json<Vec<InsertLibraryItem>>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+    for object in payload.iter_mut() {
+        object.requested_id = None;
+        object.source_id = None;
+        object.owner = Some(user.id);
+    }
+    let mut tx = pool.begin().await?;
+    let ids = database::items::insert_items(&mut tx, &payload).await?;
+    tx.commit().await?;
+    Ok(Json(ids))
+}
+
+async fn insert_homebrew_spells(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    Json(mut payload): Json<Vec<InsertLibrarySpell>>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+    for object in payload.iter_mut() {
+        object.requested_id = None;
+        object.source_id = None;
+        object.owner = Some(user.id);
+    }
+    let mut tx = pool.begin().await?;
+    let ids = database::spells::insert_spells(&mut tx, &payload).await?;
+    tx.commit().await?;
+    Ok(Json(ids))
+}
+
+async fn insert_homebrew_hazards(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    Json(mut payload): Json<Vec<InsertLibraryHazard>>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+    for object in payload.iter_mut() {
+        object.requested_id = None;
+        object.source_id = None;
+        object.owner = Some(user.id);
+    }
+    let mut tx = pool.begin().await?;
+    let ids = database::hazards::insert_hazards(&mut tx, &payload).await?;
+    tx.commit().await?;
+    Ok(Json(ids))
+}
+
+async fn share_homebrew(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    Path(id): Path<InternalId>,
+    Json(payload): Json<ShareHomebrew>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+
+    // Sharing only needs membership of the campaign- players can bring their own homebrew
+    if let Some(campaign_id) = payload.campaign_id {
+        database::campaigns::get_campaign_access(&pool, campaign_id, user.id)
+            .await?
+            .ok_or(ServerError::NotFound)?
+            .require(CampaignRole::Player)?;
+    }
+
+    let mut tx = pool.begin().await?;
+    database::set_library_object_campaign(&mut tx, id, user.id, payload.campaign_id).await?;
+    tx.commit().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,65 @@
+use std::{future::Future, pin::Pin, sync::Arc};
+
+use tokio::io::AsyncWriteExt;
+
+#[derive(Debug, Clone)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+pub type MailFuture<'a> = Pin<Box<dyn Future<Output = crate::Result<()>> + Send + 'a>>;
+
+/// Delivers emails (such as password resets) to users.
+/// Implementations can send real mail, or write it somewhere local for development and tests.
+pub trait Mailer: Send + Sync {
+    fn send<'a>(&'a self, mail: &'a Mail) -> MailFuture<'a>;
+}
+
+/// Writes mail to the server log.
+pub struct LogMailer;
+
+impl Mailer for LogMailer {
+    fn send<'a>(&'a self, mail: &'a Mail) -> MailFuture<'a> {
+        Box::pin(async move {
+            log::info!("Mail to {}: {}\n{}", mail.to, mail.subject, mail.body);
+            Ok(())
+        })
+    }
+}
+
+/// Appends mail to a local file.
+pub struct FileMailer {
+    pub path: String,
+}
+
+impl Mailer for FileMailer {
+    fn send<'a>(&'a self, mail: &'a Mail) -> MailFuture<'a> {
+        Box::pin(async move {
+            let mut file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .await
+                .map_err(|e| crate::ServerError::InternalError(e.to_string()))?;
+            let text = format!(
+                "To: {}\nSubject: {}\n\n{}\n\n",
+                mail.to, mail.subject, mail.body
+            );
+            file.write_all(text.as_bytes())
+                .await
+                .map_err(|e| crate::ServerError::InternalError(e.to_string()))?;
+            Ok(())
+        })
+    }
+}
+
+// Mail is written to MAIL_FILE if set, and to the log otherwise.
+pub fn mailer_from_env() -> Arc<dyn Mailer> {
+    if let Ok(path) = dotenvy::var("MAIL_FILE") {
+        return Arc::new(FileMailer { path });
+    }
+    log::warn!("MAIL_FILE is not set, mail (such as password resets) will be written to the log");
+    Arc::new(LogMailer)
+}
@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
 use crate::database::auth::DatabaseUser;
@@ -9,7 +10,7 @@ pub struct Session {
     pub user: User,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct User {
     pub id: InternalId,
     pub username: String,
@@ -25,3 +26,55 @@ impl From<DatabaseUser> for User {
         }
     }
 }
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiTokenScope {
+    // Only GET requests
+    Read,
+    Write,
+}
+
+impl ApiTokenScope {
+    pub fn as_i16(&self) -> i16 {
+        match self {
+            ApiTokenScope::Read => 0,
+            ApiTokenScope::Write => 1,
+        }
+    }
+
+    pub fn from_i16(i: i16) -> Self {
+        match i {
+            1 => ApiTokenScope::Write,
+            _ => ApiTokenScope::Read,
+        }
+    }
+
+    pub fn allows(&self, method: &axum::http::Method) -> bool {
+        match self {
+            ApiTokenScope::Read => {
+                method == axum::http::Method::GET || method == axum::http::Method::HEAD
+            }
+            ApiTokenScope::Write => true,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ApiToken {
+    pub id: InternalId,
+    pub name: String,
+    pub scope: ApiTokenScope,
+    // First characters of the token, to recognize it by
+    pub token_prefix: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+// Returned once on creation, as only a hash of the token is stored
+#[derive(Deserialize, Serialize, Debug)]
+pub struct CreatedApiToken {
+    pub token: String,
+    #[serde(flatten)]
+    pub api_token: ApiToken,
+}
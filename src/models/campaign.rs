@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-use super::{characters::Character, events::EventLog, ids::InternalId};
+use super::{characters::Character, encounter::Encounter, events::EventLog, ids::InternalId};
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
@@ -44,6 +44,51 @@ pub struct CampaignPartial {
     pub description: Option<String>,
     pub level: u8,
     pub total_experience: u64,
+    pub absent_characters_earn_experience: bool,
+
+    // The requesting user's role in the campaign
+    pub role: CampaignRole,
+}
+
+/// Access a user has to a campaign.
+/// Variants are ordered by privilege, so roles can be compared against a required minimum.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum CampaignRole {
+    // Read-only view of sessions, and of their own character's rewards
+    Player,
+    // Full edit access
+    CoGm,
+    // Full edit access, and manages the campaign's members
+    Owner,
+}
+
+impl CampaignRole {
+    pub fn as_i16(&self) -> i16 {
+        match self {
+            CampaignRole::Owner => 0,
+            CampaignRole::CoGm => 1,
+            CampaignRole::Player => 2,
+        }
+    }
+
+    pub fn from_i16(i: i16) -> Self {
+        match i {
+            0 => CampaignRole::Owner,
+            1 => CampaignRole::CoGm,
+            _ => CampaignRole::Player,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CampaignMember {
+    pub user_id: InternalId,
+    pub username: String,
+    pub role: CampaignRole,
+    // Character played by this member, if any
+    pub character_id: Option<InternalId>,
+    pub created_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -76,3 +121,102 @@ pub struct CampaignSessionCharacterRewards {
     pub present: bool,
     pub items: Vec<InternalId>,
 }
+
+/// Player-facing view of a campaign, as shared with players.
+/// GM-only information is left out: the campaign's description, encounters that have not
+/// been linked to a session, and the contents (description, enemies) of linked encounters.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SharedCampaign {
+    pub id: InternalId,
+    pub name: String,
+    pub level: u8,
+    pub total_experience: u64,
+
+    pub party: Vec<Character>,
+    pub sessions: Vec<SharedCampaignSession>,
+}
+
+// Returned once on creation, as only a hash of the token is stored
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CampaignShareLink {
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SharedCampaignSession {
+    pub id: InternalId,
+    pub session_order: u32,
+    pub name: String,
+    // Recap of the session
+    pub description: Option<String>,
+    pub play_date: DateTime<Utc>,
+    pub encounters: Vec<SharedEncounter>,
+
+    pub experience_at_end: u64,
+    pub level_at_end: u8,
+    pub total_experience: u64,
+    pub total_combined_treasure_value: f64,
+
+    // Loot ledger
+    pub compiled_rewards: HashMap<InternalId, CampaignSessionCharacterRewards>,
+    pub unassigned_gold_rewards: f64,
+    pub unassigned_item_rewards: Vec<InternalId>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SharedEncounter {
+    pub id: InternalId,
+    pub name: String,
+    pub total_experience: i32,
+}
+
+impl SharedCampaign {
+    pub fn new(
+        campaign: CampaignPartial,
+        party: Vec<Character>,
+        sessions: Vec<CampaignSession>,
+        encounters: Vec<Encounter>,
+    ) -> Self {
+        let mut session_encounters: HashMap<InternalId, Vec<SharedEncounter>> = HashMap::new();
+        for encounter in encounters {
+            if let Some(session_id) = encounter.session_id {
+                session_encounters
+                    .entry(session_id)
+                    .or_default()
+                    .push(SharedEncounter {
+                        id: encounter.id,
+                        name: encounter.name,
+                        total_experience: encounter.total_experience,
+                    });
+            }
+        }
+
+        let sessions = sessions
+            .into_iter()
+            .map(|session| SharedCampaignSession {
+                encounters: session_encounters.remove(&session.id).unwrap_or_default(),
+                id: session.id,
+                session_order: session.session_order,
+                name: session.name,
+                description: session.description,
+                play_date: session.play_date,
+                experience_at_end: session.experience_at_end,
+                level_at_end: session.level_at_end,
+                total_experience: session.total_experience,
+                total_combined_treasure_value: session.total_combined_treasure_value,
+                compiled_rewards: session.compiled_rewards,
+                unassigned_gold_rewards: session.unassigned_gold_rewards,
+                unassigned_item_rewards: session.unassigned_item_rewards,
+            })
+            .collect();
+
+        SharedCampaign {
+            id: campaign.id,
+            name: campaign.name,
+            level: campaign.level,
+            total_experience: campaign.total_experience,
+            party,
+            sessions,
+        }
+    }
+}
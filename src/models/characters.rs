@@ -8,6 +8,109 @@ pub struct Character {
     pub name: String,
     pub player: Option<String>,
     pub class: InternalId,
+    pub ancestry: Option<String>,
+    pub heritage: Option<String>,
+    pub background: Option<String>,
+
+    // Earned from the sessions the character was present for (or all of them, if the campaign allows)
+    #[serde(default)]
+    pub experience: u64,
+    // Set on the character, or otherwise from its experience
+    #[serde(default)]
+    pub level: u8,
+
+    #[serde(default)]
+    pub ability_modifiers: AbilityModifiers,
+    #[serde(default)]
+    pub proficiencies: CharacterProficiencies,
+}
+
+impl Character {
+    /// Total modifier to checks of a skill: its key ability modifier, plus the proficiency bonus if trained.
+    pub fn skill_modifier(&self, skill: &Skill) -> i32 {
+        self.ability_modifiers.get(&skill.key_ability()) as i32
+            + self.proficiencies.skill(skill).bonus(self.level)
+    }
+
+    pub fn perception_modifier(&self) -> i32 {
+        self.ability_modifiers.wisdom as i32 + self.proficiencies.perception.bonus(self.level)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(default)]
+pub struct AbilityModifiers {
+    pub strength: i8,
+    pub dexterity: i8,
+    pub constitution: i8,
+    pub intelligence: i8,
+    pub wisdom: i8,
+    pub charisma: i8,
+}
+
+impl AbilityModifiers {
+    pub fn get(&self, stat: &Stat) -> i8 {
+        match stat {
+            Stat::Strength => self.strength,
+            Stat::Dexterity => self.dexterity,
+            Stat::Constitution => self.constitution,
+            Stat::Intelligence => self.intelligence,
+            Stat::Wisdom => self.wisdom,
+            Stat::Charisma => self.charisma,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Proficiency {
+    #[default]
+    Untrained,
+    Trained,
+    Expert,
+    Master,
+    Legendary,
+}
+
+impl Proficiency {
+    /// Proficiency bonus at a given level. Untrained adds nothing, not even the level.
+    pub fn bonus(&self, level: u8) -> i32 {
+        match self {
+            Proficiency::Untrained => 0,
+            Proficiency::Trained => level as i32 + 2,
+            Proficiency::Expert => level as i32 + 4,
+            Proficiency::Master => level as i32 + 6,
+            Proficiency::Legendary => level as i32 + 8,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct CharacterProficiencies {
+    pub perception: Proficiency,
+    pub fortitude: Proficiency,
+    pub reflex: Proficiency,
+    pub will: Proficiency,
+    // Skills the character is at least trained in. Each Lore subject is its own skill.
+    pub skills: Vec<SkillProficiency>,
+}
+
+impl CharacterProficiencies {
+    pub fn skill(&self, skill: &Skill) -> Proficiency {
+        self.skills
+            .iter()
+            .find(|s| &s.skill == skill)
+            .map(|s| s.proficiency)
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SkillProficiency {
+    #[serde(with = "skill_serialize")]
+    pub skill: Skill,
+    pub proficiency: Proficiency,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
@@ -105,6 +208,21 @@ impl Skill {
         }
     }
 
+    pub fn key_ability(&self) -> Stat {
+        match self {
+            Self::Athletics => Stat::Strength,
+            Self::Acrobatics | Self::Stealth | Self::Thievery => Stat::Dexterity,
+            Self::Arcana | Self::Crafting | Self::Lore(_) | Self::Occultism | Self::Society => {
+                Stat::Intelligence
+            }
+            Self::Medicine | Self::Nature | Self::Religion | Self::Survival => Stat::Wisdom,
+            Self::Deception | Self::Diplomacy | Self::Intimidation | Self::Performance => {
+                Stat::Charisma
+            }
+            Self::Unknown => Stat::Wisdom,
+        }
+    }
+
     pub fn from_str(s: &str) -> Option<Self> {
         match s {
             "Acrobatics" => Some(Self::Acrobatics),
@@ -241,4 +359,43 @@ mod tests {
         let lore = serde_json::from_str::<TestStruct>(lore_str).unwrap();
         assert_eq!(lore.skill, Skill::Lore(Some("Wizards".to_string())));
     }
+
+    #[test]
+    fn test_skill_modifier() {
+        use super::*;
+
+        let character: Character = serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "name": "Alden",
+            "player": null,
+            "class": 1,
+            "ancestry": "Elf",
+            "heritage": null,
+            "background": null,
+            "level": 5,
+            "ability_modifiers": {"intelligence": 4, "wisdom": 1},
+            "proficiencies": {
+                "perception": "expert",
+                "skills": [
+                    {"skill": "Arcana", "proficiency": "master"},
+                    {"skill": "Lore (Wizards)", "proficiency": "trained"}
+                ]
+            }
+        }))
+        .unwrap();
+
+        assert_eq!(character.skill_modifier(&Skill::Arcana), 4 + 5 + 6);
+        assert_eq!(
+            character.skill_modifier(&Skill::Lore(Some("Wizards".to_string()))),
+            4 + 5 + 2
+        );
+        // Untrained, and another Lore subject
+        assert_eq!(character.skill_modifier(&Skill::Nature), 1);
+        assert_eq!(
+            character.skill_modifier(&Skill::Lore(Some("Sailing".to_string()))),
+            4
+        );
+        assert_eq!(character.perception_modifier(), 1 + 5 + 4);
+        assert_eq!(character.proficiencies.will, Proficiency::Untrained);
+    }
 }
@@ -0,0 +1,188 @@
+use serde::{Deserialize, Serialize};
+
+use super::{events::EventType, ids::InternalId};
+
+/// Running state of a combat encounter: initiative order, round counter and the state of each combatant.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CombatState {
+    pub encounter_id: InternalId,
+    pub round: u32,
+    // Index into `combatants` of whose turn it is
+    pub turn: u32,
+    pub combatants: Vec<Combatant>,
+    pub ended: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Combatant {
+    pub name: String,
+    #[serde(flatten)]
+    pub combatant_type: CombatantType,
+    pub initiative: i32,
+
+    pub hp: Option<i32>,
+    pub max_hp: Option<i32>,
+    #[serde(default)]
+    pub conditions: Vec<CombatCondition>,
+    #[serde(default)]
+    pub defeated: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "combatant_type", rename_all = "camelCase")]
+pub enum CombatantType {
+    Character {
+        id: InternalId,
+    },
+    Enemy {
+        id: InternalId,
+        #[serde(default)]
+        level_adjustment: i16,
+    },
+    Hazard {
+        id: InternalId,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CombatCondition {
+    pub name: String,
+    // For valued conditions, such as frightened 2
+    pub value: Option<u8>,
+}
+
+impl CombatState {
+    pub fn new(encounter_id: InternalId, combatants: Vec<Combatant>) -> Self {
+        let mut state = CombatState {
+            encounter_id,
+            round: 1,
+            turn: 0,
+            combatants,
+            ended: false,
+        };
+        state.sort_by_initiative();
+        state
+    }
+
+    /// Sorts combatants by initiative, highest first. On a tie, enemies and hazards act before characters.
+    pub fn sort_by_initiative(&mut self) {
+        self.combatants.sort_by_key(|c| {
+            (
+                -c.initiative,
+                matches!(c.combatant_type, CombatantType::Character { .. }),
+            )
+        });
+    }
+
+    /// Moves to the next combatant that is not defeated, starting a new round when wrapping around.
+    pub fn next_turn(&mut self) {
+        let count = self.combatants.len() as u32;
+        if count == 0 || self.combatants.iter().all(|c| c.defeated) {
+            return;
+        }
+
+        loop {
+            self.turn += 1;
+            if self.turn >= count {
+                self.turn = 0;
+                self.round += 1;
+            }
+            if !self.combatants[self.turn as usize].defeated {
+                break;
+            }
+        }
+    }
+
+    /// Events for every defeated enemy and hazard, to be added to the event log when the combat ends.
+    pub fn defeated_events(&self) -> Vec<EventType> {
+        self.combatants
+            .iter()
+            .filter(|c| c.defeated)
+            .filter_map(|c| match &c.combatant_type {
+                CombatantType::Enemy {
+                    id,
+                    level_adjustment,
+                } => Some(EventType::EnemyDefeated {
+                    id: *id,
+                    level_adjustment: *level_adjustment,
+                }),
+                CombatantType::Hazard { id } => Some(EventType::HazardDefeated { id: *id }),
+                CombatantType::Character { .. } => None,
+            })
+            .collect()
+    }
+}
+
+/// Rolls initiative as a d20 plus the modifier.
+pub fn roll_initiative(rng: &mut impl rand::Rng, modifier: i32) -> i32 {
+    rng.gen_range(1..=20) + modifier
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CombatState, Combatant, CombatantType};
+    use crate::models::{events::EventType, ids::InternalId};
+
+    fn combatant(combatant_type: CombatantType, initiative: i32) -> Combatant {
+        Combatant {
+            name: String::new(),
+            combatant_type,
+            initiative,
+            hp: None,
+            max_hp: None,
+            conditions: vec![],
+            defeated: false,
+        }
+    }
+
+    #[test]
+    fn test_turn_order() {
+        let mut state = CombatState::new(
+            InternalId(1),
+            vec![
+                combatant(CombatantType::Character { id: InternalId(1) }, 15),
+                combatant(
+                    CombatantType::Enemy {
+                        id: InternalId(2),
+                        level_adjustment: 1,
+                    },
+                    15,
+                ),
+                combatant(CombatantType::Hazard { id: InternalId(3) }, 20),
+            ],
+        );
+
+        // Highest initiative first, enemies win ties
+        let order = state
+            .combatants
+            .iter()
+            .map(|c| c.combatant_type.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            order,
+            vec![
+                CombatantType::Hazard { id: InternalId(3) },
+                CombatantType::Enemy {
+                    id: InternalId(2),
+                    level_adjustment: 1
+                },
+                CombatantType::Character { id: InternalId(1) },
+            ]
+        );
+
+        // Defeated combatants are skipped, and wrapping around starts a new round
+        state.combatants[1].defeated = true;
+        state.next_turn();
+        assert_eq!((state.round, state.turn), (1, 2));
+        state.next_turn();
+        assert_eq!((state.round, state.turn), (2, 0));
+
+        assert_eq!(
+            state.defeated_events(),
+            vec![EventType::EnemyDefeated {
+                id: InternalId(2),
+                level_adjustment: 1
+            }]
+        );
+    }
+}
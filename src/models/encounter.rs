@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-use super::{characters::Skill, ids::InternalId};
+use super::{characters::Skill, ids::InternalId, library::creature::CreatureStatBlock};
 use crate::models::characters::skill_serialize;
 
 #[derive(Default, Serialize, Deserialize, Debug, Clone)]
@@ -26,6 +26,9 @@ pub struct Encounter {
     pub treasure_currency: f32,
     pub extra_experience: i32,
 
+    // Set once a running combat for this encounter has been ended
+    pub completed: bool,
+
     // Derived values
     pub total_experience: i32,
     pub total_items_value: i32,
@@ -49,9 +52,17 @@ pub enum EncounterType {
         subsystem_type: EncounterSubsystemType,
         #[serde(default)]
         subsystem_checks: Vec<EncounterSubsystemCheck>,
+        // Only used by infiltrations
+        #[serde(default = "default_awareness_thresholds")]
+        awareness_thresholds: Vec<u32>,
     },
 }
 
+// Awareness point thresholds for infiltrations, at which complications occur (GM Core default)
+pub fn default_awareness_thresholds() -> Vec<u32> {
+    vec![5, 10, 15, 20]
+}
+
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 #[serde(rename_all = "camelCase")]
 pub enum EncounterSubsystemType {
@@ -81,6 +92,10 @@ pub struct EncounterEnemy {
     pub id: InternalId,
     #[serde(default)]
     pub level_adjustment: i16,
+
+    // Stat block of the creature with the level adjustment applied. Only filled in for encounter views.
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub stat_block: Option<CreatureStatBlock>,
 }
 
 #[derive(Serialize, Deserialize, Hash, PartialEq, Eq, Debug, Clone, Copy)]
@@ -124,20 +139,23 @@ impl EncounterDifficulty {
         .into_iter()
     }
 
+    /// The experience budget of this difficulty, adjusted for the size of the party.
+    pub fn budget_for_party_size(&self, party_size: u8) -> i32 {
+        let mut budget = self.budget();
+        // Adjust the budget based on party size
+        if party_size > 4 {
+            budget += (party_size as i32 - 4) * self.extra_player_experience_delta();
+        } else if party_size < 4 {
+            budget -= (4 - party_size as i32) * self.extra_player_experience_delta();
+        }
+        budget
+    }
+
     pub fn get_severity_boundaries(party_size: u8) -> HashMap<EncounterDifficulty, (i32, i32)> {
         let difficulties = EncounterDifficulty::iter().collect::<Vec<_>>();
         let budgets = difficulties
             .iter()
-            .map(|d| {
-                let mut budget = d.budget();
-                // Adjust the budget based on party size
-                if party_size > 4 {
-                    budget += (party_size as i32 - 4) * d.extra_player_experience_delta();
-                } else if party_size < 4 {
-                    budget -= (4 - party_size as i32) * d.extra_player_experience_delta();
-                }
-                budget
-            })
+            .map(|d| d.budget_for_party_size(party_size))
             .collect::<Vec<_>>();
 
         // Budgets are halfway between the difficulties
@@ -174,6 +192,7 @@ impl EncounterType {
         hazards: Vec<InternalId>,
         subsystem: Option<EncounterSubsystemType>,
         subsystem_rolls: Vec<EncounterSubsystemCheck>,
+        awareness_thresholds: Vec<u32>,
     ) -> EncounterType {
         match i {
             1 => EncounterType::RewardInitialization,
@@ -182,6 +201,7 @@ impl EncounterType {
             4 => EncounterType::Subsystem {
                 subsystem_type: subsystem.unwrap_or_default(),
                 subsystem_checks: subsystem_rolls,
+                awareness_thresholds,
             },
             _ => EncounterType::Unknown,
         }
@@ -246,6 +266,16 @@ impl EncounterType {
             _ => Vec::new(),
         }
     }
+
+    pub fn get_awareness_thresholds(&self) -> Vec<u32> {
+        match self {
+            EncounterType::Subsystem {
+                awareness_thresholds,
+                ..
+            } => awareness_thresholds.clone(),
+            _ => default_awareness_thresholds(),
+        }
+    }
 }
 
 impl EncounterSubsystemType {
@@ -281,19 +311,8 @@ pub fn calculate_total_adjusted_experience(
         return 0;
     }
 
-    let mut total_experience: i32 = 0;
-    for level in enemy_levels {
-        total_experience += calculate_enemy_experience(*level as i8, party_level);
-    }
-    for (level, complex) in hazard_level_complexities {
-        let exp = calculate_enemy_experience(*level as i8, party_level);
-        if *complex {
-            total_experience += exp;
-        } else {
-            // Hazards are worth 1/5 of the experience of enemies if they are not complex
-            total_experience += exp / 5;
-        }
-    }
+    let total_experience =
+        calculate_raw_experience(enemy_levels, hazard_level_complexities, party_level);
 
     let difficulty =
         EncounterDifficulty::get_difficulty_from_raw_experience(total_experience, party_size);
@@ -303,6 +322,33 @@ pub fn calculate_total_adjusted_experience(
     total_experience - diff_off * difficulty.extra_player_experience_delta()
 }
 
+/// The experience of all enemies and hazards, before any adjustment for party size.
+/// This is the value compared against the boundaries from `get_severity_boundaries`.
+pub fn calculate_raw_experience(
+    enemy_levels: &[i16],
+    hazard_level_complexities: &[(i16, bool)],
+    party_level: u8,
+) -> i32 {
+    let mut total_experience: i32 = 0;
+    for level in enemy_levels {
+        total_experience += calculate_enemy_experience(*level as i8, party_level);
+    }
+    for (level, complex) in hazard_level_complexities {
+        total_experience += calculate_hazard_experience(*level as i8, *complex, party_level);
+    }
+    total_experience
+}
+
+pub fn calculate_hazard_experience(level: i8, complex: bool, party_level: u8) -> i32 {
+    let exp = calculate_enemy_experience(level, party_level);
+    if complex {
+        exp
+    } else {
+        // Hazards are worth 1/5 of the experience of enemies if they are not complex
+        exp / 5
+    }
+}
+
 pub fn calculate_enemy_experience(level: i8, party_level: u8) -> i32 {
     let level_diff = level as i32 - party_level as i32;
     match level_diff {
@@ -318,9 +364,170 @@ pub fn calculate_enemy_experience(level: i8, party_level: u8) -> i32 {
     }
 }
 
+/// A combat encounter assembled by `generate_combat_encounter`, not yet saved.
+/// It can be sent back as-is (with a name) to create the encounter.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GeneratedEncounter {
+    pub party_level: u8,
+    pub party_size: u8,
+    pub difficulty: EncounterDifficulty,
+
+    #[serde(flatten)]
+    pub encounter_type: EncounterType,
+
+    pub total_experience: i32,
+}
+
+/// A library object that can be placed into a generated combat encounter.
+#[derive(Debug, Clone)]
+pub enum EncounterCandidate {
+    Creature {
+        id: InternalId,
+        level: i16,
+    },
+    Hazard {
+        id: InternalId,
+        level: i16,
+        complex: bool,
+    },
+}
+
+impl EncounterCandidate {
+    /// Level adjustments (weak / elite) this candidate may be placed with.
+    fn level_adjustments(&self) -> &'static [i16] {
+        match self {
+            EncounterCandidate::Creature { .. } => &[-1, 0, 1],
+            EncounterCandidate::Hazard { .. } => &[0],
+        }
+    }
+
+    fn experience(&self, level_adjustment: i16, party_level: u8) -> i32 {
+        match self {
+            EncounterCandidate::Creature { level, .. } => {
+                calculate_enemy_experience((level + level_adjustment) as i8, party_level)
+            }
+            EncounterCandidate::Hazard { level, complex, .. } => {
+                calculate_hazard_experience(*level as i8, *complex, party_level)
+            }
+        }
+    }
+}
+
+const GENERATION_ATTEMPTS: usize = 50;
+
+/// Randomly assembles enemies and hazards from the candidates until the raw experience reaches the
+/// budget of the difficulty, without leaving its `get_severity_boundaries` window.
+/// Returns None if no such combination was found.
+pub fn generate_combat_encounter(
+    rng: &mut impl rand::Rng,
+    candidates: &[EncounterCandidate],
+    party_level: u8,
+    party_size: u8,
+    difficulty: EncounterDifficulty,
+    max_entities: usize,
+) -> Option<GeneratedEncounter> {
+    use rand::seq::SliceRandom;
+
+    if party_level == 0 || party_size == 0 || max_entities == 0 {
+        return None;
+    }
+
+    let (start, end) = EncounterDifficulty::get_severity_boundaries(party_size)[&difficulty];
+    let target = difficulty.budget_for_party_size(party_size).max(start);
+
+    for _ in 0..GENERATION_ATTEMPTS {
+        let mut enemies = Vec::new();
+        let mut hazards = Vec::new();
+        let mut experience = 0;
+
+        while experience < target && enemies.len() + hazards.len() < max_entities {
+            // Only consider placements that keep us inside the window
+            let options = candidates
+                .iter()
+                .filter_map(|c| {
+                    let fitting = c
+                        .level_adjustments()
+                        .iter()
+                        .copied()
+                        .filter(|adj| experience + c.experience(*adj, party_level) < end)
+                        .collect::<Vec<_>>();
+                    (!fitting.is_empty()).then_some((c, fitting))
+                })
+                .collect::<Vec<_>>();
+            let Some((candidate, fitting)) = options.choose(rng) else {
+                break;
+            };
+
+            // Prefer unadjusted creatures, but allow weak/elite variants to fill the budget
+            let level_adjustment = if fitting.contains(&0) && rng.gen_bool(0.6) {
+                0
+            } else {
+                *fitting.choose(rng)?
+            };
+
+            experience += candidate.experience(level_adjustment, party_level);
+            match candidate {
+                EncounterCandidate::Creature { id, .. } => enemies.push(EncounterEnemy {
+                    id: *id,
+                    level_adjustment,
+                    stat_block: None,
+                }),
+                EncounterCandidate::Hazard { id, .. } => hazards.push(*id),
+            }
+        }
+
+        if experience == 0 || experience < start || experience >= end {
+            continue;
+        }
+
+        let enemy_levels = enemies
+            .iter()
+            .filter_map(|e| {
+                candidates.iter().find_map(|c| match c {
+                    EncounterCandidate::Creature { id, level } if *id == e.id => {
+                        Some(level + e.level_adjustment)
+                    }
+                    _ => None,
+                })
+            })
+            .collect::<Vec<_>>();
+        let hazard_level_complexities = hazards
+            .iter()
+            .filter_map(|h| {
+                candidates.iter().find_map(|c| match c {
+                    EncounterCandidate::Hazard { id, level, complex } if id == h => {
+                        Some((*level, *complex))
+                    }
+                    _ => None,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        return Some(GeneratedEncounter {
+            party_level,
+            party_size,
+            difficulty,
+            total_experience: calculate_total_adjusted_experience(
+                &enemy_levels,
+                &hazard_level_complexities,
+                party_level,
+                party_size,
+            ),
+            encounter_type: EncounterType::Combat { enemies, hazards },
+        });
+    }
+
+    None
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{calculate_total_adjusted_experience, EncounterDifficulty};
+    use super::{
+        calculate_raw_experience, calculate_total_adjusted_experience, generate_combat_encounter,
+        EncounterCandidate, EncounterDifficulty, EncounterType,
+    };
+    use crate::models::ids::InternalId;
+    use rand::SeedableRng;
 
     #[test]
     fn test_experience_calculation() {
@@ -504,4 +711,70 @@ mod tests {
             Some(&(105, i32::MAX))
         );
     }
+
+    #[test]
+    fn test_generated_encounters_fit_difficulty() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let candidates = (-1..=9)
+            .map(|level| EncounterCandidate::Creature {
+                id: InternalId((level + 10) as u32),
+                level,
+            })
+            .chain(std::iter::once(EncounterCandidate::Hazard {
+                id: InternalId(1),
+                level: 5,
+                complex: true,
+            }))
+            .collect::<Vec<_>>();
+        let level_of = |id: InternalId| {
+            candidates
+                .iter()
+                .find_map(|c| match c {
+                    EncounterCandidate::Creature { id: i, level } if *i == id => Some(*level),
+                    EncounterCandidate::Hazard { id: i, level, .. } if *i == id => Some(*level),
+                    _ => None,
+                })
+                .unwrap()
+        };
+
+        for party_size in 2..=6 {
+            let boundaries = EncounterDifficulty::get_severity_boundaries(party_size);
+            for difficulty in EncounterDifficulty::iter() {
+                let generated =
+                    generate_combat_encounter(&mut rng, &candidates, 5, party_size, difficulty, 8)
+                        .unwrap();
+                let EncounterType::Combat { enemies, hazards } = &generated.encounter_type else {
+                    panic!("Generated encounter is not a combat encounter");
+                };
+                assert!(!enemies.is_empty() || !hazards.is_empty());
+                assert!(enemies.len() + hazards.len() <= 8);
+
+                let enemy_levels = enemies
+                    .iter()
+                    .map(|e| level_of(e.id) + e.level_adjustment)
+                    .collect::<Vec<_>>();
+                let hazard_levels = hazards
+                    .iter()
+                    .map(|h| (level_of(*h), true))
+                    .collect::<Vec<_>>();
+                let (start, end) = boundaries[&difficulty];
+                let raw = calculate_raw_experience(&enemy_levels, &hazard_levels, 5);
+                assert!(raw >= start && raw < end);
+                assert_eq!(
+                    generated.total_experience,
+                    calculate_total_adjusted_experience(
+                        &enemy_levels,
+                        &hazard_levels,
+                        5,
+                        party_size
+                    )
+                );
+            }
+        }
+
+        // No candidates can fit an encounter
+        assert!(
+            generate_combat_encounter(&mut rng, &[], 5, 4, EncounterDifficulty::Low, 8).is_none()
+        );
+    }
 }
@@ -21,6 +21,164 @@ pub struct LibraryCreature {
 
     pub url: Option<String>,
     pub description: String,
+
+    pub stat_block: Option<CreatureStatBlock>,
+}
+
+impl LibraryCreature {
+    /// Applies the weak (negative) or elite (positive) adjustment of an encounter enemy to its level and stat block.
+    pub fn with_level_adjustment(mut self, level_adjustment: i16) -> Self {
+        if let Some(stat_block) = self.stat_block.as_mut() {
+            stat_block.apply_level_adjustment(self.level, level_adjustment);
+        }
+        self.level = (self.level as i16)
+            .saturating_add(level_adjustment)
+            .clamp(i8::MIN as i16, i8::MAX as i16) as i8;
+        self
+    }
+}
+
+/// Structured stat block of a creature, as printed in its bestiary entry.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct CreatureStatBlock {
+    pub ac: i32,
+    pub hp: i32,
+    pub perception: i32,
+    pub saves: CreatureSaves,
+    #[serde(default)]
+    pub skills: Vec<CreatureSkill>,
+    #[serde(default)]
+    pub speeds: Vec<CreatureSpeed>,
+
+    #[serde(default)]
+    pub immunities: Vec<String>,
+    #[serde(default)]
+    pub resistances: Vec<DamageAdjustment>,
+    #[serde(default)]
+    pub weaknesses: Vec<DamageAdjustment>,
+
+    #[serde(default)]
+    pub strikes: Vec<CreatureStrike>,
+    #[serde(default)]
+    pub abilities: Vec<CreatureAbility>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct CreatureSaves {
+    pub fortitude: i32,
+    pub reflex: i32,
+    pub will: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CreatureSkill {
+    pub name: String,
+    pub modifier: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CreatureSpeed {
+    // land, fly, swim, etc
+    pub movement: String,
+    pub feet: i32,
+}
+
+// A resistance or weakness to a damage type
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct DamageAdjustment {
+    pub damage_type: String,
+    pub value: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CreatureStrike {
+    pub name: String,
+    #[serde(default)]
+    pub ranged: bool,
+    pub attack: i32,
+    #[serde(default)]
+    pub traits: Vec<String>,
+    pub damage: Vec<StrikeDamage>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct StrikeDamage {
+    // Dice and flat modifier, ie: '2d8+4'
+    pub roll: String,
+    pub damage_type: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CreatureAbility {
+    pub name: String,
+    // One, two, three, reaction, free- or None for passive abilities
+    pub actions: Option<String>,
+    #[serde(default)]
+    pub traits: Vec<String>,
+    pub description: String,
+}
+
+impl CreatureStatBlock {
+    /// Applies the elite (+1 per step) or weak (-1 per step) adjustments from the GM Core
+    /// to a creature of the given level.
+    pub fn apply_level_adjustment(&mut self, level: i8, level_adjustment: i16) {
+        let mut level = level as i16;
+        for _ in 0..level_adjustment.unsigned_abs() {
+            let (modifier, hp) = if level_adjustment > 0 {
+                let hp = match level {
+                    ..=1 => 10,
+                    2..=4 => 15,
+                    5..=19 => 20,
+                    _ => 30,
+                };
+                level += 1;
+                (2, hp)
+            } else {
+                let hp = match level {
+                    ..=2 => 10,
+                    3..=5 => 15,
+                    6..=20 => 20,
+                    _ => 30,
+                };
+                level -= 1;
+                (-2, -hp)
+            };
+
+            self.ac += modifier;
+            self.hp = (self.hp + hp).max(1);
+            self.perception += modifier;
+            self.saves.fortitude += modifier;
+            self.saves.reflex += modifier;
+            self.saves.will += modifier;
+            for skill in &mut self.skills {
+                skill.modifier += modifier;
+            }
+            for strike in &mut self.strikes {
+                strike.attack += modifier;
+                // Only the first damage roll of a strike gets the damage adjustment
+                if let Some(damage) = strike.damage.first_mut() {
+                    damage.roll = adjust_roll(&damage.roll, modifier);
+                }
+            }
+        }
+    }
+}
+
+// Adds a flat modifier to a damage roll, ie: '2d8+4' + 2 = '2d8+6'
+fn adjust_roll(roll: &str, modifier: i32) -> String {
+    let roll = roll.replace(' ', "");
+    let (dice, flat) = match roll.rfind(['+', '-']) {
+        Some(i) if i > 0 => match roll[i..].trim_start_matches('+').parse::<i32>() {
+            Ok(flat) => (&roll[..i], flat),
+            Err(_) => (roll.as_str(), 0),
+        },
+        _ => (roll.as_str(), 0),
+    };
+    match flat + modifier {
+        0 => dice.to_string(),
+        flat if flat > 0 => format!("{dice}+{flat}"),
+        flat => format!("{dice}{flat}"),
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
@@ -219,3 +377,66 @@ impl Size {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level_adjustment() {
+        let stat_block = CreatureStatBlock {
+            ac: 18,
+            hp: 50,
+            perception: 9,
+            saves: CreatureSaves {
+                fortitude: 10,
+                reflex: 8,
+                will: 6,
+            },
+            skills: vec![CreatureSkill {
+                name: "Athletics".to_string(),
+                modifier: 11,
+            }],
+            strikes: vec![CreatureStrike {
+                name: "Jaws".to_string(),
+                ranged: false,
+                attack: 12,
+                traits: vec![],
+                damage: vec![
+                    StrikeDamage {
+                        roll: "2d8+4".to_string(),
+                        damage_type: "piercing".to_string(),
+                    },
+                    StrikeDamage {
+                        roll: "1d6".to_string(),
+                        damage_type: "fire".to_string(),
+                    },
+                ],
+            }],
+            ..Default::default()
+        };
+
+        let mut elite = stat_block.clone();
+        elite.apply_level_adjustment(4, 1);
+        assert_eq!(elite.ac, 20);
+        assert_eq!(elite.hp, 65);
+        assert_eq!(elite.saves.will, 8);
+        assert_eq!(elite.skills[0].modifier, 13);
+        assert_eq!(elite.strikes[0].attack, 14);
+        assert_eq!(elite.strikes[0].damage[0].roll, "2d8+6");
+        assert_eq!(elite.strikes[0].damage[1].roll, "1d6");
+
+        let mut weak = stat_block.clone();
+        weak.apply_level_adjustment(4, -1);
+        assert_eq!(weak.perception, 7);
+        assert_eq!(weak.hp, 35);
+        assert_eq!(weak.strikes[0].damage[0].roll, "2d8+2");
+
+        // Adjustments stack, using the level of each step
+        let mut elite = stat_block;
+        elite.apply_level_adjustment(4, 2);
+        assert_eq!(elite.hp, 85);
+        assert_eq!(adjust_roll("1d4", -2), "1d4-2");
+        assert_eq!(adjust_roll("1d4-2", 2), "1d4");
+    }
+}
@@ -12,6 +12,7 @@ pub struct LibraryHazard {
     pub level: i8,
     pub tags: Vec<String>,
     pub legacy: bool,
+    pub remastering_alt_id: Option<InternalId>,
 
     pub url: Option<String>,
     pub description: String,
@@ -31,6 +31,7 @@ pub struct LibraryItem {
 
     // TODO: May need to be combined with GameSystem, as its part of the game system
     pub legacy: bool,
+    pub remastering_alt_id: Option<InternalId>,
 
     pub item_type: RuneItemType,
     pub skill_boosts: Vec<SkillPotency>,
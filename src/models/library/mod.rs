@@ -14,6 +14,34 @@ pub enum LibraryObjectType {
     Item,
     Hazard,
     Spell,
+    Class,
+}
+
+impl LibraryObjectType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LibraryObjectType::Creature => "creature",
+            LibraryObjectType::Item => "item",
+            LibraryObjectType::Hazard => "hazard",
+            LibraryObjectType::Spell => "spell",
+            LibraryObjectType::Class => "class",
+        }
+    }
+}
+
+impl FromStr for LibraryObjectType {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "creature" => Ok(LibraryObjectType::Creature),
+            "item" => Ok(LibraryObjectType::Item),
+            "hazard" => Ok(LibraryObjectType::Hazard),
+            "spell" => Ok(LibraryObjectType::Spell),
+            "class" => Ok(LibraryObjectType::Class),
+            _ => Err(()),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
@@ -66,7 +94,7 @@ impl GameSystem {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum Rarity {
     #[default]
@@ -1,3 +1,5 @@
+use std::str::FromStr;
+
 use super::{GameSystem, Rarity};
 use crate::models::ids::InternalId;
 use serde::{Deserialize, Serialize};
@@ -11,10 +13,157 @@ pub struct LibrarySpell {
     pub rank: u8,
     pub tags: Vec<String>,
     pub legacy: bool,
+    pub remastering_alt_id: Option<InternalId>,
     pub traits: Vec<String>,
 
     pub traditions: Vec<String>,
+    // Time to cast, as written (eg: "2", "reaction", "1 to 3", "10 minutes")
+    pub actions: Option<String>,
+    pub saving_throw: Option<SavingThrow>,
+    pub basic_save: bool,
+    pub heightening: Vec<SpellHeightening>,
 
     pub url: Option<String>,
     pub description: String,
 }
+
+impl LibrarySpell {
+    /// Heightened entries that apply when cast at a given rank, with the number of times each applies.
+    /// Interval entries stack for every interval above the spell's rank, while only the highest
+    /// fixed entry reached applies (they restate the lower ones).
+    pub fn heightening_at(&self, rank: u8) -> Vec<(&SpellHeightening, u8)> {
+        let above = rank.saturating_sub(self.rank);
+        let fixed = self
+            .heightening
+            .iter()
+            .filter(|h| matches!(h, SpellHeightening::Fixed { rank: r, .. } if *r <= rank))
+            .max_by_key(|h| match h {
+                SpellHeightening::Fixed { rank, .. } => *rank,
+                SpellHeightening::Interval { .. } => 0,
+            });
+        self.heightening
+            .iter()
+            .filter_map(|h| match h {
+                SpellHeightening::Interval { interval, .. } if *interval > 0 => {
+                    Some((h, above / interval)).filter(|(_, times)| *times > 0)
+                }
+                SpellHeightening::Interval { .. } => None,
+                SpellHeightening::Fixed { .. } => fixed.filter(|f| *f == h).map(|f| (f, 1)),
+            })
+            .collect()
+    }
+
+    /// The spell as cast at a given rank, with the heightened entries that apply.
+    pub fn cast_at(self, rank: u8) -> SpellCast {
+        let heightened = self
+            .heightening_at(rank)
+            .into_iter()
+            .map(|(heightening, times)| AppliedHeightening {
+                heightening: heightening.clone(),
+                times,
+            })
+            .collect();
+        SpellCast {
+            spell: self,
+            cast_rank: rank,
+            heightened,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct SpellCast {
+    #[serde(flatten)]
+    pub spell: LibrarySpell,
+    pub cast_rank: u8,
+    pub heightened: Vec<AppliedHeightening>,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct AppliedHeightening {
+    #[serde(flatten)]
+    pub heightening: SpellHeightening,
+    // Number of times the entry applies (interval entries stack)
+    pub times: u8,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SavingThrow {
+    Fortitude,
+    Reflex,
+    Will,
+}
+
+impl SavingThrow {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SavingThrow::Fortitude => "fortitude",
+            SavingThrow::Reflex => "reflex",
+            SavingThrow::Will => "will",
+        }
+    }
+}
+
+impl FromStr for SavingThrow {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fortitude" => Ok(SavingThrow::Fortitude),
+            "reflex" => Ok(SavingThrow::Reflex),
+            "will" => Ok(SavingThrow::Will),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A 'Heightened' entry of a spell, for how it changes when cast using a higher rank spell slot.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SpellHeightening {
+    // 'Heightened (+2)': applies again for every `interval` ranks above the spell's rank
+    Interval { interval: u8, description: String },
+    // 'Heightened (5th)': applies when cast at `rank` or higher
+    Fixed { rank: u8, description: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heightening_at() {
+        let fixed = |rank: u8| SpellHeightening::Fixed {
+            rank,
+            description: format!("Rank {}", rank),
+        };
+        let interval = SpellHeightening::Interval {
+            interval: 2,
+            description: "More damage".to_string(),
+        };
+        let spell = LibrarySpell {
+            id: InternalId(1),
+            name: "Fireball".to_string(),
+            game_system: GameSystem::PF2E,
+            rarity: Rarity::Common,
+            rank: 3,
+            tags: vec![],
+            legacy: false,
+            remastering_alt_id: None,
+            traits: vec![],
+            traditions: vec!["Arcane".to_string()],
+            actions: Some("2".to_string()),
+            saving_throw: Some(SavingThrow::Reflex),
+            basic_save: true,
+            heightening: vec![interval.clone(), fixed(5), fixed(7)],
+            url: None,
+            description: String::new(),
+        };
+
+        assert!(spell.heightening_at(3).is_empty());
+        assert!(spell.heightening_at(4).is_empty());
+        assert_eq!(spell.heightening_at(5), [(&interval, 1), (&fixed(5), 1)]);
+        assert_eq!(spell.heightening_at(8), [(&interval, 2), (&fixed(7), 1)]);
+    }
+}
@@ -1,6 +1,7 @@
 pub mod auth;
 pub mod campaign;
 pub mod characters;
+pub mod combat;
 pub mod encounter;
 pub mod events;
 pub mod ids;
@@ -8,3 +9,4 @@ pub mod library;
 pub mod log;
 pub mod query;
 pub mod stats;
+pub mod subsystem;
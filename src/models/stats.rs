@@ -40,17 +40,34 @@ pub struct CampaignStats {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct CharacterStats {
+    // Earned from the sessions the character took part in, per the campaign's setting
+    pub total_experience: u64,
+    pub level: u8,
+    pub experience_this_level: u64,
+
     pub total_combined_treasure: f64,
     pub total_treasure_items_value: f64,
     pub total_gold: f64,
+    // Consumables used up still count as received, but are no longer part of what the character holds
+    pub spent_consumables_value: f64,
+    pub held_combined_treasure: f64,
+
+    // An even share of the party's expected treasure so far, as the expected treasure tables are by party.
+    // Not adjusted for the character's class, level or absences.
+    pub expected_combined_treasure: f64,
 
+    // Expected for the character's class, by the campaign's current level
     pub available_boosts: Vec<AssignedBoost>,
-    pub expected_boosts: Vec<AssignedBoost>,
+    pub expected_boosts: Vec<ExpectedBoost>,
+    pub expected_items: Vec<ExpectedClassItem>,
+    pub deficits: Vec<TreasureDeficit>,
 
     pub rewards_per_session: Vec<AssignedRewardsSession>,
 
     pub total_permanent_items: Vec<InternalId>,
+    // Received, whether used up or not
     pub total_consumable_items: Vec<InternalId>,
+    pub held_consumable_items: Vec<InternalId>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -60,6 +77,37 @@ pub struct AssignedBoost {
     pub potency: u32,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ExpectedBoost {
+    // Level the boost is expected from
+    pub level: u32,
+    pub boost_category_id: u32,
+    pub boost_category_name: String,
+    pub potency: u32,
+}
+
+/// A group of items a class particularly wants by a level: any of the items, or any item with one of the traits.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ExpectedClassItem {
+    pub level: u32,
+    pub importance: f64, // 0-1
+    pub item_ids: Vec<InternalId>,
+    pub item_names: Vec<String>,
+    pub traits: Vec<String>,
+    pub owned: bool,
+}
+
+/// Something a character is expected to have by now, but doesn't.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TreasureDeficit {
+    pub level: u32,
+    pub boost_category_id: Option<u32>,
+    pub potency: Option<u32>,
+    pub item_ids: Vec<InternalId>,
+    // eg: "Fighter is missing a striking rune at level 4"
+    pub description: String,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct AssignedRewardsSession {
     pub session_id: InternalId,
@@ -81,3 +129,214 @@ pub struct EncounterStats {
     pub calculated_expected_total_treasure: f32,
     pub pf_expected_total_treasure: f32,
 }
+
+/// Concrete loot proposed to bring a campaign up to the expected treasure of a level.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct TreasureParcel {
+    pub level: u32,
+    pub currency: f64,
+
+    pub permanent_items: Vec<InternalId>,
+    pub consumable_items: Vec<InternalId>,
+    pub total_items_value: f64,
+
+    // Set if the parcel was attached to an encounter as its treasure
+    pub encounter_id: Option<InternalId>,
+}
+
+/// Lists the level of every expected item that has not been given yet, one entry per item, highest level first.
+pub fn missing_items_by_level(
+    expected_by_level: &HashMap<u32, u32>,
+    given_by_level: &HashMap<u32, u32>,
+) -> Vec<u32> {
+    let mut missing = expected_by_level
+        .iter()
+        .flat_map(|(level, expected)| {
+            let given = given_by_level.get(level).copied().unwrap_or_default();
+            std::iter::repeat(*level).take(expected.saturating_sub(given) as usize)
+        })
+        .collect::<Vec<_>>();
+    missing.sort_unstable_by(|a, b| b.cmp(a));
+    missing
+}
+
+// Boost categories, as in stat_boost_category_types
+const DEVASTATING_ATTACKS: u32 = 3;
+
+// What gives a boost of a category and potency (expected devastating attacks are in damage dice)
+fn boost_item_name(boost_category_id: u32, potency: u32) -> String {
+    let grade = |potency: u32| match potency {
+        1 => "",
+        2 => "greater ",
+        _ => "major ",
+    };
+    match boost_category_id {
+        1 => format!("+{} weapon potency rune", potency),
+        2 => format!("+{} armor potency rune", potency),
+        DEVASTATING_ATTACKS => format!("{}striking rune", grade(potency.saturating_sub(1))),
+        4 => format!("+{} skill item", potency),
+        5 => format!("{}resilient rune", grade(potency)),
+        6 => format!("+{} perception item", potency),
+        7 => "attribute apex item".to_string(),
+        _ => format!("+{} item", potency),
+    }
+}
+
+/// Lists the boosts and class items a character is expected to have but doesn't.
+/// Each boost category's expected potencies are those of the latest level reached (later levels replace
+/// earlier ones), matched against the best runes and items the character has of that category.
+pub fn treasure_deficits(
+    class_name: &str,
+    expected_boosts: &[ExpectedBoost],
+    available_boosts: &[AssignedBoost],
+    expected_items: &[ExpectedClassItem],
+) -> Vec<TreasureDeficit> {
+    let mut deficits = Vec::new();
+
+    let mut latest_levels = HashMap::<u32, u32>::new();
+    for boost in expected_boosts {
+        let latest = latest_levels.entry(boost.boost_category_id).or_default();
+        *latest = (*latest).max(boost.level);
+    }
+    let mut categories = latest_levels.into_iter().collect::<Vec<_>>();
+    categories.sort_unstable();
+    for (category, level) in categories {
+        let mut expected = expected_boosts
+            .iter()
+            .filter(|b| b.boost_category_id == category && b.level == level && b.potency > 0)
+            .map(|b| b.potency)
+            .collect::<Vec<_>>();
+        expected.sort_unstable_by(|a, b| b.cmp(a));
+        let mut available = available_boosts
+            .iter()
+            .filter(|b| b.boost_category_id == category)
+            // Striking runes' potency is one less than their number of damage dice
+            .map(|b| match category {
+                DEVASTATING_ATTACKS => b.potency + 1,
+                _ => b.potency,
+            })
+            .collect::<Vec<_>>();
+        available.sort_unstable_by(|a, b| b.cmp(a));
+
+        for (ix, potency) in expected.into_iter().enumerate() {
+            if available.get(ix).is_some_and(|a| *a >= potency) {
+                continue;
+            }
+            let name = boost_item_name(category, potency);
+            let article = match name.starts_with(['a', 'e', 'i', 'o', 'u']) {
+                true => "an",
+                false => "a",
+            };
+            deficits.push(TreasureDeficit {
+                level,
+                boost_category_id: Some(category),
+                potency: Some(potency),
+                item_ids: vec![],
+                description: format!(
+                    "{} is missing {} {} at level {}",
+                    class_name, article, name, level
+                ),
+            });
+        }
+    }
+
+    for item in expected_items.iter().filter(|i| !i.owned) {
+        let wanted = item
+            .item_names
+            .iter()
+            .cloned()
+            .chain(item.traits.iter().map(|t| format!("a {} item", t)))
+            .collect::<Vec<_>>();
+        deficits.push(TreasureDeficit {
+            level: item.level,
+            boost_category_id: None,
+            potency: None,
+            item_ids: item.item_ids.clone(),
+            description: format!(
+                "{} is missing {} at level {}",
+                class_name,
+                wanted.join(" or "),
+                item.level
+            ),
+        });
+    }
+    deficits
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_missing_items_by_level() {
+        let expected = HashMap::from([(1, 2), (2, 4), (3, 2)]);
+        let given = HashMap::from([(1, 3), (2, 1), (4, 1)]);
+        assert_eq!(
+            missing_items_by_level(&expected, &given),
+            vec![3, 3, 2, 2, 2]
+        );
+
+        assert!(missing_items_by_level(&expected, &expected).is_empty());
+        assert!(missing_items_by_level(&HashMap::new(), &given).is_empty());
+    }
+
+    #[test]
+    fn test_treasure_deficits() {
+        let expected = |level, boost_category_id, potency| ExpectedBoost {
+            level,
+            boost_category_id,
+            boost_category_name: String::new(),
+            potency,
+        };
+        let available = |boost_category_id, potency| AssignedBoost {
+            boost_category_id,
+            boost_category_name: String::new(),
+            potency,
+        };
+        let expected_boosts = [
+            expected(2, 1, 1),
+            expected(4, 3, 2),
+            expected(3, 4, 1),
+            expected(6, 4, 1),
+            expected(6, 4, 1),
+        ];
+        let items = [ExpectedClassItem {
+            level: 5,
+            importance: 1.0,
+            item_ids: vec![InternalId(9)],
+            item_names: vec!["Spellbook".to_string()],
+            traits: vec![],
+            owned: false,
+        }];
+
+        let deficits = treasure_deficits(
+            "Fighter",
+            &expected_boosts,
+            &[available(1, 1), available(4, 2)],
+            &items,
+        );
+        let descriptions = deficits
+            .iter()
+            .map(|d| d.description.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            descriptions,
+            [
+                "Fighter is missing a striking rune at level 4",
+                "Fighter is missing a +1 skill item at level 6",
+                "Fighter is missing Spellbook at level 5",
+            ]
+        );
+
+        // A striking rune (potency 1) gives two dice
+        let deficits = treasure_deficits(
+            "Fighter",
+            &expected_boosts[..2],
+            &[available(1, 1), available(3, 1)],
+            &[],
+        );
+        assert!(deficits.is_empty());
+    }
+}
@@ -0,0 +1,272 @@
+use serde::{Deserialize, Serialize};
+
+use super::{
+    characters::{skill_serialize, Skill},
+    encounter::{EncounterSubsystemCheck, EncounterSubsystemType},
+    ids::InternalId,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum DegreeOfSuccess {
+    CriticalFailure,
+    Failure,
+    Success,
+    CriticalSuccess,
+}
+
+impl DegreeOfSuccess {
+    pub fn as_i16(&self) -> i16 {
+        match self {
+            DegreeOfSuccess::CriticalFailure => 0,
+            DegreeOfSuccess::Failure => 1,
+            DegreeOfSuccess::Success => 2,
+            DegreeOfSuccess::CriticalSuccess => 3,
+        }
+    }
+
+    pub fn from_i16(i: i16) -> crate::Result<Self> {
+        match i {
+            0 => Ok(DegreeOfSuccess::CriticalFailure),
+            1 => Ok(DegreeOfSuccess::Failure),
+            2 => Ok(DegreeOfSuccess::Success),
+            3 => Ok(DegreeOfSuccess::CriticalSuccess),
+            _ => Err(crate::ServerError::InternalError(format!(
+                "Unknown degree of success {}",
+                i
+            ))),
+        }
+    }
+}
+
+/// A single check attempted against one of the checks (obstacles) of a subsystem encounter.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SubsystemAttempt {
+    pub id: InternalId,
+    pub check_index: u32,
+    pub character_id: Option<InternalId>,
+    #[serde(with = "skill_serialize")]
+    pub skill: Skill,
+    pub degree_of_success: DegreeOfSuccess,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SubsystemProgress {
+    pub subsystem_type: EncounterSubsystemType,
+    pub checks: Vec<SubsystemCheckProgress>,
+
+    // Points accumulated over all checks
+    pub total_points: i32,
+    // Only used by infiltrations
+    pub awareness_points: u32,
+    pub awareness_thresholds_reached: Vec<u32>,
+
+    // Whether every check has reached its VP
+    pub completed: bool,
+    pub attempts: Vec<SubsystemAttempt>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SubsystemCheckProgress {
+    pub name: String,
+    pub vp: u8,
+    pub points: i32,
+    pub reached: bool,
+}
+
+impl EncounterSubsystemType {
+    /// Points gained (or lost) for a degree of success.
+    /// Infiltrations do not lose points on a critical failure, but gain awareness instead.
+    pub fn points_for(&self, degree: DegreeOfSuccess) -> i32 {
+        match (self, degree) {
+            (_, DegreeOfSuccess::CriticalSuccess) => 2,
+            (_, DegreeOfSuccess::Success) => 1,
+            (_, DegreeOfSuccess::Failure) => 0,
+            (EncounterSubsystemType::Infiltration, DegreeOfSuccess::CriticalFailure) => 0,
+            (_, DegreeOfSuccess::CriticalFailure) => -1,
+        }
+    }
+
+    pub fn awareness_for(&self, degree: DegreeOfSuccess) -> u32 {
+        match (self, degree) {
+            (EncounterSubsystemType::Infiltration, DegreeOfSuccess::CriticalFailure) => 1,
+            _ => 0,
+        }
+    }
+
+    /// Research pools its points over every check, each check's VP being a threshold of the pool.
+    /// Other subsystems treat each check as an obstacle with its own points.
+    pub fn pools_points(&self) -> bool {
+        matches!(self, EncounterSubsystemType::Research)
+    }
+}
+
+/// Accumulates the attempts made into the progress of each check of a subsystem.
+pub fn calculate_subsystem_progress(
+    subsystem_type: EncounterSubsystemType,
+    checks: &[EncounterSubsystemCheck],
+    awareness_thresholds: &[u32],
+    attempts: Vec<SubsystemAttempt>,
+) -> SubsystemProgress {
+    let mut check_points = vec![0; checks.len()];
+    let mut total_points: i32 = 0;
+    let mut awareness_points = 0;
+    for attempt in &attempts {
+        let points = subsystem_type.points_for(attempt.degree_of_success);
+        // Points never drop below zero
+        total_points = (total_points + points).max(0);
+        if let Some(p) = check_points.get_mut(attempt.check_index as usize) {
+            *p = (*p + points).max(0);
+        }
+        awareness_points += subsystem_type.awareness_for(attempt.degree_of_success);
+    }
+
+    let checks = checks
+        .iter()
+        .zip(check_points)
+        .map(|(check, points)| {
+            let points = if subsystem_type.pools_points() {
+                total_points
+            } else {
+                points
+            };
+            SubsystemCheckProgress {
+                name: check.name.clone(),
+                vp: check.vp,
+                points,
+                reached: points >= check.vp as i32,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let awareness_thresholds_reached = awareness_thresholds
+        .iter()
+        .copied()
+        .filter(|t| {
+            matches!(subsystem_type, EncounterSubsystemType::Infiltration) && awareness_points >= *t
+        })
+        .collect();
+
+    SubsystemProgress {
+        completed: !checks.is_empty() && checks.iter().all(|c| c.reached),
+        subsystem_type,
+        checks,
+        total_points,
+        awareness_points,
+        awareness_thresholds_reached,
+        attempts,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{calculate_subsystem_progress, DegreeOfSuccess, SubsystemAttempt};
+    use crate::models::{
+        characters::Skill,
+        encounter::{
+            default_awareness_thresholds, EncounterSubsystemCheck, EncounterSubsystemType,
+        },
+        ids::InternalId,
+    };
+
+    fn attempts(degrees: &[(u32, DegreeOfSuccess)]) -> Vec<SubsystemAttempt> {
+        degrees
+            .iter()
+            .map(|(check_index, degree)| SubsystemAttempt {
+                id: InternalId(0),
+                check_index: *check_index,
+                character_id: None,
+                skill: Skill::Athletics,
+                degree_of_success: *degree,
+            })
+            .collect()
+    }
+
+    fn checks(vps: &[u8]) -> Vec<EncounterSubsystemCheck> {
+        vps.iter()
+            .map(|vp| EncounterSubsystemCheck {
+                name: String::new(),
+                roll_options: vec![],
+                vp: *vp,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_subsystem_progress() {
+        use DegreeOfSuccess::*;
+
+        // Chase: obstacles are tracked separately, and critical failures lose points
+        let chase = calculate_subsystem_progress(
+            EncounterSubsystemType::Chase,
+            &checks(&[3, 2]),
+            &default_awareness_thresholds(),
+            attempts(&[
+                (0, CriticalFailure),
+                (0, CriticalSuccess),
+                (0, Success),
+                (1, Success),
+            ]),
+        );
+        assert_eq!(
+            chase
+                .checks
+                .iter()
+                .map(|c| (c.points, c.reached))
+                .collect::<Vec<_>>(),
+            vec![(3, true), (1, false)]
+        );
+        assert!(!chase.completed);
+
+        // Infiltration: critical failures add awareness instead, against the encounter's thresholds
+        let infiltration = calculate_subsystem_progress(
+            EncounterSubsystemType::Infiltration,
+            &checks(&[2]),
+            &[3, 6],
+            attempts(&[
+                (0, Success),
+                (0, CriticalFailure),
+                (0, CriticalFailure),
+                (0, CriticalFailure),
+                (0, CriticalFailure),
+                (0, CriticalFailure),
+                (0, Success),
+            ]),
+        );
+        assert_eq!(infiltration.checks[0].points, 2);
+        assert_eq!(infiltration.awareness_points, 5);
+        assert_eq!(infiltration.awareness_thresholds_reached, vec![3]);
+        assert!(infiltration.completed);
+
+        // Research: points are pooled, and each check is a threshold
+        let research = calculate_subsystem_progress(
+            EncounterSubsystemType::Research,
+            &checks(&[2, 5]),
+            &default_awareness_thresholds(),
+            attempts(&[(0, CriticalSuccess), (1, Success), (1, Failure)]),
+        );
+        assert_eq!(research.total_points, 3);
+        assert_eq!(
+            research
+                .checks
+                .iter()
+                .map(|c| c.reached)
+                .collect::<Vec<_>>(),
+            vec![true, false]
+        );
+        assert!(research.awareness_thresholds_reached.is_empty());
+    }
+
+    #[test]
+    fn test_degree_of_success_from_i16() {
+        for degree in [
+            DegreeOfSuccess::CriticalFailure,
+            DegreeOfSuccess::Failure,
+            DegreeOfSuccess::Success,
+            DegreeOfSuccess::CriticalSuccess,
+        ] {
+            assert_eq!(DegreeOfSuccess::from_i16(degree.as_i16()).unwrap(), degree);
+        }
+        assert!(DegreeOfSuccess::from_i16(4).is_err());
+    }
+}
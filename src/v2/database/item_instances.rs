@@ -27,8 +27,10 @@ pub struct InsertItemInstance {
 }
 
 // TODO: Filters, etc. Currently only used for exporting.
+// Item instances of a campaign: in its stash, rewarded in its sessions, or held by its characters
 pub async fn get_item_instances(
     exec: impl sqlx::Executor<'_, Database = sqlx::Postgres> + Copy,
+    campaign_id: InternalId,
 ) -> crate::Result<Vec<ItemInstance>> {
     let res = sqlx::query!(
         r#"
@@ -45,7 +47,13 @@ pub async fn get_item_instances(
             ii.nickname,
             ii.notes
         FROM item_instances ii
+        WHERE
+            ii.campaign_id = $1
+            OR ii.session_id IN (SELECT id FROM campaign_sessions WHERE campaign_id = $1)
+            OR ii.character_id IN (SELECT id FROM characters WHERE campaign = $1)
+        ORDER BY ii.id
         "#,
+        campaign_id.0 as i32,
     )
     .fetch_all(exec)
     .await?
@@ -68,6 +76,31 @@ pub async fn get_item_instances(
     Ok(res)
 }
 
+// Used up item instances of a campaign (see get_item_instances), with the session each was used in
+pub async fn get_spent_item_instances(
+    exec: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    campaign_id: InternalId,
+) -> crate::Result<HashMap<i32, Option<i32>>> {
+    let res = sqlx::query!(
+        r#"
+        SELECT ii.id, ii.spent_session_id
+        FROM item_instances ii
+        WHERE ii.spent AND (
+            ii.campaign_id = $1
+            OR ii.session_id IN (SELECT id FROM campaign_sessions WHERE campaign_id = $1)
+            OR ii.character_id IN (SELECT id FROM characters WHERE campaign = $1)
+        )
+        "#,
+        campaign_id.0 as i32,
+    )
+    .fetch_all(exec)
+    .await?
+    .into_iter()
+    .map(|row| (row.id, row.spent_session_id))
+    .collect();
+
+    Ok(res)
+}
 
 pub async fn insert_item_instances(
     tx : &mut sqlx::Transaction<'_, sqlx::Postgres>,
@@ -137,3 +170,754 @@ pub async fn insert_item_instances(
 
     Ok(res)
 }
+
+// Sets the parent of each item instance, as (item, parent) pairs
+pub async fn set_item_instance_parents(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    parents: &[(InternalId, InternalId)],
+) -> crate::Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE item_instances
+        SET parent_item_id = p.parent_item_id
+        FROM UNNEST($1::int[], $2::int[]) AS p(id, parent_item_id)
+        WHERE item_instances.id = p.id
+        "#,
+        &parents
+            .iter()
+            .map(|(id, _)| id.0 as i32)
+            .collect::<Vec<i32>>(),
+        &parents
+            .iter()
+            .map(|(_, parent)| parent.0 as i32)
+            .collect::<Vec<i32>>(),
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+// Marks item instances as used up, in the given session if any
+pub async fn set_item_instances_spent(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    spent: &[(InternalId, Option<InternalId>)],
+) -> crate::Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE item_instances
+        SET spent = TRUE, spent_session_id = s.spent_session_id
+        FROM UNNEST($1::int[], $2::int[]) AS s(id, spent_session_id)
+        WHERE item_instances.id = s.id
+        "#,
+        &spent.iter().map(|(id, _)| id.0 as i32).collect::<Vec<i32>>(),
+        &spent
+            .iter()
+            .map(|(_, session_id)| session_id.map(|id| id.0 as i32))
+            .collect::<Vec<Option<i32>>>() as _,
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferType {
+    Move,
+    Split,
+    Merge,
+    // A consumable used up
+    Use,
+}
+
+impl TransferType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransferType::Move => "move",
+            TransferType::Split => "split",
+            TransferType::Merge => "merge",
+            TransferType::Use => "use",
+        }
+    }
+}
+
+impl std::str::FromStr for TransferType {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "move" => Ok(TransferType::Move),
+            "split" => Ok(TransferType::Split),
+            "merge" => Ok(TransferType::Merge),
+            "use" => Ok(TransferType::Use),
+            _ => Err(()),
+        }
+    }
+}
+
+// An entry in the history of an item instance. A character of None is the party stash.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ItemInstanceTransfer {
+    pub id: i32,
+    pub item_instance_id: i32,
+    pub transfer_type: TransferType,
+    pub from_character_id: Option<i32>,
+    pub to_character_id: Option<i32>,
+    pub quantity: u16,
+    pub other_item_instance_id: Option<i32>,
+    pub user_id: Option<i32>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+pub struct InsertItemInstanceTransfer {
+    pub item_instance_id: InternalId,
+    pub transfer_type: TransferType,
+    pub from_character_id: Option<InternalId>,
+    pub to_character_id: Option<InternalId>,
+    pub quantity: u16,
+    pub other_item_instance_id: Option<InternalId>,
+    pub user_id: InternalId,
+}
+
+// Items held by a character, including those inside its containers. Used up consumables are not held.
+pub async fn get_character_item_instances(
+    exec: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    character_id: InternalId,
+) -> crate::Result<Vec<ItemInstance>> {
+    let res = sqlx::query!(
+        r#"
+        SELECT
+            ii.id,
+            ii.library_item_id,
+            ii.parent_item_id,
+            ii.campaign_id,
+            ii.encounter_id,
+            ii.character_id,
+            ii.session_id,
+            ii.is_reward,
+            ii.quantity,
+            ii.nickname,
+            ii.notes
+        FROM item_instances ii
+        WHERE ii.character_id = $1 AND NOT ii.spent
+        ORDER BY ii.id
+        "#,
+        character_id.0 as i32,
+    )
+    .fetch_all(exec)
+    .await?
+    .into_iter()
+    .map(|row| ItemInstance {
+        id: row.id,
+        library_item_id: row.library_item_id,
+        parent_item_id: row.parent_item_id,
+        campaign_id: row.campaign_id,
+        encounter_id: row.encounter_id,
+        character_id: row.character_id,
+        session_id: row.session_id,
+        is_reward: row.is_reward,
+        quantity: row.quantity as u16,
+        nickname: row.nickname,
+        notes: row.notes,
+    })
+    .collect::<Vec<ItemInstance>>();
+
+    Ok(res)
+}
+
+// Items of the campaign not held by any character
+pub async fn get_stash_item_instances(
+    exec: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    campaign_id: InternalId,
+) -> crate::Result<Vec<ItemInstance>> {
+    let res = sqlx::query!(
+        r#"
+        SELECT
+            ii.id,
+            ii.library_item_id,
+            ii.parent_item_id,
+            ii.campaign_id,
+            ii.encounter_id,
+            ii.character_id,
+            ii.session_id,
+            ii.is_reward,
+            ii.quantity,
+            ii.nickname,
+            ii.notes
+        FROM item_instances ii
+        WHERE ii.campaign_id = $1 AND ii.character_id IS NULL AND NOT ii.spent
+        ORDER BY ii.id
+        "#,
+        campaign_id.0 as i32,
+    )
+    .fetch_all(exec)
+    .await?
+    .into_iter()
+    .map(|row| ItemInstance {
+        id: row.id,
+        library_item_id: row.library_item_id,
+        parent_item_id: row.parent_item_id,
+        campaign_id: row.campaign_id,
+        encounter_id: row.encounter_id,
+        character_id: row.character_id,
+        session_id: row.session_id,
+        is_reward: row.is_reward,
+        quantity: row.quantity as u16,
+        nickname: row.nickname,
+        notes: row.notes,
+    })
+    .collect::<Vec<ItemInstance>>();
+
+    Ok(res)
+}
+
+// An item instance, if it belongs to the campaign (see get_item_instances). Used up items are only
+// included if asked for, as they can no longer be handled.
+pub async fn get_item_instance(
+    exec: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    campaign_id: InternalId,
+    item_instance_id: InternalId,
+    include_spent: bool,
+) -> crate::Result<Option<ItemInstance>> {
+    let res = sqlx::query!(
+        r#"
+        SELECT
+            ii.id,
+            ii.library_item_id,
+            ii.parent_item_id,
+            ii.campaign_id,
+            ii.encounter_id,
+            ii.character_id,
+            ii.session_id,
+            ii.is_reward,
+            ii.quantity,
+            ii.nickname,
+            ii.notes
+        FROM item_instances ii
+        WHERE ii.id = $2 AND ($3::bool OR NOT ii.spent) AND (
+            ii.campaign_id = $1
+            OR ii.session_id IN (SELECT id FROM campaign_sessions WHERE campaign_id = $1)
+            OR ii.character_id IN (SELECT id FROM characters WHERE campaign = $1)
+        )
+        "#,
+        campaign_id.0 as i32,
+        item_instance_id.0 as i32,
+        include_spent,
+    )
+    .fetch_optional(exec)
+    .await?
+    .map(|row| ItemInstance {
+        id: row.id,
+        library_item_id: row.library_item_id,
+        parent_item_id: row.parent_item_id,
+        campaign_id: row.campaign_id,
+        encounter_id: row.encounter_id,
+        character_id: row.character_id,
+        session_id: row.session_id,
+        is_reward: row.is_reward,
+        quantity: row.quantity as u16,
+        nickname: row.nickname,
+        notes: row.notes,
+    });
+
+    Ok(res)
+}
+
+// Locks item instances until the end of the transaction, returning their current quantities in the same order,
+// as they may have changed since they were read
+async fn lock_item_instance_quantities(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    items: &[&ItemInstance],
+) -> crate::Result<Vec<u16>> {
+    let quantities = sqlx::query!(
+        r#"
+        SELECT id, quantity
+        FROM item_instances
+        WHERE id = ANY($1::int[])
+        ORDER BY id
+        FOR UPDATE
+        "#,
+        &items.iter().map(|i| i.id).collect::<Vec<i32>>(),
+    )
+    .fetch_all(&mut **tx)
+    .await?
+    .into_iter()
+    .map(|row| (row.id, row.quantity as u16))
+    .collect::<HashMap<i32, u16>>();
+
+    items
+        .iter()
+        .map(|i| quantities.get(&i.id).copied().ok_or(ServerError::NotFound))
+        .collect()
+}
+
+// The item instance, then everything nested in it (at any depth)
+async fn get_item_instance_with_contents(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    item_instance_id: InternalId,
+) -> crate::Result<Vec<(InternalId, u16)>> {
+    let res = sqlx::query!(
+        r#"
+        WITH RECURSIVE contents AS (
+            SELECT id, quantity, 0 AS depth FROM item_instances WHERE id = $1
+            UNION
+            SELECT ii.id, ii.quantity, c.depth + 1
+            FROM item_instances ii
+            INNER JOIN contents c ON ii.parent_item_id = c.id
+        )
+        SELECT id AS "id!", quantity AS "quantity!" FROM contents ORDER BY depth, id
+        "#,
+        item_instance_id.0 as i32,
+    )
+    .fetch_all(&mut **tx)
+    .await?
+    .into_iter()
+    .map(|row| (InternalId(row.id as u32), row.quantity as u16))
+    .collect();
+
+    Ok(res)
+}
+
+pub async fn get_item_instance_transfers(
+    exec: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    item_instance_id: InternalId,
+) -> crate::Result<Vec<ItemInstanceTransfer>> {
+    let res = sqlx::query!(
+        r#"
+        SELECT
+            id,
+            item_instance_id,
+            transfer_type,
+            from_character_id,
+            to_character_id,
+            quantity,
+            other_item_instance_id,
+            user_id,
+            created_at
+        FROM item_instance_transfers
+        WHERE item_instance_id = $1
+        ORDER BY created_at, id
+        "#,
+        item_instance_id.0 as i32,
+    )
+    .fetch_all(exec)
+    .await?
+    .into_iter()
+    .map(|row| {
+        Ok(ItemInstanceTransfer {
+            id: row.id,
+            item_instance_id: row.item_instance_id,
+            transfer_type: row.transfer_type.parse().map_err(|_| {
+                ServerError::InternalError(format!("Invalid transfer type: {}", row.transfer_type))
+            })?,
+            from_character_id: row.from_character_id,
+            to_character_id: row.to_character_id,
+            quantity: row.quantity as u16,
+            other_item_instance_id: row.other_item_instance_id,
+            user_id: row.user_id,
+            created_at: row.created_at,
+        })
+    })
+    .collect::<crate::Result<Vec<ItemInstanceTransfer>>>()?;
+
+    Ok(res)
+}
+
+pub async fn insert_item_instance_transfers(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    transfers: &[InsertItemInstanceTransfer],
+) -> crate::Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO item_instance_transfers (item_instance_id, transfer_type, from_character_id, to_character_id, quantity, other_item_instance_id, user_id)
+        SELECT * FROM UNNEST($1::int[], $2::text[], $3::int[], $4::int[], $5::smallint[], $6::int[], $7::int[])
+        "#,
+        &transfers.iter().map(|t| t.item_instance_id.0 as i32).collect::<Vec<i32>>(),
+        &transfers.iter().map(|t| t.transfer_type.as_str().to_string()).collect::<Vec<String>>(),
+        &transfers.iter().map(|t| t.from_character_id.map(|id| id.0 as i32)).collect::<Vec<Option<i32>>>() as _,
+        &transfers.iter().map(|t| t.to_character_id.map(|id| id.0 as i32)).collect::<Vec<Option<i32>>>() as _,
+        &transfers.iter().map(|t| t.quantity as i16).collect::<Vec<i16>>(),
+        &transfers.iter().map(|t| t.other_item_instance_id.map(|id| id.0 as i32)).collect::<Vec<Option<i32>>>() as _,
+        &transfers.iter().map(|t| t.user_id.0 as i32).collect::<Vec<i32>>(),
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// Gives an item instance, and everything in it, to a character (or to the party stash, if None).
+/// The item is taken out of any container it was in, as that stays with its holder.
+pub async fn move_item_instance(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    campaign_id: InternalId,
+    item: &ItemInstance,
+    to_character_id: Option<InternalId>,
+    user_id: InternalId,
+) -> crate::Result<()> {
+    require_unspent(tx, &[item]).await?;
+    sqlx::query!(
+        r#"
+        UPDATE item_instances
+        SET parent_item_id = NULL
+        WHERE id = $1
+        "#,
+        item.id,
+    )
+    .execute(&mut **tx)
+    .await?;
+    set_item_instance_holder(tx, campaign_id, item, to_character_id, user_id).await
+}
+
+// Sets the holder of an item instance and its contents, recording the change if there is one
+async fn set_item_instance_holder(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    campaign_id: InternalId,
+    item: &ItemInstance,
+    to_character_id: Option<InternalId>,
+    user_id: InternalId,
+) -> crate::Result<()> {
+    let from_character_id = item.character_id.map(|id| InternalId(id as u32));
+    if from_character_id == to_character_id {
+        return Ok(());
+    }
+
+    let moved = get_item_instance_with_contents(tx, InternalId(item.id as u32)).await?;
+    sqlx::query!(
+        r#"
+        UPDATE item_instances
+        SET character_id = $1, campaign_id = $2
+        WHERE id = ANY($3::int[])
+        "#,
+        to_character_id.map(|id| id.0 as i32),
+        campaign_id.0 as i32,
+        &moved
+            .iter()
+            .map(|(id, _)| id.0 as i32)
+            .collect::<Vec<i32>>(),
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    let transfers = moved
+        .into_iter()
+        .map(|(id, quantity)| InsertItemInstanceTransfer {
+            item_instance_id: id,
+            transfer_type: TransferType::Move,
+            from_character_id,
+            to_character_id,
+            quantity,
+            other_item_instance_id: None,
+            user_id,
+        })
+        .collect::<Vec<_>>();
+    insert_item_instance_transfers(tx, &transfers).await
+}
+
+/// Splits `quantity` off a stack into a new instance, held in the same place. Returns the new instance.
+pub async fn split_item_instance(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    item: &ItemInstance,
+    quantity: u16,
+    user_id: InternalId,
+) -> crate::Result<ItemInstance> {
+    require_unspent(tx, &[item]).await?;
+    let current = lock_item_instance_quantities(tx, &[item]).await?[0];
+    if quantity == 0 || quantity >= current {
+        return Err(ServerError::BadRequest(format!(
+            "Can only split between 1 and {} from a stack of {}",
+            current.saturating_sub(1),
+            current
+        )));
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE item_instances
+        SET quantity = quantity - $1
+        WHERE id = $2
+        "#,
+        quantity as i16,
+        item.id,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    let insert = InsertItemInstance {
+        library_item_id: InternalId(item.library_item_id as u32),
+        parent_item_id: item.parent_item_id.map(|id| InternalId(id as u32)),
+        campaign_id: item.campaign_id.map(|id| InternalId(id as u32)),
+        encounter_id: item.encounter_id.map(|id| InternalId(id as u32)),
+        character_id: item.character_id.map(|id| InternalId(id as u32)),
+        session_id: item.session_id.map(|id| InternalId(id as u32)),
+        is_reward: item.is_reward,
+        quantity,
+        nickname: item.nickname.clone(),
+        notes: item.notes.clone(),
+    };
+    let id = insert_item_instances(tx, vec![insert])
+        .await?
+        .into_iter()
+        .next()
+        .ok_or_else(|| ServerError::InternalError("Item instance was not inserted".to_string()))?;
+
+    let holder = item.character_id.map(|id| InternalId(id as u32));
+    insert_item_instance_transfers(
+        tx,
+        &[InsertItemInstanceTransfer {
+            item_instance_id: id,
+            transfer_type: TransferType::Split,
+            from_character_id: holder,
+            to_character_id: holder,
+            quantity,
+            other_item_instance_id: Some(InternalId(item.id as u32)),
+            user_id,
+        }],
+    )
+    .await?;
+
+    Ok(ItemInstance {
+        id: id.0 as i32,
+        quantity,
+        ..item.clone()
+    })
+}
+
+/// Merges a stack into another of the same item, held by the same character. The merged instance is
+/// removed: its contents and history carry over to the one it was merged into.
+pub async fn merge_item_instances(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    item: &ItemInstance,
+    into: &ItemInstance,
+    user_id: InternalId,
+) -> crate::Result<()> {
+    if item.id == into.id
+        || item.library_item_id != into.library_item_id
+        || item.character_id != into.character_id
+    {
+        return Err(ServerError::BadRequest(
+            "Can only merge different stacks of the same item, held by the same character"
+                .to_string(),
+        ));
+    }
+    // Otherwise, treasure would move between sessions
+    if item.session_id != into.session_id || item.is_reward != into.is_reward {
+        return Err(ServerError::BadRequest(
+            "Can't merge items rewarded in different sessions".to_string(),
+        ));
+    }
+    require_unspent(tx, &[item, into]).await?;
+    // Merging a container into something inside it would nest that inside itself
+    let contents = get_item_instance_with_contents(tx, InternalId(item.id as u32)).await?;
+    if contents.iter().any(|(id, _)| id.0 as i32 == into.id) {
+        return Err(ServerError::BadRequest(
+            "Can't merge a container into something inside it".to_string(),
+        ));
+    }
+
+    let quantities = lock_item_instance_quantities(tx, &[item, into]).await?;
+    let (item_quantity, into_quantity) = (quantities[0], quantities[1]);
+    let quantity = item_quantity as u32 + into_quantity as u32;
+    if quantity > i16::MAX as u32 {
+        return Err(ServerError::BadRequest(format!(
+            "Stacks can't exceed a quantity of {}",
+            i16::MAX
+        )));
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE item_instances
+        SET quantity = $1
+        WHERE id = $2
+        "#,
+        quantity as i16,
+        into.id,
+    )
+    .execute(&mut **tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE item_instances
+        SET parent_item_id = $1
+        WHERE parent_item_id = $2
+        "#,
+        into.id,
+        item.id,
+    )
+    .execute(&mut **tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE item_instance_transfers
+        SET item_instance_id = $1
+        WHERE item_instance_id = $2
+        "#,
+        into.id,
+        item.id,
+    )
+    .execute(&mut **tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        DELETE FROM item_instances
+        WHERE id = $1
+        "#,
+        item.id,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    let holder = into.character_id.map(|id| InternalId(id as u32));
+    insert_item_instance_transfers(
+        tx,
+        &[InsertItemInstanceTransfer {
+            item_instance_id: InternalId(into.id as u32),
+            transfer_type: TransferType::Merge,
+            from_character_id: holder,
+            to_character_id: holder,
+            quantity: item_quantity,
+            other_item_instance_id: Some(InternalId(item.id as u32)),
+            user_id,
+        }],
+    )
+    .await
+}
+
+/// Puts an item instance into a container (or takes it out, if None). Items in a container are held
+/// by whoever holds the container.
+pub async fn set_item_instance_container(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    campaign_id: InternalId,
+    item: &ItemInstance,
+    container: Option<&ItemInstance>,
+    user_id: InternalId,
+) -> crate::Result<()> {
+    require_unspent(tx, &[Some(item), container].into_iter().flatten().collect::<Vec<_>>()).await?;
+    if let Some(container) = container {
+        let contents = get_item_instance_with_contents(tx, InternalId(item.id as u32)).await?;
+        if contents.iter().any(|(id, _)| id.0 as i32 == container.id) {
+            return Err(ServerError::BadRequest(
+                "Can't put an item inside itself".to_string(),
+            ));
+        }
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE item_instances
+        SET parent_item_id = $1
+        WHERE id = $2
+        "#,
+        container.map(|c| c.id),
+        item.id,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    match container {
+        Some(container) => {
+            let holder = container.character_id.map(|id| InternalId(id as u32));
+            set_item_instance_holder(tx, campaign_id, item, holder, user_id).await
+        }
+        None => Ok(()),
+    }
+}
+
+async fn require_unspent(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    items: &[&ItemInstance],
+) -> crate::Result<()> {
+    let spent = sqlx::query!(
+        r#"
+        SELECT EXISTS(SELECT 1 FROM item_instances WHERE id = ANY($1::int[]) AND spent) AS "spent!"
+        "#,
+        &items.iter().map(|i| i.id).collect::<Vec<i32>>(),
+    )
+    .fetch_one(&mut **tx)
+    .await?
+    .spent;
+    if spent {
+        return Err(ServerError::BadRequest(
+            "Used up items can't be moved, split or merged".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Uses up `quantity` of a consumable, in a session if given. The used quantity is split off the stack
+/// (or the whole instance is, if it is all used) and marked as spent, so it still counts as received.
+pub async fn use_item_instance(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    item: &ItemInstance,
+    quantity: u16,
+    session_id: Option<InternalId>,
+    user_id: InternalId,
+) -> crate::Result<()> {
+    let consumable = sqlx::query!(
+        r#"
+        SELECT consumable FROM library_items WHERE id = $1
+        "#,
+        item.library_item_id,
+    )
+    .fetch_one(&mut **tx)
+    .await?
+    .consumable;
+    if !consumable {
+        return Err(ServerError::BadRequest(
+            "Only consumable items can be used up".to_string(),
+        ));
+    }
+    require_unspent(tx, &[item]).await?;
+    let current = lock_item_instance_quantities(tx, &[item]).await?[0];
+    if quantity == 0 || quantity > current {
+        return Err(ServerError::BadRequest(format!(
+            "Can only use between 1 and {} from this stack",
+            current
+        )));
+    }
+
+    let used = if quantity < current {
+        split_item_instance(tx, item, quantity, user_id).await?
+    } else {
+        item.clone()
+    };
+
+    sqlx::query!(
+        r#"
+        UPDATE item_instances
+        SET spent = TRUE, spent_session_id = $1, parent_item_id = NULL
+        WHERE id = $2
+        "#,
+        session_id.map(|id| id.0 as i32),
+        used.id,
+    )
+    .execute(&mut **tx)
+    .await?;
+    // Anything inside a used up container falls out, to where it was
+    sqlx::query!(
+        r#"
+        UPDATE item_instances
+        SET parent_item_id = $1
+        WHERE parent_item_id = $2
+        "#,
+        used.parent_item_id,
+        used.id,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    let holder = used.character_id.map(|id| InternalId(id as u32));
+    insert_item_instance_transfers(
+        tx,
+        &[InsertItemInstanceTransfer {
+            item_instance_id: InternalId(used.id as u32),
+            transfer_type: TransferType::Use,
+            from_character_id: holder,
+            to_character_id: holder,
+            quantity,
+            other_item_instance_id: None,
+            user_id,
+        }],
+    )
+    .await
+}
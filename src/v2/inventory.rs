@@ -0,0 +1,293 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    routing::{get, post, put},
+    Json, Router,
+};
+use axum_extra::extract::CookieJar;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{
+    auth::extract_user_from_cookies,
+    database::{self, campaigns::CampaignAccess, characters::CharacterFilters},
+    models::{campaign::CampaignRole, ids::InternalId},
+    v2::database::{item_instances, models::item_instances::ItemInstance},
+    AppState, ServerError,
+};
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/{id}/stash/items", get(get_stash_items))
+        .route(
+            "/{id}/characters/{character_id}/items",
+            get(get_character_items),
+        )
+        .route("/{id}/items/{item_id}/history", get(get_item_history))
+        .route("/{id}/items/{item_id}/move", post(move_item))
+        .route("/{id}/items/{item_id}/split", post(split_item))
+        .route("/{id}/items/{item_id}/merge", post(merge_item))
+        .route("/{id}/items/{item_id}/container", put(set_item_container))
+        .route("/{id}/items/{item_id}/use", post(use_item))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MoveItem {
+    // None gives the item to the party stash
+    pub character_id: Option<InternalId>,
+    // Moves only part of a stack, splitting it off first
+    pub quantity: Option<u16>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SplitItem {
+    pub quantity: u16,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MergeItem {
+    pub into_item_id: InternalId,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SetItemContainer {
+    // None takes the item out of its container
+    pub parent_item_id: Option<InternalId>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UseItem {
+    // Defaults to one
+    pub quantity: Option<u16>,
+    // Session the item was used in
+    pub session_id: Option<InternalId>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SplitItemResponse {
+    pub item_id: i32,
+}
+
+async fn get_access(
+    pool: &PgPool,
+    campaign_id: InternalId,
+    user_id: InternalId,
+) -> crate::Result<CampaignAccess> {
+    let access = database::campaigns::get_campaign_access(pool, campaign_id, user_id)
+        .await?
+        .ok_or(ServerError::NotFound)?;
+    access.require(CampaignRole::Player)?;
+    Ok(access)
+}
+
+// Players may only handle items their own character holds
+async fn get_item(
+    pool: &PgPool,
+    access: &CampaignAccess,
+    item_id: InternalId,
+    include_spent: bool,
+) -> crate::Result<ItemInstance> {
+    let item = item_instances::get_item_instance(pool, access.campaign_id, item_id, include_spent)
+        .await?
+        .ok_or(ServerError::NotFound)?;
+    let held_by_player = item.character_id.is_some()
+        && item.character_id == access.character_id.map(|id| id.0 as i32);
+    if !held_by_player {
+        access.require(CampaignRole::CoGm)?;
+    }
+    Ok(item)
+}
+
+async fn require_character(
+    pool: &PgPool,
+    access: &CampaignAccess,
+    character_id: InternalId,
+) -> crate::Result<()> {
+    let characters = database::characters::get_characters(
+        pool,
+        access.owner,
+        access.campaign_id,
+        &CharacterFilters::default(),
+    )
+    .await?;
+    if !characters.iter().any(|c| c.id == character_id) {
+        return Err(ServerError::NotFound);
+    }
+    Ok(())
+}
+
+async fn get_stash_items(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    Path(id): Path<InternalId>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+    let access = get_access(&pool, id, user.id).await?;
+
+    let items = item_instances::get_stash_item_instances(&pool, access.campaign_id).await?;
+    Ok(Json(items))
+}
+
+async fn get_character_items(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    Path((id, character_id)): Path<(InternalId, InternalId)>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+    let access = get_access(&pool, id, user.id).await?;
+
+    // Players only see their own character's items
+    if access.character_id != Some(character_id) {
+        access.require(CampaignRole::CoGm)?;
+    }
+    require_character(&pool, &access, character_id).await?;
+
+    let items = item_instances::get_character_item_instances(&pool, character_id).await?;
+    Ok(Json(items))
+}
+
+async fn get_item_history(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    Path((id, item_id)): Path<(InternalId, InternalId)>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+    let access = get_access(&pool, id, user.id).await?;
+    get_item(&pool, &access, item_id, true).await?;
+
+    let transfers = item_instances::get_item_instance_transfers(&pool, item_id).await?;
+    Ok(Json(transfers))
+}
+
+async fn move_item(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    Path((id, item_id)): Path<(InternalId, InternalId)>,
+    Json(payload): Json<MoveItem>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+    let access = get_access(&pool, id, user.id).await?;
+    let item = get_item(&pool, &access, item_id, false).await?;
+    if let Some(character_id) = payload.character_id {
+        require_character(&pool, &access, character_id).await?;
+    }
+
+    if let Some(quantity) = payload.quantity {
+        if quantity == 0 || quantity > item.quantity {
+            return Err(ServerError::BadRequest(format!(
+                "Can only move between 1 and {} from this stack",
+                item.quantity
+            )));
+        }
+    }
+
+    let mut tx = pool.begin().await?;
+    let item = match payload.quantity {
+        Some(quantity) if quantity < item.quantity => {
+            item_instances::split_item_instance(&mut tx, &item, quantity, user.id).await?
+        }
+        _ => item,
+    };
+    item_instances::move_item_instance(
+        &mut tx,
+        access.campaign_id,
+        &item,
+        payload.character_id,
+        user.id,
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn split_item(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    Path((id, item_id)): Path<(InternalId, InternalId)>,
+    Json(payload): Json<SplitItem>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+    let access = get_access(&pool, id, user.id).await?;
+    let item = get_item(&pool, &access, item_id, false).await?;
+
+    let mut tx = pool.begin().await?;
+    let split =
+        item_instances::split_item_instance(&mut tx, &item, payload.quantity, user.id).await?;
+    tx.commit().await?;
+    Ok(Json(SplitItemResponse { item_id: split.id }))
+}
+
+async fn merge_item(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    Path((id, item_id)): Path<(InternalId, InternalId)>,
+    Json(payload): Json<MergeItem>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+    let access = get_access(&pool, id, user.id).await?;
+    let item = get_item(&pool, &access, item_id, false).await?;
+    let into = get_item(&pool, &access, payload.into_item_id, false).await?;
+
+    let mut tx = pool.begin().await?;
+    item_instances::merge_item_instances(&mut tx, &item, &into, user.id).await?;
+    tx.commit().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn set_item_container(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    Path((id, item_id)): Path<(InternalId, InternalId)>,
+    Json(payload): Json<SetItemContainer>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+    let access = get_access(&pool, id, user.id).await?;
+    let item = get_item(&pool, &access, item_id, false).await?;
+    let container = match payload.parent_item_id {
+        Some(parent_item_id) => Some(get_item(&pool, &access, parent_item_id, false).await?),
+        None => None,
+    };
+
+    let mut tx = pool.begin().await?;
+    item_instances::set_item_instance_container(
+        &mut tx,
+        access.campaign_id,
+        &item,
+        container.as_ref(),
+        user.id,
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn use_item(
+    State(pool): State<PgPool>,
+    jar: CookieJar,
+    Path((id, item_id)): Path<(InternalId, InternalId)>,
+    Json(payload): Json<UseItem>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = extract_user_from_cookies(&jar, &pool).await?;
+    let access = get_access(&pool, id, user.id).await?;
+    let item = get_item(&pool, &access, item_id, false).await?;
+    if let Some(session_id) = payload.session_id {
+        let sessions =
+            database::sessions::get_sessions(&pool, access.owner, access.campaign_id).await?;
+        if !sessions.iter().any(|s| s.id == session_id) {
+            return Err(ServerError::NotFound);
+        }
+    }
+
+    let mut tx = pool.begin().await?;
+    item_instances::use_item_instance(
+        &mut tx,
+        &item,
+        payload.quantity.unwrap_or(1),
+        payload.session_id,
+        user.id,
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
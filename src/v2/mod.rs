@@ -1,3 +1,8 @@
 pub mod database;
+pub mod inventory;
 pub mod models;
-pub mod routes;
\ No newline at end of file
+pub mod routes;
+
+pub fn router() -> axum::Router<crate::AppState> {
+    routes::router().nest("/campaign", inventory::router())
+}
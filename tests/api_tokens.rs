@@ -0,0 +1,51 @@
+#[path = "common/mod.rs"]
+mod common;
+
+use axum::http::header;
+use common::{send, send_as, signup};
+use machete::app;
+use reqwest::StatusCode;
+use serde_json::json;
+use sqlx::PgPool;
+
+#[sqlx::test]
+async fn api_token_test(pool: PgPool) -> sqlx::Result<()> {
+    let app = app(pool);
+    let cookie = signup(&app, "test").await;
+
+    let (status, created) = send(
+        &app,
+        "POST",
+        "/auth/tokens",
+        &cookie,
+        json!({"name": "script", "scope": "read"}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let bearer = format!("Bearer {}", created["token"].as_str().unwrap());
+    let auth = (header::AUTHORIZATION.as_str(), bearer.as_str());
+
+    // Read tokens authenticate GET requests
+    let (status, me) = send_as(&app, "GET", "/auth/me", auth, json!({})).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(me["username"], "test");
+
+    // ... but not writes
+    let (status, _) = send_as(&app, "POST", "/campaign", auth, json!({"name": "campaign"})).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // Revoked tokens are rejected
+    let (status, _) = send(
+        &app,
+        "DELETE",
+        &format!("/auth/tokens/{}", created["id"]),
+        &cookie,
+        json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = send_as(&app, "GET", "/auth/me", auth, json!({})).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    Ok(())
+}
@@ -0,0 +1,144 @@
+#[path = "common/mod.rs"]
+mod common;
+
+use axum::{body::Bytes, Router};
+use common::{send_raw, signup};
+use machete::app;
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+
+// Imports a campaign and returns its export
+async fn import_export(app: &Router, cookie: &str, campaign: Value) -> Bytes {
+    let (status, imported) = send_raw(
+        app,
+        "POST",
+        "/campaign/import",
+        ("cookie", cookie),
+        campaign,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{:?}", imported);
+    let imported: Value = serde_json::from_slice(&imported).unwrap();
+
+    let uri = format!("/campaign/{}/export", imported["id"]);
+    let (status, exported) = send_raw(app, "GET", &uri, ("cookie", cookie), json!({})).await;
+    assert_eq!(status, StatusCode::OK, "{:?}", exported);
+    exported
+}
+
+#[sqlx::test]
+async fn campaign_export_round_trip_test(pool: PgPool) -> sqlx::Result<()> {
+    // Library objects referenced by the campaign
+    sqlx::query(
+        "INSERT INTO library_objects (id, name, game_system) VALUES (1, 'Fighter', 0), (2, 'Goblin', 0), (3, 'Longsword', 0)",
+    )
+    .execute(&pool)
+    .await?;
+    sqlx::query("INSERT INTO library_classes (id, rarity, hp) VALUES (1, 0, 10)")
+        .execute(&pool)
+        .await?;
+    sqlx::query("INSERT INTO library_creatures (id, rarity, level) VALUES (2, 0, 1)")
+        .execute(&pool)
+        .await?;
+    sqlx::query("INSERT INTO library_items (id, rarity, level, price) VALUES (3, 0, 1, 1.0)")
+        .execute(&pool)
+        .await?;
+
+    let app = app(pool);
+    let cookie = signup(&app, "test").await;
+
+    // Unversioned exports are migrated
+    let v0 = json!({
+        "id_hash": 100,
+        "name": "Campaign",
+        "level": 1,
+        "description": "Notes",
+        "characters": [
+            {"id_hash": 7, "name": "Alden", "player": "John", "class": 1},
+            {"id_hash": 8, "name": "Brina", "player": null, "class": 1}
+        ],
+        "sessions": [
+            {"id_hash": 20, "name": "Session 1", "description": null, "date": "2021-01-01T00:00:00Z",
+                "compiled_rewards": {"7": {"gold": 1.5}, "8": {"gold": 0.0, "present": false}}},
+            {"id_hash": 21, "name": null, "description": "Recap", "date": "2021-01-08T00:00:00Z",
+                "compiled_rewards": {}}
+        ],
+        "encounters": [
+            {"id_hash": 30, "name": "Ambush", "description": null, "session_ix": 0, "party_level": 1, "party_size": 2,
+                "encounter_type": "combat", "enemies": [{"id": 2, "level_adjustment": 0}], "hazards": [],
+                "treasure_items": [3], "treasure_currency": 2.0, "extra_experience": 0}
+        ],
+        "items": []
+    });
+    let exported: Value = serde_json::from_slice(&import_export(&app, &cookie, v0).await).unwrap();
+    assert_eq!(exported["version"], 1);
+    assert_eq!(exported["absent_characters_earn_experience"], true);
+    assert_eq!(exported["sessions"][0]["rewards"]["1"]["gold"], 1.5);
+    assert_eq!(exported["items"][0]["encounter_id"], 1);
+
+    // Add an event log, nested items and campaign settings, then check that export -> import -> export is stable
+    let mut campaign = exported;
+    campaign["absent_characters_earn_experience"] = json!(false);
+    campaign["characters"][0] = json!({
+        "id": 1, "name": "Alden", "player": "John", "class": 1,
+        "ancestry": "Human", "heritage": "Versatile Human", "background": "Guard", "level": 3,
+        "ability_modifiers": {"strength": 4, "dexterity": 1, "constitution": 2, "intelligence": 0, "wisdom": 1, "charisma": -1},
+        "proficiencies": {
+            "perception": "expert", "fortitude": "expert", "reflex": "trained", "will": "trained",
+            "skills": [
+                {"skill": "Athletics", "proficiency": "expert"},
+                {"skill": "Lore (Legal)", "proficiency": "trained"}
+            ]
+        }
+    });
+    campaign["items"].as_array_mut().unwrap().push(json!({
+        "id": 2, "library_item_id": 3, "parent_item_id": 3, "encounter_id": null, "character_id": 1,
+        "session_id": 1, "is_reward": false, "quantity": 2, "nickname": "Spare", "notes": null
+    }));
+    campaign["items"].as_array_mut().unwrap().push(json!({
+        "id": 3, "library_item_id": 3, "parent_item_id": null, "encounter_id": null, "character_id": 2,
+        "session_id": 2, "is_reward": true, "quantity": 1, "nickname": null, "notes": "Bag"
+    }));
+    campaign["encounters"].as_array_mut().unwrap().push(json!({
+        "id": 2, "session_id": null, "name": "Patrol", "description": null, "party_level": 1, "party_size": 2,
+        "encounter_type": "combat", "enemies": [{"id": 2, "level_adjustment": 0}], "hazards": [],
+        "treasure_currency": 0.0, "extra_experience": 0
+    }));
+    campaign["event_groups"] = json!([
+        {"id": 1, "session_id": 1, "name": "Ambush", "description": null,
+            "timestamp": "2021-01-01T12:00:00", "intra_session_order": 1}
+    ]);
+    campaign["events"] = json!([
+        {"event_group_id": 1, "session_id": 1, "character_id": null, "timestamp": "2021-01-01T12:00:00",
+            "intra_session_order": 1, "event_type": "EnemyDefeated", "data": {"id": 2, "level_adjustment": 0}},
+        {"event_group_id": null, "session_id": 2, "character_id": 1, "timestamp": "2021-01-08T12:00:00",
+            "intra_session_order": 1, "event_type": "CurrencyGain", "data": {"currency": 3.0}}
+    ]);
+
+    let first = import_export(&app, &cookie, campaign).await;
+    let second = import_export(&app, &cookie, serde_json::from_slice(&first).unwrap()).await;
+    assert_eq!(
+        std::str::from_utf8(&first).unwrap(),
+        std::str::from_utf8(&second).unwrap()
+    );
+
+    let exported: Value = serde_json::from_slice(&first).unwrap();
+    assert_eq!(exported["absent_characters_earn_experience"], false);
+    assert_eq!(exported["items"].as_array().unwrap().len(), 3);
+    // Encounters of the campaign that are not linked to a session are kept
+    assert_eq!(exported["encounters"].as_array().unwrap().len(), 2);
+    assert_eq!(exported["encounters"][1]["name"], "Patrol");
+    assert_eq!(exported["encounters"][1]["session_id"], Value::Null);
+    assert_eq!(exported["items"][1]["parent_item_id"], 3);
+    assert_eq!(exported["events"].as_array().unwrap().len(), 2);
+    assert_eq!(exported["characters"][0]["level"], 3);
+    assert_eq!(
+        exported["characters"][0]["proficiencies"]["skills"][1]["skill"],
+        "Lore (Legal)"
+    );
+    // Not set, so tracked from experience instead
+    assert_eq!(exported["characters"][1]["level"], Value::Null);
+
+    Ok(())
+}
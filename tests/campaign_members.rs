@@ -0,0 +1,264 @@
+#[path = "common/mod.rs"]
+mod common;
+
+use common::{send, signup};
+use machete::app;
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+
+#[sqlx::test]
+async fn campaign_members_test(pool: PgPool) -> sqlx::Result<()> {
+    let app = app(pool);
+    let owner = signup(&app, "owner").await;
+    let gm = signup(&app, "gm").await;
+    let player = signup(&app, "player").await;
+
+    let (status, campaign) = send(
+        &app,
+        "POST",
+        "/campaign",
+        &owner,
+        json!({"name": "campaign"}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let campaign_id = campaign["id"].clone();
+    let campaign = format!("/campaign/{}", campaign_id);
+
+    // Strangers cannot see the campaign
+    let (status, _) = send(&app, "GET", &format!("{campaign}/sessions"), &gm, json!({})).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    for (username, role) in [("gm", "co_gm"), ("player", "player")] {
+        let (status, _) = send(
+            &app,
+            "POST",
+            &format!("{campaign}/members"),
+            &owner,
+            json!({"username": username, "role": role}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    // Shared campaigns are listed with the user's role
+    let (_, campaigns) = send(&app, "GET", "/campaign", &player, json!({})).await;
+    assert_eq!(campaigns[0]["role"], "player");
+
+    // Players have a read-only view
+    let (status, _) = send(
+        &app,
+        "GET",
+        &format!("{campaign}/sessions"),
+        &player,
+        json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let edit = json!({"name": "renamed"});
+    let (status, _) = send(&app, "PATCH", &campaign, &player, edit.clone()).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // Co-GMs can edit, but not delete or manage members
+    let (status, _) = send(&app, "PATCH", &campaign, &gm, edit).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = send(&app, "DELETE", &campaign, &gm, json!({})).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = send(
+        &app,
+        "POST",
+        &format!("{campaign}/members"),
+        &gm,
+        json!({"username": "player", "role": "co_gm"}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // Co-GMs can edit the campaign's encounters, including those not linked to a session
+    let encounter = json!([{"name": "Ambush", "campaign_id": campaign_id, "party_level": 1,
+        "party_size": 4, "encounter_type": "combat", "enemies": [], "hazards": [],
+        "treasure_items": [], "treasure_currency": 0.0, "extra_experience": 0}]);
+    let (status, _) = send(&app, "POST", "/encounters", &player, encounter.clone()).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, encounters) = send(&app, "POST", "/encounters", &owner, encounter).await;
+    assert_eq!(status, StatusCode::OK, "{:?}", encounters);
+    assert_eq!(encounters[0]["campaign_id"], campaign_id);
+    let encounter = format!("/encounters/{}", encounters[0]["id"]);
+    let rename = json!({"name": "Renamed ambush"});
+    let (status, _) = send(&app, "PATCH", &encounter, &player, rename.clone()).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send(&app, "PATCH", &encounter, &gm, rename).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (_, listed) = send(
+        &app,
+        "GET",
+        &format!("/encounters?campaign_id={}", campaign_id),
+        &gm,
+        json!({}),
+    )
+    .await;
+    assert_eq!(listed[0]["name"], "Renamed ambush");
+
+    // Share links give a read-only view without an account, until revoked
+    let (status, link) = send(&app, "POST", &format!("{campaign}/share"), &gm, json!({})).await;
+    assert_eq!(status, StatusCode::OK);
+    let shared = format!("/campaign/shared/{}", link["token"].as_str().unwrap());
+    let (status, view) = send(&app, "GET", &shared, "", json!({})).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(view["name"], "renamed");
+    assert!(view.get("description").is_none());
+    let (status, _) = send(
+        &app,
+        "DELETE",
+        &format!("{campaign}/share"),
+        &owner,
+        json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = send(&app, "GET", &shared, "", json!({})).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn shared_view_rewards_test(pool: PgPool) -> sqlx::Result<()> {
+    sqlx::query("INSERT INTO library_objects (id, name, game_system) VALUES (1, 'Fighter', 0)")
+        .execute(&pool)
+        .await?;
+    sqlx::query("INSERT INTO library_classes (id, rarity, hp) VALUES (1, 0, 10)")
+        .execute(&pool)
+        .await?;
+
+    let app = app(pool);
+    let owner = signup(&app, "owner").await;
+    let player = signup(&app, "player").await;
+    let (status, campaign) = send(
+        &app,
+        "POST",
+        "/campaign/import",
+        &owner,
+        json!({
+            "id_hash": 100, "name": "Campaign", "level": 1, "description": null,
+            "characters": [{"id_hash": 7, "name": "Alden", "player": null, "class": 1}],
+            "sessions": [{
+                "id_hash": 200, "name": "Session 1", "description": null, "date": "2024-01-01T00:00:00Z",
+                "compiled_rewards": {"7": {"gold": 10.0, "present": true}}
+            }],
+            "encounters": [], "items": []
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{:?}", campaign);
+    let campaign = format!("/campaign/{}", campaign["id"]);
+    let (status, _) = send(
+        &app,
+        "POST",
+        &format!("{campaign}/members"),
+        &owner,
+        json!({"username": "player", "role": "player"}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, link) = send(
+        &app,
+        "POST",
+        &format!("{campaign}/share"),
+        &owner,
+        json!({}),
+    )
+    .await;
+    let shared = format!("/campaign/shared/{}", link["token"].as_str().unwrap());
+
+    // Only the GM sees the rewards of every character
+    let view = format!("{campaign}/view");
+    let (_, gm_view) = send(&app, "GET", &view, &owner, json!({})).await;
+    assert_eq!(
+        gm_view["sessions"][0]["compiled_rewards"]
+            .as_object()
+            .unwrap()
+            .len(),
+        1
+    );
+    let (_, player_view) = send(&app, "GET", &view, &player, json!({})).await;
+    let (status, shared_view) = send(&app, "GET", &shared, "", json!({})).await;
+    assert_eq!(status, StatusCode::OK);
+    for view in [player_view, shared_view] {
+        assert_eq!(view["sessions"][0]["compiled_rewards"], json!({}));
+    }
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn member_character_test(pool: PgPool) -> sqlx::Result<()> {
+    sqlx::query("INSERT INTO library_objects (id, name, game_system) VALUES (1, 'Fighter', 0)")
+        .execute(&pool)
+        .await?;
+    sqlx::query("INSERT INTO library_classes (id, rarity, hp) VALUES (1, 0, 10)")
+        .execute(&pool)
+        .await?;
+
+    let app = app(pool.clone());
+    let owner = signup(&app, "owner").await;
+    signup(&app, "player").await;
+    let (status, campaign) = send(
+        &app,
+        "POST",
+        "/campaign/import",
+        &owner,
+        json!({
+            "id_hash": 100, "name": "Campaign", "level": 1, "description": null,
+            "characters": [{"id_hash": 7, "name": "Alden", "player": null, "class": 1}],
+            "sessions": [], "encounters": [], "items": []
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{:?}", campaign);
+    let (character_id,): (i32,) = sqlx::query_as("SELECT id FROM characters WHERE campaign = $1")
+        .bind(campaign["id"].as_i64().unwrap() as i32)
+        .fetch_one(&pool)
+        .await?;
+    let campaign = format!("/campaign/{}", campaign["id"]);
+
+    let (status, _) = send(
+        &app,
+        "POST",
+        &format!("{campaign}/members"),
+        &owner,
+        json!({"username": "player", "role": "player", "character_id": character_id}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, members) = send(
+        &app,
+        "GET",
+        &format!("{campaign}/members"),
+        &owner,
+        json!({}),
+    )
+    .await;
+    let member = format!("{campaign}/members/{}", members[0]["user_id"]);
+
+    // Leaving out the character keeps it, while null unlinks it
+    for (edit, expected) in [
+        (json!({"role": "co_gm"}), json!(character_id)),
+        (json!({"character_id": null}), Value::Null),
+    ] {
+        let (status, _) = send(&app, "PATCH", &member, &owner, edit).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (_, members) = send(
+            &app,
+            "GET",
+            &format!("{campaign}/members"),
+            &owner,
+            json!({}),
+        )
+        .await;
+        assert_eq!(members[0]["character_id"], expected);
+    }
+
+    Ok(())
+}
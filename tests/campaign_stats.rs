@@ -0,0 +1,242 @@
+#[path = "common/mod.rs"]
+mod common;
+
+use axum::Router;
+use common::{send, signup};
+use machete::app;
+use reqwest::StatusCode;
+use serde_json::json;
+use sqlx::PgPool;
+
+#[sqlx::test]
+async fn class_treasure_deficits_test(pool: PgPool) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO library_objects (id, name, game_system) VALUES (1, 'Wizard', 0), (2, 'Spellbook', 0)",
+    )
+    .execute(&pool)
+    .await?;
+    sqlx::query("INSERT INTO library_classes (id, rarity, hp) VALUES (1, 0, 6)")
+        .execute(&pool)
+        .await?;
+    sqlx::query("INSERT INTO library_items (id, rarity, level, price) VALUES (2, 0, 0, 1.0)")
+        .execute(&pool)
+        .await?;
+    // Wizards want a spellbook by level 2
+    sqlx::query(
+        "INSERT INTO expected_treasure_by_class_by_level (item_group_id, class_id, level, importance)
+            VALUES (1, 1, 2, 1.0)",
+    )
+    .execute(&pool)
+    .await?;
+    sqlx::query(
+        "INSERT INTO expected_treasure_by_class_by_level_items (item_group_id, item_id) VALUES (1, 2)",
+    )
+    .execute(&pool)
+    .await?;
+
+    let app = app(pool.clone());
+    let cookie = signup(&app, "test").await;
+
+    // Wizards expect no weapon potency, set by admins
+    let boosts = json!([{"level": 1, "boost_category_id": 1, "potency": 0}]);
+    let uri = "/library/classes/1/expected_boosts";
+    let (status, _) = send(&app, "PUT", uri, &cookie, boosts.clone()).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    sqlx::query("UPDATE users SET is_admin = TRUE WHERE username = 'test'")
+        .execute(&pool)
+        .await?;
+    let unknown = json!([{"level": 1, "boost_category_id": 99, "potency": 0}]);
+    let (status, _) = send(&app, "PUT", uri, &cookie, unknown).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = send(&app, "PUT", uri, &cookie, boosts).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let (status, campaign) = send(
+        &app,
+        "POST",
+        "/campaign/import",
+        &cookie,
+        json!({
+            "id_hash": 100, "name": "Campaign", "level": 1, "description": null,
+            "characters": [{"id_hash": 7, "name": "Alden", "player": null, "class": 1}],
+            "sessions": [], "encounters": [], "items": []
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{:?}", campaign);
+    // Level 4
+    sqlx::query("UPDATE campaigns SET total_experience = 3000")
+        .execute(&pool)
+        .await?;
+
+    let (status, stats) = send(
+        &app,
+        "GET",
+        &format!("/campaign/{}/stats", campaign["id"]),
+        &cookie,
+        json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{:?}", stats);
+    let character = stats["character_stats"]
+        .as_object()
+        .unwrap()
+        .values()
+        .next()
+        .unwrap();
+    let deficits = character["deficits"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|d| d["description"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        deficits,
+        [
+            "Wizard is missing a striking rune at level 4",
+            "Wizard is missing a +1 skill item at level 3",
+            "Wizard is missing Spellbook at level 2",
+        ]
+    );
+    assert_eq!(
+        character["expected_items"][0]["item_names"],
+        json!(["Spellbook"])
+    );
+    Ok(())
+}
+
+#[sqlx::test]
+async fn character_experience_test(pool: PgPool) -> sqlx::Result<()> {
+    sqlx::query("INSERT INTO library_objects (id, name, game_system) VALUES (1, 'Fighter', 0)")
+        .execute(&pool)
+        .await?;
+    sqlx::query("INSERT INTO library_classes (id, rarity, hp) VALUES (1, 0, 10)")
+        .execute(&pool)
+        .await?;
+
+    let app = app(pool.clone());
+    let cookie = signup(&app, "test").await;
+    let (status, campaign) = send(
+        &app,
+        "POST",
+        "/campaign/import",
+        &cookie,
+        json!({
+            "id_hash": 100, "name": "Campaign", "level": 1, "description": null,
+            "characters": [
+                {"id_hash": 7, "name": "Alden", "player": null, "class": 1},
+                {"id_hash": 8, "name": "Brisa", "player": null, "class": 1}
+            ],
+            "sessions": [{
+                "id_hash": 200, "name": "Session 1", "description": null, "date": "2024-01-01T00:00:00Z",
+                "compiled_rewards": {
+                    "7": {"gold": 0.0, "present": true},
+                    "8": {"gold": 0.0, "present": false}
+                }
+            }],
+            "encounters": [{
+                "id_hash": 300, "name": "Fight", "description": null, "session_ix": 0,
+                "party_level": 1, "party_size": 2, "encounter_type": "combat",
+                "enemies": [], "hazards": [], "treasure_items": [], "treasure_currency": 0.0,
+                "extra_experience": 1200
+            }],
+            "items": []
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{:?}", campaign);
+    let campaign_uri = format!("/campaign/{}", campaign["id"]);
+
+    // Name, experience, level and experience into that level of each character, checked against the stats
+    let experience = |app: Router, cookie: String| {
+        let campaign_uri = campaign_uri.clone();
+        async move {
+            let (status, characters) = send(
+                &app,
+                "GET",
+                &format!("{}/characters", campaign_uri),
+                &cookie,
+                json!({}),
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK, "{:?}", characters);
+            let (status, stats) = send(
+                &app,
+                "GET",
+                &format!("{}/stats", campaign_uri),
+                &cookie,
+                json!({}),
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK, "{:?}", stats);
+            let mut experience = characters
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|c| {
+                    let character = &stats["character_stats"][c["id"].to_string()];
+                    assert_eq!(character["total_experience"], c["experience"]);
+                    assert_eq!(character["level"], c["level"]);
+                    (
+                        c["name"].as_str().unwrap().to_string(),
+                        c["experience"].as_u64().unwrap(),
+                        c["level"].as_u64().unwrap(),
+                        character["experience_this_level"].as_u64().unwrap(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            experience.sort();
+            experience
+        }
+    };
+
+    // By default, absent characters still earn experience
+    assert_eq!(
+        experience(app.clone(), cookie.clone()).await,
+        [
+            ("Alden".to_string(), 1200, 2, 200),
+            ("Brisa".to_string(), 1200, 2, 200)
+        ]
+    );
+
+    let (status, body) = send(
+        &app,
+        "PATCH",
+        &campaign_uri,
+        &cookie,
+        json!({"absent_characters_earn_experience": false}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT, "{:?}", body);
+    assert_eq!(
+        experience(app.clone(), cookie.clone()).await,
+        [
+            ("Alden".to_string(), 1200, 2, 200),
+            ("Brisa".to_string(), 0, 1, 0)
+        ]
+    );
+
+    // A level set on a character overrides its experience, until it is unset again
+    let characters_uri = format!("{}/characters", campaign_uri);
+    let (_, characters) = send(&app, "GET", &characters_uri, &cookie, json!({})).await;
+    let brisa = characters
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|c| c["name"] == "Brisa")
+        .unwrap();
+    let brisa_uri = format!("{}/{}", characters_uri, brisa["id"]);
+    for (level, expected) in [(json!(3), 3), (json!(null), 1)] {
+        let (status, body) = send(&app, "PUT", &brisa_uri, &cookie, json!({"level": level})).await;
+        assert_eq!(status, StatusCode::NO_CONTENT, "{:?}", body);
+        let (_, characters) = send(&app, "GET", &characters_uri, &cookie, json!({})).await;
+        let brisa = characters
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|c| c["name"] == "Brisa")
+            .unwrap();
+        assert_eq!(brisa["level"], expected);
+    }
+    Ok(())
+}
@@ -0,0 +1,81 @@
+#[path = "common/mod.rs"]
+mod common;
+
+use common::{send, signup};
+use machete::app;
+use reqwest::StatusCode;
+use serde_json::json;
+use sqlx::PgPool;
+
+#[sqlx::test]
+async fn combat_test(pool: PgPool) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO library_objects (id, name, game_system) VALUES (1, 'Goblin Warrior', 0), (2, 'Ogre', 0)",
+    )
+    .execute(&pool)
+    .await?;
+    sqlx::query("INSERT INTO library_creatures (id, rarity, level) VALUES (1, 0, -1), (2, 0, 3)")
+        .execute(&pool)
+        .await?;
+
+    let app = app(pool);
+    let cookie = signup(&app, "test").await;
+    let (status, encounters) = send(
+        &app,
+        "POST",
+        "/encounters",
+        &cookie,
+        json!([{"name": "Ambush", "party_level": 1, "party_size": 4,
+            "encounter_type": "combat", "enemies": [{"id": 1}], "hazards": [],
+            "treasure_items": [], "treasure_currency": 0.0, "extra_experience": 0}]),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{:?}", encounters);
+    let combat = format!("/encounters/{}/combat", encounters[0]["id"]);
+
+    let goblin =
+        json!({"name": "Goblin", "combatant_type": "enemy", "id": 1, "initiative": 12, "hp": 6});
+    let (status, state) = send(
+        &app,
+        "POST",
+        &combat,
+        &cookie,
+        json!({"combatants": [goblin]}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{:?}", state);
+
+    // Replacement combatants must be part of the encounter, like when starting
+    let ogre = json!({"name": "Ogre", "combatant_type": "enemy", "id": 2, "initiative": 8});
+    let stranger =
+        json!({"name": "Stranger", "combatant_type": "character", "id": 999, "initiative": 8});
+    for combatant in [ogre, stranger] {
+        let combatants = json!({"combatants": [goblin, combatant]});
+        let (status, _) = send(&app, "PATCH", &combat, &cookie, combatants).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+    let (status, state) = send(
+        &app,
+        "PATCH",
+        &combat,
+        &cookie,
+        json!({"combatants": [{"name": "Goblin", "combatant_type": "enemy", "id": 1, "initiative": 12,
+            "hp": 0, "defeated": true}]}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{:?}", state);
+
+    // An ended combat can't be changed or ended again
+    let end = format!("{combat}/end");
+    let (status, state) = send(&app, "POST", &end, &cookie, json!({})).await;
+    assert_eq!(status, StatusCode::OK, "{:?}", state);
+    assert_eq!(state["ended"], true);
+    let (status, _) = send(&app, "POST", &end, &cookie, json!({})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = send(&app, "POST", &format!("{combat}/next"), &cookie, json!({})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = send(&app, "PATCH", &combat, &cookie, json!({"round": 2})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    Ok(())
+}
@@ -0,0 +1,74 @@
+// Helpers shared by the integration tests, which include this module with
+// `#[path = "common/mod.rs"] mod common;` (tests/common.rs is a test of its own).
+// Each test only uses some of the helpers.
+#![allow(dead_code)]
+
+use axum::{
+    body::{Body, Bytes},
+    http::{header, Request},
+    Router,
+};
+use http_body_util::BodyExt;
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use tower::util::ServiceExt;
+
+// Sends a JSON request authenticated by the given header (eg: a session cookie or bearer token), returning the raw body
+pub async fn send_raw(
+    app: &Router,
+    method: &str,
+    uri: &str,
+    auth: (&str, &str),
+    body: Value,
+) -> (StatusCode, Bytes) {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(auth.0, auth.1)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    (
+        status,
+        response.into_body().collect().await.unwrap().to_bytes(),
+    )
+}
+
+// As send_raw, parsing the response as JSON (null if it isn't)
+pub async fn send_as(
+    app: &Router,
+    method: &str,
+    uri: &str,
+    auth: (&str, &str),
+    body: Value,
+) -> (StatusCode, Value) {
+    let (status, body) = send_raw(app, method, uri, auth, body).await;
+    (status, serde_json::from_slice(&body).unwrap_or_default())
+}
+
+// Sends a JSON request with a session cookie (empty for none)
+pub async fn send(
+    app: &Router,
+    method: &str,
+    uri: &str,
+    cookie: &str,
+    body: Value,
+) -> (StatusCode, Value) {
+    send_as(app, method, uri, ("cookie", cookie), body).await
+}
+
+// Signs up a new user, returning their session cookie
+pub async fn signup(app: &Router, username: &str) -> String {
+    let (status, session) = send(
+        app,
+        "POST",
+        "/auth/signup",
+        "",
+        json!({"username": username, "password": "test"}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    format!("session_id={}", session["token"].as_str().unwrap())
+}
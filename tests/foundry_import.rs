@@ -0,0 +1,248 @@
+#[path = "common/mod.rs"]
+mod common;
+
+use common::{send, signup};
+use machete::app;
+use reqwest::StatusCode;
+use serde_json::json;
+use sqlx::PgPool;
+
+#[sqlx::test]
+async fn foundry_import_test(pool: PgPool) -> sqlx::Result<()> {
+    // A compendium with one pack per document type
+    let dir = std::env::temp_dir().join(format!("foundry-{}", rand::random::<u32>()));
+    let packs = [
+        (
+            "equipment/striking.json",
+            json!({"name": "Striking", "type": "equipment", "system": {
+                "level": {"value": 4}, "price": {"value": {"gp": 65}},
+                "traits": {"rarity": "common", "value": ["magical"]},
+                "usage": {"value": "etched-onto-a-weapon"}, "publication": {"remaster": true}}}),
+        ),
+        (
+            "equipment/longsword.json",
+            json!({"name": "+1 Striking Longsword", "type": "weapon", "system": {
+                "level": {"value": 4}, "price": {"value": {"gp": 100}},
+                "traits": {"rarity": "common", "value": ["versatile-p"]},
+                "runes": {"potency": 1, "striking": 1}, "publication": {"remaster": true}}}),
+        ),
+        (
+            "spells/light.json",
+            json!({"name": "Light", "type": "spell", "system": {
+                "level": {"value": 1}, "traits": {"rarity": "common", "value": ["cantrip", "light"],
+                "traditions": ["arcane", "divine"]}, "time": {"value": "2"},
+                "description": {"value": "<p>Glows.</p><hr /><p><strong>Heightened (4th)</strong> Glows brighter.</p>"}}}),
+        ),
+        (
+            "bestiary.db",
+            json!({"name": "Goblin Warrior", "type": "npc", "system": {
+                "details": {"level": {"value": -1}, "publication": {"remaster": true}},
+                "traits": {"rarity": "common", "size": {"value": "sm"}, "value": ["goblin"]},
+                "attributes": {"ac": {"value": 16}, "hp": {"max": 6}, "speed": {"value": 25},
+                    "weaknesses": [{"type": "fire", "value": 2}]},
+                "perception": {"mod": 2}, "skills": {"stealth": {"base": 5}},
+                "saves": {"fortitude": {"value": 5}, "reflex": {"value": 7}, "will": {"value": 3}}},
+                "items": [{"name": "Dogslicer", "type": "melee", "system": {"bonus": {"value": 8},
+                    "damageRolls": {"x": {"damage": "1d6", "damageType": "slashing"}},
+                    "traits": {"value": ["agile"]}}}]}),
+        ),
+        (
+            "hazards/_folders.json",
+            json!([{"name": "Traps", "type": "Item"}]),
+        ),
+        (
+            "hazards/pit.json",
+            json!({"name": "Hidden Pit", "type": "hazard", "system": {
+                "details": {"level": {"value": 0}, "isComplex": false},
+                "traits": {"rarity": "common", "value": ["trap"]}}}),
+        ),
+    ];
+    for (path, document) in packs {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, document.to_string()).unwrap();
+    }
+
+    let app = app(pool.clone());
+    let cookie = signup(&app, "admin").await;
+
+    let import = json!({"path": dir.to_str().unwrap()});
+    let (status, _) = send(
+        &app,
+        "POST",
+        "/library/import/foundry",
+        &cookie,
+        import.clone(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    sqlx::query("UPDATE users SET is_admin = TRUE WHERE username = 'admin'")
+        .execute(&pool)
+        .await?;
+    let (status, report) = send(
+        &app,
+        "POST",
+        "/library/import/foundry",
+        &cookie,
+        import.clone(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{:?}", report);
+    for (object_type, created) in [
+        ("creatures", 1),
+        ("items", 2),
+        ("spells", 1),
+        ("hazards", 1),
+    ] {
+        assert_eq!(
+            report[object_type]["created"].as_array().unwrap().len(),
+            created
+        );
+    }
+    assert_eq!(report["skipped"], 1);
+
+    // Etched runes are linked to the imported rune items
+    let (_, items) = send(&app, "GET", "/library/items?name=Longsword", "", json!({})).await;
+    assert_eq!(items[0]["runes"].as_array().unwrap().len(), 1);
+    let (_, spells) = send(&app, "GET", "/library/spells?name=Light", "", json!({})).await;
+    assert_eq!(spells[0]["traditions"], json!(["Arcane", "Divine"]));
+    assert_eq!(spells[0]["actions"], "2");
+    assert_eq!(
+        spells[0]["heightening"],
+        json!([{"type": "fixed", "rank": 4, "description": "Glows brighter."}])
+    );
+
+    // Creature stat blocks, with the elite adjustment applied on request
+    let (_, creatures) = send(&app, "GET", "/library/creatures?name=Goblin", "", json!({})).await;
+    let stat_block = &creatures[0]["stat_block"];
+    assert_eq!(stat_block["ac"], 16);
+    assert_eq!(stat_block["skills"][0]["name"], "Stealth");
+    assert_eq!(stat_block["weaknesses"][0]["damage_type"], "fire");
+    assert_eq!(stat_block["strikes"][0]["damage"][0]["roll"], "1d6");
+    let (_, elite) = send(
+        &app,
+        "GET",
+        &format!(
+            "/library/creatures/{}?level_adjustment=1",
+            creatures[0]["id"]
+        ),
+        "",
+        json!({}),
+    )
+    .await;
+    assert_eq!(elite["level"], 0);
+    assert_eq!(elite["stat_block"]["hp"], 16);
+    assert_eq!(elite["stat_block"]["strikes"][0]["attack"], 10);
+    assert_eq!(
+        elite["stat_block"]["strikes"][0]["damage"][0]["roll"],
+        "1d6+2"
+    );
+    for level_adjustment in [2, 32767] {
+        let (status, _) = send(
+            &app,
+            "GET",
+            &format!(
+                "/library/creatures/{}?level_adjustment={}",
+                creatures[0]["id"], level_adjustment
+            ),
+            "",
+            json!({}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    // Importing again does not duplicate anything
+    let (status, report) = send(
+        &app,
+        "POST",
+        "/library/import/foundry",
+        &cookie,
+        import.clone(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(report["items"]["created"], json!([]));
+    assert_eq!(report["items"]["unchanged"].as_array().unwrap().len(), 2);
+
+    // A renamed document is updated in place, keeping its id
+    let longsword_id = items[0]["id"].clone();
+    std::fs::write(
+        dir.join("equipment/longsword.json"),
+        json!({"_id": "abc", "name": "+1 Striking Longsword", "type": "weapon", "system": {
+            "level": {"value": 4}, "price": {"value": {"gp": 100}},
+            "traits": {"rarity": "common", "value": ["versatile-p"]},
+            "runes": {"potency": 1, "striking": 1}, "publication": {"remaster": true}}})
+        .to_string(),
+    )
+    .unwrap();
+    let (_, report) = send(
+        &app,
+        "POST",
+        "/library/import/foundry",
+        &cookie,
+        import.clone(),
+    )
+    .await;
+    assert_eq!(report["items"]["updated"], json!([longsword_id]));
+    std::fs::write(
+        dir.join("equipment/longsword.json"),
+        json!({"_id": "abc", "name": "Longsword (+1 Striking)", "type": "weapon", "system": {
+            "level": {"value": 5}, "price": {"value": {"gp": 100}},
+            "traits": {"rarity": "common", "value": ["versatile-p"]},
+            "runes": {"potency": 1, "striking": 1}, "publication": {"remaster": true}}})
+        .to_string(),
+    )
+    .unwrap();
+    let (_, report) = send(&app, "POST", "/library/import/foundry", &cookie, import).await;
+    assert_eq!(report["items"]["updated"], json!([longsword_id]));
+    let (_, item) = send(
+        &app,
+        "GET",
+        &format!("/library/items/{}", longsword_id),
+        "",
+        json!({}),
+    )
+    .await;
+    assert_eq!(item["name"], "Longsword (+1 Striking)");
+    assert_eq!(item["level"], 5);
+    assert_eq!(item["runes"].as_array().unwrap().len(), 1);
+
+    // Library routes can upsert too
+    let creature = json!([{"name": "Goblin Warrior", "game_system": "pf2e", "rarity": "common", "level": 1,
+        "tags": [], "alignment": "N", "size": "small", "legacy": false, "remastering_alt_id": null,
+        "url": null, "description": ""}]);
+    let (status, report) = send(
+        &app,
+        "POST",
+        "/library/creatures?upsert=true",
+        &cookie,
+        creature.clone(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{:?}", report);
+    assert_eq!(report["updated"].as_array().unwrap().len(), 1);
+
+    // Objects given twice, or matching the same object twice, are rejected
+    let mut by_source_id = creature[0].clone();
+    by_source_id["source_id"] = json!("homebrew:goblin");
+    for batch in [
+        json!([creature[0], creature[0]]),
+        json!([by_source_id, by_source_id]),
+        json!([creature[0], by_source_id]),
+    ] {
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/library/creatures?upsert=true",
+            &cookie,
+            batch,
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    std::fs::remove_dir_all(dir).unwrap();
+    Ok(())
+}
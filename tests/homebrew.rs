@@ -0,0 +1,243 @@
+#[path = "common/mod.rs"]
+mod common;
+
+use common::{send, signup};
+use machete::app;
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+
+fn names(objects: &Value) -> Vec<&str> {
+    objects
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|o| o["name"].as_str().unwrap())
+        .collect()
+}
+
+#[sqlx::test]
+async fn homebrew_test(pool: PgPool) -> sqlx::Result<()> {
+    let app = app(pool);
+    let owner = signup(&app, "owner").await;
+    let player = signup(&app, "player").await;
+
+    let creature = json!([{"name": "Homebrew Dragon", "game_system": "pf2e", "rarity": "common", "level": 3,
+        "tags": [], "alignment": "N", "size": "large", "legacy": false, "remastering_alt_id": null,
+        "url": null, "description": "", "stat_block": {"ac": 18, "hp": 45, "perception": 9,
+            "saves": {"fortitude": 10, "reflex": 8, "will": 7}}}]);
+    let (status, _) = send(
+        &app,
+        "POST",
+        "/library/homebrew/creatures",
+        "",
+        creature.clone(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, ids) = send(
+        &app,
+        "POST",
+        "/library/homebrew/creatures",
+        &owner,
+        creature,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let id = ids[0].as_u64().unwrap();
+
+    // Only the owner sees their homebrew
+    let search = "/library/creatures?name=Homebrew";
+    let (_, creatures) = send(&app, "GET", search, &owner, json!({})).await;
+    assert_eq!(names(&creatures), ["Homebrew Dragon"]);
+    for cookie in [player.as_str(), ""] {
+        let (_, creatures) = send(&app, "GET", search, cookie, json!({})).await;
+        assert!(names(&creatures).is_empty());
+    }
+    let (status, _) = send(
+        &app,
+        "GET",
+        &format!("/library/creatures/{id}"),
+        &player,
+        json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // Sharing to a campaign makes it visible to its members
+    let (_, campaign) = send(
+        &app,
+        "POST",
+        "/campaign",
+        &owner,
+        json!({"name": "campaign"}),
+    )
+    .await;
+    let share = json!({"campaign_id": campaign["id"]});
+    let (status, _) = send(
+        &app,
+        "PATCH",
+        &format!("/library/homebrew/{id}"),
+        &player,
+        share.clone(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send(
+        &app,
+        "PATCH",
+        &format!("/library/homebrew/{id}"),
+        &owner,
+        share,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (_, creatures) = send(&app, "GET", search, &player, json!({})).await;
+    assert!(names(&creatures).is_empty());
+
+    let (status, _) = send(
+        &app,
+        "POST",
+        &format!("/campaign/{}/members", campaign["id"]),
+        &owner,
+        json!({"username": "player", "role": "player"}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, creatures) = send(&app, "GET", search, &player, json!({})).await;
+    assert_eq!(names(&creatures), ["Homebrew Dragon"]);
+
+    // Homebrew works in encounters like official content
+    let encounter = json!([{"name": "Lair", "party_level": 3, "party_size": 4,
+        "encounter_type": "combat", "enemies": [{"id": id}], "hazards": [],
+        "treasure_items": [], "treasure_currency": 0.0, "extra_experience": 0}]);
+    let (status, encounters) = send(&app, "POST", "/encounters", &owner, encounter).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(encounters[0]["total_experience"], 40);
+
+    // Encounter views carry the stat blocks of elite or weak enemies, which combat starts from
+    let encounter = json!([{"name": "Lair", "party_level": 3, "party_size": 4, "campaign_id": campaign["id"],
+        "encounter_type": "combat", "enemies": [{"id": id, "level_adjustment": 1}], "hazards": [],
+        "treasure_items": [], "treasure_currency": 0.0, "extra_experience": 0}]);
+    let (status, encounters) = send(&app, "POST", "/encounters", &owner, encounter).await;
+    assert_eq!(status, StatusCode::OK, "{:?}", encounters);
+    let uri = format!("/encounters/{}", encounters[0]["id"]);
+    let (_, encounter) = send(&app, "GET", &uri, &owner, json!({})).await;
+    assert_eq!(encounter["enemies"][0]["stat_block"]["hp"], 60);
+    assert_eq!(encounter["enemies"][0]["stat_block"]["ac"], 20);
+    let start = json!({"combatants": [{"name": "Dragon", "combatant_type": "enemy", "id": id,
+        "level_adjustment": 1, "initiative": 12}]});
+    let (status, combat) = send(&app, "POST", &format!("{uri}/combat"), &owner, start).await;
+    assert_eq!(status, StatusCode::OK, "{:?}", combat);
+    assert_eq!(combat["combatants"][0]["hp"], 60);
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn homebrew_private_to_others_test(pool: PgPool) -> sqlx::Result<()> {
+    let app = app(pool.clone());
+    let owner = signup(&app, "owner").await;
+    let other = signup(&app, "other").await;
+
+    // The only creature and item in the library are the owner's private homebrew
+    let creature = json!([{"name": "Homebrew Goblin", "game_system": "pf2e", "rarity": "common", "level": 1,
+        "tags": [], "alignment": "N", "size": "small", "legacy": false, "remastering_alt_id": null,
+        "url": null, "description": ""}]);
+    let (status, ids) = send(
+        &app,
+        "POST",
+        "/library/homebrew/creatures",
+        &owner,
+        creature,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let creature_id = ids[0].as_u64().unwrap();
+    sqlx::query(
+        "INSERT INTO library_objects (id, name, game_system, owner)
+            SELECT 1000, 'Homebrew Sword', 0, id FROM users WHERE username = 'owner'",
+    )
+    .execute(&pool)
+    .await?;
+    sqlx::query("INSERT INTO library_items (id, rarity, level, price) VALUES (1000, 0, 1, 10.0)")
+        .execute(&pool)
+        .await?;
+
+    // Others can't attach it to encounters by id
+    let encounter = |enemies: Value, treasure_items: Value| {
+        json!([{"name": "Ambush", "party_level": 1, "party_size": 4,
+            "encounter_type": "combat", "enemies": enemies, "hazards": [],
+            "treasure_items": treasure_items, "treasure_currency": 0.0, "extra_experience": 0}])
+    };
+    let (status, _) = send(
+        &app,
+        "POST",
+        "/encounters",
+        &other,
+        encounter(json!([{"id": creature_id}]), json!([])),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = send(
+        &app,
+        "POST",
+        "/encounters",
+        &other,
+        encounter(json!([]), json!([1000])),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, encounters) = send(
+        &app,
+        "POST",
+        "/encounters",
+        &other,
+        encounter(json!([]), json!([])),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let uri = format!("/encounters/{}", encounters[0]["id"]);
+    let (status, _) = send(
+        &app,
+        "PATCH",
+        &uri,
+        &other,
+        json!({"enemies": [{"id": creature_id}]}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // Nor is it generated for them
+    let generate = json!({"party_level": 1, "party_size": 4, "difficulty": "Moderate"});
+    let (status, generated) = send(
+        &app,
+        "POST",
+        "/encounters/generate",
+        &owner,
+        generate.clone(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{:?}", generated);
+    let (status, _) = send(&app, "POST", "/encounters/generate", &other, generate).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    for (cookie, given_homebrew) in [(&owner, true), (&other, false)] {
+        let (_, campaign) = send(
+            &app,
+            "POST",
+            "/campaign",
+            cookie,
+            json!({"name": "campaign"}),
+        )
+        .await;
+        let uri = format!("/campaign/{}/treasure", campaign["id"]);
+        let (status, parcel) = send(&app, "POST", &uri, cookie, json!({})).await;
+        assert_eq!(status, StatusCode::OK, "{:?}", parcel);
+        let items = parcel["permanent_items"].as_array().unwrap();
+        assert_eq!(items.contains(&json!(1000)), given_homebrew);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,450 @@
+#[path = "common/mod.rs"]
+mod common;
+
+use common::{send, signup};
+use machete::app;
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+
+// (library item, quantity, container) of each listed item instance
+fn summary(items: &Value) -> Vec<(i64, i64, Option<i64>)> {
+    items
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|i| {
+            (
+                i["library_item_id"].as_i64().unwrap(),
+                i["quantity"].as_i64().unwrap(),
+                i["parent_item_id"].as_i64(),
+            )
+        })
+        .collect()
+}
+
+#[sqlx::test]
+async fn inventory_test(pool: PgPool) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO library_objects (id, name, game_system) VALUES (1, 'Fighter', 0), (2, 'Backpack', 0), (3, 'Healing Potion', 0)",
+    )
+    .execute(&pool)
+    .await?;
+    sqlx::query("INSERT INTO library_classes (id, rarity, hp) VALUES (1, 0, 10)")
+        .execute(&pool)
+        .await?;
+    sqlx::query("INSERT INTO library_items (id, rarity, level, price) VALUES (2, 0, 0, 0.1), (3, 0, 1, 4.0)")
+        .execute(&pool)
+        .await?;
+
+    let app = app(pool.clone());
+    let cookie = signup(&app, "test").await;
+    let (status, campaign) = send(
+        &app,
+        "POST",
+        "/campaign/import",
+        &cookie,
+        json!({
+            "id_hash": 100, "name": "Campaign", "level": 1, "description": null,
+            "characters": [
+                {"id_hash": 7, "name": "Alden", "player": null, "class": 1},
+                {"id_hash": 8, "name": "Brisa", "player": null, "class": 1}
+            ],
+            "sessions": [], "encounters": [], "items": []
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{:?}", campaign);
+    let campaign_id = campaign["id"].as_i64().unwrap();
+    let (_, characters) = send(
+        &app,
+        "GET",
+        &format!("/campaign/{}/characters", campaign_id),
+        &cookie,
+        json!({}),
+    )
+    .await;
+    let character_id = |name: &str| {
+        characters
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|c| c["name"] == name)
+            .unwrap()["id"]
+            .as_i64()
+            .unwrap()
+    };
+    let (alden, brisa) = (character_id("Alden"), character_id("Brisa"));
+
+    let (backpack, potions): (i32, i32) = sqlx::query_as(
+        "WITH i AS (
+            INSERT INTO item_instances (library_item_id, campaign_id, character_id, quantity)
+            VALUES (2, $1, $2, 1), (3, $1, $2, 5) RETURNING id, library_item_id
+        ) SELECT (SELECT id FROM i WHERE library_item_id = 2), (SELECT id FROM i WHERE library_item_id = 3)",
+    )
+    .bind(campaign_id as i32)
+    .bind(alden as i32)
+    .fetch_one(&pool)
+    .await?;
+
+    let uri = |path: String| format!("/v2/campaign/{}/{}", campaign_id, path);
+    let items_of = |character: i64| uri(format!("characters/{}/items", character));
+
+    // Split two potions off, and hand them to Brisa
+    let (status, split) = send(
+        &app,
+        "POST",
+        &uri(format!("items/{}/split", potions)),
+        &cookie,
+        json!({"quantity": 2}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{:?}", split);
+    let split = split["item_id"].as_i64().unwrap();
+    let (status, body) = send(
+        &app,
+        "POST",
+        &uri(format!("items/{}/move", split)),
+        &cookie,
+        json!({"character_id": brisa}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT, "{:?}", body);
+
+    // Only part of the stack, or all of it, can be moved
+    for quantity in [0, 4] {
+        let (status, _) = send(
+            &app,
+            "POST",
+            &uri(format!("items/{}/move", potions)),
+            &cookie,
+            json!({"character_id": brisa, "quantity": quantity}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    // Leave one in the party stash
+    let (status, body) = send(
+        &app,
+        "POST",
+        &uri(format!("items/{}/move", potions)),
+        &cookie,
+        json!({"character_id": null, "quantity": 1}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT, "{:?}", body);
+    let (_, stash) = send(
+        &app,
+        "GET",
+        &uri("stash/items".to_string()),
+        &cookie,
+        json!({}),
+    )
+    .await;
+    assert_eq!(summary(&stash), [(3, 1, None)]);
+
+    // Pack the remaining potions, then give the backpack (and so, the potions) to Brisa
+    let (status, body) = send(
+        &app,
+        "PUT",
+        &uri(format!("items/{}/container", potions)),
+        &cookie,
+        json!({"parent_item_id": backpack}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT, "{:?}", body);
+    let (status, _) = send(
+        &app,
+        "PUT",
+        &uri(format!("items/{}/container", backpack)),
+        &cookie,
+        json!({"parent_item_id": potions}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, body) = send(
+        &app,
+        "POST",
+        &uri(format!("items/{}/move", backpack)),
+        &cookie,
+        json!({"character_id": brisa}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT, "{:?}", body);
+    let (_, items) = send(&app, "GET", &items_of(alden), &cookie, json!({})).await;
+    assert_eq!(summary(&items), []);
+
+    // Brisa combines her potions into one stack
+    let (status, body) = send(
+        &app,
+        "POST",
+        &uri(format!("items/{}/merge", split)),
+        &cookie,
+        json!({"into_item_id": potions}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT, "{:?}", body);
+    let (_, items) = send(&app, "GET", &items_of(brisa), &cookie, json!({})).await;
+    assert_eq!(
+        summary(&items),
+        [(2, 1, None), (3, 4, Some(backpack as i64))]
+    );
+
+    // The merged stack's history carries over
+    let (status, history) = send(
+        &app,
+        "GET",
+        &uri(format!("items/{}/history", potions)),
+        &cookie,
+        json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{:?}", history);
+    let history = history
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|t| {
+            (
+                t["transfer_type"].as_str().unwrap(),
+                t["from_character_id"].as_i64(),
+                t["to_character_id"].as_i64(),
+                t["quantity"].as_i64().unwrap(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        history,
+        [
+            ("split", Some(alden), Some(alden), 2),
+            ("move", Some(alden), Some(brisa), 2),
+            ("move", Some(alden), Some(brisa), 2),
+            ("merge", Some(brisa), Some(brisa), 2),
+        ]
+    );
+
+    // A container can't be merged into one inside it
+    let (inner_backpack,): (i32,) = sqlx::query_as(
+        "INSERT INTO item_instances (library_item_id, campaign_id, character_id, parent_item_id)
+            VALUES (2, $1, $2, $3) RETURNING id",
+    )
+    .bind(campaign_id as i32)
+    .bind(brisa as i32)
+    .bind(backpack)
+    .fetch_one(&pool)
+    .await?;
+    let (status, _) = send(
+        &app,
+        "POST",
+        &uri(format!("items/{}/merge", backpack)),
+        &cookie,
+        json!({"into_item_id": inner_backpack}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    Ok(())
+}
+
+#[sqlx::test]
+async fn consumable_usage_test(pool: PgPool) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO library_objects (id, name, game_system) VALUES (1, 'Fighter', 0), (2, 'Longsword', 0), (3, 'Healing Potion', 0)",
+    )
+    .execute(&pool)
+    .await?;
+    sqlx::query("INSERT INTO library_classes (id, rarity, hp) VALUES (1, 0, 10)")
+        .execute(&pool)
+        .await?;
+    sqlx::query(
+        "INSERT INTO library_items (id, rarity, level, price, consumable) VALUES (2, 0, 0, 1.0, false), (3, 0, 1, 4.0, true)",
+    )
+    .execute(&pool)
+    .await?;
+
+    let app = app(pool.clone());
+    let cookie = signup(&app, "test").await;
+    let (status, campaign) = send(
+        &app,
+        "POST",
+        "/campaign/import",
+        &cookie,
+        json!({
+            "id_hash": 100, "name": "Campaign", "level": 1, "description": null,
+            "characters": [{"id_hash": 7, "name": "Alden", "player": null, "class": 1}],
+            "sessions": [{
+                "id_hash": 200, "name": "Session 1", "description": null, "date": "2024-01-01T00:00:00Z",
+                "compiled_rewards": {"7": {"gold": 0.0, "present": true}}
+            }],
+            "encounters": [], "items": []
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{:?}", campaign);
+    let campaign_id = campaign["id"].as_i64().unwrap();
+
+    // Three potions and a longsword, rewarded to Alden in the first session
+    let (character_id, session_id): (i32, i32) = sqlx::query_as(
+        "SELECT ch.id, cs.id FROM characters ch INNER JOIN campaign_sessions cs ON cs.campaign_id = ch.campaign WHERE ch.campaign = $1",
+    )
+    .bind(campaign_id as i32)
+    .fetch_one(&pool)
+    .await?;
+    let (potions, longsword): (i32, i32) = sqlx::query_as(
+        "WITH i AS (
+            INSERT INTO item_instances (library_item_id, campaign_id, character_id, session_id, quantity)
+            VALUES (3, $1, $2, $3, 3), (2, $1, $2, $3, 1) RETURNING id, library_item_id
+        ) SELECT (SELECT id FROM i WHERE library_item_id = 3), (SELECT id FROM i WHERE library_item_id = 2)",
+    )
+    .bind(campaign_id as i32)
+    .bind(character_id)
+    .bind(session_id)
+    .fetch_one(&pool)
+    .await?;
+
+    let uri = |path: String| format!("/v2/campaign/{}/{}", campaign_id, path);
+    let (status, body) = send(
+        &app,
+        "POST",
+        &uri(format!("items/{}/use", potions)),
+        &cookie,
+        json!({"session_id": session_id}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT, "{:?}", body);
+    let (status, _) = send(
+        &app,
+        "POST",
+        &uri(format!("items/{}/use", longsword)),
+        &cookie,
+        json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = send(
+        &app,
+        "POST",
+        &uri(format!("items/{}/use", potions)),
+        &cookie,
+        json!({"quantity": 3}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (_, items) = send(
+        &app,
+        "GET",
+        &uri(format!("characters/{}/items", character_id)),
+        &cookie,
+        json!({}),
+    )
+    .await;
+    assert_eq!(summary(&items), [(3, 2, None), (2, 1, None)]);
+
+    let (status, stats) = send(
+        &app,
+        "GET",
+        &format!("/campaign/{}/stats", campaign_id),
+        &cookie,
+        json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{:?}", stats);
+    let character = &stats["character_stats"][character_id.to_string()];
+    assert_eq!(character["total_consumable_items"], json!([3, 3, 3]));
+    assert_eq!(character["held_consumable_items"], json!([3, 3]));
+    assert_eq!(character["total_combined_treasure"], 13.0);
+    assert_eq!(character["spent_consumables_value"], 4.0);
+    assert_eq!(character["held_combined_treasure"], 9.0);
+
+    // Using the rest marks the stack itself as spent
+    let (status, body) = send(
+        &app,
+        "POST",
+        &uri(format!("items/{}/use", potions)),
+        &cookie,
+        json!({"quantity": 2, "session_id": session_id}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT, "{:?}", body);
+    let (_, items) = send(
+        &app,
+        "GET",
+        &uri(format!("characters/{}/items", character_id)),
+        &cookie,
+        json!({}),
+    )
+    .await;
+    assert_eq!(summary(&items), [(2, 1, None)]);
+    let (_, history) = send(
+        &app,
+        "GET",
+        &uri(format!("items/{}/history", potions)),
+        &cookie,
+        json!({}),
+    )
+    .await;
+    assert_eq!(history[0]["transfer_type"], "use");
+    assert_eq!(history[0]["quantity"], 2);
+
+    // Used up potions can't be given away or packed
+    let (status, _) = send(
+        &app,
+        "POST",
+        &uri(format!("items/{}/move", potions)),
+        &cookie,
+        json!({"character_id": null}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send(
+        &app,
+        "PUT",
+        &uri(format!("items/{}/container", longsword)),
+        &cookie,
+        json!({"parent_item_id": potions}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // Spent items, and when they were used, survive an export and import
+    let (status, exported) = send(
+        &app,
+        "GET",
+        &format!("/campaign/{}/export", campaign_id),
+        &cookie,
+        json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{:?}", exported);
+    let spent = |export: &Value| {
+        export["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|i| (i["spent"].clone(), i["spent_session_id"].clone()))
+            .collect::<Vec<_>>()
+    };
+    let session = exported["sessions"][0]["id"].clone();
+    assert_eq!(
+        spent(&exported),
+        [
+            (json!(true), session.clone()),
+            (json!(false), Value::Null),
+            (json!(true), session)
+        ]
+    );
+    let (status, imported) =
+        send(&app, "POST", "/campaign/import", &cookie, exported.clone()).await;
+    assert_eq!(status, StatusCode::OK, "{:?}", imported);
+    let (_, reexported) = send(
+        &app,
+        "GET",
+        &format!("/campaign/{}/export", imported["id"]),
+        &cookie,
+        json!({}),
+    )
+    .await;
+    assert_eq!(spent(&reexported), spent(&exported));
+    Ok(())
+}
@@ -0,0 +1,296 @@
+#[path = "common/mod.rs"]
+mod common;
+
+use axum::Router;
+use common::send;
+use machete::app;
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+
+async fn get(app: &Router, uri: &str) -> (StatusCode, Value) {
+    send(app, "GET", uri, "", json!({})).await
+}
+
+#[sqlx::test]
+async fn library_search_test(pool: PgPool) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO library_objects (id, name, game_system, legacy) VALUES
+            (1, 'Goblin Warrior', 0, FALSE), (2, 'Goblin Pox', 0, FALSE), (3, 'Goblin Song', 0, FALSE),
+            (4, 'Goblin Pit', 0, FALSE), (5, 'Goblin Commando', 0, TRUE), (6, 'Longsword', 0, FALSE)",
+    )
+    .execute(&pool)
+    .await?;
+    sqlx::query("INSERT INTO library_creatures (id, rarity, level) VALUES (1, 0, -1), (5, 1, 1)")
+        .execute(&pool)
+        .await?;
+    sqlx::query(
+        "INSERT INTO library_items (id, rarity, level, price) VALUES (2, 0, 1, 6.0), (6, 0, 0, 1.0)",
+    )
+    .execute(&pool)
+    .await?;
+    sqlx::query("INSERT INTO library_spells (id, rarity, rank) VALUES (3, 0, 1)")
+        .execute(&pool)
+        .await?;
+    sqlx::query("INSERT INTO library_hazards (id, rarity, level, complex, haunt) VALUES (4, 0, 0, FALSE, FALSE)")
+        .execute(&pool)
+        .await?;
+
+    let app = app(pool);
+    let (status, search) = get(&app, "/library/search?query=goblin").await;
+    assert_eq!(status, StatusCode::OK, "{:?}", search);
+    assert_eq!(search["total"], 5);
+    assert_eq!(
+        search["facets"],
+        json!({"creature": 2, "item": 1, "spell": 1, "hazard": 1})
+    );
+    // Ranked, with the shortest name first among equally good matches
+    assert_eq!(search["results"][0]["name"], "Goblin Pit");
+    assert_eq!(search["results"][0]["object_type"], "hazard");
+
+    // Shared filters apply across every type
+    let (_, search) = get(
+        &app,
+        "/library/search?query=goblin&min_level=1&legacy=remaster_only",
+    )
+    .await;
+    let names = search["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| r["name"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["Goblin Pox", "Goblin Song"]);
+
+    let (_, search) = get(
+        &app,
+        "/library/search?query=goblin&object_types=creature&object_types=spell&limit=1&page=1",
+    )
+    .await;
+    assert_eq!(search["total"], 3);
+    assert_eq!(search["results"].as_array().unwrap().len(), 1);
+
+    // Facets are still returned past the last page
+    let (_, search) = get(&app, "/library/search?query=goblin&page=10").await;
+    assert_eq!(search["results"], json!([]));
+    assert_eq!(search["total"], 5);
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn item_facets_test(pool: PgPool) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO library_objects (id, name, game_system) VALUES
+            (1, 'Healing Potion', 0), (2, 'Elixir of Life', 0), (3, 'Longsword', 0), (4, 'Holy Water', 0)",
+    )
+    .execute(&pool)
+    .await?;
+    sqlx::query(
+        "INSERT INTO library_items (id, rarity, level, price, item_categories, consumable, magical) VALUES
+            (1, 0, 1, 4.0, '{Potion}', TRUE, TRUE), (2, 0, 1, 3.0, '{Alchemical}', TRUE, FALSE),
+            (3, 0, 0, 1.0, '{Weapon}', FALSE, FALSE), (4, 1, 1, 3.0, '{Consumable}', TRUE, TRUE)",
+    )
+    .execute(&pool)
+    .await?;
+    sqlx::query(
+        "INSERT INTO library_tags (id, tag, trait) VALUES (1, 'Healing', TRUE), (2, 'Holy', TRUE)",
+    )
+    .execute(&pool)
+    .await?;
+    sqlx::query("INSERT INTO library_objects_tags (library_object_id, tag_id) VALUES (1, 1), (2, 1), (4, 2)")
+        .execute(&pool)
+        .await?;
+
+    let app = app(pool);
+    let (status, items) = get(&app, "/library/items?consumable=true&limit=1&facets=true").await;
+    assert_eq!(status, StatusCode::OK, "{:?}", items);
+    assert_eq!(items["results"].as_array().unwrap().len(), 1);
+    assert_eq!(
+        items["facets"],
+        json!({
+            "total": 3,
+            "rarity": {"common": 2, "uncommon": 1},
+            "level": {"1": 3},
+            "traits": {"Healing": 2, "Holy": 1},
+            "item_categories": {"Alchemical": 1, "Consumable": 1, "Potion": 1}
+        })
+    );
+
+    // Without asking for facets, the response is unchanged
+    let (_, items) = get(&app, "/library/items?consumable=true").await;
+    assert_eq!(items.as_array().unwrap().len(), 3);
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn cursor_pagination_test(pool: PgPool) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO library_objects (id, name, game_system, legacy) VALUES
+            (1, 'Dagger', 0, FALSE), (2, 'Flail', 0, FALSE), (3, 'Club', 0, FALSE),
+            (4, 'Axe', 0, FALSE), (5, 'Bow', 0, FALSE)",
+    )
+    .execute(&pool)
+    .await?;
+    sqlx::query(
+        "INSERT INTO library_items (id, rarity, level, price) VALUES
+            (1, 0, 2, 1.0), (2, 0, 1, 1.0), (3, 0, 2, 1.0), (4, 0, 0, 1.0), (5, 0, 1, 1.0)",
+    )
+    .execute(&pool)
+    .await?;
+
+    let app = app(pool.clone());
+    let mut names = Vec::new();
+    let mut cursor = "start".to_string();
+    for page in 0.. {
+        let (status, listing) = get(
+            &app,
+            &format!("/library/items?sort_by=level&order_by=desc&limit=2&cursor={cursor}"),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{:?}", listing);
+        names.extend(
+            listing["results"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|i| i["name"].as_str().unwrap().to_string()),
+        );
+
+        // Objects added before the cursor don't shift the pages after it
+        if page == 0 {
+            sqlx::query("INSERT INTO library_objects (id, name, game_system, legacy) VALUES (6, 'Spear', 0, FALSE)")
+                .execute(&pool)
+                .await?;
+            sqlx::query(
+                "INSERT INTO library_items (id, rarity, level, price) VALUES (6, 0, 3, 1.0)",
+            )
+            .execute(&pool)
+            .await?;
+        }
+        match listing["next_cursor"].as_str() {
+            Some(next) => cursor = next.to_string(),
+            None => break,
+        }
+    }
+    // Ties in level are broken by id
+    assert_eq!(names, ["Dagger", "Club", "Flail", "Bow", "Axe"]);
+
+    // Cursors only continue the sort they were made with
+    let (_, listing) = get(
+        &app,
+        "/library/items?sort_by=level&order_by=desc&limit=2&cursor=start",
+    )
+    .await;
+    let cursor = listing["next_cursor"].as_str().unwrap();
+    let (status, _) = get(
+        &app,
+        &format!("/library/items?sort_by=name&cursor={cursor}"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = get(&app, "/library/items?cursor=nonsense").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // Several sort columns, each with its own order
+    let mut names = Vec::new();
+    let mut cursor = "start".to_string();
+    loop {
+        let (status, listing) = get(
+            &app,
+            &format!("/library/items?sort_by=level:asc,name:desc&limit=4&cursor={cursor}"),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{:?}", listing);
+        names.extend(
+            listing["results"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|i| i["name"].as_str().unwrap().to_string()),
+        );
+        match listing["next_cursor"].as_str() {
+            Some(next) => cursor = next.to_string(),
+            None => break,
+        }
+    }
+    assert_eq!(names, ["Axe", "Flail", "Bow", "Dagger", "Club", "Spear"]);
+
+    // Without a cursor, listings are still plain pages
+    let (_, listing) = get(&app, "/library/items?sort_by=name&limit=2&page=1").await;
+    let names = listing
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|i| i["name"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["Club", "Dagger"]);
+    Ok(())
+}
+
+#[sqlx::test]
+async fn spell_filters_test(pool: PgPool) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO library_objects (id, name, game_system, legacy) VALUES
+            (1, 'Force Barrage', 0, FALSE), (2, 'Heal', 0, FALSE), (3, 'Fireball', 0, FALSE),
+            (4, 'Feather Fall', 0, FALSE)",
+    )
+    .execute(&pool)
+    .await?;
+    sqlx::query(
+        "INSERT INTO library_spells (id, rarity, rank, traditions, actions, saving_throw) VALUES
+            (1, 0, 1, '{Arcane,Occult}', '1 to 3', NULL),
+            (2, 0, 1, '{Divine,Primal}', '1 to 3', NULL),
+            (3, 0, 3, '{Arcane,Primal}', '2', 'reflex'),
+            (4, 0, 1, '{Arcane,Primal}', 'reaction', NULL)",
+    )
+    .execute(&pool)
+    .await?;
+    sqlx::query(
+        r#"UPDATE library_spells SET heightening = '[{"type": "interval", "interval": 1, "description": "2d6 more damage"}]' WHERE id = 3"#,
+    )
+    .execute(&pool)
+    .await?;
+
+    let app = app(pool);
+    let names = |listing: Value| {
+        listing
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| s["name"].as_str().unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+
+    // Spells an arcane caster can cast with a 2nd rank slot
+    let (status, spells) = get(&app, "/library/spells?traditions_any=arcane&cast_rank=2").await;
+    assert_eq!(status, StatusCode::OK, "{:?}", spells);
+    assert_eq!(names(spells), ["Feather Fall", "Force Barrage"]);
+
+    let (_, spells) = get(
+        &app,
+        "/library/spells?traditions_all=arcane&traditions_all=primal",
+    )
+    .await;
+    assert_eq!(names(spells), ["Feather Fall", "Fireball"]);
+
+    let (_, spells) = get(&app, "/library/spells?saving_throw=reflex").await;
+    assert_eq!(names(spells), ["Fireball"]);
+    let (_, spells) = get(&app, "/library/spells?actions=reaction").await;
+    assert_eq!(names(spells), ["Feather Fall"]);
+    let (_, spells) = get(&app, "/library/spells?min_rank=2&max_rank=3").await;
+    assert_eq!(names(spells), ["Fireball"]);
+
+    // Heightened entries that apply when cast at a higher rank
+    let (status, fireball) = get(&app, "/library/spells/3?cast_rank=5").await;
+    assert_eq!(status, StatusCode::OK, "{:?}", fireball);
+    assert_eq!(fireball["cast_rank"], 5);
+    assert_eq!(
+        fireball["heightened"],
+        json!([{"type": "interval", "interval": 1, "description": "2d6 more damage", "times": 2}])
+    );
+    let (status, _) = get(&app, "/library/spells/3?cast_rank=2").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    Ok(())
+}
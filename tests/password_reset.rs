@@ -0,0 +1,128 @@
+#[path = "common/mod.rs"]
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use axum::Router;
+use common::{send, send_as, signup};
+use machete::{
+    app, app_with_state,
+    mail::{Mail, MailFuture, Mailer},
+    AppState,
+};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+
+#[derive(Default)]
+struct CapturingMailer {
+    sent: Mutex<Vec<Mail>>,
+}
+
+impl Mailer for CapturingMailer {
+    fn send<'a>(&'a self, mail: &'a Mail) -> MailFuture<'a> {
+        self.sent.lock().unwrap().push(mail.clone());
+        Box::pin(async { Ok(()) })
+    }
+}
+
+async fn post(app: &Router, uri: &str, body: Value) -> StatusCode {
+    send(app, "POST", uri, "", body).await.0
+}
+
+#[sqlx::test]
+async fn password_reset_test(pool: PgPool) -> sqlx::Result<()> {
+    let mailer = Arc::new(CapturingMailer::default());
+    let app = app_with_state(AppState {
+        pool,
+        mailer: mailer.clone(),
+    });
+
+    let credentials = json!({"username": "test", "email": "test@example.com", "password": "old"});
+    let (status, session) = send(&app, "POST", "/auth/signup", "", credentials).await;
+    assert_eq!(status, StatusCode::OK);
+    let cookie = format!("session_id={}", session["token"].as_str().unwrap());
+
+    // Emails are unique to a user
+    let credentials = json!({"username": "other", "email": "test@example.com", "password": "old"});
+    let (status, _) = send(&app, "POST", "/auth/signup", "", credentials).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, created) = send(
+        &app,
+        "POST",
+        "/auth/tokens",
+        &cookie,
+        json!({"name": "script", "scope": "read"}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let bearer = format!("Bearer {}", created["token"].as_str().unwrap());
+
+    // Unknown users are not revealed
+    let status = post(&app, "/auth/password/reset", json!({"username": "nobody"})).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert!(mailer.sent.lock().unwrap().is_empty());
+
+    let status = post(&app, "/auth/password/reset", json!({"username": "test"})).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let mail = mailer.sent.lock().unwrap().pop().unwrap();
+    assert_eq!(mail.to, "test@example.com");
+    let token = mail.body.lines().last().unwrap().to_string();
+
+    let confirm = json!({"token": token, "new_password": "new"});
+    let status = post(&app, "/auth/password/reset/confirm", confirm.clone()).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    // Existing sessions and API tokens are revoked
+    for auth in [
+        ("cookie", cookie.as_str()),
+        ("authorization", bearer.as_str()),
+    ] {
+        let (status, _) = send_as(&app, "GET", "/auth/me", auth, json!({})).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    // Tokens are single use
+    let status = post(&app, "/auth/password/reset/confirm", confirm).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let login = json!({"username": "test", "password": "old"});
+    assert_eq!(
+        post(&app, "/auth/login", login).await,
+        StatusCode::UNAUTHORIZED
+    );
+    let login = json!({"username": "test", "password": "new"});
+    assert_eq!(post(&app, "/auth/login", login).await, StatusCode::OK);
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn change_password_test(pool: PgPool) -> sqlx::Result<()> {
+    let app = app(pool);
+    let cookie = signup(&app, "test").await;
+
+    // The current password must be given, by a logged in user
+    let change = json!({"current_password": "wrong", "new_password": "new"});
+    let (status, _) = send(&app, "POST", "/auth/password/change", &cookie, change).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let change = json!({"current_password": "test", "new_password": "new"});
+    assert_eq!(
+        post(&app, "/auth/password/change", change.clone()).await,
+        StatusCode::UNAUTHORIZED
+    );
+
+    let (status, _) = send(&app, "POST", "/auth/password/change", &cookie, change).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let login = json!({"username": "test", "password": "test"});
+    assert_eq!(
+        post(&app, "/auth/login", login).await,
+        StatusCode::UNAUTHORIZED
+    );
+    let login = json!({"username": "test", "password": "new"});
+    assert_eq!(post(&app, "/auth/login", login).await, StatusCode::OK);
+
+    Ok(())
+}
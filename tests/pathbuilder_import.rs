@@ -0,0 +1,140 @@
+#[path = "common/mod.rs"]
+mod common;
+
+use common::{send, signup};
+use machete::app;
+use reqwest::StatusCode;
+use serde_json::json;
+use sqlx::PgPool;
+
+#[sqlx::test]
+async fn pathbuilder_import_test(pool: PgPool) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO library_objects (id, name, game_system, legacy) VALUES
+            (1, 'Fighter', 0, false), (2, 'Longsword', 0, true), (3, 'Longsword', 0, false), (4, 'Backpack', 0, false)",
+    )
+    .execute(&pool)
+    .await?;
+    sqlx::query("INSERT INTO library_classes (id, rarity, hp) VALUES (1, 0, 10)")
+        .execute(&pool)
+        .await?;
+    sqlx::query(
+        "INSERT INTO library_items (id, rarity, level, price) VALUES (2, 0, 0, 1.0), (3, 0, 0, 1.0), (4, 0, 0, 0.1)",
+    )
+    .execute(&pool)
+    .await?;
+
+    let app = app(pool.clone());
+    let cookie = signup(&app, "test").await;
+    let (status, campaign) = send(
+        &app,
+        "POST",
+        "/campaign/import",
+        &cookie,
+        json!({
+            "id_hash": 100, "name": "Campaign", "level": 1, "description": null,
+            "characters": [], "sessions": [], "encounters": [], "items": []
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{:?}", campaign);
+    let campaign_uri = format!("/campaign/{}", campaign["id"]);
+
+    let mut export = json!({
+        "success": true,
+        "build": {
+            "name": "Alden",
+            "class": "fighter",
+            "level": 3,
+            "ancestry": "Human",
+            "heritage": "Versatile Human",
+            "background": "Guard",
+            "abilities": {"str": 18, "dex": 14, "con": 14, "int": 10, "wis": 12, "cha": 8},
+            "proficiencies": {"perception": 4, "fortitude": 4, "reflex": 2, "will": 2, "athletics": 4},
+            "lores": [["Legal", 2]],
+            "equipment": [["Backpack", 1], ["Rations", 2]],
+            "weapons": [{"name": "Longsword", "qty": 1}],
+            "armor": []
+        }
+    });
+    let uri = format!("{}/characters/pathbuilder", campaign_uri);
+    let (status, report) = send(&app, "POST", &uri, &cookie, export.clone()).await;
+    assert_eq!(status, StatusCode::OK, "{:?}", report);
+    assert_eq!(report["created"], true);
+    assert_eq!(report["items_added"], 2);
+    assert_eq!(report["unresolved_items"], json!(["Rations"]));
+
+    let (status, characters) = send(
+        &app,
+        "GET",
+        &format!("{}/characters", campaign_uri),
+        &cookie,
+        json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{:?}", characters);
+    let character = &characters[0];
+    assert_eq!(character["id"], report["character_id"]);
+    assert_eq!(character["class"], 1);
+    assert_eq!(character["ancestry"], "Human");
+    assert_eq!(character["level"], 3);
+    assert_eq!(character["ability_modifiers"]["strength"], 4);
+    assert_eq!(character["proficiencies"]["perception"], "expert");
+    assert_eq!(
+        character["proficiencies"]["skills"],
+        json!([
+            {"skill": "Athletics", "proficiency": "expert"},
+            {"skill": "Lore (Legal)", "proficiency": "trained"}
+        ])
+    );
+
+    // The remastered longsword is preferred
+    let held: Vec<(i32, i16)> = sqlx::query_as(
+        "SELECT library_item_id, quantity FROM item_instances WHERE character_id = $1 ORDER BY library_item_id",
+    )
+    .bind(report["character_id"].as_i64().unwrap() as i32)
+    .fetch_all(&pool)
+    .await?;
+    assert_eq!(held, [(3, 1), (4, 1)]);
+
+    // Re-importing after levelling up updates the same character, without duplicating its gear
+    export["build"]["level"] = json!(4);
+    let uri = format!(
+        "{}/characters/{}/pathbuilder",
+        campaign_uri, report["character_id"]
+    );
+    let (status, updated) = send(&app, "PUT", &uri, &cookie, export.clone()).await;
+    assert_eq!(status, StatusCode::OK, "{:?}", updated);
+    assert_eq!(updated["character_id"], report["character_id"]);
+    assert_eq!(updated["created"], false);
+    assert_eq!(updated["items_added"], 0);
+
+    // Levels and quantities must be in range
+    for (key, value) in [
+        ("level", json!(0)),
+        ("level", json!(21)),
+        ("equipment", json!([["Backpack", 40000]])),
+        ("equipment", json!([["Backpack", 0]])),
+    ] {
+        let mut invalid = export.clone();
+        invalid["build"][key] = value;
+        let (status, _) = send(&app, "PUT", &uri, &cookie, invalid).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    export["build"]["class"] = json!("Alchemist");
+    let (status, _) = send(&app, "PUT", &uri, &cookie, export).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (_, characters) = send(
+        &app,
+        "GET",
+        &format!("{}/characters", campaign_uri),
+        &cookie,
+        json!({}),
+    )
+    .await;
+    assert_eq!(characters.as_array().unwrap().len(), 1);
+    assert_eq!(characters[0]["level"], 4);
+    Ok(())
+}
@@ -0,0 +1,144 @@
+#[path = "common/mod.rs"]
+mod common;
+
+use common::{send, signup};
+use machete::app;
+use reqwest::StatusCode;
+use serde_json::json;
+use sqlx::PgPool;
+
+#[sqlx::test]
+async fn remaster_test(pool: PgPool) -> sqlx::Result<()> {
+    // Legacy goblin (1) and longsword (2), and their remasters (3, 4)
+    sqlx::query(
+        "INSERT INTO library_objects (id, name, game_system, legacy) VALUES
+            (1, 'Goblin Warrior', 0, TRUE), (2, 'Longsword', 0, TRUE),
+            (3, 'Goblin Warrior', 0, FALSE), (4, 'Longsword', 0, FALSE), (5, 'Goblin Pyro', 0, FALSE)",
+    )
+    .execute(&pool)
+    .await?;
+    sqlx::query(
+        "INSERT INTO library_creatures (id, rarity, level) VALUES (1, 0, -1), (3, 0, 1), (5, 0, 1)",
+    )
+    .execute(&pool)
+    .await?;
+    sqlx::query("INSERT INTO library_items (id, rarity, level, price) VALUES (2, 0, 0, 1.0), (4, 0, 0, 2.0)")
+        .execute(&pool)
+        .await?;
+
+    let app = app(pool.clone());
+    let cookie = signup(&app, "admin").await;
+    sqlx::query("UPDATE users SET is_admin = TRUE")
+        .execute(&pool)
+        .await?;
+
+    // Pairs are proposed by name, within each type
+    let (status, proposals) = send(
+        &app,
+        "GET",
+        "/library/remaster/proposals?object_type=creature",
+        &cookie,
+        json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{:?}", proposals);
+    assert_eq!(proposals.as_array().unwrap().len(), 1);
+    assert_eq!(proposals[0]["legacy_id"], 1);
+    assert_eq!(proposals[0]["remaster_id"], 3);
+
+    let (status, _) = send(
+        &app,
+        "POST",
+        "/library/remaster",
+        &cookie,
+        json!([{"legacy_id": 1, "remaster_id": 4}]),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = send(
+        &app,
+        "POST",
+        "/library/remaster",
+        &cookie,
+        json!([{"legacy_id": 1, "remaster_id": 3}, {"legacy_id": 2, "remaster_id": 4}]),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (_, item) = send(&app, "GET", "/library/items/2", "", json!({})).await;
+    assert_eq!(item["remastering_alt_id"], 4);
+    let (_, items) = send(&app, "GET", "/library/items?legacy=remaster", "", json!({})).await;
+    assert_eq!(items.as_array().unwrap().len(), 1);
+    assert_eq!(items[0]["id"], 4);
+
+    // A campaign using the legacy goblin and longsword
+    let (status, campaign) = send(
+        &app,
+        "POST",
+        "/campaign/import",
+        &cookie,
+        json!({
+            "id_hash": 1, "name": "Campaign", "level": 1, "description": null, "characters": [],
+            "sessions": [{"id_hash": 2, "name": "Session 1", "description": null,
+                "date": "2021-01-01T00:00:00Z", "compiled_rewards": {}}],
+            "encounters": [{"id_hash": 3, "name": "Ambush", "description": null, "session_ix": 0,
+                "party_level": 1, "party_size": 4, "encounter_type": "combat",
+                "enemies": [{"id": 1, "level_adjustment": 0}], "hazards": [],
+                "treasure_items": [2], "treasure_currency": 0.0, "extra_experience": 0}],
+            "items": []
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{:?}", campaign);
+    let remaster = format!("/campaign/{}/remaster", campaign["id"]);
+
+    // Encounters of the campaign that are not linked to a session are remastered too
+    let (status, encounters) = send(
+        &app,
+        "POST",
+        "/encounters",
+        &cookie,
+        json!([{"name": "Patrol", "campaign_id": campaign["id"], "party_level": 1, "party_size": 4,
+            "encounter_type": "combat", "enemies": [{"id": 1}], "hazards": [],
+            "treasure_items": [], "treasure_currency": 0.0, "extra_experience": 0}]),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{:?}", encounters);
+
+    // A dry run reports the changes without making them
+    let (status, report) = send(&app, "POST", &remaster, &cookie, json!({"dry_run": true})).await;
+    assert_eq!(status, StatusCode::OK, "{:?}", report);
+    let changes = report["changes"].as_array().unwrap();
+    assert_eq!(changes.len(), 3);
+    assert_eq!(
+        changes
+            .iter()
+            .filter(|c| c["object_type"] == "creature" && c["remaster_id"] == 3)
+            .count(),
+        2
+    );
+    assert!(changes
+        .iter()
+        .any(|c| c["object_type"] == "item" && c["remaster_id"] == 4));
+    let (_, report) = send(&app, "POST", &remaster, &cookie, json!({"dry_run": true})).await;
+    assert_eq!(report["changes"].as_array().unwrap().len(), 3);
+
+    let (_, report) = send(&app, "POST", &remaster, &cookie, json!({})).await;
+    assert_eq!(report["changes"].as_array().unwrap().len(), 3);
+    let (_, encounters) = send(&app, "GET", "/encounters", &cookie, json!({})).await;
+    for encounter in encounters.as_array().unwrap() {
+        assert_eq!(encounter["enemies"][0]["id"], 3);
+        // The remastered goblin is level 1, up from -1
+        assert_eq!(encounter["total_experience"], 40);
+    }
+    let ambush = encounters
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|e| e["name"] == "Ambush")
+        .unwrap();
+    assert_eq!(ambush["treasure_items"], json!([4]));
+    let (_, report) = send(&app, "POST", &remaster, &cookie, json!({})).await;
+    assert_eq!(report["changes"], json!([]));
+
+    Ok(())
+}
@@ -0,0 +1,140 @@
+#[path = "common/mod.rs"]
+mod common;
+
+use common::{send, signup};
+use machete::app;
+use reqwest::StatusCode;
+use serde_json::json;
+use sqlx::PgPool;
+
+#[sqlx::test]
+async fn infiltration_progress_test(pool: PgPool) -> sqlx::Result<()> {
+    let app = app(pool.clone());
+    let cookie = signup(&app, "test").await;
+
+    let (status, encounters) = send(
+        &app,
+        "POST",
+        "/encounters",
+        &cookie,
+        json!([{"name": "Heist", "party_level": 1, "party_size": 4,
+            "encounter_type": "subsystem", "subsystem_type": "infiltration",
+            "subsystem_checks": [
+                {"name": "Guards", "vp": 2, "roll_options": [{"skill": "Stealth", "dc": 15}]},
+                {"name": "Vault", "vp": 1, "roll_options": [{"skill": "Thievery", "dc": 18}]}
+            ],
+            "awareness_thresholds": [2, 4],
+            "treasure_items": [], "treasure_currency": 0.0, "extra_experience": 0}]),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{:?}", encounters);
+    let encounter_id = encounters[0]["id"].clone();
+    let subsystem = format!("/encounters/{}/subsystem", encounter_id);
+    let attempts = format!("{}/attempts", subsystem);
+
+    let (status, progress) = send(&app, "GET", &subsystem, &cookie, json!({})).await;
+    assert_eq!(status, StatusCode::OK, "{:?}", progress);
+    assert_eq!(progress["total_points"], 0);
+    assert_eq!(progress["completed"], false);
+
+    // Critical failures add awareness rather than losing points
+    for (check_index, skill, degree) in [
+        (0, "Stealth", "success"),
+        (0, "Stealth", "criticalFailure"),
+        (0, "Stealth", "criticalFailure"),
+        (1, "Thievery", "failure"),
+    ] {
+        let (status, progress) = send(
+            &app,
+            "POST",
+            &attempts,
+            &cookie,
+            json!({"check_index": check_index, "skill": skill, "degree_of_success": degree}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{:?}", progress);
+    }
+    let (status, progress) = send(&app, "GET", &subsystem, &cookie, json!({})).await;
+    assert_eq!(status, StatusCode::OK, "{:?}", progress);
+    assert_eq!(progress["checks"][0]["points"], 1);
+    assert_eq!(progress["checks"][1]["points"], 0);
+    assert_eq!(progress["awareness_points"], 2);
+    assert_eq!(progress["awareness_thresholds_reached"], json!([2]));
+    assert_eq!(progress["attempts"].as_array().unwrap().len(), 4);
+
+    let (status, progress) = send(
+        &app,
+        "POST",
+        &attempts,
+        &cookie,
+        json!({"check_index": 1, "skill": "Thievery", "degree_of_success": "criticalSuccess"}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{:?}", progress);
+    let (status, progress) = send(
+        &app,
+        "POST",
+        &attempts,
+        &cookie,
+        json!({"check_index": 0, "skill": "Stealth", "degree_of_success": "success"}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{:?}", progress);
+    assert_eq!(
+        progress["checks"],
+        json!([
+            {"name": "Guards", "vp": 2, "points": 2, "reached": true},
+            {"name": "Vault", "vp": 1, "points": 2, "reached": true}
+        ])
+    );
+    assert_eq!(progress["completed"], true);
+
+    // Checks must exist in the encounter
+    let (status, _) = send(
+        &app,
+        "POST",
+        &attempts,
+        &cookie,
+        json!({"check_index": 2, "skill": "Stealth", "degree_of_success": "success"}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // Thresholds can be changed on the encounter, and removing an attempt updates the progress
+    let (status, _) = send(
+        &app,
+        "PATCH",
+        &format!("/encounters/{}", encounter_id),
+        &cookie,
+        json!({"awareness_thresholds": [1, 3]}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let first_failure = progress["attempts"][1]["id"].clone();
+    let (status, _) = send(
+        &app,
+        "DELETE",
+        &format!("{}/{}", attempts, first_failure),
+        &cookie,
+        json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, progress) = send(&app, "GET", &subsystem, &cookie, json!({})).await;
+    assert_eq!(status, StatusCode::OK, "{:?}", progress);
+    assert_eq!(progress["awareness_points"], 1);
+    assert_eq!(progress["awareness_thresholds_reached"], json!([1]));
+    assert_eq!(progress["attempts"].as_array().unwrap().len(), 5);
+
+    // Unknown degrees of success are not read as failures
+    sqlx::query(
+        "INSERT INTO encounter_subsystem_attempts (encounter_id, check_index, skill, degree_of_success)
+            VALUES ($1, 0, 'Stealth', 7)",
+    )
+    .bind(encounter_id.as_i64().unwrap() as i32)
+    .execute(&pool)
+    .await?;
+    let (status, _) = send(&app, "GET", &subsystem, &cookie, json!({})).await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    Ok(())
+}
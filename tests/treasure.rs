@@ -0,0 +1,203 @@
+#[path = "common/mod.rs"]
+mod common;
+
+use axum::Router;
+use common::{send, signup};
+use machete::{
+    app,
+    database::treasure::{attach_treasure_parcel, generate_treasure_parcel, GenerateTreasure},
+    models::ids::InternalId,
+};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+
+// One permanent and one consumable item at each of levels 1 and 2
+async fn insert_items(pool: &PgPool) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO library_objects (id, name, game_system) VALUES
+            (1, 'Ring', 0), (2, 'Cloak', 0), (3, 'Potion', 0), (4, 'Elixir', 0)",
+    )
+    .execute(pool)
+    .await?;
+    sqlx::query(
+        "INSERT INTO library_items (id, rarity, level, price, consumable) VALUES
+            (1, 0, 1, 10.0, false), (2, 0, 2, 20.0, false), (3, 0, 1, 3.0, true), (4, 0, 2, 5.0, true)",
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Imports a level 1 campaign with a single session and encounter, returning the campaign id and encounter id
+async fn import_campaign(app: &Router, pool: &PgPool, cookie: &str, name: &str) -> (Value, i32) {
+    let (status, campaign) = send(
+        app,
+        "POST",
+        "/campaign/import",
+        cookie,
+        json!({
+            "id_hash": 100, "name": name, "level": 1, "description": null,
+            "characters": [{"id_hash": 7, "name": "Alden", "player": null, "class": 100}],
+            "sessions": [{
+                "id_hash": 200, "name": "Session 1", "description": null, "date": "2024-01-01T00:00:00Z",
+                "compiled_rewards": {}
+            }],
+            "encounters": [{
+                "id_hash": 300, "name": format!("{} fight", name), "description": null, "session_ix": 0,
+                "party_level": 1, "party_size": 1, "encounter_type": "combat",
+                "enemies": [], "hazards": [], "treasure_items": [], "treasure_currency": 0.0,
+                "extra_experience": 0
+            }],
+            "items": []
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{:?}", campaign);
+    let encounter_id = sqlx::query_scalar::<_, i32>("SELECT id FROM encounters WHERE name = $1")
+        .bind(format!("{} fight", name))
+        .fetch_one(pool)
+        .await
+        .unwrap();
+    (campaign["id"].clone(), encounter_id)
+}
+
+async fn encounter_treasure(pool: &PgPool, encounter_id: i32) -> sqlx::Result<(i64, f64)> {
+    sqlx::query_as(
+        "SELECT
+            (SELECT COUNT(*) FROM item_instances WHERE encounter_id = $1 AND is_reward),
+            treasure_currency
+        FROM encounters WHERE id = $1",
+    )
+    .bind(encounter_id)
+    .fetch_one(pool)
+    .await
+}
+
+#[sqlx::test]
+async fn treasure_parcel_test(pool: PgPool) -> sqlx::Result<()> {
+    sqlx::query("INSERT INTO library_objects (id, name, game_system) VALUES (100, 'Fighter', 0)")
+        .execute(&pool)
+        .await?;
+    sqlx::query("INSERT INTO library_classes (id, rarity, hp) VALUES (100, 0, 10)")
+        .execute(&pool)
+        .await?;
+    insert_items(&pool).await?;
+
+    let app = app(pool.clone());
+    let cookie = signup(&app, "test").await;
+    let (campaign_id, encounter_id) = import_campaign(&app, &pool, &cookie, "Campaign").await;
+    let owner = sqlx::query_scalar::<_, i32>("SELECT id FROM users WHERE username = 'test'")
+        .fetch_one(&pool)
+        .await?;
+    let campaign_id = InternalId(campaign_id.as_u64().unwrap() as u32);
+
+    // Level 1 expects two permanent items of each of levels 1 and 2, and two level 2 and three level 1
+    // consumables. With a single character, the party currency is reduced for three missing players.
+    let parcel = generate_treasure_parcel(
+        &pool,
+        InternalId(owner as u32),
+        campaign_id,
+        &GenerateTreasure {
+            level: Some(1),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(parcel.level, 1);
+    assert_eq!(parcel.currency, 10.0);
+    let mut permanent = parcel
+        .permanent_items
+        .iter()
+        .map(|id| id.0)
+        .collect::<Vec<_>>();
+    permanent.sort();
+    assert_eq!(permanent, [1, 1, 2, 2]);
+    let mut consumable = parcel
+        .consumable_items
+        .iter()
+        .map(|id| id.0)
+        .collect::<Vec<_>>();
+    consumable.sort();
+    assert_eq!(consumable, [3, 3, 3, 4, 4]);
+    assert_eq!(
+        parcel.total_items_value,
+        2.0 * 10.0 + 2.0 * 20.0 + 3.0 * 3.0 + 2.0 * 5.0
+    );
+
+    // Attaching adds the items as rewards and the currency to the encounter
+    let mut tx = pool.begin().await?;
+    attach_treasure_parcel(
+        &mut tx,
+        InternalId(owner as u32),
+        campaign_id,
+        InternalId(encounter_id as u32),
+        &parcel,
+    )
+    .await
+    .unwrap();
+    tx.commit().await?;
+    assert_eq!(encounter_treasure(&pool, encounter_id).await?, (9, 10.0));
+
+    // Once given, the expected treasure for the level is no longer missing
+    let parcel = generate_treasure_parcel(
+        &pool,
+        InternalId(owner as u32),
+        campaign_id,
+        &GenerateTreasure {
+            level: Some(1),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert!(parcel.permanent_items.is_empty());
+    assert!(parcel.consumable_items.is_empty());
+    Ok(())
+}
+
+#[sqlx::test]
+async fn treasure_encounter_campaign_test(pool: PgPool) -> sqlx::Result<()> {
+    sqlx::query("INSERT INTO library_objects (id, name, game_system) VALUES (100, 'Fighter', 0)")
+        .execute(&pool)
+        .await?;
+    sqlx::query("INSERT INTO library_classes (id, rarity, hp) VALUES (100, 0, 10)")
+        .execute(&pool)
+        .await?;
+    insert_items(&pool).await?;
+
+    let app = app(pool.clone());
+    let cookie = signup(&app, "test").await;
+    let (campaign_id, encounter_id) = import_campaign(&app, &pool, &cookie, "First").await;
+    let (_, other_encounter_id) = import_campaign(&app, &pool, &cookie, "Second").await;
+
+    // The user can edit both encounters, but the second is not part of the first campaign
+    let uri = format!("/campaign/{}/treasure", campaign_id);
+    let (status, _) = send(
+        &app,
+        "POST",
+        &uri,
+        &cookie,
+        json!({ "level": 1, "encounter_id": other_encounter_id }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(
+        encounter_treasure(&pool, other_encounter_id).await?,
+        (0, 0.0)
+    );
+
+    let (status, parcel) = send(
+        &app,
+        "POST",
+        &uri,
+        &cookie,
+        json!({ "level": 1, "encounter_id": encounter_id }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{:?}", parcel);
+    assert_eq!(parcel["encounter_id"], json!(encounter_id));
+    assert_eq!(encounter_treasure(&pool, encounter_id).await?, (9, 10.0));
+    Ok(())
+}